@@ -46,7 +46,8 @@ if [[ -z "${HIGHFIVE_GH_TOKEN}" ]]; then
 fi
 
 git checkout "${GIT_BRANCH}"
-GITHUB_TOKEN="${HIGHFIVE_GH_TOKEN}" cargo run --release -- data
+# Exit code 5 means the timeout was reached, which is expected on CI
+GITHUB_TOKEN="${HIGHFIVE_GH_TOKEN}" cargo run --release -- data || [[ $? -eq 5 ]]
 
 
 if git diff --quiet data/; then
@@ -0,0 +1,369 @@
+// Copyright (c) 2018 Pietro Albini <pietro@pietroalbini.org>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies
+// of the Software, and to permit persons to whom the Software is furnished to do
+// so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Cross-reference of the locked packages with the [RustSec advisory database][db].
+//!
+//! [db]: https://github.com/rustsec/advisory-db
+
+use csv;
+use data::{write_atomically, Data};
+use prelude::*;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use version::{Version, VersionReq};
+
+static PLATFORM: &str = "github";
+
+struct Advisory {
+    id: String,
+    package: String,
+    /// Versions not affected by the advisory, either because they're patched or because they
+    /// predate the vulnerability.
+    safe: Vec<VersionReq>,
+}
+
+impl Advisory {
+    fn affects(&self, version: &Version) -> bool {
+        !self.safe.iter().any(|req| req.matches(version))
+    }
+}
+
+/// Write a CSV listing the repositories locking a crate version affected by an advisory.
+pub fn report(data: &Data, advisory_db: &Path, output: &Path) -> Fallible<()> {
+    let advisories = load_advisories(advisory_db)?;
+    info!("loaded {} advisories", advisories.len());
+
+    let mut by_package: HashMap<&str, Vec<&Advisory>> = HashMap::new();
+    for advisory in &advisories {
+        by_package
+            .entry(advisory.package.as_str())
+            .or_default()
+            .push(advisory);
+    }
+
+    let mut matches = Vec::new();
+    for package in data.locked_packages(PLATFORM)? {
+        let package = package?;
+
+        // Packages from git repositories or local paths can share names with unrelated crates
+        if !package.is_from_crates_io() {
+            continue;
+        }
+
+        let advisories = match by_package.get(package.name.as_str()) {
+            Some(advisories) => advisories,
+            None => continue,
+        };
+        let version = match package.version.parse::<Version>() {
+            Ok(version) => version,
+            Err(err) => {
+                warn!("skipping {} {}: {}", package.name, package.version, err);
+                continue;
+            }
+        };
+        for advisory in advisories.iter().filter(|adv| adv.affects(&version)) {
+            matches.push((
+                package.repo_id.clone(),
+                package.name.clone(),
+                package.version.clone(),
+                advisory.id.clone(),
+            ));
+        }
+    }
+
+    let names = data
+        .repos(PLATFORM)?
+        .map(|repo| repo.map(|repo| (repo.id, repo.name)))
+        .collect::<Fallible<HashMap<_, _>>>()?;
+
+    write_atomically(output, |file| {
+        let mut csv = csv::Writer::from_writer(file);
+        csv.write_record(["repo_id", "repo_name", "package", "version", "advisory"])?;
+        for (repo_id, package, version, advisory) in &matches {
+            let name = names.get(repo_id).map(|name| name.as_str()).unwrap_or("");
+            csv.write_record([repo_id.as_str(), name, package, version, advisory])?;
+        }
+        csv.flush()?;
+        Ok(())
+    })
+    .context(ErrorKind::Storage)?;
+
+    info!(
+        "found {} vulnerable packages, written to {}",
+        matches.len(),
+        output.to_string_lossy()
+    );
+    Ok(())
+}
+
+fn load_advisories(db: &Path) -> Fallible<Vec<Advisory>> {
+    let crates = db.join("crates");
+    if !crates.is_dir() {
+        bail!(
+            "{} doesn't look like a clone of the advisory database",
+            db.to_string_lossy()
+        );
+    }
+
+    let mut advisories = Vec::new();
+    for krate in fs::read_dir(&crates)? {
+        for file in fs::read_dir(krate?.path())? {
+            let path = file?.path();
+            let content = fs::read_to_string(&path)?;
+            let toml = match path.extension().and_then(|ext| ext.to_str()) {
+                Some(extension) => match metadata(extension, &content) {
+                    Some(toml) => toml,
+                    None => continue,
+                },
+                None => continue,
+            };
+
+            let advisory = parse_advisory(toml)
+                .with_context(|_| format!("failed to parse {}", path.to_string_lossy()))?;
+            if let Some(advisory) = advisory {
+                advisories.push(advisory);
+            }
+        }
+    }
+
+    Ok(advisories)
+}
+
+/// Extract the TOML metadata from the content of an advisory file, or `None` if the file isn't an
+/// advisory.
+fn metadata<'a>(extension: &str, content: &'a str) -> Option<&'a str> {
+    match extension {
+        // Newer advisories are Markdown files with the metadata in a TOML code block
+        "md" => Some(
+            content
+                .split("```toml")
+                .nth(1)
+                .and_then(|rest| rest.split("```").next())
+                .unwrap_or(""),
+        ),
+        "toml" => Some(content),
+        _ => None,
+    }
+}
+
+/// Parse the fields needed from the metadata of an advisory, returning `None` for withdrawn and
+/// informational advisories.
+///
+/// Only the subset of TOML used by the advisories is understood: tables, strings and arrays of
+/// strings, possibly spanning multiple lines. Multi-line strings (like the descriptions of the
+/// older advisories) are skipped.
+fn parse_advisory(toml: &str) -> Fallible<Option<Advisory>> {
+    let mut fields: HashMap<(String, String), Vec<String>> = HashMap::new();
+    let mut table = String::new();
+
+    let mut lines = toml.lines();
+    while let Some(line) = lines.next() {
+        let line = line.trim();
+        if line.starts_with('[') && !line.contains('=') {
+            table = line.trim_matches(|c| c == '[' || c == ']').to_string();
+            continue;
+        }
+
+        let mut parts = line.splitn(2, '=');
+        let (key, mut value) = match (parts.next(), parts.next()) {
+            (Some(key), Some(value)) if !key.trim().starts_with('#') => {
+                (key.trim().to_string(), value.trim().to_string())
+            }
+            _ => continue,
+        };
+        if let Some(rest) = value.strip_prefix("\"\"\"") {
+            // Skip the multi-line string, whose lines could look like tables or keys
+            if !rest.contains("\"\"\"") {
+                loop {
+                    match lines.next() {
+                        Some(next) if next.contains("\"\"\"") => break,
+                        Some(_) => {}
+                        None => bail!("unterminated string for key {}", key),
+                    }
+                }
+            }
+            continue;
+        }
+        if value.starts_with('[') {
+            while !value.contains(']') {
+                match lines.next() {
+                    Some(next) => value.push_str(next),
+                    None => bail!("unterminated array for key {}", key),
+                }
+            }
+        }
+
+        // Quoted strings are at the odd positions when splitting by quotes
+        let strings = value
+            .split('"')
+            .skip(1)
+            .step_by(2)
+            .map(String::from)
+            .collect();
+        fields.insert((table.clone(), key), strings);
+    }
+
+    let get = |table: &str, key: &str| fields.get(&(table.to_string(), key.to_string()));
+    if get("advisory", "withdrawn").is_some() || get("advisory", "informational").is_some() {
+        return Ok(None);
+    }
+
+    let first = |key: &str| {
+        get("advisory", key)
+            .and_then(|values| values.first().cloned())
+            .ok_or_else(|| format_err!("missing advisory.{}", key))
+    };
+    let safe = ["patched", "unaffected"]
+        .iter()
+        .flat_map(|key| get("versions", key).cloned().unwrap_or_default())
+        .map(|req| req.parse())
+        .collect::<Fallible<_>>()?;
+
+    Ok(Some(Advisory {
+        id: first("id")?,
+        package: first("package")?,
+        safe,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{metadata, parse_advisory, Advisory};
+    use version::Version;
+
+    fn affects(advisory: &Advisory, version: &str) -> bool {
+        advisory.affects(&version.parse::<Version>().unwrap())
+    }
+
+    #[test]
+    fn test_markdown_advisory() {
+        let content = r#"```toml
+[advisory]
+id = "RUSTSEC-2020-0071"
+package = "time"
+date = "2020-11-18"
+url = "https://github.com/time-rs/time/issues/293"
+categories = ["code-execution", "memory-corruption"]
+keywords = ["segfault"]
+aliases = ["CVE-2020-26235", "GHSA-wcg3-cvx6-7396"]
+
+[affected]
+os = [
+    "linux",
+    "redox",
+    "solaris",
+    "android",
+    "ios",
+    "macos",
+    "netbsd",
+    "openbsd",
+    "freebsd",
+]
+functions = { "time::UtcOffset::current_local_offset" = ["<0.2.23, >=0.2.7"] }
+
+[versions]
+patched = [">= 0.2.23"]
+unaffected = [
+    "= 0.2.0",
+    "= 0.2.1",
+    "= 0.2.2",
+    "= 0.2.3",
+    "= 0.2.4",
+    "= 0.2.5",
+    "= 0.2.6",
+]
+```
+
+# Potential segfault in the time crate
+
+### Impact
+
+The affected functions set environment variables without synchronization.
+"#;
+        let advisory = parse_advisory(metadata("md", content).unwrap())
+            .unwrap()
+            .unwrap();
+        assert_eq!(advisory.id, "RUSTSEC-2020-0071");
+        assert_eq!(advisory.package, "time");
+        assert!(affects(&advisory, "0.2.7"));
+        assert!(affects(&advisory, "0.2.22"));
+        assert!(affects(&advisory, "0.1.43"));
+        assert!(!affects(&advisory, "0.2.6"));
+        assert!(!affects(&advisory, "0.2.23"));
+        assert!(!affects(&advisory, "0.3.0"));
+        // A pre-release of a patched version is still patched
+        assert!(!affects(&advisory, "0.3.0-alpha-1"));
+    }
+
+    #[test]
+    fn test_toml_advisory() {
+        let content = r#"[advisory]
+id = "RUSTSEC-2019-0033"
+package = "http"
+date = "2019-11-16"
+title = "Integer Overflow in HeaderMap::reserve() can cause Denial of Service"
+description = """
+HeaderMap::reserve() used usize::next_power_of_two() to calculate the increased capacity.
+Crates depending on it, like the "hyper" crate, are affected too: with
+package = "hyper" in their lockfile the crate is pulled transitively.
+"""
+url = "https://github.com/hyperium/http/issues/352"
+keywords = ["http", "integer-overflow", "DoS"]
+
+[versions]
+patched = [">= 0.1.20"]
+"#;
+        let advisory = parse_advisory(metadata("toml", content).unwrap())
+            .unwrap()
+            .unwrap();
+        assert_eq!(advisory.id, "RUSTSEC-2019-0033");
+        assert_eq!(advisory.package, "http");
+        assert!(affects(&advisory, "0.1.19"));
+        assert!(!affects(&advisory, "0.1.20"));
+        assert!(!affects(&advisory, "0.2.9"));
+    }
+
+    #[test]
+    fn test_skipped_advisories() {
+        let informational = r#"[advisory]
+id = "RUSTSEC-2016-0005"
+package = "rust-crypto"
+informational = "unmaintained"
+
+[versions]
+patched = []
+"#;
+        assert!(parse_advisory(informational).unwrap().is_none());
+
+        let withdrawn = r#"[advisory]
+id = "RUSTSEC-2020-0097"
+package = "xcb"
+withdrawn = "2020-12-22"
+
+[versions]
+patched = []
+"#;
+        assert!(parse_advisory(withdrawn).unwrap().is_none());
+
+        assert!(metadata("json", "{}").is_none());
+        assert!(parse_advisory("[advisory]\nid = \"RUSTSEC-0000-0000\"\n").is_err());
+    }
+}
@@ -0,0 +1,196 @@
+// Copyright (c) 2018 Pietro Albini <pietro@pietroalbini.org>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies
+// of the Software, and to permit persons to whom the Software is furnished to do
+// so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use config::Config;
+use data::Data;
+use http;
+use prelude::*;
+use reqwest::blocking::Client;
+use std::collections::VecDeque;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use summary::Summary;
+
+/// How often the alerting rules are evaluated.
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+/// Period the error rate is calculated over.
+const ERROR_RATE_WINDOW: Duration = Duration::from_secs(10 * 60);
+/// Minimum number of requests in the window before the error rate is considered, to avoid
+/// alerting on a single failure.
+const ERROR_RATE_MIN_REQUESTS: usize = 20;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Rule {
+    ErrorRate,
+    Stall,
+    TokensQuarantined,
+    DiskSpace,
+}
+
+impl Rule {
+    fn name(self) -> &'static str {
+        match self {
+            Rule::ErrorRate => "error_rate",
+            Rule::Stall => "stall",
+            Rule::TokensQuarantined => "tokens_quarantined",
+            Rule::DiskSpace => "disk_space",
+        }
+    }
+}
+
+/// Background checker sending a webhook when the scraper fails too often or stops making
+/// progress, and again when it recovers.
+struct Alerter {
+    client: Client,
+    webhook: String,
+    data: Data,
+    summary: Arc<Summary>,
+    max_error_rate: f64,
+    max_stall: Duration,
+    started_at: Instant,
+    /// Number of pages fetched and errors at each check within the window.
+    history: VecDeque<(Instant, usize, usize)>,
+    firing: Vec<Rule>,
+}
+
+pub fn spawn(config: &Config, data: Data, summary: Arc<Summary>) -> Fallible<()> {
+    let webhook = match &config.alert_webhook {
+        Some(webhook) => webhook.clone(),
+        None => return Ok(()),
+    };
+
+    let mut alerter = Alerter {
+        client: http::client(config)?,
+        webhook,
+        data,
+        summary,
+        max_error_rate: config.alert_max_error_rate,
+        max_stall: Duration::from_secs(config.alert_max_stall),
+        started_at: Instant::now(),
+        history: VecDeque::new(),
+        firing: Vec::new(),
+    };
+    thread::spawn(move || loop {
+        thread::sleep(CHECK_INTERVAL);
+        alerter.check();
+    });
+
+    Ok(())
+}
+
+impl Alerter {
+    fn check(&mut self) {
+        let now = Instant::now();
+        let pages = self.summary.pages_fetched.load(Ordering::SeqCst);
+        let errors = self.summary.errors_count();
+        self.history.push_back((now, pages, errors));
+        while self
+            .history
+            .front()
+            .map(|&(at, _, _)| now.duration_since(at) > ERROR_RATE_WINDOW)
+            .unwrap_or(false)
+        {
+            self.history.pop_front();
+        }
+
+        let (_, oldest_pages, oldest_errors) = self.history[0];
+        let (window_pages, window_errors) = (pages - oldest_pages, errors - oldest_errors);
+        let requests = window_pages + window_errors;
+        let error_rate = if requests >= ERROR_RATE_MIN_REQUESTS {
+            window_errors as f64 * 100.0 / requests as f64
+        } else {
+            0.0
+        };
+        self.update(
+            Rule::ErrorRate,
+            error_rate > self.max_error_rate,
+            &format!(
+                "{:.1}% of the requests failed in the last {} minutes",
+                error_rate,
+                ERROR_RATE_WINDOW.as_secs() / 60
+            ),
+        );
+
+        let stalled_for = self
+            .summary
+            .since_last_progress()
+            .unwrap_or_else(|| self.started_at.elapsed());
+        self.update(
+            Rule::Stall,
+            stalled_for > self.max_stall,
+            &format!(
+                "no progress made in the last {} minutes",
+                stalled_for.as_secs() / 60
+            ),
+        );
+
+        // Quarantined tokens are never used again during the run, so this rule never recovers
+        let quarantined = self.summary.tokens_quarantined.load(Ordering::SeqCst);
+        self.update(
+            Rule::TokensQuarantined,
+            quarantined > 0,
+            &format!(
+                "{} GitHub tokens were rejected and quarantined",
+                quarantined
+            ),
+        );
+
+        self.update(
+            Rule::DiskSpace,
+            self.data.is_low_on_disk_space(),
+            "writes are paused as the disk of the data directory is almost full",
+        );
+    }
+
+    /// Notify when a rule starts or stops firing.
+    fn update(&mut self, rule: Rule, failing: bool, message: &str) {
+        let was_firing = self.firing.contains(&rule);
+        let text = if failing && !was_firing {
+            self.firing.push(rule);
+            format!("rust-repos alert: {}", message)
+        } else if !failing && was_firing {
+            self.firing.retain(|&firing| firing != rule);
+            format!("rust-repos recovered: {} is back to normal", rule.name())
+        } else {
+            return;
+        };
+
+        warn!("{}", text);
+        if let Err(err) = self.send(rule, failing, &text) {
+            warn!("failed to send the alert to the webhook: {}", err);
+        }
+    }
+
+    fn send(&self, rule: Rule, failing: bool, text: &str) -> Fallible<()> {
+        self.client
+            .post(&self.webhook)
+            .json(&json!({
+                "text": text,
+                "forge": "github",
+                "rule": rule.name(),
+                "status": if failing { "firing" } else { "resolved" },
+            }))
+            .send()?
+            .error_for_status()?;
+        Ok(())
+    }
+}
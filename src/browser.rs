@@ -0,0 +1,210 @@
+// Copyright (c) 2018 Pietro Albini <pietro@pietroalbini.org>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies
+// of the Software, and to permit persons to whom the Software is furnished to do
+// so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Static site to browse the dataset, made of a single page searching and paginating the
+//! repositories in the browser. The rows are split into scripts loaded one after the other, so
+//! the site also works when opened from the disk, and can be hosted on GitHub or Codeberg Pages.
+
+use data::{Data, Repo};
+use prelude::*;
+use render::escape_html;
+use serde_json;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::SystemTime;
+use utils::format_timestamp;
+
+static PLATFORM: &str = "github";
+
+/// Directory of the site containing the scripts with the repositories.
+static CHUNKS_DIR: &str = "chunks";
+/// File marking the chunks directory as written by this exporter, so it can be safely replaced.
+static MARKER: &str = ".rust-repos-browser";
+/// Number of repositories in each script of the chunks directory.
+const CHUNK_SIZE: usize = 10_000;
+
+static PAGE: &str = "\
+<!DOCTYPE html>
+<html>
+<head>
+<meta charset=\"utf-8\">
+<meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">
+<title>Rust repositories on GitHub</title>
+<style>
+body { font-family: sans-serif; max-width: 70em; margin: 2em auto; padding: 0 1em; color: #222; }
+input { width: 100%; padding: 0.4em; font-size: 1.1em; box-sizing: border-box; }
+table { border-collapse: collapse; margin: 1em 0; width: 100%; }
+th, td { border: 1px solid #ccc; padding: 0.3em 0.6em; text-align: left; vertical-align: top; }
+th { background: #f4f4f4; }
+td.number { text-align: right; }
+nav { text-align: center; }
+</style>
+</head>
+<body>
+<h1>Rust repositories on GitHub</h1>
+<p><em>{{ count }} repositories, generated on {{ generated_at }}.</em></p>
+<input id=\"search\" type=\"search\" placeholder=\"Search by name, description, license or category\">
+<p id=\"status\">Loading the repositories...</p>
+<noscript><p>Browsing the repositories requires JavaScript.</p></noscript>
+<table>
+<thead><tr><th>Repository</th><th>Description</th><th>Stars</th><th>License</th><th>Edition</th><th>Kind</th><th>Categories</th></tr></thead>
+<tbody id=\"rows\"></tbody>
+</table>
+<nav><button id=\"previous\">Previous</button> <span id=\"page\"></span> <button id=\"next\">Next</button></nav>
+<script>
+var CHUNKS = {{ chunks }};
+var PAGE_SIZE = 50;
+var repos = [], matches = [], page = 0;
+var rustRepos = {
+  load: function (rows) {
+    repos.push.apply(repos, rows);
+    if (repos.length < {{ count }}) {
+      loadChunk(Math.ceil(repos.length / {{ chunk_size }}));
+    } else {
+      search();
+    }
+  }
+};
+function loadChunk(i) {
+  var script = document.createElement('script');
+  script.src = 'chunks/' + i + '.js';
+  document.body.appendChild(script);
+}
+function search() {
+  var terms = document.getElementById('search').value.toLowerCase().split(/\\s+/).filter(Boolean);
+  matches = repos.filter(function (repo) {
+    var text = [repo[0], repo[1], repo[3], repo[6]].join(' ').toLowerCase();
+    return terms.every(function (term) { return text.indexOf(term) !== -1; });
+  });
+  page = 0;
+  render();
+}
+function cell(row, text, className) {
+  var td = document.createElement('td');
+  td.textContent = text === null ? '' : text;
+  if (className) { td.className = className; }
+  row.appendChild(td);
+  return td;
+}
+function render() {
+  var pages = Math.max(1, Math.ceil(matches.length / PAGE_SIZE));
+  var tbody = document.getElementById('rows');
+  tbody.textContent = '';
+  matches.slice(page * PAGE_SIZE, (page + 1) * PAGE_SIZE).forEach(function (repo) {
+    var row = document.createElement('tr');
+    var link = document.createElement('a');
+    link.href = 'https://github.com/' + repo[0];
+    link.textContent = repo[0];
+    cell(row, '').appendChild(link);
+    cell(row, repo[1]);
+    cell(row, repo[2], 'number');
+    for (var i = 3; i < repo.length; i++) { cell(row, repo[i]); }
+    tbody.appendChild(row);
+  });
+  document.getElementById('status').textContent = matches.length + ' matching repositories';
+  document.getElementById('page').textContent = 'Page ' + (page + 1) + ' of ' + pages;
+  document.getElementById('previous').disabled = page === 0;
+  document.getElementById('next').disabled = page + 1 >= pages;
+}
+document.getElementById('search').addEventListener('input', search);
+document.getElementById('previous').addEventListener('click', function () { page--; render(); });
+document.getElementById('next').addEventListener('click', function () { page++; render(); });
+if (CHUNKS > 0) { loadChunk(0); } else { search(); }
+</script>
+</body>
+</html>
+";
+
+/// Whether two paths point to the same existing directory.
+fn is_same_dir(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// Columns of a repository shown in the browser, in the order expected by the page.
+fn row(repo: &Repo) -> serde_json::Value {
+    json!([
+        repo.name,
+        repo.description,
+        repo.stars,
+        repo.license,
+        repo.edition,
+        repo.crate_kind,
+        repo.categories,
+    ])
+}
+
+/// Write the site to the `output` directory, replacing the previous version. The latest row of
+/// each repository is included, sorted by stars.
+pub fn write(data: &Data, output: &Path) -> Fallible<()> {
+    let mut latest = HashMap::new();
+    for repo in data.repos(PLATFORM)? {
+        let repo = repo?;
+        latest.insert(repo.id.clone(), repo);
+    }
+    let mut repos = latest.into_values().collect::<Vec<_>>();
+    repos.sort_by(|a, b| b.stars.cmp(&a.stars).then_with(|| a.name.cmp(&b.name)));
+
+    // The previous chunks are only removed if this exporter wrote them, to never wipe a directory
+    // that happens to have the same name
+    let chunks_dir = output.join(CHUNKS_DIR);
+    if chunks_dir.exists() {
+        if is_same_dir(&chunks_dir, data.base_dir()) || !chunks_dir.join(MARKER).is_file() {
+            bail!(
+                "refusing to replace {}, which wasn't written by the html-browser export",
+                chunks_dir.display()
+            );
+        }
+        fs::remove_dir_all(&chunks_dir)
+            .with_context(|_| format!("failed to remove {}", chunks_dir.display()))?;
+    }
+    fs::create_dir_all(&chunks_dir)
+        .with_context(|_| format!("failed to create {}", chunks_dir.display()))?;
+    fs::write(chunks_dir.join(MARKER), "")?;
+
+    let chunks = repos.chunks(CHUNK_SIZE).collect::<Vec<_>>();
+    for (i, chunk) in chunks.iter().enumerate() {
+        let path = chunks_dir.join(format!("{}.js", i));
+        let mut out = BufWriter::new(fs::File::create(&path)?);
+        out.write_all(b"rustRepos.load(")?;
+        serde_json::to_writer(&mut out, &chunk.iter().map(row).collect::<Vec<_>>())?;
+        out.write_all(b");\n")?;
+        out.flush()?;
+    }
+
+    let generated_at = format_timestamp(SystemTime::now());
+    let page = PAGE
+        .replace("{{ count }}", &repos.len().to_string())
+        .replace("{{ chunks }}", &chunks.len().to_string())
+        .replace("{{ chunk_size }}", &CHUNK_SIZE.to_string())
+        .replace("{{ generated_at }}", &escape_html(&generated_at));
+    fs::write(output.join("index.html"), page)?;
+
+    info!(
+        "wrote a browser of {} repositories to {}",
+        repos.len(),
+        output.display()
+    );
+    Ok(())
+}
@@ -0,0 +1,531 @@
+// Copyright (c) 2018 Pietro Albini <pietro@pietroalbini.org>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies
+// of the Software, and to permit persons to whom the Software is furnished to do
+// so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use clone;
+use config::Config;
+use depgraph;
+#[cfg(feature = "export")]
+use export;
+use feed;
+use github;
+use history;
+use prelude::*;
+use query;
+use render;
+use sbom;
+use search;
+use state::StateChanges;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+pub enum Command {
+    Scrape {
+        data_dir: PathBuf,
+        discovery_only: bool,
+        backfill: bool,
+    },
+    Popular {
+        data_dir: PathBuf,
+        min_stars: u64,
+    },
+    Canonicalize(PathBuf),
+    TokensStatus,
+    Schema,
+    #[cfg(feature = "export")]
+    Export {
+        target: export::Target,
+        data_dir: PathBuf,
+        output: PathBuf,
+    },
+    Query {
+        data_dir: PathBuf,
+        sql: String,
+        format: query::Format,
+    },
+    Sample {
+        data_dir: PathBuf,
+        size: usize,
+        seed: u64,
+        stratify_by: Option<String>,
+    },
+    CraterList {
+        data_dir: PathBuf,
+        filter: Option<query::Filter>,
+        include_archived: bool,
+    },
+    Clone {
+        data_dir: PathBuf,
+        dest: PathBuf,
+        filter: Option<query::Filter>,
+        jobs: usize,
+    },
+    Lockfiles(PathBuf),
+    Seed {
+        data_dir: PathBuf,
+        dump: PathBuf,
+    },
+    Advisories {
+        data_dir: PathBuf,
+        advisory_db: PathBuf,
+        output: PathBuf,
+    },
+    CratesMap {
+        data_dir: PathBuf,
+        crates_csv: PathBuf,
+        output: PathBuf,
+    },
+    Sbom {
+        data_dir: PathBuf,
+        format: sbom::Format,
+    },
+    DepGraph {
+        data_dir: PathBuf,
+        format: depgraph::Format,
+        min_repos: usize,
+    },
+    Owners {
+        data_dir: PathBuf,
+        size: usize,
+        seed: u64,
+        budget: u64,
+    },
+    #[cfg(feature = "monitoring")]
+    Digest {
+        data_dir: PathBuf,
+        days: u64,
+    },
+    Feed {
+        data_dir: PathBuf,
+        filter: Option<query::Filter>,
+        limit: usize,
+        output: Option<PathBuf>,
+    },
+    History {
+        data_dir: PathBuf,
+        filter: Option<query::Filter>,
+        as_of: Option<String>,
+        output: Option<PathBuf>,
+    },
+    DecryptExport {
+        data_dir: PathBuf,
+        output: Option<PathBuf>,
+    },
+    Stats {
+        data_dir: PathBuf,
+        format: render::Format,
+    },
+    Report {
+        data_dir: PathBuf,
+        format: render::Format,
+        template: Option<PathBuf>,
+    },
+    Compact {
+        data_dir: PathBuf,
+        sort: bool,
+    },
+    Enrich {
+        data_dir: PathBuf,
+        fields: Vec<github::Field>,
+        budget: Option<u64>,
+    },
+    StateShow(PathBuf),
+    StateSet {
+        data_dir: PathBuf,
+        forge: String,
+        changes: StateChanges,
+    },
+    StatePush {
+        data_dir: PathBuf,
+        with_data: bool,
+    },
+    StatePull {
+        data_dir: PathBuf,
+        with_data: bool,
+    },
+    Index(PathBuf),
+    Search {
+        data_dir: PathBuf,
+        query: String,
+        limit: usize,
+    },
+}
+
+/// Flags accepted by all the commands.
+#[derive(Default)]
+pub struct Options {
+    record_http: Option<PathBuf>,
+    replay_http: Option<PathBuf>,
+}
+
+impl Options {
+    pub fn load_config(&self, data_dir: PathBuf) -> Fallible<Config> {
+        let mut config = Config::from_env(data_dir)?;
+        config.record_http = self.record_http.clone();
+        config.replay_http = self.replay_http.clone();
+        Ok(config)
+    }
+}
+
+/// Flags that don't take a value, and are set to `true` when present.
+static SWITCHES: &[&str] = &[
+    "include-archived",
+    "sort",
+    "discovery-only",
+    "clear-retry-queue",
+    "backfill",
+    "with-data",
+];
+
+/// Flags passed on the command line, as `--name value` or `--name=value`. Each command takes the
+/// flags it supports, and the remaining ones are reported as unknown.
+#[derive(Default)]
+struct Flags(HashMap<String, String>);
+
+impl Flags {
+    fn take(&mut self, name: &str) -> Option<String> {
+        self.0.remove(name)
+    }
+
+    fn take_switch(&mut self, name: &str) -> Fallible<bool> {
+        Ok(self.take_parsed(name)?.unwrap_or(false))
+    }
+
+    fn take_parsed<T>(&mut self, name: &str) -> Fallible<Option<T>>
+    where
+        T: FromStr,
+        T::Err: Into<Error>,
+    {
+        match self.take(name) {
+            Some(value) => match value.parse::<T>() {
+                Ok(parsed) => Ok(Some(parsed)),
+                Err(err) => Err(err
+                    .into()
+                    .context(format!("invalid value for --{}: {}", name, value))
+                    .into()),
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// Take the `--where` flag, parsed as a filter expression.
+    fn take_filter(&mut self) -> Fallible<Option<query::Filter>> {
+        match self.take("where") {
+            Some(expr) => Ok(Some(query::Filter::parse(&expr)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn finish(self) -> Fallible<()> {
+        let mut unknown = self.0.keys().map(|name| name.as_str()).collect::<Vec<_>>();
+        unknown.sort();
+        match unknown.first() {
+            Some(name) => bail!("unknown flag: --{}", name),
+            None => Ok(()),
+        }
+    }
+}
+
+pub fn parse_args() -> Fallible<(Options, Command)> {
+    let mut flags = Flags::default();
+    let mut args = Vec::new();
+
+    let mut raw_args = std::env::args().skip(1);
+    while let Some(arg) = raw_args.next() {
+        if !arg.starts_with("--") {
+            args.push(arg);
+            continue;
+        }
+
+        let (name, value) = match arg.find('=') {
+            Some(pos) => (arg[2..pos].to_string(), arg[pos + 1..].to_string()),
+            None if SWITCHES.contains(&&arg[2..]) => (arg[2..].to_string(), "true".to_string()),
+            None => {
+                let value = raw_args
+                    .next()
+                    .ok_or_else(|| format_err!("missing value for {}", arg))?;
+                (arg[2..].to_string(), value)
+            }
+        };
+        flags.0.insert(name, value);
+    }
+    let args = args.iter().map(|arg| arg.as_str()).collect::<Vec<&str>>();
+
+    let options = Options {
+        record_http: flags.take("record-http").map(PathBuf::from),
+        replay_http: flags.take("replay-http").map(PathBuf::from),
+    };
+
+    let command = match args.as_slice() {
+        // Containers can provide the data directory in the environment instead
+        [] => match std::env::var_os("RUST_REPOS_DATA_DIR") {
+            Some(data_dir) => Command::Scrape {
+                data_dir: PathBuf::from(data_dir),
+                discovery_only: flags.take_switch("discovery-only")?,
+                backfill: flags.take_switch("backfill")?,
+            },
+            None => bail!("missing argument: <data_dir> (or the RUST_REPOS_DATA_DIR variable)"),
+        },
+        ["popular", data_dir] => Command::Popular {
+            data_dir: PathBuf::from(data_dir),
+            min_stars: flags
+                .take_parsed("min-stars")?
+                .unwrap_or(github::DEFAULT_MIN_STARS),
+        },
+        ["popular", ..] => bail!("usage: rust-repos popular <data_dir> [--min-stars <n>]"),
+        ["canonicalize", data_dir] => Command::Canonicalize(PathBuf::from(data_dir)),
+        ["canonicalize", ..] => bail!("usage: rust-repos canonicalize <data_dir> < <urls>"),
+        ["tokens", "status"] => Command::TokensStatus,
+        ["tokens", ..] => bail!("usage: rust-repos tokens status"),
+        // Scraping into a directory with this name requires writing it as `./schema`
+        ["schema"] => Command::Schema,
+        #[cfg(feature = "export")]
+        ["export", target, data_dir] => Command::Export {
+            target: target.parse()?,
+            data_dir: PathBuf::from(data_dir),
+            output: flags
+                .take("output")
+                .map(PathBuf::from)
+                .unwrap_or_else(|| PathBuf::from("site")),
+        },
+        #[cfg(feature = "export")]
+        ["export", data_dir] => Command::Export {
+            target: flags
+                .take_parsed("format")?
+                .ok_or_else(|| err_msg("missing flag: --format"))?,
+            data_dir: PathBuf::from(data_dir),
+            output: flags
+                .take("output")
+                .map(PathBuf::from)
+                .unwrap_or_else(|| PathBuf::from("site")),
+        },
+        #[cfg(feature = "export")]
+        ["export", ..] => bail!(
+            "usage: rust-repos export <clickhouse|bigquery|html-browser> <data_dir> [--output <dir>]"
+        ),
+        #[cfg(not(feature = "export"))]
+        ["export", ..] => bail!("rust-repos was built without the `export` feature"),
+        ["query", data_dir, sql] => Command::Query {
+            data_dir: PathBuf::from(data_dir),
+            sql: sql.to_string(),
+            format: flags.take_parsed("format")?.unwrap_or(query::Format::Table),
+        },
+        ["query", ..] => {
+            bail!("usage: rust-repos query <data_dir> <sql> [--format table|csv|json]")
+        }
+        ["sample", data_dir] => Command::Sample {
+            data_dir: PathBuf::from(data_dir),
+            size: flags
+                .take_parsed("n")?
+                .ok_or_else(|| err_msg("missing flag: --n"))?,
+            seed: flags.take_parsed("seed")?.unwrap_or(0),
+            stratify_by: flags.take("stratify-by"),
+        },
+        ["sample", ..] => bail!(
+            "usage: rust-repos sample <data_dir> --n <size> [--seed <n>] [--stratify-by <column>]"
+        ),
+        ["crater-list", data_dir] => Command::CraterList {
+            data_dir: PathBuf::from(data_dir),
+            filter: flags.take_filter()?,
+            include_archived: flags.take_switch("include-archived")?,
+        },
+        ["crater-list", ..] => {
+            bail!("usage: rust-repos crater-list <data_dir> [--where <expr>] [--include-archived]")
+        }
+        ["clone", data_dir, dest] => Command::Clone {
+            data_dir: PathBuf::from(data_dir),
+            dest: PathBuf::from(dest),
+            filter: flags.take_filter()?,
+            jobs: flags.take_parsed("jobs")?.unwrap_or(clone::DEFAULT_JOBS),
+        },
+        ["clone", ..] => {
+            bail!("usage: rust-repos clone <data_dir> <dest> [--where <expr>] [--jobs <n>]")
+        }
+        ["lockfiles", data_dir] => Command::Lockfiles(PathBuf::from(data_dir)),
+        ["lockfiles", ..] => bail!("usage: rust-repos lockfiles <data_dir>"),
+        ["advisories", data_dir, advisory_db] => Command::Advisories {
+            data_dir: PathBuf::from(data_dir),
+            advisory_db: PathBuf::from(advisory_db),
+            output: flags
+                .take("output")
+                .map(PathBuf::from)
+                .unwrap_or_else(|| PathBuf::from("advisories.csv")),
+        },
+        ["advisories", ..] => {
+            bail!("usage: rust-repos advisories <data_dir> <advisory_db> [--output <file>]")
+        }
+        ["crates-map", data_dir, crates_csv] => Command::CratesMap {
+            data_dir: PathBuf::from(data_dir),
+            crates_csv: PathBuf::from(crates_csv),
+            output: flags
+                .take("output")
+                .map(PathBuf::from)
+                .unwrap_or_else(|| PathBuf::from("crates_to_repos.csv")),
+        },
+        ["crates-map", ..] => {
+            bail!("usage: rust-repos crates-map <data_dir> <crates.csv> [--output <file>]")
+        }
+        ["sbom", data_dir] => Command::Sbom {
+            data_dir: PathBuf::from(data_dir),
+            format: flags
+                .take_parsed("format")?
+                .unwrap_or(sbom::Format::CycloneDx),
+        },
+        ["sbom", ..] => bail!("usage: rust-repos sbom <data_dir> [--format cyclonedx|spdx]"),
+        ["dep-graph", data_dir] => Command::DepGraph {
+            data_dir: PathBuf::from(data_dir),
+            format: flags
+                .take_parsed("format")?
+                .unwrap_or(depgraph::Format::Dot),
+            min_repos: flags.take_parsed("min-repos")?.unwrap_or(1),
+        },
+        ["dep-graph", ..] => {
+            bail!("usage: rust-repos dep-graph <data_dir> [--format dot|graphml] [--min-repos <n>]")
+        }
+        ["owners", data_dir] => Command::Owners {
+            data_dir: PathBuf::from(data_dir),
+            size: flags
+                .take_parsed("n")?
+                .ok_or_else(|| err_msg("missing flag: --n"))?,
+            seed: flags.take_parsed("seed")?.unwrap_or(0),
+            budget: flags
+                .take_parsed("budget")?
+                .unwrap_or(github::DEFAULT_OWNERS_BUDGET),
+        },
+        ["owners", ..] => {
+            bail!("usage: rust-repos owners <data_dir> --n <size> [--seed <n>] [--budget <points>]")
+        }
+        #[cfg(feature = "monitoring")]
+        ["digest", data_dir] => Command::Digest {
+            data_dir: PathBuf::from(data_dir),
+            days: flags.take_parsed("days")?.unwrap_or(7),
+        },
+        #[cfg(feature = "monitoring")]
+        ["digest", ..] => bail!("usage: rust-repos digest <data_dir> [--days <n>]"),
+        #[cfg(not(feature = "monitoring"))]
+        ["digest", ..] => bail!("rust-repos was built without the `monitoring` feature"),
+        ["feed", data_dir] => Command::Feed {
+            data_dir: PathBuf::from(data_dir),
+            filter: flags.take_filter()?,
+            limit: flags.take_parsed("limit")?.unwrap_or(feed::DEFAULT_LIMIT),
+            output: flags.take("output").map(PathBuf::from),
+        },
+        ["feed", ..] => bail!(
+            "usage: rust-repos feed <data_dir> [--where <expr>] [--limit <n>] [--output <file>]"
+        ),
+        ["history", data_dir] => Command::History {
+            data_dir: PathBuf::from(data_dir),
+            filter: flags.take_filter()?,
+            as_of: flags
+                .take("as-of")
+                .map(|as_of| history::parse_as_of(&as_of))
+                .transpose()?,
+            output: flags.take("output").map(PathBuf::from),
+        },
+        ["history", ..] => bail!(
+            "usage: rust-repos history <data_dir> [--where <expr>] [--as-of <date>] [--output <file>]"
+        ),
+        ["decrypt-export", data_dir] => Command::DecryptExport {
+            data_dir: PathBuf::from(data_dir),
+            output: flags.take("output").map(PathBuf::from),
+        },
+        ["decrypt-export", ..] => bail!("usage: rust-repos decrypt-export <data_dir> [--output <file>]"),
+        ["stats", data_dir] => Command::Stats {
+            data_dir: PathBuf::from(data_dir),
+            format: flags
+                .take_parsed("format")?
+                .unwrap_or(render::Format::Markdown),
+        },
+        ["stats", ..] => bail!("usage: rust-repos stats <data_dir> [--format markdown|html]"),
+        ["report", data_dir] => Command::Report {
+            data_dir: PathBuf::from(data_dir),
+            format: flags.take_parsed("format")?.unwrap_or(render::Format::Html),
+            template: flags.take("template").map(PathBuf::from),
+        },
+        ["report", ..] => bail!(
+            "usage: rust-repos report <data_dir> [--format markdown|html] [--template <path>]"
+        ),
+        ["compact", data_dir] => Command::Compact {
+            data_dir: PathBuf::from(data_dir),
+            sort: flags.take_switch("sort")?,
+        },
+        ["compact", ..] => bail!("usage: rust-repos compact <data_dir> [--sort]"),
+        ["enrich", data_dir] => Command::Enrich {
+            data_dir: PathBuf::from(data_dir),
+            fields: flags
+                .take("missing-fields")
+                .ok_or_else(|| err_msg("missing flag: --missing-fields"))?
+                .split(',')
+                .map(|field| field.trim().parse())
+                .collect::<Fallible<_>>()?,
+            budget: flags.take_parsed("budget")?,
+        },
+        ["enrich", ..] => bail!(
+            "usage: rust-repos enrich <data_dir> --missing-fields <columns> [--budget <points>]"
+        ),
+        ["state", "show", data_dir] => Command::StateShow(PathBuf::from(data_dir)),
+        ["state", "set", data_dir, forge] => Command::StateSet {
+            data_dir: PathBuf::from(data_dir),
+            forge: forge.to_string(),
+            changes: StateChanges {
+                last_id: flags.take_parsed("last-id")?,
+                lockfile_position: flags.take_parsed("lockfile-position")?,
+                clear_retry_queue: flags.take_switch("clear-retry-queue")?,
+            },
+        },
+        ["state", "push", data_dir] => Command::StatePush {
+            data_dir: PathBuf::from(data_dir),
+            with_data: flags.take_switch("with-data")?,
+        },
+        ["state", "pull", data_dir] => Command::StatePull {
+            data_dir: PathBuf::from(data_dir),
+            with_data: flags.take_switch("with-data")?,
+        },
+        ["state", "show", ..] => bail!("usage: rust-repos state show <data_dir>"),
+        ["state", "set", ..] => bail!(
+            "usage: rust-repos state set <data_dir> <forge> [--last-id <id>] [--lockfile-position <row>] [--clear-retry-queue]"
+        ),
+        ["state", "push", ..] => bail!("usage: rust-repos state push <data_dir> [--with-data]"),
+        ["state", "pull", ..] => bail!("usage: rust-repos state pull <data_dir> [--with-data]"),
+        ["state", ..] => bail!("usage: rust-repos state <show|set|push|pull> <data_dir>"),
+        ["seed", data_dir, dump] => Command::Seed {
+            data_dir: PathBuf::from(data_dir),
+            dump: PathBuf::from(dump),
+        },
+        ["seed", ..] => bail!("usage: rust-repos seed <data_dir> <projects.csv>"),
+        ["index", data_dir] => Command::Index(PathBuf::from(data_dir)),
+        ["index", ..] => bail!("usage: rust-repos index <data_dir>"),
+        ["search", data_dir, query] => Command::Search {
+            data_dir: PathBuf::from(data_dir),
+            query: query.to_string(),
+            limit: flags.take_parsed("limit")?.unwrap_or(search::DEFAULT_LIMIT),
+        },
+        ["search", ..] => bail!("usage: rust-repos search <data_dir> <query> [--limit <n>]"),
+        [data_dir] => Command::Scrape {
+            data_dir: PathBuf::from(data_dir),
+            discovery_only: flags.take_switch("discovery-only")?,
+            backfill: flags.take_switch("backfill")?,
+        },
+        _ => bail!("too many arguments"),
+    };
+
+    flags.finish()?;
+    Ok((options, command))
+}
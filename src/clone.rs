@@ -0,0 +1,273 @@
+// Copyright (c) 2018 Pietro Albini <pietro@pietroalbini.org>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies
+// of the Software, and to permit persons to whom the Software is furnished to do
+// so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use canonical::RepoName;
+use crossbeam_utils::thread::scope;
+use csv;
+use data::Data;
+use prelude::*;
+use query::Filter;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+use utils::log_error;
+
+static PLATFORM: &str = "github";
+static MANIFEST: &str = "manifest.csv";
+
+/// Number of clones running in parallel when no limit is provided.
+pub const DEFAULT_JOBS: usize = 4;
+
+#[derive(Serialize, Deserialize)]
+struct ManifestEntry {
+    name: String,
+    status: Status,
+    sha: Option<String>,
+    error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum Status {
+    Cloned,
+    Failed,
+}
+
+/// Shallow-clone the repositories matching the filter into `<dest>/<owner>/<name>`, recording
+/// the outcome of each clone in `<dest>/manifest.csv`. Repositories already cloned according to
+/// the manifest are skipped, so an interrupted run can be resumed, and failed ones are retried.
+pub fn run(data: &Data, dest: &Path, filter: Option<&Filter>, jobs: usize) -> Fallible<()> {
+    fs::create_dir_all(dest).context(ErrorKind::Storage)?;
+    let manifest_path = dest.join(MANIFEST);
+
+    // Later entries override earlier ones, as failed repositories are retried
+    let mut cloned = HashMap::new();
+    if manifest_path.exists() {
+        for entry in csv::Reader::from_path(&manifest_path)?.into_deserialize() {
+            let entry: ManifestEntry = entry?;
+            cloned.insert(entry.name, entry.status == Status::Cloned);
+        }
+    }
+
+    // Only the latest row of each repository is current, and two ids can share a name when a
+    // repository was deleted and another one was created in its place
+    let mut latest = HashMap::new();
+    for repo in data.repos(PLATFORM)? {
+        let repo = repo?;
+        latest.insert(repo.id.clone(), repo);
+    }
+    let mut repos = latest.into_values().collect::<Vec<_>>();
+    repos.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut queue = Vec::new();
+    let mut queued = HashSet::new();
+    for repo in repos {
+        if cloned.get(&repo.name) == Some(&true) || queued.contains(&repo.name) {
+            continue;
+        }
+        if let Some(filter) = filter {
+            if !filter.matches(&repo)? {
+                continue;
+            }
+        }
+        queued.insert(repo.name.clone());
+        queue.push(repo.name);
+    }
+    info!(
+        "cloning {} repositories ({} already cloned)",
+        queue.len(),
+        cloned.values().filter(|cloned| **cloned).count()
+    );
+
+    let exists = manifest_path.exists();
+    let mut manifest = csv::WriterBuilder::new().has_headers(false).from_writer(
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&manifest_path)
+            .context(ErrorKind::Storage)?,
+    );
+    if !exists {
+        manifest.write_record(["name", "status", "sha", "error"])?;
+        manifest.flush()?;
+    }
+    let manifest = Mutex::new(manifest);
+
+    let queue = Mutex::new(queue.into_iter());
+    let failed = Mutex::new(0usize);
+    scope(|scope| {
+        for _ in 0..jobs.max(1) {
+            scope.spawn(|_| loop {
+                let name = match queue.lock().unwrap().next() {
+                    Some(name) => name,
+                    None => break,
+                };
+
+                let entry = match clone(dest, &name) {
+                    Ok(sha) => {
+                        info!("cloned {}", name);
+                        ManifestEntry {
+                            name,
+                            status: Status::Cloned,
+                            sha: Some(sha),
+                            error: None,
+                        }
+                    }
+                    Err(err) => {
+                        warn!("failed to clone {}: {}", name, err);
+                        *failed.lock().unwrap() += 1;
+                        ManifestEntry {
+                            name,
+                            status: Status::Failed,
+                            sha: None,
+                            error: Some(err.to_string()),
+                        }
+                    }
+                };
+
+                // Flush every entry, so the manifest is accurate even if the process is killed
+                let mut manifest = manifest.lock().unwrap();
+                if let Err(err) = manifest
+                    .serialize(&entry)
+                    .map_err(Error::from)
+                    .and_then(|()| Ok(manifest.flush()?))
+                {
+                    log_error(&err);
+                }
+            });
+        }
+    })
+    .map_err(|_| err_msg("a clone thread panicked"))?;
+
+    let failed = *failed.lock().unwrap();
+    if failed > 0 {
+        bail!("failed to clone {} repositories, see {}", failed, MANIFEST);
+    }
+    Ok(())
+}
+
+/// Path to clone a repository to. The name comes from the scraped data, and is validated so it
+/// can't point outside of `dest`.
+fn repo_path(dest: &Path, name: &str) -> Fallible<PathBuf> {
+    let repo = RepoName::parse(name)?;
+    if repo.to_string() != name {
+        bail!("invalid repository name: {}", name);
+    }
+    Ok(dest.join(&repo.owner).join(&repo.name))
+}
+
+/// Shallow-clone a repository, returning the commit it was cloned at.
+fn clone(dest: &Path, name: &str) -> Fallible<String> {
+    let path = repo_path(dest, name)?;
+
+    // Leftovers of interrupted clones are not in the manifest, and must be removed first
+    if path.exists() {
+        let resolved = fs::canonicalize(&path)?;
+        let dest = fs::canonicalize(dest)?;
+        if resolved == dest || !resolved.starts_with(&dest) {
+            bail!(
+                "refusing to remove {}, which is outside of {}",
+                resolved.to_string_lossy(),
+                dest.to_string_lossy()
+            );
+        }
+        fs::remove_dir_all(&path)?;
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let output = Command::new("git")
+        .arg("clone")
+        .arg("--depth=1")
+        .arg("--quiet")
+        .arg(format!("https://github.com/{}", name))
+        .arg(&path)
+        // Missing repositories would otherwise prompt for credentials
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .stdin(Stdio::null())
+        .output()
+        .context("failed to run git")?;
+    if !output.status.success() {
+        bail!(
+            "git clone failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    let output = Command::new("git")
+        .arg("rev-parse")
+        .arg("HEAD")
+        .current_dir(&path)
+        .output()?;
+    if !output.status.success() {
+        bail!("failed to get the cloned commit");
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{clone, repo_path};
+    use std::fs;
+    use std::path::Path;
+
+    #[test]
+    fn test_repo_path() {
+        let dest = Path::new("/clones");
+        assert_eq!(
+            repo_path(dest, "rust-lang/rust").unwrap(),
+            dest.join("rust-lang").join("rust")
+        );
+        for name in &[
+            "../rust",
+            "rust-lang/..",
+            "rust-lang/../../etc",
+            "/etc/passwd",
+            "rust-lang/rust/extra",
+            "https://github.com/rust-lang/rust",
+            "rust-lang",
+        ] {
+            assert!(repo_path(dest, name).is_err(), "{} was accepted", name);
+        }
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_refuse_removing_outside_dest() {
+        use std::os::unix::fs::symlink;
+
+        let base = ::std::env::temp_dir()
+            .join(format!("rust-repos-clone-{}-outside", ::std::process::id()));
+        let _ = fs::remove_dir_all(&base);
+        let dest = base.join("dest");
+        let outside = base.join("outside");
+        fs::create_dir_all(outside.join("rust")).unwrap();
+        fs::create_dir_all(&dest).unwrap();
+        symlink(&outside, dest.join("rust-lang")).unwrap();
+
+        assert!(clone(&dest, "rust-lang/rust").is_err());
+        assert!(outside.join("rust").exists());
+
+        fs::remove_dir_all(&base).unwrap();
+    }
+}
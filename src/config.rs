@@ -18,10 +18,431 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-use std::path::PathBuf;
+use encryption::Key;
+use feed;
+use prelude::*;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Where container runtimes mount secrets, used when no token is provided otherwise.
+static DEFAULT_TOKEN_FILE: &str = "/run/secrets/github_token";
+/// Directories usually containing third-party code, vendored into the repository.
+static DEFAULT_VENDORED_DIRS: &[&str] = &["vendor", "third_party", "node_modules"];
+
+/// How files are organized inside the data directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataLayout {
+    /// `<forge>.csv` files and a shared `state.json` at the top level.
+    Flat,
+    /// A `<forge>/` subdirectory for each forge, containing `repos.csv` and `state.json`.
+    PerForge,
+}
+
+/// How failed HTTP requests are retried.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Maximum number of retries of a request, or `None` to retry forever.
+    pub max_retries: Option<u32>,
+    /// Seconds to wait before the first retry, doubled after each one.
+    pub base_backoff: u64,
+    /// Maximum number of seconds to wait between retries.
+    pub max_backoff: u64,
+    /// Fraction of the wait randomly added or removed, to avoid retrying in lockstep.
+    pub jitter: f64,
+}
+
+impl RetryPolicy {
+    /// Load the policy of a forge from the environment. The `RUST_REPOS_<FORGE>_RETRY_*`
+    /// variables override the `RUST_REPOS_RETRY_*` ones.
+    fn from_env(forge: &str) -> Fallible<Self> {
+        fn var<T>(forge: &str, name: &str) -> Fallible<Option<T>>
+        where
+            T: FromStr,
+            T::Err: Fail,
+        {
+            match env_parse(&format!("RUST_REPOS_{}_RETRY_{}", forge, name))? {
+                Some(value) => Ok(Some(value)),
+                None => env_parse(&format!("RUST_REPOS_RETRY_{}", name)),
+            }
+        }
+
+        let policy = RetryPolicy {
+            max_retries: var(forge, "MAX")?,
+            base_backoff: var(forge, "BASE_BACKOFF")?.unwrap_or(10),
+            max_backoff: var(forge, "MAX_BACKOFF")?.unwrap_or(640),
+            jitter: var(forge, "JITTER")?.unwrap_or(0.0),
+        };
+        if !(0.0..=1.0).contains(&policy.jitter) {
+            bail!("the retry jitter must be between 0 and 1");
+        }
+        if policy.base_backoff > policy.max_backoff {
+            bail!("the base retry backoff can't be greater than the maximum one");
+        }
+        Ok(policy)
+    }
+}
+
+/// Where logs are written besides stderr. It's loaded separately from the rest of the
+/// configuration, as logging is set up before parsing the command line.
+pub struct LogConfig {
+    pub dir: Option<PathBuf>,
+    /// Number of daily log files kept.
+    pub retention: usize,
+}
+
+impl LogConfig {
+    pub fn from_env() -> Fallible<Self> {
+        Ok(LogConfig {
+            dir: std::env::var_os("RUST_REPOS_LOG_DIR").map(PathBuf::from),
+            retention: env_parse("RUST_REPOS_LOG_RETENTION")?.unwrap_or(14),
+        })
+    }
+}
 
 pub struct Config {
-    pub github_token: String,
+    pub github_tokens: Vec<String>,
+    pub contact: Option<String>,
+    pub user_agent: String,
+    pub github_user_agent: String,
     pub data_dir: PathBuf,
+    pub data_layout: DataLayout,
     pub timeout: Option<u64>,
+    /// Remaining rate limit, summed across all the tokens, below which scraping stops.
+    pub rate_limit_floor: Option<u64>,
+    pub follow_interval: Option<u64>,
+    pub github_retry: RetryPolicy,
+    /// Seconds before an HTTP request is aborted, including reading the response.
+    pub http_timeout: u64,
+    pub http_connect_timeout: u64,
+    /// Maximum number of idle connections kept open for each host.
+    pub http_pool_size: Option<usize>,
+    /// Seconds idle connections are kept open for reuse.
+    pub http_pool_idle_timeout: u64,
+    /// Interval between TCP keep-alive probes on open connections, if enabled.
+    pub http_tcp_keepalive: Option<u64>,
+    /// Disable HTTP/2, for proxies or servers mishandling it.
+    pub http1_only: bool,
+    /// Proxy all the HTTP requests are sent through, overriding the system one.
+    pub http_proxy: Option<String>,
+    /// Request compressed responses, to reduce the transferred data.
+    pub http_compression: bool,
+    /// Free space in bytes on the filesystem of the data directory below which writes pause.
+    pub min_free_space: u64,
+    /// Key the repositories CSV is encrypted with, if encryption at rest is enabled.
+    pub encryption_key: Option<Key>,
+    /// Never store the personal data of the owners, for datasets published publicly.
+    pub scrub_personal_data: bool,
+    /// Keep every version of the records of the repositories when compacting.
+    pub keep_history: bool,
+    /// Times a page of the REST API is fetched and loaded again when some of its batches fail,
+    /// or `None` to move past the failed batches.
+    pub page_retries: Option<u32>,
+    /// Fail on malformed entities in the API responses instead of skipping them.
+    pub strict_parsing: bool,
+    /// Maximum size in bytes of the file trees fetched for each repository.
+    pub tree_max_size: u64,
+    /// Seconds fetching the file tree of a repository can take.
+    pub tree_timeout: u64,
+    /// Directories containing third-party code, whose manifests are not counted.
+    pub vendored_dirs: Vec<String>,
+    /// Files looked for at the root of the repositories, in addition to `Cargo.toml` and
+    /// `Cargo.lock`.
+    pub extra_files: Vec<String>,
+
+    pub skip_archived: bool,
+    pub skip_empty: bool,
+    pub skip_disabled: bool,
+    /// Load again the repositories previously found not to use Rust.
+    pub recheck_non_rust: bool,
+    /// Load the number of open issues and pull requests while scraping, which costs more points.
+    pub issue_counts: bool,
+    /// Load the combined CI status of the default branch while scraping.
+    pub ci_status: bool,
+    /// Store the beginning of the README of the repositories without a description.
+    pub readme_excerpt: bool,
+    /// Maximum length of the README excerpts, in bytes.
+    pub readme_excerpt_length: usize,
+    /// Repositories with less Rust code than this, in bytes, are not stored.
+    pub min_rust_bytes: u64,
+    /// Only store the repositories tagged with at least one of these topics, if any is set.
+    pub include_topics: Vec<String>,
+    pub exclude_topics: Vec<String>,
+
+    pub owner_denylist: Vec<String>,
+    pub owner_denylist_url: Option<String>,
+
+    pub health_addr: Option<String>,
+    /// Filter of the repositories in the feed served by the health check server.
+    pub feed_where: Option<String>,
+    pub feed_limit: usize,
+    pub health_max_stall: u64,
+
+    pub search_index: bool,
+    pub sorted_output: bool,
+    /// Number of threads deserializing the dataset when reading it, if overridden.
+    pub read_threads: Option<usize>,
+
+    /// URL notified when the alerting rules start or stop failing.
+    pub alert_webhook: Option<String>,
+    /// TOML file configuring the channels notified of the repositories found.
+    pub notifiers: Option<PathBuf>,
+    /// Percentage of failed requests above which an alert is sent.
+    pub alert_max_error_rate: f64,
+    /// Seconds without progress after which an alert is sent.
+    pub alert_max_stall: u64,
+
+    /// Only discover the Rust repositories, without looking at their content.
+    pub discovery_only: bool,
+
+    pub record_http: Option<PathBuf>,
+    pub replay_http: Option<PathBuf>,
+
+    pub clickhouse_url: Option<String>,
+    pub clickhouse_table: String,
+    pub clickhouse_user: Option<String>,
+    pub clickhouse_password: Option<String>,
+    pub bigquery_table: Option<String>,
+
+    /// Base URL of the object storage the state is pushed to and pulled from.
+    pub checkpoint_url: Option<String>,
+    /// Bearer token sent to the object storage.
+    pub checkpoint_token: Option<String>,
+
+    /// URL of the lease object used to elect the replica scraping, if replicated.
+    pub leader_url: Option<String>,
+    /// Bearer token sent when reading and writing the lease.
+    pub leader_token: Option<String>,
+    /// Duration of the lease in seconds.
+    pub leader_lease: u64,
+}
+
+impl Config {
+    pub fn from_env(data_dir: PathBuf) -> Fallible<Self> {
+        let timeout = env_parse("RUST_REPOS_TIMEOUT")?;
+        let follow_interval = if env_flag("RUST_REPOS_FOLLOW")? {
+            Some(env_parse("RUST_REPOS_FOLLOW_INTERVAL")?.unwrap_or(5 * 60))
+        } else {
+            None
+        };
+
+        let alert_max_error_rate = env_parse("RUST_REPOS_ALERT_MAX_ERROR_RATE")?.unwrap_or(10.0);
+        if !(0.0..=100.0).contains(&alert_max_error_rate) {
+            bail!("RUST_REPOS_ALERT_MAX_ERROR_RATE must be a percentage between 0 and 100");
+        }
+
+        let data_layout = match std::env::var("RUST_REPOS_DATA_LAYOUT")
+            .as_ref()
+            .map(|s| s.as_str())
+        {
+            Ok("v1") | Err(_) => DataLayout::Flat,
+            Ok("v2") => DataLayout::PerForge,
+            Ok(other) => bail!("invalid value for RUST_REPOS_DATA_LAYOUT: {}", other),
+        };
+
+        // The contact is only required when talking to the APIs, see `require_api_access`
+        let contact = std::env::var("RUST_REPOS_CONTACT").ok();
+        let with_contact = |product: String| match contact {
+            Some(ref contact) => format!("{} ({})", product, contact),
+            None => product,
+        };
+        let user_agent = with_contact(
+            std::env::var("RUST_REPOS_USER_AGENT").unwrap_or_else(|_| "rust-repos".into()),
+        );
+        let github_user_agent = match std::env::var("RUST_REPOS_GITHUB_USER_AGENT") {
+            Ok(product) => with_contact(product),
+            Err(_) => user_agent.clone(),
+        };
+
+        Ok(Config {
+            github_tokens: get_tokens_from_env()?,
+            contact,
+            user_agent,
+            github_user_agent,
+            data_dir,
+            data_layout,
+            timeout,
+            rate_limit_floor: env_parse("RUST_REPOS_RATE_LIMIT_FLOOR")?,
+            follow_interval,
+            github_retry: RetryPolicy::from_env("GITHUB")?,
+            http_timeout: env_parse("RUST_REPOS_HTTP_TIMEOUT")?.unwrap_or(30),
+            http_connect_timeout: env_parse("RUST_REPOS_HTTP_CONNECT_TIMEOUT")?.unwrap_or(10),
+            http_pool_size: env_parse("RUST_REPOS_HTTP_POOL_SIZE")?,
+            http_pool_idle_timeout: env_parse("RUST_REPOS_HTTP_POOL_IDLE_TIMEOUT")?.unwrap_or(90),
+            http_tcp_keepalive: env_parse("RUST_REPOS_HTTP_TCP_KEEPALIVE")?,
+            http1_only: env_flag("RUST_REPOS_HTTP1_ONLY")?,
+            http_proxy: std::env::var("RUST_REPOS_HTTP_PROXY").ok(),
+            http_compression: !env_flag("RUST_REPOS_HTTP_NO_COMPRESSION")?,
+            min_free_space: env_parse("RUST_REPOS_MIN_FREE_SPACE")?.unwrap_or(100 * 1024 * 1024),
+            encryption_key: get_encryption_key_from_env()?,
+            scrub_personal_data: env_flag("RUST_REPOS_SCRUB_PERSONAL_DATA")?,
+            keep_history: env_flag("RUST_REPOS_KEEP_HISTORY")?,
+            page_retries: env_parse("RUST_REPOS_PAGE_RETRIES")?,
+            strict_parsing: env_flag("RUST_REPOS_STRICT_PARSING")?,
+            tree_max_size: env_parse("RUST_REPOS_TREE_MAX_SIZE")?.unwrap_or(50 * 1024 * 1024),
+            tree_timeout: env_parse("RUST_REPOS_TREE_TIMEOUT")?.unwrap_or(120),
+            vendored_dirs: match std::env::var("RUST_REPOS_VENDORED_DIRS") {
+                Ok(dirs) => split_list(&dirs),
+                Err(_) => DEFAULT_VENDORED_DIRS
+                    .iter()
+                    .map(|&dir| dir.into())
+                    .collect(),
+            },
+            extra_files: env_list("RUST_REPOS_EXTRA_FILES"),
+
+            skip_archived: env_flag("RUST_REPOS_SKIP_ARCHIVED")?,
+            skip_empty: env_flag("RUST_REPOS_SKIP_EMPTY")?,
+            skip_disabled: env_flag("RUST_REPOS_SKIP_DISABLED")?,
+            recheck_non_rust: env_flag("RUST_REPOS_RECHECK_NON_RUST")?,
+            issue_counts: env_flag("RUST_REPOS_ISSUE_COUNTS")?,
+            ci_status: env_flag("RUST_REPOS_CI_STATUS")?,
+            readme_excerpt: env_flag("RUST_REPOS_README_EXCERPT")?,
+            readme_excerpt_length: env_parse("RUST_REPOS_README_EXCERPT_LENGTH")?.unwrap_or(200),
+            min_rust_bytes: env_parse("RUST_REPOS_MIN_RUST_BYTES")?.unwrap_or(0),
+            // Topics are always lowercase on GitHub
+            include_topics: env_list("RUST_REPOS_INCLUDE_TOPICS")
+                .into_iter()
+                .map(|topic| topic.to_lowercase())
+                .collect(),
+            exclude_topics: env_list("RUST_REPOS_EXCLUDE_TOPICS")
+                .into_iter()
+                .map(|topic| topic.to_lowercase())
+                .collect(),
+
+            owner_denylist: env_list("RUST_REPOS_OWNER_DENYLIST"),
+            owner_denylist_url: std::env::var("RUST_REPOS_OWNER_DENYLIST_URL").ok(),
+
+            health_addr: std::env::var("RUST_REPOS_HEALTH_ADDR").ok(),
+            feed_where: std::env::var("RUST_REPOS_FEED_WHERE").ok(),
+            feed_limit: env_parse("RUST_REPOS_FEED_LIMIT")?.unwrap_or(feed::DEFAULT_LIMIT),
+            health_max_stall: env_parse("RUST_REPOS_HEALTH_MAX_STALL")?.unwrap_or(10 * 60),
+
+            search_index: env_flag("RUST_REPOS_SEARCH_INDEX")?,
+            sorted_output: env_flag("RUST_REPOS_SORTED_OUTPUT")?,
+            read_threads: env_parse("RUST_REPOS_READ_THREADS")?,
+
+            alert_webhook: std::env::var("RUST_REPOS_ALERT_WEBHOOK").ok(),
+            notifiers: std::env::var_os("RUST_REPOS_NOTIFIERS").map(PathBuf::from),
+            alert_max_error_rate,
+            alert_max_stall: env_parse("RUST_REPOS_ALERT_MAX_STALL")?.unwrap_or(30 * 60),
+
+            discovery_only: false,
+
+            record_http: None,
+            replay_http: None,
+
+            clickhouse_url: std::env::var("RUST_REPOS_CLICKHOUSE_URL").ok(),
+            clickhouse_table: std::env::var("RUST_REPOS_CLICKHOUSE_TABLE")
+                .unwrap_or_else(|_| "github_repos".into()),
+            clickhouse_user: std::env::var("RUST_REPOS_CLICKHOUSE_USER").ok(),
+            clickhouse_password: std::env::var("RUST_REPOS_CLICKHOUSE_PASSWORD").ok(),
+            bigquery_table: std::env::var("RUST_REPOS_BIGQUERY_TABLE").ok(),
+
+            checkpoint_url: std::env::var("RUST_REPOS_CHECKPOINT_URL").ok(),
+            checkpoint_token: std::env::var("RUST_REPOS_CHECKPOINT_TOKEN").ok(),
+
+            leader_url: std::env::var("RUST_REPOS_LEADER_URL").ok(),
+            leader_token: std::env::var("RUST_REPOS_LEADER_TOKEN").ok(),
+            leader_lease: env_parse("RUST_REPOS_LEADER_LEASE")?.unwrap_or(60),
+        })
+    }
+
+    /// Ensure the configuration needed to call the APIs is present. Commands working only on
+    /// the local data don't need it.
+    pub fn require_api_access(&self) -> Fallible<()> {
+        if self.github_tokens.is_empty() {
+            bail!("no GitHub API token provided in GITHUB_TOKEN or GITHUB_TOKEN_FILE");
+        }
+        // API providers ask for identifiable user agents, so a contact is required
+        if self.contact.is_none() {
+            bail!(
+                "missing RUST_REPOS_CONTACT (an URL or email address to include in the user agent)"
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Get the encryption key from `RUST_REPOS_ENCRYPTION_KEY`, or from the file pointed to by
+/// `RUST_REPOS_ENCRYPTION_KEY_FILE`. The key only encrypts the repositories CSV: the other files
+/// of the data directory stay in plaintext.
+fn get_encryption_key_from_env() -> Fallible<Option<Key>> {
+    let hex = if let Ok(hex) = std::env::var("RUST_REPOS_ENCRYPTION_KEY") {
+        hex
+    } else if let Some(path) = std::env::var_os("RUST_REPOS_ENCRYPTION_KEY_FILE") {
+        std::fs::read_to_string(&path).with_context(|_| {
+            format!(
+                "failed to read the encryption key from {}",
+                Path::new(&path).display()
+            )
+        })?
+    } else {
+        return Ok(None);
+    };
+    if !cfg!(feature = "encryption") {
+        bail!("an encryption key was provided, but rust-repos was built without the encryption feature");
+    }
+    Ok(Some(Key::parse(&hex)?))
+}
+
+/// Get the GitHub tokens from the environment. Multiple tokens can be provided by separating
+/// them with commas, and requests will be spread across all of them.
+///
+/// When `GITHUB_TOKEN` is not set the tokens are read from the file in `GITHUB_TOKEN_FILE`, or
+/// from the default location of container secrets, with one or more tokens per line.
+pub fn get_tokens_from_env() -> Fallible<Vec<String>> {
+    if std::env::var_os("GITHUB_TOKEN").is_some() {
+        return Ok(env_list("GITHUB_TOKEN"));
+    }
+
+    let path = match std::env::var_os("GITHUB_TOKEN_FILE") {
+        Some(path) => PathBuf::from(path),
+        None if Path::new(DEFAULT_TOKEN_FILE).is_file() => PathBuf::from(DEFAULT_TOKEN_FILE),
+        None => return Ok(Vec::new()),
+    };
+    let content = std::fs::read_to_string(&path)
+        .with_context(|_| format!("failed to read the tokens from {}", path.to_string_lossy()))?;
+    Ok(content.lines().flat_map(split_list).collect())
+}
+
+/// Parse a boolean flag from the environment, defaulting to `false` when it's not set.
+pub fn env_flag(name: &str) -> Fallible<bool> {
+    match std::env::var(name) {
+        Ok(var) => match var.as_str() {
+            "1" | "true" | "yes" => Ok(true),
+            "0" | "false" | "no" | "" => Ok(false),
+            other => bail!("invalid value for {}: {}", name, other),
+        },
+        Err(_) => Ok(false),
+    }
+}
+
+/// Parse a value from the environment, returning `None` when it's not set.
+pub fn env_parse<T>(name: &str) -> Fallible<Option<T>>
+where
+    T: FromStr,
+    T::Err: Fail,
+{
+    match std::env::var(name) {
+        Ok(var) => Ok(Some(
+            var.parse::<T>()
+                .with_context(|_| format!("failed to parse {}", name))?,
+        )),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Parse a comma-separated list from the environment, returning an empty list when it's not set.
+pub fn env_list(name: &str) -> Vec<String> {
+    std::env::var(name)
+        .map(|var| split_list(&var))
+        .unwrap_or_default()
+}
+
+fn split_list(content: &str) -> Vec<String> {
+    content
+        .split(',')
+        .map(|item| item.trim())
+        .filter(|item| !item.is_empty())
+        .map(String::from)
+        .collect()
 }
@@ -0,0 +1,62 @@
+// Copyright (c) 2018 Pietro Albini <pietro@pietroalbini.org>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies
+// of the Software, and to permit persons to whom the Software is furnished to do
+// so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use data::{Data, Repo};
+use prelude::*;
+use query::Filter;
+use std::io::{self, Write};
+
+static PLATFORM: &str = "github";
+
+/// Print the list of repositories to test in a crater experiment, as `<url>@<sha>` lines.
+///
+/// Only repositories with a `Cargo.toml` and a known commit are included, and archived or
+/// disabled ones are skipped unless `include_archived` is set. The list can be further narrowed
+/// down with a filter expression, like `has_cargo_lock AND stars >= 10`.
+pub fn list(data: &Data, filter: Option<&Filter>, include_archived: bool) -> Fallible<()> {
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+
+    let mut count = 0;
+    for repo in data.repos(PLATFORM)? {
+        let repo = repo?;
+        if !is_testable(&repo, include_archived) {
+            continue;
+        }
+        if let Some(filter) = filter {
+            if !filter.matches(&repo)? {
+                continue;
+            }
+        }
+
+        if let Some(sha) = &repo.head_sha {
+            writeln!(out, "https://github.com/{}@{}", repo.name, sha)?;
+            count += 1;
+        }
+    }
+
+    info!("listed {} repositories", count);
+    Ok(())
+}
+
+fn is_testable(repo: &Repo, include_archived: bool) -> bool {
+    repo.has_cargo_toml
+        && (include_archived || (repo.is_archived != Some(true) && repo.is_disabled != Some(true)))
+}
@@ -0,0 +1,152 @@
+// Copyright (c) 2018 Pietro Albini <pietro@pietroalbini.org>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies
+// of the Software, and to permit persons to whom the Software is furnished to do
+// so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Mapping of the crates published on crates.io to the repositories of the dataset, from the
+//! `crates.csv` file of the [crates.io database dump][dump].
+//!
+//! [dump]: https://crates.io/data-access
+
+use canonical::{Renames, RepoName};
+use csv;
+use data::{write_atomically, Data};
+use prelude::*;
+use std::collections::HashMap;
+use std::path::Path;
+
+static PLATFORM: &str = "github";
+
+/// How a crate was matched to a repository, from the most to the least reliable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Confidence {
+    /// The `repository` of the crate is the repository.
+    Exact,
+    /// The `repository` of the crate is an older name of the repository.
+    Renamed,
+    /// The `homepage` of the crate is the repository.
+    Homepage,
+    /// The crate has no repository, and a single repository with a `Cargo.toml` has its name.
+    Name,
+}
+
+impl Confidence {
+    fn name(self) -> &'static str {
+        match self {
+            Confidence::Exact => "exact",
+            Confidence::Renamed => "renamed",
+            Confidence::Homepage => "homepage",
+            Confidence::Name => "name",
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Crate {
+    name: String,
+    #[serde(default)]
+    repository: Option<String>,
+    #[serde(default)]
+    homepage: Option<String>,
+}
+
+/// Crate names are case insensitive, and don't distinguish hyphens and underscores.
+fn normalize_crate_name(name: &str) -> String {
+    name.to_lowercase().replace('_', "-")
+}
+
+/// Write a CSV mapping each crate to the repository it was matched to, with the confidence of
+/// the match. Crates not matched to any repository are left out.
+pub fn map(data: &Data, crates_csv: &Path, output: &Path) -> Fallible<()> {
+    let renames = Renames::load(data)?;
+
+    // Latest name of each repository, and the repositories with a `Cargo.toml` by their name
+    let mut latest = HashMap::new();
+    for repo in data.repos(PLATFORM)? {
+        let repo = repo?;
+        latest.insert(repo.id.clone(), repo);
+    }
+    let mut by_key = HashMap::new();
+    let mut by_name: HashMap<String, Vec<&str>> = HashMap::new();
+    for repo in latest.values() {
+        by_key.insert(
+            repo.name.to_lowercase(),
+            (repo.id.as_str(), repo.name.as_str()),
+        );
+        if repo.has_cargo_toml {
+            if let Some((_, name)) = repo.name.split_once('/') {
+                by_name
+                    .entry(normalize_crate_name(name))
+                    .or_default()
+                    .push(repo.id.as_str());
+            }
+        }
+    }
+
+    let find = |url: &Option<String>| -> Option<(&str, &str, bool)> {
+        let name = RepoName::parse(url.as_ref()?).ok()?;
+        if let Some((id, name)) = by_key.get(&name.key()) {
+            return Some((id, name, false));
+        }
+        by_key
+            .get(&renames.resolve(&name))
+            .map(|(id, name)| (*id, *name, true))
+    };
+
+    let mut reader = csv::Reader::from_path(crates_csv)
+        .with_context(|_| format!("failed to open {}", crates_csv.display()))?;
+    let mut matches = Vec::new();
+    let mut crates = 0;
+    for krate in reader.deserialize() {
+        let krate: Crate = krate?;
+        crates += 1;
+
+        let found = match (find(&krate.repository), find(&krate.homepage)) {
+            (Some((id, name, false)), _) => Some((id, name, Confidence::Exact)),
+            (Some((id, name, true)), _) => Some((id, name, Confidence::Renamed)),
+            (None, Some((id, name, _))) => Some((id, name, Confidence::Homepage)),
+            (None, None) if krate.repository.is_none() => by_name
+                .get(&normalize_crate_name(&krate.name))
+                .filter(|ids| ids.len() == 1)
+                .map(|ids| (ids[0], latest[ids[0]].name.as_str(), Confidence::Name)),
+            (None, None) => None,
+        };
+        if let Some((id, name, confidence)) = found {
+            matches.push((krate.name, id, name, confidence));
+        }
+    }
+
+    write_atomically(output, |file| {
+        let mut csv = csv::Writer::from_writer(file);
+        csv.write_record(["crate", "repo_id", "repo_name", "confidence"])?;
+        for (krate, id, name, confidence) in &matches {
+            csv.write_record([krate.as_str(), id, name, confidence.name()])?;
+        }
+        csv.flush()?;
+        Ok(())
+    })
+    .context(ErrorKind::Storage)?;
+
+    info!(
+        "matched {} of {} crates to repositories, written to {}",
+        matches.len(),
+        crates,
+        output.display()
+    );
+    Ok(())
+}
@@ -0,0 +1,218 @@
+// Copyright (c) 2018 Pietro Albini <pietro@pietroalbini.org>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies
+// of the Software, and to permit persons to whom the Software is furnished to do
+// so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Parallel deserialization of big CSV files: a thread reads the raw records in batches, and a
+//! pool of threads deserializes them, while the rows are still returned in order.
+
+use csv;
+use prelude::*;
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use std::io::Read;
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// Number of records deserialized at once by a worker.
+const BATCH_SIZE: usize = 4096;
+/// Maximum number of deserialization threads.
+const MAX_WORKERS: usize = 8;
+/// Number of batches each thread can have read ahead of the consumer.
+const BATCHES_PER_WORKER: usize = 4;
+
+type RawBatch = (usize, Result<Vec<csv::ByteRecord>, csv::Error>);
+type Batch<T> = (usize, Vec<Fallible<T>>);
+
+/// Deserialize all the records of a CSV file with headers, using multiple threads when more
+/// than one CPU is available. The number of threads defaults to the number of CPUs.
+pub fn deserialize<R, T>(
+    mut reader: csv::Reader<R>,
+    threads: Option<usize>,
+) -> Fallible<Box<dyn Iterator<Item = Fallible<T>>>>
+where
+    R: Read + Send + 'static,
+    T: DeserializeOwned + Send + 'static,
+{
+    let workers = threads.unwrap_or_else(|| {
+        thread::available_parallelism()
+            .map(|count| count.get())
+            .unwrap_or(1)
+            .min(MAX_WORKERS)
+    });
+    if workers <= 1 {
+        // Moving the records across threads would only slow things down
+        return Ok(Box::new(
+            reader.into_deserialize().map(|record| Ok(record?)),
+        ));
+    }
+
+    let headers = Arc::new(reader.byte_headers()?.clone());
+
+    // A batch is only read once a permit is available, and the consumer gives one back for each
+    // batch it's done with: this bounds the batches in memory, including the ones received out
+    // of order while a slow batch is being deserialized. Dropping the iterator early stops the
+    // threads, as the channels are disconnected.
+    let window = workers * BATCHES_PER_WORKER;
+    let (permit_tx, permit_rx) = sync_channel::<()>(window);
+    for _ in 0..window {
+        permit_tx.send(())?;
+    }
+    let (raw_tx, raw_rx) = sync_channel::<RawBatch>(workers * 2);
+    let (parsed_tx, parsed_rx) = sync_channel::<Batch<T>>(workers * 2);
+
+    thread::spawn(move || {
+        let mut records = reader.into_byte_records();
+        for idx in 0.. {
+            if permit_rx.recv().is_err() {
+                return;
+            }
+            let mut batch = Vec::with_capacity(BATCH_SIZE);
+            let mut error = None;
+            for record in records.by_ref().take(BATCH_SIZE) {
+                match record {
+                    Ok(record) => batch.push(record),
+                    Err(err) => {
+                        error = Some(err);
+                        break;
+                    }
+                }
+            }
+            let last = batch.len() < BATCH_SIZE;
+            // Reading stops at the first error, reported after the records read before it
+            let sent = match error {
+                Some(err) => raw_tx
+                    .send((idx, Ok(batch)))
+                    .and_then(|()| raw_tx.send((idx + 1, Err(err)))),
+                None => raw_tx.send((idx, Ok(batch))),
+            };
+            if last || sent.is_err() {
+                return;
+            }
+        }
+    });
+
+    let raw_rx = Arc::new(Mutex::new(raw_rx));
+    for _ in 0..workers {
+        let raw_rx = raw_rx.clone();
+        let parsed_tx = parsed_tx.clone();
+        let headers = headers.clone();
+        thread::spawn(move || loop {
+            let received = raw_rx.lock().unwrap().recv();
+            let (idx, batch) = match received {
+                Ok(batch) => batch,
+                Err(_) => return,
+            };
+            let parsed = match batch {
+                Ok(records) => records
+                    .iter()
+                    .map(|record| Ok(record.deserialize(Some(&headers))?))
+                    .collect(),
+                Err(err) => vec![Err(err.into())],
+            };
+            if parsed_tx.send((idx, parsed)).is_err() {
+                return;
+            }
+        });
+    }
+
+    Ok(Box::new(InOrder {
+        receiver: parsed_rx,
+        permits: permit_tx,
+        pending: HashMap::new(),
+        next: 0,
+        current: Vec::new().into_iter(),
+    }))
+}
+
+/// Iterator returning the deserialized batches in the order they were read.
+struct InOrder<T> {
+    receiver: Receiver<Batch<T>>,
+    /// Allows the reader thread to read a new batch for each one consumed.
+    permits: SyncSender<()>,
+    /// Batches received before the ones preceding them, at most one window of batches.
+    pending: HashMap<usize, Vec<Fallible<T>>>,
+    next: usize,
+    current: ::std::vec::IntoIter<Fallible<T>>,
+}
+
+impl<T> Iterator for InOrder<T> {
+    type Item = Fallible<T>;
+
+    fn next(&mut self) -> Option<Fallible<T>> {
+        loop {
+            if let Some(item) = self.current.next() {
+                return Some(item);
+            }
+            let batch = match self.pending.remove(&self.next) {
+                Some(batch) => batch,
+                None => loop {
+                    // All the threads exited once every batch was received
+                    let (idx, batch) = self.receiver.recv().ok()?;
+                    if idx == self.next {
+                        break batch;
+                    }
+                    self.pending.insert(idx, batch);
+                },
+            };
+            self.next += 1;
+            self.current = batch.into_iter();
+            // The reader thread is gone once every batch was read
+            let _ = self.permits.try_send(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{deserialize, BATCH_SIZE};
+    use csv;
+
+    #[test]
+    fn test_rows_in_order() {
+        let rows = BATCH_SIZE * 20 + 7;
+        let mut content = "id,name\n".to_string();
+        for id in 0..rows {
+            content.push_str(&format!("{},repo-{}\n", id, id));
+        }
+        let reader = csv::Reader::from_reader(::std::io::Cursor::new(content.into_bytes()));
+
+        let parsed = deserialize::<_, (usize, String)>(reader, Some(4))
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(parsed.len(), rows);
+        for (i, (id, name)) in parsed.into_iter().enumerate() {
+            assert_eq!(id, i);
+            assert_eq!(name, format!("repo-{}", i));
+        }
+    }
+
+    #[test]
+    fn test_error_after_rows() {
+        let content = "id,name\n1,foo\n2,bar,extra\n3,baz\n";
+        let reader = csv::Reader::from_reader(::std::io::Cursor::new(content.as_bytes().to_vec()));
+        let parsed = deserialize::<_, (usize, String)>(reader, Some(2))
+            .unwrap()
+            .collect::<Vec<_>>();
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed[0].as_ref().unwrap(), &(1, "foo".to_string()));
+        assert!(parsed[1].is_err());
+    }
+}
@@ -18,101 +18,2452 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-use config::Config;
+use config::{Config, DataLayout};
 use csv;
+use csv_reader;
+use encryption::{self, Key};
+use idset::IdSet;
+use lock::DataDirLock;
 use prelude::*;
+use search;
 use serde_json;
-use std::collections::HashMap;
-use std::path::PathBuf;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{sync_channel, SyncSender};
 use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime};
 use std::{
     fs::{self, File, OpenOptions},
-    io::{prelude::*, BufWriter},
+    io::{prelude::*, BufReader, BufWriter},
+    mem,
 };
+use utils::{format_timestamp, log_error, Rng};
 
-#[derive(Default, Serialize, Deserialize)]
+/// Maximum number of repositories kept in memory before they're written to disk.
+const BUFFER_CAPACITY: usize = 1000;
+/// Number of batches of repositories queued for the storage task of a platform, after which
+/// storing more blocks until the task catches up.
+const STORAGE_QUEUE_CAPACITY: usize = 32;
+
+/// Size of the write buffer used for CSV files, big enough to fit a whole batch of rows.
+const CSV_BUFFER_CAPACITY: usize = 128 * 1024;
+/// Initial size of the reused record, which fits most rows without reallocating.
+const RECORD_CAPACITY: usize = 128;
+
+/// Range of IDs, as (first, last).
+type IdRange = (u64, u64);
+
+/// How many repositories found not to use Rust are collected before saving them to disk.
+const NON_RUST_SAVE_INTERVAL: usize = 10_000;
+
+/// How often the free disk space is checked again while writes are paused.
+const DISK_SPACE_RECHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Forges the scraper knows about, whose files are moved when migrating to the per-forge layout.
+const PLATFORMS: &[&str] = &["github"];
+
+/// Version of the format of `state.json` written by this version of the scraper.
+const STATE_SCHEMA_VERSION: u32 = 2;
+
+#[derive(Serialize, Deserialize)]
 struct State {
+    schema_version: u32,
+    #[serde(default)]
+    forges: BTreeMap<String, ForgeState>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State {
+            schema_version: STATE_SCHEMA_VERSION,
+            forges: BTreeMap::new(),
+        }
+    }
+}
+
+impl State {
+    fn forge(&mut self, platform: &str) -> &mut ForgeState {
+        self.forges.entry(platform.to_string()).or_default()
+    }
+}
+
+/// Progress of the scraper on a single forge.
+#[derive(Default, Clone, Serialize, Deserialize)]
+pub struct ForgeState {
+    /// Last numeric ID scraped, for forges listing repositories by ID.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_id: Option<usize>,
+    /// Numeric ID up to which every repository was loaded and stored, which scraping resumes
+    /// from. Lags behind `last_id` while the scraped repositories are being loaded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub processed_id: Option<usize>,
+    /// Number of rows of the repositories CSV when the processed ID was saved. The rows after
+    /// it were stored by a run that didn't save its progress.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stored_rows: Option<usize>,
+    /// Size of the repositories CSV when the processed ID was saved, to read only the rows after
+    /// it. Unset when the file was rewritten since.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stored_bytes: Option<u64>,
+    /// Opaque pagination cursor, for forges listing repositories with string cursors.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cursor: Option<String>,
+    /// Repositories whose loading crashed, to be loaded again on the next run.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub retry_queue: Vec<String>,
+    /// Most recent `last_checked_at` exported to each destination, for incremental exports.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub export_watermarks: BTreeMap<String, String>,
+    /// Number of rows of the repositories CSV whose lockfile was already fetched.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub lockfile_position: usize,
+    /// Totals accumulated across all the runs.
+    #[serde(default)]
+    pub counters: RunCounters,
+    /// When the last scraper run on this forge finished.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_run_at: Option<String>,
+    /// Progress of the search of popular repositories, when it didn't complete yet.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub popular_search: Option<SearchProgress>,
+}
+
+/// Search of popular repositories split into partitions returning at most 1000 results each.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SearchProgress {
+    pub min_stars: u64,
+    /// Partitions left to cover, starting with the one being paginated.
+    pub partitions: Vec<SearchPartition>,
+    /// Cursor of the next page of the first partition.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cursor: Option<String>,
+}
+
+/// Repositories with a number of stars in a range, optionally created in a range of days since
+/// the Unix epoch. The ranges are inclusive.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SearchPartition {
+    pub min_stars: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_stars: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created: Option<(u64, u64)>,
+}
+
+/// Totals accumulated across all the scraper runs on a forge.
+#[derive(Default, Clone, Serialize, Deserialize)]
+pub struct RunCounters {
+    #[serde(default)]
+    pub runs: u64,
+    #[serde(default)]
+    pub repos_seen: u64,
+    #[serde(default)]
+    pub repos_stored: u64,
+}
+
+/// Format of `state.json` written before the schema was versioned, with a map for each field
+/// keyed by the forge.
+#[derive(Deserialize)]
+struct StateV1 {
+    #[serde(default)]
     last_id: HashMap<String, usize>,
+    #[serde(default)]
+    retry_queue: HashMap<String, Vec<String>>,
+    #[serde(default)]
+    export_watermarks: HashMap<String, BTreeMap<String, String>>,
+    #[serde(default)]
+    lockfile_position: HashMap<String, usize>,
 }
 
-#[derive(Serialize, Deserialize)]
+impl StateV1 {
+    fn migrate(mut self) -> State {
+        let mut state = State::default();
+        for (platform, id) in self.last_id.drain() {
+            state.forge(&platform).last_id = Some(id);
+        }
+        for (platform, queue) in self.retry_queue.drain() {
+            state.forge(&platform).retry_queue = queue;
+        }
+        for (platform, watermarks) in self.export_watermarks.drain() {
+            state.forge(&platform).export_watermarks = watermarks;
+        }
+        for (platform, position) in self.lockfile_position.drain() {
+            state.forge(&platform).lockfile_position = position;
+        }
+        state
+    }
+}
+
+#[allow(clippy::trivially_copy_pass_by_ref)]
+fn is_zero(value: &usize) -> bool {
+    *value == 0
+}
+
+/// Type of a column of the dataset, as stored in the CSV.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ColumnType {
+    String,
+    Bool,
+    OptionalBool,
+    /// An RFC 3339 timestamp in UTC.
+    OptionalTimestamp,
+    OptionalString,
+    OptionalInteger,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Column {
+    pub name: &'static str,
+    #[serde(rename = "type")]
+    pub kind: ColumnType,
+}
+
+impl Column {
+    const fn new(name: &'static str, kind: ColumnType) -> Self {
+        Column { name, kind }
+    }
+}
+
+static SCHEMA: &[Column] = &[
+    Column::new("id", ColumnType::String),
+    Column::new("name", ColumnType::String),
+    Column::new("has_cargo_toml", ColumnType::Bool),
+    Column::new("has_cargo_lock", ColumnType::Bool),
+    Column::new("is_archived", ColumnType::OptionalBool),
+    Column::new("is_empty", ColumnType::OptionalBool),
+    Column::new("is_disabled", ColumnType::OptionalBool),
+    Column::new("last_checked_at", ColumnType::OptionalTimestamp),
+    Column::new("description", ColumnType::OptionalString),
+    Column::new("stars", ColumnType::OptionalInteger),
+    Column::new("head_sha", ColumnType::OptionalString),
+    Column::new("toolchain", ColumnType::OptionalString),
+    Column::new("uses_nightly", ColumnType::OptionalBool),
+    Column::new("rust_version", ColumnType::OptionalString),
+    Column::new("edition", ColumnType::OptionalString),
+    Column::new("license", ColumnType::OptionalString),
+    Column::new("rust_adopted_at", ColumnType::OptionalTimestamp),
+    Column::new("open_issues", ColumnType::OptionalInteger),
+    Column::new("pull_requests", ColumnType::OptionalInteger),
+    Column::new("ci_status", ColumnType::OptionalString),
+    Column::new("readme", ColumnType::OptionalString),
+    Column::new("readme_excerpt", ColumnType::OptionalString),
+    Column::new("has_funding", ColumnType::OptionalBool),
+    Column::new("has_code_of_conduct", ColumnType::OptionalBool),
+    Column::new("has_contributing", ColumnType::OptionalBool),
+    Column::new("crate_kind", ColumnType::OptionalString),
+    Column::new("template", ColumnType::OptionalString),
+    Column::new("rust_share", ColumnType::OptionalInteger),
+    Column::new("rust_is_top_language", ColumnType::OptionalBool),
+    Column::new("categories", ColumnType::OptionalString),
+    Column::new("keywords", ColumnType::OptionalString),
+    Column::new("contributors", ColumnType::OptionalInteger),
+    Column::new("recent_commits", ColumnType::OptionalInteger),
+    Column::new("manifests", ColumnType::OptionalInteger),
+    Column::new("tree_truncated", ColumnType::OptionalBool),
+];
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Repo {
     pub id: String,
     pub name: String,
     pub has_cargo_toml: bool,
     pub has_cargo_lock: bool,
+    // Columns added after the initial release are optional, as they're unknown for the rows
+    // scraped before they were introduced.
+    pub is_archived: Option<bool>,
+    pub is_empty: Option<bool>,
+    pub is_disabled: Option<bool>,
+    pub last_checked_at: Option<String>,
+    pub description: Option<String>,
+    pub stars: Option<u64>,
+    pub head_sha: Option<String>,
+    pub toolchain: Option<String>,
+    /// Whether the repository likely requires a nightly compiler.
+    pub uses_nightly: Option<bool>,
+    /// Minimum supported Rust version declared in the root `Cargo.toml`.
+    pub rust_version: Option<String>,
+    pub edition: Option<String>,
+    /// SPDX identifier of the license detected by the forge.
+    pub license: Option<String>,
+    /// When the root `Cargo.toml` was first committed, only filled by the `enrich` subcommand.
+    pub rust_adopted_at: Option<String>,
+    /// Number of open issues, only filled when `RUST_REPOS_ISSUE_COUNTS` is set.
+    pub open_issues: Option<u64>,
+    /// Number of pull requests, in any state.
+    pub pull_requests: Option<u64>,
+    /// Outcome of the CI of the default branch, only filled when `RUST_REPOS_CI_STATUS` is set.
+    pub ci_status: Option<String>,
+    /// Format of the README (`md`, `rst`, `adoc`, `txt`, `other` or `none`).
+    pub readme: Option<String>,
+    /// Beginning of the README, only filled when `RUST_REPOS_README_EXCERPT` is set and the
+    /// repository has no description.
+    pub readme_excerpt: Option<String>,
+    /// Whether sponsorship is configured, with `FUNDING.yml` or the funding links of the owner.
+    pub has_funding: Option<bool>,
+    /// Whether a `CODE_OF_CONDUCT` file is present at the root of the repository.
+    pub has_code_of_conduct: Option<bool>,
+    /// Whether a `CONTRIBUTING` file is present at the root of the repository.
+    pub has_contributing: Option<bool>,
+    /// Whether the root crate is a `bin`, a `lib`, `both` or a virtual `workspace`.
+    pub crate_kind: Option<String>,
+    /// Name of the template the repository was generated from.
+    pub template: Option<String>,
+    /// Percentage of the code of the repository written in Rust.
+    pub rust_share: Option<u64>,
+    /// Whether Rust is the language with the most code, false for Rust code inside bigger
+    /// projects written in other languages.
+    pub rust_is_top_language: Option<bool>,
+    /// Space-separated crates.io categories declared in the `Cargo.toml`.
+    pub categories: Option<String>,
+    /// Space-separated crates.io keywords declared in the `Cargo.toml`.
+    pub keywords: Option<String>,
+    /// Approximate number of contributors, only filled by the `enrich` subcommand.
+    pub contributors: Option<u64>,
+    /// Commits in the default branch in the last 90 days, only filled by the `enrich` subcommand.
+    pub recent_commits: Option<u64>,
+    /// Number of `Cargo.toml` files anywhere in the repository, only filled by the `enrich`
+    /// subcommand. A lower bound when `tree_truncated` is set.
+    pub manifests: Option<u64>,
+    /// Whether the file tree of the repository was too big to be fully inspected.
+    pub tree_truncated: Option<bool>,
+    /// Whether each of the files configured with `RUST_REPOS_EXTRA_FILES` is present at the root
+    /// of the repository, stored in a separate CSV as the set of columns is not fixed.
+    #[serde(skip)]
+    pub extra_files: Vec<Option<bool>>,
+}
+
+impl Repo {
+    /// Columns of the CSV and their types, in the same order as the struct fields. This is the
+    /// source of truth for the tools and export targets deriving their own schema.
+    pub fn schema() -> &'static [Column] {
+        SCHEMA
+    }
+
+    /// Names of the CSV columns, in the same order as the struct fields.
+    pub fn columns() -> Vec<&'static str> {
+        SCHEMA.iter().map(|column| column.name).collect()
+    }
+
+    /// Whether two records of a repository only differ by when they were checked.
+    fn same_content(&self, other: &Repo) -> bool {
+        let (mut a, mut b) = (csv::ByteRecord::new(), csv::ByteRecord::new());
+        self.write_record(&mut a);
+        other.write_record(&mut b);
+        let checked_at = SCHEMA
+            .iter()
+            .position(|column| column.name == "last_checked_at");
+        a.iter()
+            .zip(b.iter())
+            .enumerate()
+            .all(|(i, (a, b))| Some(i) == checked_at || a == b)
+    }
+
+    /// Write the repository into a reusable record, in the same format serde would produce but
+    /// without its per-row overhead.
+    pub fn write_record(&self, record: &mut csv::ByteRecord) {
+        let mut digits = [0; 20];
+        record.clear();
+        record.push_field(self.id.as_bytes());
+        record.push_field(self.name.as_bytes());
+        record.push_field(bool_field(self.has_cargo_toml));
+        record.push_field(bool_field(self.has_cargo_lock));
+        record.push_field(optional_bool_field(self.is_archived));
+        record.push_field(optional_bool_field(self.is_empty));
+        record.push_field(optional_bool_field(self.is_disabled));
+        record.push_field(optional_str_field(&self.last_checked_at));
+        record.push_field(optional_str_field(&self.description));
+        record.push_field(optional_u64_field(self.stars, &mut digits));
+        record.push_field(optional_str_field(&self.head_sha));
+        record.push_field(optional_str_field(&self.toolchain));
+        record.push_field(optional_bool_field(self.uses_nightly));
+        record.push_field(optional_str_field(&self.rust_version));
+        record.push_field(optional_str_field(&self.edition));
+        record.push_field(optional_str_field(&self.license));
+        record.push_field(optional_str_field(&self.rust_adopted_at));
+        record.push_field(optional_u64_field(self.open_issues, &mut digits));
+        record.push_field(optional_u64_field(self.pull_requests, &mut digits));
+        record.push_field(optional_str_field(&self.ci_status));
+        record.push_field(optional_str_field(&self.readme));
+        record.push_field(optional_str_field(&self.readme_excerpt));
+        record.push_field(optional_bool_field(self.has_funding));
+        record.push_field(optional_bool_field(self.has_code_of_conduct));
+        record.push_field(optional_bool_field(self.has_contributing));
+        record.push_field(optional_str_field(&self.crate_kind));
+        record.push_field(optional_str_field(&self.template));
+        record.push_field(optional_u64_field(self.rust_share, &mut digits));
+        record.push_field(optional_bool_field(self.rust_is_top_language));
+        record.push_field(optional_str_field(&self.categories));
+        record.push_field(optional_str_field(&self.keywords));
+        record.push_field(optional_u64_field(self.contributors, &mut digits));
+        record.push_field(optional_u64_field(self.recent_commits, &mut digits));
+        record.push_field(optional_u64_field(self.manifests, &mut digits));
+        record.push_field(optional_bool_field(self.tree_truncated));
+    }
+}
+
+/// Name of the column recording whether a file is present, like `has_deny_toml` for `deny.toml`.
+pub fn file_column(name: &str) -> String {
+    let name = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '_'
+            }
+        })
+        .collect::<String>();
+    format!("has_{}", name)
+}
+
+/// Format a number in `digits` without allocating, as `write_record` is called for every row.
+fn optional_u64_field(value: Option<u64>, digits: &mut [u8; 20]) -> &[u8] {
+    let mut value = match value {
+        Some(value) => value,
+        None => return b"",
+    };
+    let mut start = digits.len();
+    loop {
+        start -= 1;
+        digits[start] = b'0' + (value % 10) as u8;
+        value /= 10;
+        if value == 0 {
+            break;
+        }
+    }
+    &digits[start..]
+}
+
+fn bool_field(value: bool) -> &'static [u8] {
+    if value {
+        b"true"
+    } else {
+        b"false"
+    }
 }
 
+fn optional_bool_field(value: Option<bool>) -> &'static [u8] {
+    value.map(bool_field).unwrap_or(b"")
+}
+
+fn optional_str_field(value: &Option<String>) -> &[u8] {
+    value.as_ref().map(|value| value.as_bytes()).unwrap_or(b"")
+}
+
+/// A package locked in the `Cargo.lock` of a repository.
+#[derive(Serialize, Deserialize)]
+pub struct LockedPackage {
+    pub repo_id: String,
+    pub name: String,
+    pub version: String,
+    pub source: Option<String>,
+    /// Names of the packages this package depends on, separated by spaces.
+    #[serde(default)]
+    pub dependencies: String,
+}
+
+impl LockedPackage {
+    /// Names of the CSV columns, in the same order as the struct fields.
+    pub const COLUMNS: &'static [&'static str] =
+        &["repo_id", "name", "version", "source", "dependencies"];
+
+    pub fn is_from_crates_io(&self) -> bool {
+        self.source
+            .as_ref()
+            .map(|source| source.contains("crates.io-index") || source.contains("index.crates.io"))
+            .unwrap_or(false)
+    }
+}
+
+/// Version of the record of a repository, valid from when it was first checked until a
+/// different record was stored. The bounds are unknown for rows stored before the
+/// `last_checked_at` column was introduced.
+pub struct RepoVersion {
+    pub repo: Repo,
+    pub valid_from: Option<String>,
+    pub valid_to: Option<String>,
+}
+
+impl RepoVersion {
+    fn is_valid_at(&self, timestamp: &str) -> bool {
+        self.valid_from
+            .as_deref()
+            .is_some_and(|from| from <= timestamp)
+            && self.valid_to.as_deref().is_none_or(|to| to > timestamp)
+    }
+}
+
+/// Profile of the account owning some repositories.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Owner {
+    pub login: String,
+    /// `User` or `Organization`, or empty if the account doesn't exist anymore.
+    pub kind: Option<String>,
+    pub company: Option<String>,
+    pub location: Option<String>,
+    pub fetched_at: String,
+}
+
+impl Owner {
+    /// Remove the personal data from the profile of a user. Organizations are left as is.
+    fn scrub(&mut self) {
+        if self.kind.as_deref() == Some("User") {
+            self.company = None;
+            self.location = None;
+        }
+    }
+}
+
+enum StorageMessage {
+    Store(Vec<Repo>),
+    /// Write the buffered repositories, and reply with the outcome.
+    Flush(SyncSender<Fallible<()>>),
+}
+
+/// Thread writing the repositories of a platform, fed through a bounded channel.
+struct StorageTask {
+    sender: SyncSender<StorageMessage>,
+    /// Why the task stopped, if it failed to write outside of a flush.
+    error: Arc<Mutex<Option<Error>>>,
+    thread: thread::JoinHandle<()>,
+}
+
+/// Storage tasks of each platform, shared by the clones of `Data` but not by the tasks themselves.
+#[derive(Default)]
+struct StorageTasks(Mutex<HashMap<String, StorageTask>>);
+
+impl Drop for StorageTasks {
+    /// Closing the queues makes the tasks write the repositories still buffered, which must be
+    /// done before the process exits.
+    fn drop(&mut self) {
+        for (_, task) in self.0.get_mut().unwrap().drain() {
+            mem::drop(task.sender);
+            if task.thread.join().is_err() {
+                error!("a storage task panicked");
+            }
+        }
+    }
+}
+
+/// ID set stored in the data directory, kept in memory once loaded.
+struct CachedIdSet {
+    ids: IdSet,
+    /// Number of IDs added since the set was last written to disk.
+    unsaved: usize,
+}
+
+impl CachedIdSet {
+    fn save(&mut self, path: &Path) -> Fallible<()> {
+        fs::create_dir_all(path.parent().unwrap())?;
+        write_atomically(path, |file| self.ids.write_to(file))?;
+        self.unsaved = 0;
+        Ok(())
+    }
+}
+
+/// Repository known not to use Rust, imported from a third-party dataset.
+#[derive(Serialize, Deserialize)]
+pub struct SeededRepo {
+    pub name: String,
+    /// Main language of the repository according to the dataset.
+    pub language: String,
+}
+
+/// Daily totals of a platform, appended to the growth CSV.
+#[derive(Serialize, Deserialize)]
+pub struct GrowthRow {
+    pub date: String,
+    /// Highest repository ID scanned by the scraper, including repositories in other languages.
+    pub known_repos: usize,
+    pub rust_repos: usize,
+    pub with_cargo_toml: usize,
+    pub with_cargo_lock: usize,
+}
+
+/// Batch of repositories that failed to load, appended to the errors CSV.
+#[derive(Serialize, Deserialize)]
+pub struct ErrorRow {
+    pub timestamp: String,
+    pub category: String,
+    /// IDs of the repositories in the batch, separated by spaces.
+    pub ids: String,
+    pub error: String,
+}
+
+#[derive(Clone)]
 pub struct Data {
     base_dir: PathBuf,
+    layout: DataLayout,
+    search_index: bool,
+    read_threads: Option<usize>,
+    extra_files: Vec<String>,
 
-    csv_write_lock: Arc<Mutex<()>>,
+    /// Storage task of each platform, started when its first repositories are stored.
+    storage_tasks: Arc<StorageTasks>,
+    csv_write_lock: Arc<Mutex<HashSet<PathBuf>>>,
 
-    state_path: PathBuf,
-    state_cache: Arc<Mutex<Option<State>>>,
+    state_cache: Arc<Mutex<HashMap<PathBuf, State>>>,
+    /// ID sets (like the repositories known not to use Rust), loaded lazily.
+    id_sets: Arc<Mutex<HashMap<PathBuf, CachedIdSet>>>,
+    /// Date of the last row of the growth CSV of each platform.
+    growth_dates: Arc<Mutex<HashMap<String, String>>>,
+    /// Free space in bytes below which writes are paused.
+    min_free_space: u64,
+    /// Whether writes are currently paused because the disk is almost full.
+    low_disk_space: Arc<AtomicBool>,
+    /// Whether compacting keeps every version of the records.
+    keep_history: bool,
+    /// Key the repositories CSV is encrypted with.
+    encryption_key: Option<Key>,
+    /// Whether personal data is dropped from the owner profiles.
+    scrub_personal_data: bool,
+    /// Processed IDs of each platform, saved in the state once their repositories are flushed.
+    processed_ids: Arc<Mutex<HashMap<String, usize>>>,
+    /// Ranges of IDs scraped by each platform, saved in the coverage once their repositories are
+    /// flushed.
+    pending_coverage: Arc<Mutex<HashMap<String, Vec<IdRange>>>>,
+    /// Repositories loaded by each platform, removed from its retry queue once they're flushed.
+    pending_retries: Arc<Mutex<HashMap<String, HashSet<String>>>>,
+    /// Number of rows of the repositories CSV of each platform, once counted by `reconcile`.
+    csv_rows: Arc<Mutex<HashMap<String, usize>>>,
+    /// Set when this replica lost the leadership, after which nothing is written anymore.
+    fence: Option<Arc<AtomicBool>>,
 }
 
 impl Data {
-    pub fn new(config: &Config) -> Self {
-        Data {
+    pub fn new(config: &Config) -> Fallible<Self> {
+        let data = Data {
             base_dir: config.data_dir.clone(),
+            layout: config.data_layout,
+            search_index: config.search_index,
+            read_threads: config.read_threads,
+            extra_files: config.extra_files.clone(),
+
+            storage_tasks: Arc::new(StorageTasks::default()),
+            csv_write_lock: Arc::new(Mutex::new(HashSet::new())),
 
-            csv_write_lock: Arc::new(Mutex::new(())),
+            state_cache: Arc::new(Mutex::new(HashMap::new())),
+            id_sets: Arc::new(Mutex::new(HashMap::new())),
+            growth_dates: Arc::new(Mutex::new(HashMap::new())),
+            min_free_space: config.min_free_space,
+            low_disk_space: Arc::new(AtomicBool::new(false)),
+            keep_history: config.keep_history,
+            encryption_key: config.encryption_key.clone(),
+            scrub_personal_data: config.scrub_personal_data,
+            processed_ids: Arc::new(Mutex::new(HashMap::new())),
+            pending_coverage: Arc::new(Mutex::new(HashMap::new())),
+            pending_retries: Arc::new(Mutex::new(HashMap::new())),
+            csv_rows: Arc::new(Mutex::new(HashMap::new())),
+            fence: None,
+        };
 
-            state_path: config.data_dir.join("state.json"),
-            state_cache: Arc::new(Mutex::new(None)),
+        if data.layout == DataLayout::PerForge {
+            data.migrate_to_per_forge().context(ErrorKind::Storage)?;
         }
+        data.encrypt_plaintext_csvs().context(ErrorKind::Storage)?;
+
+        Ok(data)
     }
 
-    fn edit_state<T, F: Fn(&mut State) -> Fallible<T>>(&self, f: F) -> Fallible<T> {
-        let mut state_cache = self.state_cache.lock().unwrap();
+    /// Encrypt the repositories CSVs written before a key was provided, as encrypted files can't
+    /// be read in plaintext once a key is provided.
+    fn encrypt_plaintext_csvs(&self) -> Fallible<()> {
+        let key = match &self.encryption_key {
+            Some(key) => key,
+            None => return Ok(()),
+        };
+        for platform in PLATFORMS {
+            let file = self.csv_path(platform);
+            if !file.exists() || encryption::is_encrypted(&file)? {
+                continue;
+            }
 
-        if state_cache.is_none() {
-            if self.state_path.exists() {
-                *state_cache = Some(serde_json::from_slice(&fs::read(&self.state_path)?)?);
+            // Read-only commands don't hold the lock, which is needed to replace the file under
+            // the feet of the other processes
+            let _lock = if DataDirLock::is_held(&self.base_dir) {
+                None
             } else {
-                *state_cache = Some(Default::default());
+                Some(DataDirLock::acquire(&self.base_dir)?)
+            };
+            migrate_csv(&file, &Repo::columns(), Some(key))?;
+            self.edit_forge_state(platform, |state| state.stored_bytes = None)?;
+        }
+        Ok(())
+    }
+
+    /// Stop writing to the data directory once the flag is set, for example when the leadership
+    /// is lost. This must be called before storing anything.
+    pub fn set_fence(&mut self, fence: Arc<AtomicBool>) {
+        self.fence = Some(fence);
+    }
+
+    fn check_fence(&self) -> Fallible<()> {
+        if let Some(fence) = &self.fence {
+            if fence.load(Ordering::SeqCst) {
+                bail!("the leadership was lost, refusing to write to the data directory");
             }
         }
+        Ok(())
+    }
 
-        let state = state_cache.as_mut().unwrap();
-        let result = f(state)?;
+    fn csv_path(&self, platform: &str) -> PathBuf {
+        match self.layout {
+            DataLayout::Flat => self.base_dir.join(format!("{}.csv", platform)),
+            DataLayout::PerForge => self.base_dir.join(platform).join("repos.csv"),
+        }
+    }
+
+    pub fn base_dir(&self) -> &Path {
+        &self.base_dir
+    }
+
+    pub fn state_path(&self, platform: &str) -> PathBuf {
+        match self.layout {
+            DataLayout::Flat => self.base_dir.join("state.json"),
+            DataLayout::PerForge => self.base_dir.join(platform).join("state.json"),
+        }
+    }
+
+    fn lockfiles_path(&self, platform: &str) -> PathBuf {
+        match self.layout {
+            DataLayout::Flat => self
+                .base_dir
+                .join("lockfiles")
+                .join(format!("{}.csv", platform)),
+            DataLayout::PerForge => self.base_dir.join(platform).join("lockfiles.csv"),
+        }
+    }
+
+    fn owners_path(&self, platform: &str) -> PathBuf {
+        match self.layout {
+            DataLayout::Flat => self
+                .base_dir
+                .join("owners")
+                .join(format!("{}.csv", platform)),
+            DataLayout::PerForge => self.base_dir.join(platform).join("owners.csv"),
+        }
+    }
+
+    fn growth_path(&self, platform: &str) -> PathBuf {
+        match self.layout {
+            DataLayout::Flat => self
+                .base_dir
+                .join("growth")
+                .join(format!("{}.csv", platform)),
+            DataLayout::PerForge => self.base_dir.join(platform).join("growth.csv"),
+        }
+    }
+
+    fn files_path(&self, platform: &str) -> PathBuf {
+        match self.layout {
+            DataLayout::Flat => self
+                .base_dir
+                .join("files")
+                .join(format!("{}.csv", platform)),
+            DataLayout::PerForge => self.base_dir.join(platform).join("files.csv"),
+        }
+    }
+
+    fn errors_path(&self, platform: &str) -> PathBuf {
+        match self.layout {
+            DataLayout::Flat => self
+                .base_dir
+                .join("errors")
+                .join(format!("{}.csv", platform)),
+            DataLayout::PerForge => self.base_dir.join(platform).join("errors.csv"),
+        }
+    }
+
+    fn seed_path(&self, platform: &str) -> PathBuf {
+        match self.layout {
+            DataLayout::Flat => self.base_dir.join("seed").join(format!("{}.csv", platform)),
+            DataLayout::PerForge => self.base_dir.join(platform).join("seed.csv"),
+        }
+    }
+
+    fn non_rust_path(&self, platform: &str) -> PathBuf {
+        match self.layout {
+            DataLayout::Flat => self
+                .base_dir
+                .join("non-rust")
+                .join(format!("{}.bin", platform)),
+            DataLayout::PerForge => self.base_dir.join(platform).join("non-rust.bin"),
+        }
+    }
+
+    fn coverage_path(&self, platform: &str) -> PathBuf {
+        match self.layout {
+            DataLayout::Flat => self
+                .base_dir
+                .join("coverage")
+                .join(format!("{}.bin", platform)),
+            DataLayout::PerForge => self.base_dir.join(platform).join("coverage.bin"),
+        }
+    }
+
+    pub fn sbom_dir(&self, platform: &str) -> PathBuf {
+        match self.layout {
+            DataLayout::Flat => self.base_dir.join("sbom").join(platform),
+            DataLayout::PerForge => self.base_dir.join(platform).join("sbom"),
+        }
+    }
+
+    pub fn search_dir(&self, platform: &str) -> PathBuf {
+        match self.layout {
+            DataLayout::Flat => self.base_dir.join("search").join(platform),
+            DataLayout::PerForge => self.base_dir.join(platform).join("search"),
+        }
+    }
+
+    /// Move the files of the flat layout (`<forge>.csv` and a shared `state.json`) into the
+    /// per-forge subdirectories.
+    fn migrate_to_per_forge(&self) -> Fallible<()> {
+        let flat_state_path = self.base_dir.join("state.json");
+        if !flat_state_path.exists() && self.per_forge_moves()?.is_empty() {
+            return Ok(());
+        }
+
+        // Read-only commands don't hold the lock, which is needed to move the files under the
+        // feet of the other processes
+        let _lock = if DataDirLock::is_held(&self.base_dir) {
+            None
+        } else {
+            Some(DataDirLock::acquire(&self.base_dir)?)
+        };
+
+        let flat_state = if flat_state_path.exists() {
+            Some(read_state(&flat_state_path)?)
+        } else {
+            None
+        };
+
+        for (path, new_path) in self.per_forge_moves()? {
+            if new_path.exists() {
+                bail!(
+                    "both {} and {} exist, can't migrate the data directory",
+                    path.to_string_lossy(),
+                    new_path.to_string_lossy()
+                );
+            }
+
+            info!(
+                "migrating {} to {}",
+                path.to_string_lossy(),
+                new_path.to_string_lossy()
+            );
+            fs::create_dir_all(new_path.parent().unwrap())?;
+            fs::rename(&path, &new_path)?;
+        }
+
+        if let Some(flat_state) = flat_state {
+            for (platform, forge) in flat_state.forges {
+                let mut state = State::default();
+                state.forges.insert(platform.clone(), forge);
+                write_state(&self.state_path(&platform), &state)?;
+            }
+
+            info!(
+                "migrated {} to the per-forge layout",
+                flat_state_path.to_string_lossy()
+            );
+            fs::remove_file(&flat_state_path)?;
+        }
+
+        Ok(())
+    }
+
+    /// Files of the flat layout to move, with their path in the per-forge layout. Only the files of
+    /// the known forges are moved, as other files in the data directory aren't ours to touch.
+    fn per_forge_moves(&self) -> Fallible<Vec<(PathBuf, PathBuf)>> {
+        let mut moves = Vec::new();
+        for platform in PLATFORMS {
+            let csv = format!("{}.csv", platform);
+            let bin = format!("{}.bin", platform);
+            let base = &self.base_dir;
+            let candidates = vec![
+                (base.join(&csv), self.csv_path(platform)),
+                (
+                    base.join("lockfiles").join(&csv),
+                    self.lockfiles_path(platform),
+                ),
+                (base.join("owners").join(&csv), self.owners_path(platform)),
+                (base.join("growth").join(&csv), self.growth_path(platform)),
+                (base.join("seed").join(&csv), self.seed_path(platform)),
+                (base.join("files").join(&csv), self.files_path(platform)),
+                (base.join("errors").join(&csv), self.errors_path(platform)),
+                (
+                    base.join("non-rust").join(&bin),
+                    self.non_rust_path(platform),
+                ),
+                (
+                    base.join("coverage").join(&bin),
+                    self.coverage_path(platform),
+                ),
+                (
+                    base.join("search").join(platform),
+                    self.search_dir(platform),
+                ),
+                (base.join("sbom").join(platform), self.sbom_dir(platform)),
+            ];
+            moves.extend(candidates.into_iter().filter(|(path, _)| path.exists()));
+        }
+        Ok(moves)
+    }
+
+    fn edit_state<T, F: Fn(&mut State) -> Fallible<T>>(&self, platform: &str, f: F) -> Fallible<T> {
+        Ok(self
+            .edit_state_inner(platform, f)
+            .context(ErrorKind::Storage)?)
+    }
+
+    fn edit_state_inner<T, F: Fn(&mut State) -> Fallible<T>>(
+        &self,
+        platform: &str,
+        f: F,
+    ) -> Fallible<T> {
+        let mut state_cache = self.state_cache.lock().unwrap();
+
+        let path = self.state_path(platform);
+        if !state_cache.contains_key(&path) {
+            let state = if path.exists() {
+                read_state(&path)?
+            } else {
+                State::default()
+            };
+            state_cache.insert(path.clone(), state);
+        }
 
-        let mut file = BufWriter::new(File::create(&self.state_path)?);
-        serde_json::to_writer_pretty(&mut file, &state)?;
-        file.write_all(&[b'\n'])?;
+        let state = state_cache.get_mut(&path).unwrap();
+        let result = f(state)?;
+        self.check_fence()?;
+        write_state(&path, state)?;
 
         Ok(result)
     }
 
     pub fn get_last_id(&self, platform: &str) -> Fallible<Option<usize>> {
-        self.edit_state(|state| Ok(state.last_id.get(platform).cloned()))
+        self.edit_state(platform, |state| Ok(state.forge(platform).last_id))
     }
 
+    /// Record the highest ID seen so far, ignoring IDs lower than the current one.
     pub fn set_last_id(&self, platform: &str, id: usize) -> Fallible<()> {
-        self.edit_state(|state| {
-            state.last_id.insert(platform.to_string(), id);
+        self.edit_state(platform, |state| {
+            let forge = state.forge(platform);
+            forge.last_id = Some(forge.last_id.map_or(id, |last| last.max(id)));
             Ok(())
         })
     }
 
-    pub fn store_repo(&self, platform: &str, repo: Repo) -> Fallible<()> {
-        // Ensure only one thread can write to CSV files at once
-        let _lock = self.csv_write_lock.lock().unwrap();
+    /// ID to resume scraping from: the processed ID, or the last ID for state files not
+    /// tracking it yet.
+    pub fn get_resume_id(&self, platform: &str) -> Fallible<Option<usize>> {
+        self.edit_state(platform, |state| {
+            let forge = state.forge(platform);
+            Ok(forge.processed_id.or(forge.last_id))
+        })
+    }
 
-        let file = self.base_dir.join(format!("{}.csv", platform));
+    /// Count the rows of the repositories CSV, returning the IDs of the repositories stored after
+    /// the processed ID was last saved. Those are scraped again when resuming from the processed
+    /// ID, and must be skipped not to store them twice.
+    pub fn reconcile(&self, platform: &str) -> Fallible<HashSet<String>> {
+        let state = self.forge_state(platform)?;
+        let interrupted = state.processed_id < state.last_id;
 
-        // Create the new file or append to it
-        let mut csv = if file.exists() {
-            csv::WriterBuilder::new()
+        // Only the rows after the saved progress are read when the file wasn't rewritten since
+        if let (Some(stored_rows), Some(stored_bytes)) = (state.stored_rows, state.stored_bytes) {
+            if stored_bytes <= self.csv_size(platform)? {
+                let stored = self.repos_after(platform, stored_bytes)?;
+                self.csv_rows
+                    .lock()
+                    .unwrap()
+                    .insert(platform.to_string(), stored_rows + stored.len());
+                return Ok(if interrupted {
+                    stored.into_iter().collect()
+                } else {
+                    HashSet::new()
+                });
+            }
+        }
+
+        let mut rows = 0;
+        let mut stored = HashSet::new();
+        for repo in self.repos(platform)? {
+            let repo = repo?;
+            if interrupted
+                && state
+                    .stored_rows
+                    .is_some_and(|stored_rows| rows >= stored_rows)
+            {
+                stored.insert(repo.id);
+            }
+            rows += 1;
+        }
+        self.csv_rows
+            .lock()
+            .unwrap()
+            .insert(platform.to_string(), rows);
+
+        Ok(stored)
+    }
+
+    /// IDs of the repositories in the rows of the CSV after `offset`, which must be the size the
+    /// file had at the end of a previous write.
+    fn repos_after(&self, platform: &str, offset: u64) -> Fallible<Vec<String>> {
+        let file = self.csv_path(platform);
+        let key = self.encryption_key.as_ref();
+        let inner = || -> Fallible<Vec<String>> {
+            let headers = csv::Reader::from_reader(encryption::reader(&file, key)?)
+                .byte_headers()?
+                .clone();
+            let mut tail = csv::ReaderBuilder::new()
                 .has_headers(false)
-                .from_writer(OpenOptions::new().append(true).open(&file)?)
+                .from_reader(encryption::reader_at(&file, key, offset)?);
+            let mut ids = Vec::new();
+            for record in tail.byte_records() {
+                let repo: Repo = record?.deserialize(Some(&headers))?;
+                ids.push(repo.id);
+            }
+            Ok(ids)
+        };
+        Ok(inner().context(ErrorKind::Storage)?)
+    }
+
+    /// Record that every repository up to the ID was loaded. The ID is saved in the state by the
+    /// next flush, once the repositories are written to disk.
+    pub fn set_processed_id(&self, platform: &str, id: usize) {
+        self.processed_ids
+            .lock()
+            .unwrap()
+            .insert(platform.to_string(), id);
+    }
+
+    /// Queue repositories to be loaded again, unless they're already queued.
+    pub fn add_to_retry_queue(&self, platform: &str, ids: &[String]) -> Fallible<()> {
+        self.edit_state(platform, |state| {
+            let queue = &mut state.forge(platform).retry_queue;
+            for id in ids {
+                if !queue.contains(id) {
+                    queue.push(id.clone());
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// Repositories in the retry queue of a platform. They stay queued until they're passed to
+    /// `remove_from_retry_queue`, so the ones failing again or lost by a crash are not dropped.
+    pub fn retry_queue(&self, platform: &str) -> Fallible<Vec<String>> {
+        self.edit_state(platform, |state| {
+            Ok(state.forge(platform).retry_queue.clone())
+        })
+    }
+
+    /// Record that some repositories were loaded. They're removed from the retry queue by the next
+    /// flush, once they're written to disk.
+    pub fn remove_from_retry_queue(&self, platform: &str, ids: &[String]) {
+        self.pending_retries
+            .lock()
+            .unwrap()
+            .entry(platform.to_string())
+            .or_default()
+            .extend(ids.iter().cloned());
+    }
+
+    pub fn get_export_watermark(&self, platform: &str, target: &str) -> Fallible<Option<String>> {
+        self.edit_state(platform, |state| {
+            Ok(state.forge(platform).export_watermarks.get(target).cloned())
+        })
+    }
+
+    pub fn set_export_watermark(&self, platform: &str, target: &str, time: &str) -> Fallible<()> {
+        self.edit_state(platform, |state| {
+            state
+                .forge(platform)
+                .export_watermarks
+                .insert(target.to_string(), time.to_string());
+            Ok(())
+        })
+    }
+
+    pub fn forge_state(&self, platform: &str) -> Fallible<ForgeState> {
+        self.edit_state(platform, |state| Ok(state.forge(platform).clone()))
+    }
+
+    pub fn edit_forge_state<F: Fn(&mut ForgeState)>(&self, platform: &str, f: F) -> Fallible<()> {
+        self.edit_state(platform, |state| {
+            f(state.forge(platform));
+            Ok(())
+        })
+    }
+
+    /// Record the end of a scraper run, adding its totals to the ones of the previous runs.
+    pub fn record_run(&self, platform: &str, repos_seen: u64, repos_stored: u64) -> Fallible<()> {
+        self.edit_state(platform, |state| {
+            let forge = state.forge(platform);
+            forge.counters.runs += 1;
+            forge.counters.repos_seen += repos_seen;
+            forge.counters.repos_stored += repos_stored;
+            forge.last_run_at = Some(format_timestamp(SystemTime::now()));
+            Ok(())
+        })
+    }
+
+    /// Read all the stored repositories of a platform. Rows written by older versions have the
+    /// columns they lack set to `None`.
+    pub fn repos(&self, platform: &str) -> Fallible<impl Iterator<Item = Fallible<Repo>>> {
+        let file = self.csv_path(platform);
+        let repos = if file.exists() {
+            let reader = csv::Reader::from_reader(
+                encryption::reader(&file, self.encryption_key.as_ref())
+                    .context(ErrorKind::Storage)?,
+            );
+            Some(csv_reader::deserialize(reader, self.read_threads).context(ErrorKind::Storage)?)
         } else {
-            csv::WriterBuilder::new().from_path(&file)?
+            None
         };
 
-        csv.serialize(repo)?;
+        Ok(repos
+            .into_iter()
+            .flatten()
+            .map(|repo| Ok(repo.context(ErrorKind::Storage)?)))
+    }
 
-        Ok(())
+    pub fn get_lockfile_position(&self, platform: &str) -> Fallible<usize> {
+        self.edit_state(platform, |state| {
+            Ok(state.forge(platform).lockfile_position)
+        })
+    }
+
+    pub fn set_lockfile_position(&self, platform: &str, position: usize) -> Fallible<()> {
+        self.edit_state(platform, |state| {
+            state.forge(platform).lockfile_position = position;
+            Ok(())
+        })
+    }
+
+    /// Append the packages locked by some repositories to the lockfiles CSV.
+    pub fn store_locked_packages(
+        &self,
+        platform: &str,
+        packages: &[LockedPackage],
+    ) -> Fallible<()> {
+        let mut migrated = self.csv_write_lock.lock().unwrap();
+        let mut inner = || -> Fallible<()> {
+            let file = self.lockfiles_path(platform);
+            fs::create_dir_all(file.parent().unwrap())?;
+
+            if file.exists() && !migrated.contains(&file) {
+                migrate_csv(&file, LockedPackage::COLUMNS, None)?;
+                migrated.insert(file.clone());
+            }
+
+            let exists = file.exists();
+            let mut csv = csv::WriterBuilder::new()
+                .has_headers(!exists)
+                .buffer_capacity(CSV_BUFFER_CAPACITY)
+                .from_writer(OpenOptions::new().create(true).append(true).open(&file)?);
+            for package in packages {
+                csv.serialize(package)?;
+            }
+            csv.flush()?;
+            Ok(())
+        };
+        Ok(inner().context(ErrorKind::Storage)?)
+    }
+
+    /// Read all the packages locked by the repositories of a platform.
+    pub fn locked_packages(
+        &self,
+        platform: &str,
+    ) -> Fallible<impl Iterator<Item = Fallible<LockedPackage>>> {
+        let file = self.lockfiles_path(platform);
+        let reader = if file.exists() {
+            Some(csv::Reader::from_path(&file).context(ErrorKind::Storage)?)
+        } else {
+            None
+        };
+
+        Ok(reader.into_iter().flat_map(|reader| {
+            reader
+                .into_deserialize()
+                .map(|package| Ok(package.context(ErrorKind::Storage)?))
+        }))
+    }
+
+    /// Append some owner profiles to the owners CSV.
+    pub fn store_owners(&self, platform: &str, owners: &[Owner]) -> Fallible<()> {
+        let _lock = self.csv_write_lock.lock().unwrap();
+        let inner = || -> Fallible<()> {
+            let file = self.owners_path(platform);
+            fs::create_dir_all(file.parent().unwrap())?;
+
+            let exists = file.exists();
+            let mut csv = csv::WriterBuilder::new()
+                .has_headers(!exists)
+                .from_writer(OpenOptions::new().create(true).append(true).open(&file)?);
+            for owner in owners {
+                if self.scrub_personal_data {
+                    let mut owner = owner.clone();
+                    owner.scrub();
+                    csv.serialize(owner)?;
+                } else {
+                    csv.serialize(owner)?;
+                }
+            }
+            csv.flush()?;
+            Ok(())
+        };
+        Ok(inner().context(ErrorKind::Storage)?)
+    }
+
+    /// Append a batch of repositories that failed to load to the errors CSV.
+    pub fn store_error(
+        &self,
+        platform: &str,
+        category: &str,
+        ids: &[String],
+        error: &str,
+    ) -> Fallible<()> {
+        let _lock = self.csv_write_lock.lock().unwrap();
+        let inner = || -> Fallible<()> {
+            let file = self.errors_path(platform);
+            fs::create_dir_all(file.parent().unwrap())?;
+
+            let exists = file.exists();
+            let mut csv = csv::WriterBuilder::new()
+                .has_headers(!exists)
+                .from_writer(OpenOptions::new().create(true).append(true).open(&file)?);
+            csv.serialize(ErrorRow {
+                timestamp: format_timestamp(SystemTime::now()),
+                category: category.to_string(),
+                ids: ids.join(" "),
+                error: error.to_string(),
+            })?;
+            csv.flush()?;
+            Ok(())
+        };
+        Ok(inner().context(ErrorKind::Storage)?)
+    }
+
+    /// Read the fetched owner profiles, keeping only the most recent one for each account.
+    /// Profiles stored before personal data was scrubbed are scrubbed when read, and on disk by
+    /// the next compaction.
+    pub fn owners(&self, platform: &str) -> Fallible<HashMap<String, Owner>> {
+        let file = self.owners_path(platform);
+        let mut owners = HashMap::new();
+        if file.exists() {
+            let reader = csv::Reader::from_path(&file).context(ErrorKind::Storage)?;
+            for owner in reader.into_deserialize() {
+                let mut owner: Owner = owner.context(ErrorKind::Storage)?;
+                if self.scrub_personal_data {
+                    owner.scrub();
+                }
+                owners.insert(owner.login.to_lowercase(), owner);
+            }
+        }
+        Ok(owners)
+    }
+
+    /// Replace the repositories known not to use Rust, returning how many were stored.
+    pub fn store_seed<I>(&self, platform: &str, repos: I) -> Fallible<usize>
+    where
+        I: IntoIterator<Item = Fallible<SeededRepo>>,
+    {
+        let file = self.seed_path(platform);
+        fs::create_dir_all(file.parent().unwrap()).context(ErrorKind::Storage)?;
+
+        let mut count = 0;
+        write_atomically(&file, |writer| {
+            let mut csv = csv::Writer::from_writer(writer);
+            for repo in repos {
+                csv.serialize(repo?)?;
+                count += 1;
+            }
+            csv.flush()?;
+            Ok(())
+        })?;
+        Ok(count)
+    }
+
+    /// Read the repositories known not to use Rust, if a seed was imported.
+    pub fn seed(
+        &self,
+        platform: &str,
+    ) -> Fallible<Option<impl Iterator<Item = Fallible<SeededRepo>>>> {
+        let file = self.seed_path(platform);
+        if !file.exists() {
+            return Ok(None);
+        }
+        let reader = csv::Reader::from_path(&file).context(ErrorKind::Storage)?;
+        Ok(Some(
+            reader
+                .into_deserialize()
+                .map(|repo| Ok(repo.context(ErrorKind::Storage)?)),
+        ))
+    }
+
+    /// Read the packages locked by each repository, grouped by repository.
+    pub fn lockfiles(
+        &self,
+        platform: &str,
+    ) -> Fallible<impl Iterator<Item = Fallible<(String, Vec<LockedPackage>)>>> {
+        // The packages of each repository are stored next to each other
+        let mut packages = self.locked_packages(platform)?.peekable();
+        Ok(::std::iter::from_fn(move || {
+            let first = match packages.next()? {
+                Ok(first) => first,
+                Err(err) => return Some(Err(err)),
+            };
+            let repo_id = first.repo_id.clone();
+            let mut group = vec![first];
+            while let Some(Ok(next)) = packages.peek() {
+                if next.repo_id != repo_id {
+                    break;
+                }
+                group.push(packages.next().unwrap().unwrap());
+            }
+            Some(Ok((repo_id, group)))
+        }))
+    }
+
+    /// Pick a reproducible random sample of up to `size` repositories. The stored data is read
+    /// twice instead of being loaded in memory: first to count the repositories in each stratum
+    /// returned by `stratum`, and then to sample each of them proportionally to its size.
+    pub fn sample<F>(
+        &self,
+        platform: &str,
+        size: usize,
+        seed: u64,
+        stratum: F,
+    ) -> Fallible<Vec<Repo>>
+    where
+        F: Fn(&Repo) -> String,
+    {
+        let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+        for repo in self.repos(platform)? {
+            *counts.entry(stratum(&repo?)).or_default() += 1;
+        }
+        let total = counts.values().sum::<usize>();
+        if total == 0 {
+            return Ok(Vec::new());
+        }
+
+        // Allocate the sample with the largest remainder method, so the quotas add up to the
+        // requested size
+        let size = size.min(total);
+        let mut quotas = counts
+            .iter()
+            .map(|(key, count)| (key.clone(), count * size / total))
+            .collect::<BTreeMap<_, _>>();
+        let mut remainders = counts
+            .iter()
+            .map(|(key, count)| (count * size % total, key.clone()))
+            .collect::<Vec<_>>();
+        remainders.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+        let missing = size - quotas.values().sum::<usize>();
+        for (_, key) in remainders.into_iter().take(missing) {
+            *quotas.get_mut(&key).unwrap() += 1;
+        }
+
+        // Reservoir sampling inside each stratum
+        let mut rng = Rng::new(seed);
+        let mut reservoirs: HashMap<String, (usize, Vec<(usize, Repo)>)> = HashMap::new();
+        for (position, repo) in self.repos(platform)?.enumerate() {
+            let repo = repo?;
+            let key = stratum(&repo);
+            // A scraper running at the same time can append repositories in a stratum that
+            // didn't exist during the first pass, which are never sampled
+            let quota = quotas.get(&key).copied().unwrap_or(0);
+            let (seen, reservoir) = reservoirs.entry(key).or_default();
+
+            if reservoir.len() < quota {
+                reservoir.push((position, repo));
+            } else {
+                let slot = rng.below(*seen + 1);
+                if slot < quota {
+                    reservoir[slot] = (position, repo);
+                }
+            }
+            *seen += 1;
+        }
+
+        let mut sample = reservoirs
+            .into_iter()
+            .flat_map(|(_, (_, reservoir))| reservoir)
+            .collect::<Vec<_>>();
+        sample.sort_by_key(|(position, _)| *position);
+        Ok(sample.into_iter().map(|(_, repo)| repo).collect())
+    }
+
+    /// Every version of the record of each repository, in the order they were first stored.
+    /// Records only differing by when they were checked are the same version.
+    pub fn versions(&self, platform: &str) -> Fallible<Vec<RepoVersion>> {
+        let (_, versions) = self.positioned_versions(platform)?;
+        Ok(versions.into_iter().map(|(_, version)| version).collect())
+    }
+
+    /// The latest version of each repository stored at `timestamp` (an RFC 3339 timestamp in
+    /// UTC), which must have been checked by then.
+    pub fn as_of(&self, platform: &str, timestamp: &str) -> Fallible<Vec<RepoVersion>> {
+        Ok(self
+            .versions(platform)?
+            .into_iter()
+            .filter(|version| version.is_valid_at(timestamp))
+            .collect())
+    }
+
+    /// The versions of the repositories with the position of their first row, and the total
+    /// number of rows.
+    fn positioned_versions(&self, platform: &str) -> Fallible<(usize, Vec<(usize, RepoVersion)>)> {
+        let mut by_id: HashMap<String, Vec<(usize, RepoVersion)>> = HashMap::new();
+        let mut rows = 0;
+        for (position, repo) in self.repos(platform)?.enumerate() {
+            let repo = repo?;
+            rows += 1;
+            let versions = by_id.entry(repo.id.clone()).or_default();
+            if let Some((_, previous)) = versions.last_mut() {
+                if previous.repo.same_content(&repo) {
+                    continue;
+                }
+                previous.valid_to = repo.last_checked_at.clone();
+            }
+            versions.push((
+                position,
+                RepoVersion {
+                    valid_from: repo.last_checked_at.clone(),
+                    valid_to: None,
+                    repo,
+                },
+            ));
+        }
+
+        let mut versions = by_id.into_values().flatten().collect::<Vec<_>>();
+        versions.sort_by_key(|(position, _)| *position);
+        Ok((rows, versions))
+    }
+
+    /// Rewrite the CSV files of a platform keeping only the latest record of each repository
+    /// (and of each owner), optionally sorting them by ID. Returns the number of rows removed.
+    ///
+    /// When history is kept, every version of the records is kept instead, and only the rows
+    /// checking again a repository that didn't change are removed.
+    pub fn compact(&self, platform: &str, sort: bool) -> Fallible<usize> {
+        self.flush()?;
+        let _lock = self.csv_write_lock.lock().unwrap();
+        let inner = || -> Fallible<usize> {
+            let mut removed = 0;
+
+            let file = self.csv_path(platform);
+            if file.exists() {
+                migrate_csv(&file, &Repo::columns(), self.encryption_key.as_ref())?;
+                let lockfile_position = self.get_lockfile_position(platform)?;
+
+                let (rows, mut repos) = if self.keep_history {
+                    let (rows, versions) = self.positioned_versions(platform)?;
+                    let repos = versions
+                        .into_iter()
+                        .map(|(position, version)| (position, version.repo))
+                        .collect::<Vec<_>>();
+                    (rows, repos)
+                } else {
+                    // Keep the position of the last record of each repository
+                    let mut latest = HashMap::new();
+                    let mut rows = 0;
+                    for (position, repo) in self.repos(platform)?.enumerate() {
+                        let repo = repo?;
+                        latest.insert(repo.id.clone(), (position, repo));
+                        rows += 1;
+                    }
+                    (rows, latest.into_values().collect::<Vec<_>>())
+                };
+                // The sort is stable, keeping the versions of each repository in order
+                if sort {
+                    repos.sort_by(|a, b| a.1.id.cmp(&b.1.id));
+                } else {
+                    repos.sort_by_key(|(position, _)| *position);
+                }
+                removed += rows - repos.len();
+
+                write_atomically(&file, |out| {
+                    let out = encryption::Writer::new(out, self.encryption_key.as_ref())?;
+                    let mut csv = csv::Writer::from_writer(out);
+                    csv.write_record(Repo::columns())?;
+                    let mut record =
+                        csv::ByteRecord::with_capacity(RECORD_CAPACITY, Repo::schema().len());
+                    for (_, repo) in &repos {
+                        repo.write_record(&mut record);
+                        csv.write_byte_record(&record)?;
+                    }
+                    finish_csv(csv)?;
+                    Ok(())
+                })?;
+
+                // Lockfiles are fetched in the order of the rows, so the rows already processed
+                // must still be a prefix of the file: the first unprocessed row ends it, and the
+                // following ones will be fetched again.
+                let processed = repos
+                    .iter()
+                    .take_while(|(position, _)| *position < lockfile_position)
+                    .count();
+                self.set_lockfile_position(platform, processed)?;
+
+                // Same for the rows stored when the processed ID was saved
+                let stored_rows = self.forge_state(platform)?.stored_rows;
+                let stored_rows = stored_rows.filter(|_| !sort).map(|stored_rows| {
+                    repos
+                        .iter()
+                        .take_while(|(position, _)| *position < stored_rows)
+                        .count()
+                });
+                // The offset of the stored rows is only known if no row after them was kept
+                let stored_bytes = match stored_rows {
+                    Some(stored_rows) if stored_rows == repos.len() => {
+                        Some(self.csv_size(platform)?)
+                    }
+                    _ => None,
+                };
+                self.edit_forge_state(platform, |state| {
+                    state.stored_rows = stored_rows;
+                    state.stored_bytes = stored_bytes;
+                })?;
+                if let Some(rows) = self.csv_rows.lock().unwrap().get_mut(platform) {
+                    *rows = repos.len();
+                }
+            }
+
+            let file = self.lockfiles_path(platform);
+            if file.exists() {
+                migrate_csv(&file, LockedPackage::COLUMNS, None)?;
+                let mut latest: HashMap<String, (usize, Vec<LockedPackage>)> = HashMap::new();
+                let mut rows = 0;
+                for (position, group) in self.lockfiles(platform)?.enumerate() {
+                    let (repo_id, mut packages) = group?;
+                    rows += packages.len();
+                    // Fetching the same lockfile twice in a row results in a single group
+                    let mut seen = HashSet::new();
+                    packages.retain(|package| {
+                        seen.insert((
+                            package.name.clone(),
+                            package.version.clone(),
+                            package.source.clone(),
+                        ))
+                    });
+                    latest.insert(repo_id, (position, packages));
+                }
+                let mut groups = latest.into_iter().collect::<Vec<_>>();
+                if sort {
+                    groups.sort_by(|a, b| a.0.cmp(&b.0));
+                } else {
+                    groups.sort_by_key(|(_, (position, _))| *position);
+                }
+                removed += rows - groups.iter().map(|(_, (_, p))| p.len()).sum::<usize>();
+
+                write_atomically(&file, |out| {
+                    let mut csv = csv::Writer::from_writer(out);
+                    for (_, (_, packages)) in &groups {
+                        for package in packages {
+                            csv.serialize(package)?;
+                        }
+                    }
+                    csv.flush()?;
+                    Ok(())
+                })?;
+            }
+
+            let file = self.owners_path(platform);
+            if file.exists() {
+                let rows = csv::Reader::from_path(&file)?.records().count();
+                let mut owners = self.owners(platform)?.into_values().collect::<Vec<_>>();
+                owners.sort_by(|a, b| a.login.cmp(&b.login));
+                removed += rows - owners.len();
+
+                write_atomically(&file, |out| {
+                    let mut csv = csv::Writer::from_writer(out);
+                    for owner in &owners {
+                        csv.serialize(owner)?;
+                    }
+                    csv.flush()?;
+                    Ok(())
+                })?;
+            }
+
+            Ok(removed)
+        };
+        Ok(inner().context(ErrorKind::Storage)?)
+    }
+
+    /// Size in bytes of the CSV file of a platform, or zero if it doesn't exist yet.
+    pub fn csv_size(&self, platform: &str) -> Fallible<u64> {
+        let file = self.csv_path(platform);
+        if file.exists() {
+            Ok(fs::metadata(&file).context(ErrorKind::Storage)?.len())
+        } else {
+            Ok(0)
+        }
+    }
+
+    /// Write the CSV file of a platform in plaintext, decrypting it if it's encrypted.
+    pub fn decrypt_repos(&self, platform: &str, output: &mut dyn Write) -> Fallible<()> {
+        let file = self.csv_path(platform);
+        if !file.exists() {
+            bail!("no repositories stored in {}", file.display());
+        }
+        Ok(
+            encryption::decrypt(&file, self.encryption_key.as_ref(), output)
+                .context(ErrorKind::Storage)?,
+        )
+    }
+
+    /// Queue a batch of repositories to be written by the storage task of the platform, which
+    /// buffers them and writes the buffer to disk once full. Storing blocks while the queue is
+    /// full, so the scraper slows down when writing lags behind.
+    pub fn store_repos(&self, platform: &str, repos: Vec<Repo>) -> Fallible<()> {
+        if repos.is_empty() {
+            return Ok(());
+        }
+        self.send_to_storage(platform, StorageMessage::Store(repos))
+    }
+
+    /// Send a message to the storage task of a platform, starting it if needed. The task only
+    /// stops after failing to write, and its error is then returned.
+    fn send_to_storage(&self, platform: &str, message: StorageMessage) -> Fallible<()> {
+        let (sender, error) = {
+            let mut tasks = self.storage_tasks.0.lock().unwrap();
+            let task = tasks
+                .entry(platform.to_string())
+                .or_insert_with(|| self.spawn_storage_task(platform));
+            (task.sender.clone(), task.error.clone())
+        };
+
+        if sender.send(message).is_err() {
+            self.storage_tasks.0.lock().unwrap().remove(platform);
+            return Err(match error.lock().unwrap().take() {
+                Some(err) => err,
+                None => err_msg("the storage task stopped unexpectedly"),
+            }
+            .context(ErrorKind::Storage)
+            .into());
+        }
+        Ok(())
+    }
+
+    fn spawn_storage_task(&self, platform: &str) -> StorageTask {
+        let (sender, receiver) = sync_channel(STORAGE_QUEUE_CAPACITY);
+        let error = Arc::new(Mutex::new(None));
+
+        // The task must not own its own sender, or its queue would never be closed
+        let mut data = self.clone();
+        data.storage_tasks = Arc::new(StorageTasks::default());
+        let platform = platform.to_string();
+        let task_error = error.clone();
+        let thread = thread::spawn(move || {
+            let mut buffer = Vec::with_capacity(BUFFER_CAPACITY);
+            for message in receiver {
+                let (result, reply) = match message {
+                    StorageMessage::Store(repos) => {
+                        buffer.extend(repos);
+                        if buffer.len() < BUFFER_CAPACITY {
+                            continue;
+                        }
+                        let repos = mem::replace(&mut buffer, Vec::with_capacity(BUFFER_CAPACITY));
+                        (data.write_csv(&platform, repos), None)
+                    }
+                    StorageMessage::Flush(reply) => {
+                        let result = if buffer.is_empty() {
+                            Ok(())
+                        } else {
+                            data.write_csv(&platform, mem::take(&mut buffer))
+                        };
+                        (result, Some(reply))
+                    }
+                };
+
+                match (result, reply) {
+                    (result, Some(reply)) => {
+                        let failed = result.is_err();
+                        let _ = reply.send(result);
+                        if failed {
+                            return;
+                        }
+                    }
+                    (Ok(()), None) => {}
+                    (Err(err), None) => {
+                        *task_error.lock().unwrap() = Some(err);
+                        return;
+                    }
+                }
+            }
+
+            // The queue is closed once `Data` is dropped, possibly without flushing: the
+            // repositories are still written, but the progress isn't saved and they will be
+            // loaded again by the next run
+            if !buffer.is_empty() {
+                warn!(
+                    "writing {} repositories that were not flushed",
+                    buffer.len()
+                );
+                if let Err(err) = data.write_csv(&platform, buffer) {
+                    log_error(&err);
+                }
+            }
+        });
+
+        StorageTask {
+            sender,
+            error,
+            thread,
+        }
+    }
+
+    fn with_id_set<T, F>(&self, path: &Path, f: F) -> Fallible<T>
+    where
+        F: FnOnce(&mut CachedIdSet) -> Fallible<T>,
+    {
+        let inner = || -> Fallible<T> {
+            let mut caches = self.id_sets.lock().unwrap();
+            if !caches.contains_key(path) {
+                let ids = if path.exists() {
+                    let mut file = BufReader::new(File::open(path)?);
+                    IdSet::read_from(&mut file)
+                        .with_context(|_| format!("failed to read {}", path.display()))?
+                } else {
+                    IdSet::default()
+                };
+                debug!("loaded {} IDs from {}", ids.len(), path.display());
+                caches.insert(path.to_path_buf(), CachedIdSet { ids, unsaved: 0 });
+            }
+            f(caches.get_mut(path).unwrap())
+        };
+        Ok(inner().context(ErrorKind::Storage)?)
+    }
+
+    /// Whether a repository was already found not to use Rust, or to be a fork.
+    pub fn is_known_non_rust(&self, platform: &str, id: u64) -> Fallible<bool> {
+        self.with_id_set(&self.non_rust_path(platform), |cache| {
+            Ok(cache.ids.contains(id))
+        })
+    }
+
+    /// Remember that some repositories don't use Rust or are forks, so they are not loaded again.
+    pub fn record_non_rust(&self, platform: &str, ids: &[u64]) -> Fallible<()> {
+        let path = self.non_rust_path(platform);
+        self.with_id_set(&path, |cache| {
+            for id in ids {
+                if cache.ids.insert(*id) {
+                    cache.unsaved += 1;
+                }
+            }
+            if cache.unsaved >= NON_RUST_SAVE_INTERVAL {
+                cache.save(&path)?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Record that every repository between `start` and `end` (inclusive) was loaded. The range
+    /// is saved in the coverage by the next flush, once the repositories are written to disk.
+    pub fn record_coverage(&self, platform: &str, start: u64, end: u64) {
+        self.pending_coverage
+            .lock()
+            .unwrap()
+            .entry(platform.to_string())
+            .or_default()
+            .push((start, end));
+    }
+
+    /// Ranges of the ID space that were scraped, as (first, last).
+    pub fn coverage(&self, platform: &str) -> Fallible<Vec<(u64, u64)>> {
+        self.with_id_set(&self.coverage_path(platform), |cache| {
+            Ok(cache.ids.ranges())
+        })
+    }
+
+    /// Ranges of the ID space below the highest scraped ID that were not scraped.
+    pub fn coverage_gaps(&self, platform: &str) -> Fallible<Vec<(u64, u64)>> {
+        self.with_id_set(&self.coverage_path(platform), |cache| Ok(cache.ids.gaps(1)))
+    }
+
+    /// Whether writes are paused until more disk space is available.
+    pub fn is_low_on_disk_space(&self) -> bool {
+        self.low_disk_space.load(Ordering::SeqCst)
+    }
+
+    /// Block until the filesystem of the data directory has at least the configured free space,
+    /// so that writes don't fail halfway through and leave truncated files behind.
+    fn wait_for_disk_space(&self) -> Fallible<()> {
+        if self.min_free_space == 0 {
+            return Ok(());
+        }
+
+        // The data directory might not be created yet
+        let mut dir = self.base_dir.as_path();
+        while !dir.exists() {
+            dir = match dir.parent() {
+                Some(parent) if parent != Path::new("") => parent,
+                _ => Path::new("."),
+            };
+        }
+
+        loop {
+            let free = match free_space(dir).context(ErrorKind::Storage)? {
+                Some(free) => free,
+                None => return Ok(()),
+            };
+            if free >= self.min_free_space {
+                if self.low_disk_space.swap(false, Ordering::SeqCst) {
+                    info!("{} bytes free in the data directory, resuming writes", free);
+                }
+                return Ok(());
+            }
+
+            if !self.low_disk_space.swap(true, Ordering::SeqCst) {
+                error!(
+                    "only {} bytes free in the data directory (RUST_REPOS_MIN_FREE_SPACE is {}), \
+                     pausing writes until space is freed",
+                    free, self.min_free_space
+                );
+            }
+            thread::sleep(DISK_SPACE_RECHECK_INTERVAL);
+        }
+    }
+
+    /// Write all the buffered repositories to disk, waiting for enough free disk space first.
+    pub fn flush(&self) -> Fallible<()> {
+        self.check_fence()?;
+        self.wait_for_disk_space()?;
+
+        let platforms = self
+            .storage_tasks
+            .0
+            .lock()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+        for platform in platforms {
+            let (reply, result) = sync_channel(1);
+            self.send_to_storage(&platform, StorageMessage::Flush(reply))?;
+            match result.recv() {
+                Ok(result) => result.context(ErrorKind::Storage)?,
+                Err(_) => bail!("the storage task stopped unexpectedly"),
+            }
+        }
+
+        let pending_coverage = mem::take(&mut *self.pending_coverage.lock().unwrap());
+        for (platform, ranges) in pending_coverage {
+            self.with_id_set(&self.coverage_path(&platform), |cache| {
+                for (start, end) in ranges {
+                    cache.ids.insert_range(start, end);
+                }
+                cache.unsaved += 1;
+                Ok(())
+            })?;
+        }
+
+        for (path, cache) in self.id_sets.lock().unwrap().iter_mut() {
+            if cache.unsaved > 0 {
+                cache.save(path).context(ErrorKind::Storage)?;
+            }
+        }
+
+        let pending_retries = mem::take(&mut *self.pending_retries.lock().unwrap());
+        for (platform, loaded) in pending_retries {
+            self.edit_state(&platform, |state| {
+                let queue = &mut state.forge(&platform).retry_queue;
+                if queue.iter().any(|id| loaded.contains(id)) {
+                    queue.retain(|id| !loaded.contains(id));
+                }
+                Ok(())
+            })?;
+        }
+
+        let processed_ids = mem::take(&mut *self.processed_ids.lock().unwrap());
+        for (platform, id) in processed_ids {
+            let rows = self.csv_rows.lock().unwrap().get(&platform).cloned();
+            let bytes = match rows {
+                Some(_) => Some(self.csv_size(&platform)?),
+                None => None,
+            };
+            self.edit_state(&platform, |state| {
+                let forge = state.forge(&platform);
+                forge.processed_id = Some(id);
+                forge.stored_rows = rows;
+                forge.stored_bytes = bytes;
+                Ok(())
+            })?;
+        }
+
+        Ok(())
+    }
+
+    fn write_csv(&self, platform: &str, repos: Vec<Repo>) -> Fallible<()> {
+        // Ensure only one thread can write to CSV files at once
+        let mut migrated = self.csv_write_lock.lock().unwrap();
+        self.check_fence()?;
+
+        let file = self.csv_path(platform);
+        if let Some(parent) = file.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        // Files created by older versions might lack some columns, or not be encrypted yet
+        if file.exists() && !migrated.contains(&file) {
+            if migrate_csv(&file, &Repo::columns(), self.encryption_key.as_ref())? {
+                self.edit_forge_state(platform, |state| state.stored_bytes = None)?;
+            }
+            migrated.insert(file.clone());
+        }
+
+        // Create the new file or append to it
+        let exists = file.exists();
+        let key = self.encryption_key.as_ref();
+        let out = OpenOptions::new().create(true).append(true).open(&file)?;
+        let out = if exists {
+            encryption::Writer::append(out, key, &file)?
+        } else {
+            encryption::Writer::new(out, key)?
+        };
+        let mut csv = csv::WriterBuilder::new()
+            .buffer_capacity(CSV_BUFFER_CAPACITY)
+            .from_writer(out);
+        if !exists {
+            csv.write_record(Repo::columns())?;
+        }
+
+        let mut record = csv::ByteRecord::with_capacity(RECORD_CAPACITY, Repo::schema().len());
+        for repo in &repos {
+            repo.write_record(&mut record);
+            csv.write_byte_record(&record)?;
+        }
+        finish_csv(csv)?;
+        if let Some(rows) = self.csv_rows.lock().unwrap().get_mut(platform) {
+            *rows += repos.len();
+        }
+
+        if !self.extra_files.is_empty() {
+            self.write_files_csv(platform, &repos, &mut migrated)?;
+        }
+
+        if self.search_index {
+            search::index_repos(&self.search_dir(platform), &repos)?;
+        }
+
+        self.record_growth(platform)?;
+
+        Ok(())
+    }
+
+    /// Append the presence of the extra files of each repository to their own CSV, which has a
+    /// column for each of the configured files.
+    fn write_files_csv(
+        &self,
+        platform: &str,
+        repos: &[Repo],
+        migrated: &mut HashSet<PathBuf>,
+    ) -> Fallible<()> {
+        let file = self.files_path(platform);
+        fs::create_dir_all(file.parent().unwrap())?;
+
+        let mut columns = vec!["id".to_string()];
+        columns.extend(self.extra_files.iter().map(|name| file_column(name)));
+        let columns = columns.iter().map(String::as_str).collect::<Vec<_>>();
+
+        // The configured files might have changed since the previous run
+        if file.exists() && !migrated.contains(&file) {
+            migrate_csv(&file, &columns, None)?;
+            migrated.insert(file.clone());
+        }
+
+        let exists = file.exists();
+        let mut csv = csv::WriterBuilder::new()
+            .from_writer(OpenOptions::new().create(true).append(true).open(&file)?);
+        if !exists {
+            csv.write_record(&columns)?;
+        }
+
+        let mut record = csv::ByteRecord::with_capacity(RECORD_CAPACITY, columns.len());
+        for repo in repos.iter().filter(|repo| !repo.extra_files.is_empty()) {
+            record.clear();
+            record.push_field(repo.id.as_bytes());
+            for &present in &repo.extra_files {
+                record.push_field(optional_bool_field(present));
+            }
+            csv.write_byte_record(&record)?;
+        }
+        csv.flush()?;
+        Ok(())
+    }
+
+    /// Append today's totals to the growth CSV, if they weren't recorded yet. This is called
+    /// after every write to the repositories CSV, but only scans it once a day.
+    fn record_growth(&self, platform: &str) -> Fallible<()> {
+        let today = format_timestamp(SystemTime::now())[..10].to_string();
+        let mut dates = self.growth_dates.lock().unwrap();
+        if dates.get(platform) == Some(&today) {
+            return Ok(());
+        }
+
+        let file = self.growth_path(platform);
+        if !dates.contains_key(platform) {
+            if let Some(last) = self.growth(platform)?.pop() {
+                dates.insert(platform.to_string(), last.date.clone());
+                if last.date == today {
+                    return Ok(());
+                }
+            }
+        }
+
+        // Repositories checked again are appended multiple times, only their last row counts
+        let mut latest = HashMap::new();
+        for repo in self.repos(platform)? {
+            let repo = repo?;
+            let mut hasher = DefaultHasher::new();
+            repo.id.hash(&mut hasher);
+            latest.insert(hasher.finish(), (repo.has_cargo_toml, repo.has_cargo_lock));
+        }
+        let row = GrowthRow {
+            date: today.clone(),
+            known_repos: self.get_last_id(platform)?.unwrap_or(0),
+            rust_repos: latest.len(),
+            with_cargo_toml: latest.values().filter(|(toml, _)| *toml).count(),
+            with_cargo_lock: latest.values().filter(|(_, lock)| *lock).count(),
+        };
+
+        fs::create_dir_all(file.parent().unwrap())?;
+        let exists = file.exists();
+        let mut csv = csv::WriterBuilder::new()
+            .has_headers(!exists)
+            .from_writer(OpenOptions::new().create(true).append(true).open(&file)?);
+        csv.serialize(&row)?;
+        csv.flush()?;
+
+        dates.insert(platform.to_string(), today);
+        Ok(())
+    }
+
+    /// Read the daily totals recorded for a platform, oldest first.
+    pub fn growth(&self, platform: &str) -> Fallible<Vec<GrowthRow>> {
+        let file = self.growth_path(platform);
+        if !file.exists() {
+            return Ok(Vec::new());
+        }
+        let mut rows = Vec::new();
+        for row in csv::Reader::from_path(&file)?.into_deserialize() {
+            rows.push(row?);
+        }
+        Ok(rows)
+    }
+}
+
+/// Read a `state.json` file, migrating it from the older formats.
+fn read_state(path: &Path) -> Fallible<State> {
+    let content: serde_json::Value = serde_json::from_slice(&fs::read(path)?)?;
+    let version = match content.get("schema_version") {
+        Some(version) => version
+            .as_u64()
+            .ok_or_else(|| err_msg("invalid schema_version in the state file"))?,
+        None => 1,
+    };
+
+    match version {
+        1 => {
+            info!(
+                "migrating {} to schema version {}",
+                path.to_string_lossy(),
+                STATE_SCHEMA_VERSION
+            );
+            Ok(serde_json::from_value::<StateV1>(content)?.migrate())
+        }
+        2 => Ok(serde_json::from_value(content)?),
+        other => bail!(
+            "{} has schema version {}, which is newer than the supported one",
+            path.to_string_lossy(),
+            other
+        ),
+    }
+}
+
+fn write_state(path: &Path, state: &State) -> Fallible<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    write_atomically(path, |file| {
+        serde_json::to_writer_pretty(&mut *file, state)?;
+        file.write_all(b"\n")?;
+        Ok(())
+    })
+}
+
+/// Space in bytes available to unprivileged users on the filesystem containing `path`.
+#[cfg(unix)]
+fn free_space(path: &Path) -> Fallible<Option<u64>> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    let mut stats: libc::statvfs = unsafe { mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stats) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(Some(stats.f_bavail as u64 * stats.f_frsize as u64))
+}
+
+/// Free space is not checked on other platforms.
+#[cfg(not(unix))]
+fn free_space(_path: &Path) -> Fallible<Option<u64>> {
+    Ok(None)
+}
+
+/// Write a file by writing a temporary file next to it and then renaming it over the original,
+/// so a crash never leaves a partially written file behind.
+///
+/// The temporary file is closed before being renamed, and the original file must not be open
+/// either: Windows doesn't allow renaming over open files.
+pub fn write_atomically<F>(path: &Path, f: F) -> Fallible<()>
+where
+    F: FnOnce(&mut BufWriter<File>) -> Fallible<()>,
+{
+    let tmp = tmp_path(path);
+
+    let result = File::create(&tmp)
+        .map_err(Error::from)
+        .and_then(|file| {
+            let mut writer = BufWriter::new(file);
+            f(&mut writer)?;
+            writer.flush()?;
+            writer.get_ref().sync_all()?;
+            Ok(())
+        })
+        .and_then(|()| Ok(fs::rename(&tmp, path)?));
+
+    if result.is_err() && tmp.exists() {
+        let _ = fs::remove_file(&tmp);
+    }
+    result
+}
+
+fn tmp_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".tmp");
+    path.with_file_name(name)
+}
+
+/// Flush a CSV writer and write the last encrypted frame, returning the underlying writer.
+fn finish_csv<W: Write>(mut csv: csv::Writer<encryption::Writer<W>>) -> Fallible<W> {
+    csv.flush()?;
+    csv.into_inner()
+        .map_err(|err| format_err!("{}", err.error()))?
+        .finish()
+}
+
+/// Rewrite the CSV file if its header doesn't match the provided set of columns, filling the
+/// missing columns with empty values, or if it's not encrypted while a key is provided. Returns
+/// whether the file was rewritten.
+fn migrate_csv(file: &Path, columns: &[&str], key: Option<&Key>) -> Fallible<bool> {
+    // Plaintext files are rejected when a key is provided, except here to encrypt them
+    let encrypt = key.is_some() && !encryption::is_encrypted(file)?;
+    let read_key = if encrypt { None } else { key };
+    let mut reader = csv::Reader::from_reader(encryption::reader(file, read_key)?);
+    let headers = reader.headers()?.clone();
+    if headers.iter().eq(columns.iter().cloned()) && !encrypt {
+        return Ok(false);
+    }
+
+    if encrypt {
+        info!("encrypting {}", file.to_string_lossy());
+    } else {
+        info!(
+            "migrating {} to the current set of columns",
+            file.to_string_lossy()
+        );
+    }
+
+    let positions = columns
+        .iter()
+        .map(|column| headers.iter().position(|header| header == *column))
+        .collect::<Vec<_>>();
+
+    let tmp = tmp_path(file);
+    let mut writer = csv::Writer::from_writer(encryption::Writer::new(File::create(&tmp)?, key)?);
+    writer.write_record(columns)?;
+    for record in reader.records() {
+        let record = record?;
+        writer.write_record(
+            positions
+                .iter()
+                .map(|pos| pos.and_then(|pos| record.get(pos)).unwrap_or("")),
+        )?;
+    }
+    let writer = finish_csv(writer)?;
+
+    // Both files must be closed before the rename, as Windows doesn't allow renaming open files
+    ::std::mem::drop(writer);
+    ::std::mem::drop(reader);
+
+    fs::rename(&tmp, file)?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ColumnType, Data, Repo, CSV_BUFFER_CAPACITY, SCHEMA};
+    use config::{Config, DataLayout};
+    use csv;
+    use std::cell::Cell;
+    use std::fs;
+    use std::time::Instant;
+
+    /// A row with a different value in each column, so swapped columns don't go unnoticed, or
+    /// with the optional columns left empty.
+    fn row(optional: bool) -> csv::ByteRecord {
+        let fields = SCHEMA
+            .iter()
+            .enumerate()
+            .map(|(i, column)| match column.kind {
+                ColumnType::String => format!("value {}", i),
+                ColumnType::Bool => i.is_multiple_of(2).to_string(),
+                _ if !optional => String::new(),
+                ColumnType::OptionalString => format!("value {}", i),
+                ColumnType::OptionalBool => i.is_multiple_of(2).to_string(),
+                ColumnType::OptionalTimestamp => format!("2020-01-{:02}T00:00:00Z", i),
+                ColumnType::OptionalInteger => i.to_string(),
+            });
+        csv::ByteRecord::from(fields.collect::<Vec<_>>())
+    }
+
+    /// Compare how `write_csv` wrote the rows before (with serde and the default buffer) and now
+    /// (with a reused record and a bigger buffer). Run it with
+    /// `cargo test --release -- --ignored --nocapture bench_write_record`.
+    #[test]
+    #[ignore]
+    fn bench_write_record() {
+        let columns = Repo::columns();
+        let repos = (0..500_000)
+            .map(|i| {
+                row(i % 2 == 0)
+                    .deserialize::<Repo>(Some(&csv::ByteRecord::from(columns.clone())))
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+        let path = ::std::env::temp_dir().join(format!(
+            "rust-repos-data-{}-bench.csv",
+            ::std::process::id()
+        ));
+
+        let start = Instant::now();
+        let mut writer = csv::Writer::from_path(&path).unwrap();
+        for repo in &repos {
+            writer.serialize(repo).unwrap();
+        }
+        writer.flush().unwrap();
+        let before = start.elapsed();
+
+        let start = Instant::now();
+        let mut writer = csv::WriterBuilder::new()
+            .buffer_capacity(CSV_BUFFER_CAPACITY)
+            .from_path(&path)
+            .unwrap();
+        let mut record = csv::ByteRecord::new();
+        for repo in &repos {
+            repo.write_record(&mut record);
+            writer.write_byte_record(&record).unwrap();
+        }
+        writer.flush().unwrap();
+        let after = start.elapsed();
+
+        fs::remove_file(&path).unwrap();
+        println!(
+            "{} rows: {:?} before, {:?} after ({:.2}x)",
+            repos.len(),
+            before,
+            after,
+            before.as_secs_f64() / after.as_secs_f64()
+        );
+    }
+
+    #[test]
+    fn test_write_record_matches_columns() {
+        let columns = Repo::columns();
+        for &optional in &[true, false] {
+            let expected = row(optional);
+            let repo: Repo = expected
+                .deserialize(Some(&csv::ByteRecord::from(columns.clone())))
+                .unwrap();
+
+            let mut record = csv::ByteRecord::new();
+            repo.write_record(&mut record);
+            assert_eq!(record, expected);
+
+            // The fast path must produce the same output as serde
+            let mut serde = csv::Writer::from_writer(Vec::new());
+            serde.serialize(&repo).unwrap();
+            let serde = serde.into_inner().unwrap();
+            let mut reader = csv::Reader::from_reader(serde.as_slice());
+            assert_eq!(reader.byte_headers().unwrap(), &columns[..]);
+            assert_eq!(reader.byte_records().next().unwrap().unwrap(), record);
+        }
+    }
+
+    #[test]
+    fn test_migrate_only_known_forges() {
+        let dir = ::std::env::temp_dir()
+            .join(format!("rust-repos-data-{}-migrate", ::std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("errors")).unwrap();
+        fs::write(dir.join("github.csv"), "id\n").unwrap();
+        fs::write(dir.join("errors").join("github.csv"), "id\n").unwrap();
+        fs::write(dir.join("advisories.csv"), "id\n").unwrap();
+
+        let mut config = Config::from_env(dir.clone()).unwrap();
+        config.data_layout = DataLayout::PerForge;
+        Data::new(&config).unwrap();
+
+        assert!(dir.join("github").join("repos.csv").exists());
+        assert!(dir.join("github").join("errors.csv").exists());
+        assert!(!dir.join("github.csv").exists());
+        // Files of other tools aren't forges, and must be left alone
+        assert!(dir.join("advisories.csv").exists());
+        assert!(!dir.join("advisories").exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_sample_new_stratum() {
+        let dir =
+            ::std::env::temp_dir().join(format!("rust-repos-data-{}-sample", ::std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let mut csv = csv::Writer::from_path(dir.join("github.csv")).unwrap();
+        csv.write_byte_record(&csv::ByteRecord::from(Repo::columns()))
+            .unwrap();
+        for _ in 0..4 {
+            csv.write_byte_record(&row(true)).unwrap();
+        }
+        csv.flush().unwrap();
+
+        // The repositories seen in the second pass are in a stratum the first pass didn't see,
+        // like ones appended by a scraper running at the same time
+        let calls = Cell::new(0);
+        let stratum = |_: &Repo| {
+            calls.set(calls.get() + 1);
+            if calls.get() <= 4 { "old" } else { "new" }.to_string()
+        };
+        let data = Data::new(&Config::from_env(dir.clone()).unwrap()).unwrap();
+        assert!(data.sample("github", 2, 0, stratum).unwrap().is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "encryption")]
+    fn test_encrypt_plaintext_csv() {
+        use encryption::{self, Key};
+
+        let dir = ::std::env::temp_dir()
+            .join(format!("rust-repos-data-{}-encrypt", ::std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let mut csv = csv::Writer::from_path(dir.join("github.csv")).unwrap();
+        csv.write_byte_record(&csv::ByteRecord::from(Repo::columns()))
+            .unwrap();
+        csv.write_byte_record(&row(true)).unwrap();
+        csv.flush().unwrap();
+
+        let mut config = Config::from_env(dir.clone()).unwrap();
+        config.encryption_key = Some(Key::parse(&"42".repeat(32)).unwrap());
+        let data = Data::new(&config).unwrap();
+        assert!(encryption::is_encrypted(&dir.join("github.csv")).unwrap());
+        assert_eq!(data.repos("github").unwrap().count(), 1);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_drop_without_flush() {
+        let dir =
+            ::std::env::temp_dir().join(format!("rust-repos-data-{}-drop", ::std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let columns = csv::ByteRecord::from(Repo::columns());
+        let repo: Repo = row(true).deserialize(Some(&columns)).unwrap();
+
+        let data = Data::new(&Config::from_env(dir.clone()).unwrap()).unwrap();
+        data.store_repos("github", vec![repo]).unwrap();
+        let clone = data.clone();
+        drop(data);
+        drop(clone);
+
+        // The buffered repository is written before the last clone is dropped
+        let data = Data::new(&Config::from_env(dir.clone()).unwrap()).unwrap();
+        assert_eq!(data.repos("github").unwrap().count(), 1);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// A repository with the given ID, and a different value in each other column.
+    fn repo(id: &str) -> Repo {
+        let columns = csv::ByteRecord::from(Repo::columns());
+        let record = row(true)
+            .iter()
+            .zip(columns.iter())
+            .map(|(value, column)| {
+                if column == b"id" {
+                    id.as_bytes()
+                } else {
+                    value
+                }
+            })
+            .collect::<csv::ByteRecord>();
+        record.deserialize(Some(&columns)).unwrap()
+    }
+
+    #[test]
+    fn test_reconcile_reads_the_tail() {
+        let dir = ::std::env::temp_dir().join(format!(
+            "rust-repos-data-{}-reconcile",
+            ::std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        let config = Config::from_env(dir.clone()).unwrap();
+
+        let data = Data::new(&config).unwrap();
+        data.edit_forge_state("github", |state| state.last_id = Some(10))
+            .unwrap();
+        assert!(data.reconcile("github").unwrap().is_empty());
+        data.store_repos("github", vec![repo("a"), repo("b")])
+            .unwrap();
+        data.set_processed_id("github", 5);
+        data.flush().unwrap();
+        // Stored without saving the progress, like when a run crashes
+        data.store_repos("github", vec![repo("c")]).unwrap();
+        data.flush().unwrap();
+        drop(data);
+
+        // Only the rows after the saved progress are read, so a corrupted row before them
+        // doesn't matter
+        let path = dir.join("github.csv");
+        let content = fs::read_to_string(&path).unwrap();
+        let header_end = content.find('\n').unwrap();
+        let corrupted = header_end + content[header_end..].find("true").unwrap();
+        let mut content = content.into_bytes();
+        content[corrupted..corrupted + 4].copy_from_slice(b"xxxx");
+        fs::write(&path, &content).unwrap();
+
+        let data = Data::new(&config).unwrap();
+        let stored = data.reconcile("github").unwrap();
+        assert_eq!(
+            stored.into_iter().collect::<Vec<_>>(),
+            vec!["c".to_string()]
+        );
+        assert!(data.repos("github").unwrap().any(|repo| repo.is_err()));
+
+        fs::remove_dir_all(&dir).unwrap();
     }
 }
@@ -0,0 +1,115 @@
+// Copyright (c) 2018 Pietro Albini <pietro@pietroalbini.org>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies
+// of the Software, and to permit persons to whom the Software is furnished to do
+// so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use config::Config;
+use data::write_atomically;
+use http::{self, ResponseExt};
+use prelude::*;
+use std::collections::HashSet;
+use std::fs;
+use std::io::Write;
+use std::time::Duration;
+use utils::log_error;
+
+/// How long the cached copy of the remote denylist is used before fetching it again.
+const CACHE_DURATION: Duration = Duration::from_secs(24 * 60 * 60);
+
+static CACHE_FILE: &str = "owner-denylist.txt";
+
+/// List of accounts whose repositories should never be stored.
+pub struct Denylist {
+    owners: HashSet<String>,
+}
+
+impl Denylist {
+    pub fn load(config: &Config) -> Fallible<Self> {
+        let mut owners = config
+            .owner_denylist
+            .iter()
+            .map(|owner| owner.to_lowercase())
+            .collect::<HashSet<_>>();
+
+        if let Some(url) = &config.owner_denylist_url {
+            owners.extend(parse(&fetch_cached(config, url)?));
+        }
+
+        if !owners.is_empty() {
+            info!("loaded {} denylisted owners", owners.len());
+        }
+
+        Ok(Denylist { owners })
+    }
+
+    /// Check whether the owner of a repository (in the `owner/name` format) is denylisted.
+    pub fn contains_repo(&self, name_with_owner: &str) -> bool {
+        let owner = name_with_owner.split('/').next().unwrap_or("");
+        self.owners.contains(&owner.to_lowercase())
+    }
+}
+
+/// Parse the denylist format: one owner per line, with `#` starting a comment.
+fn parse(content: &str) -> impl Iterator<Item = String> + '_ {
+    content
+        .lines()
+        .map(|line| line.split('#').next().unwrap().trim())
+        .filter(|line| !line.is_empty())
+        .map(|line| line.to_lowercase())
+}
+
+fn fetch_cached(config: &Config, url: &str) -> Fallible<String> {
+    let cache = config.data_dir.join(CACHE_FILE);
+
+    let fresh = fs::metadata(&cache)
+        .and_then(|meta| meta.modified())
+        .ok()
+        .and_then(|modified| modified.elapsed().ok())
+        .map(|elapsed| elapsed < CACHE_DURATION)
+        .unwrap_or(false);
+    if fresh {
+        debug!("using the cached owner denylist");
+        return Ok(fs::read_to_string(&cache)?);
+    }
+
+    match fetch(config, url) {
+        Ok(content) => {
+            write_atomically(&cache, |file| Ok(file.write_all(content.as_bytes())?))?;
+            Ok(content)
+        }
+        Err(err) => {
+            if cache.exists() {
+                warn!("failed to fetch the owner denylist, using the stale cached copy");
+                log_error(&err);
+                Ok(fs::read_to_string(&cache)?)
+            } else {
+                Err(err.context("failed to fetch the owner denylist").into())
+            }
+        }
+    }
+}
+
+fn fetch(config: &Config, url: &str) -> Fallible<String> {
+    info!("fetching the owner denylist from {}", url);
+    Ok(http::client(config)?
+        .get(url)
+        .send()?
+        .decompressed()?
+        .error_for_status()?
+        .text()?)
+}
@@ -0,0 +1,155 @@
+// Copyright (c) 2018 Pietro Albini <pietro@pietroalbini.org>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies
+// of the Software, and to permit persons to whom the Software is furnished to do
+// so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Aggregate dependency graph of the crates.io packages locked across the corpus.
+
+use data::Data;
+use prelude::*;
+use std::collections::{BTreeMap, HashSet};
+use std::io::{self, Write};
+use std::str::FromStr;
+
+static PLATFORM: &str = "github";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Dot,
+    GraphMl,
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Fallible<Self> {
+        match s {
+            "dot" => Ok(Format::Dot),
+            "graphml" => Ok(Format::GraphMl),
+            other => bail!("unknown graph format: {}", other),
+        }
+    }
+}
+
+/// Crates are the nodes and dependencies the edges, both weighted by the number of repositories
+/// locking them.
+#[derive(Default)]
+struct Graph {
+    nodes: BTreeMap<String, usize>,
+    edges: BTreeMap<(String, String), usize>,
+}
+
+/// Print the dependency graph, only including the crates and edges found in at least
+/// `min_repos` repositories.
+pub fn export(data: &Data, format: Format, min_repos: usize) -> Fallible<()> {
+    let mut graph = Graph::default();
+    for lockfile in data.lockfiles(PLATFORM)? {
+        let (_, packages) = lockfile?;
+
+        // Local and git packages are specific to each repository, only keep crates.io ones
+        let published = packages
+            .iter()
+            .filter(|package| package.is_from_crates_io())
+            .map(|package| package.name.as_str())
+            .collect::<HashSet<_>>();
+
+        let mut edges = HashSet::new();
+        for package in packages
+            .iter()
+            .filter(|package| package.is_from_crates_io())
+        {
+            for dep in package.dependencies.split_whitespace() {
+                if published.contains(dep) {
+                    edges.insert((package.name.as_str(), dep));
+                }
+            }
+        }
+
+        for name in published {
+            *graph.nodes.entry(name.to_string()).or_default() += 1;
+        }
+        for (from, to) in edges {
+            *graph
+                .edges
+                .entry((from.to_string(), to.to_string()))
+                .or_default() += 1;
+        }
+    }
+
+    graph.nodes.retain(|_, repos| *repos >= min_repos);
+    let nodes = &graph.nodes;
+    graph.edges.retain(|(from, to), repos| {
+        *repos >= min_repos && nodes.contains_key(from) && nodes.contains_key(to)
+    });
+    info!(
+        "dependency graph has {} crates and {} edges",
+        graph.nodes.len(),
+        graph.edges.len()
+    );
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    match format {
+        Format::Dot => write_dot(&mut out, &graph)?,
+        Format::GraphMl => write_graphml(&mut out, &graph)?,
+    }
+    Ok(())
+}
+
+fn write_dot<W: Write>(out: &mut W, graph: &Graph) -> Fallible<()> {
+    writeln!(out, "digraph dependencies {{")?;
+    for (name, repos) in &graph.nodes {
+        writeln!(out, "    \"{}\" [weight={}];", name, repos)?;
+    }
+    for ((from, to), repos) in &graph.edges {
+        writeln!(out, "    \"{}\" -> \"{}\" [weight={}];", from, to, repos)?;
+    }
+    writeln!(out, "}}")?;
+    Ok(())
+}
+
+fn write_graphml<W: Write>(out: &mut W, graph: &Graph) -> Fallible<()> {
+    // Crate names only contain alphanumeric characters, `-` and `_`, so nothing needs escaping
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        out,
+        r#"<graphml xmlns="http://graphml.graphdrawing.org/xmlns">"#
+    )?;
+    writeln!(
+        out,
+        r#"  <key id="repos" for="all" attr.name="repos" attr.type="int"/>"#
+    )?;
+    writeln!(out, r#"  <graph id="dependencies" edgedefault="directed">"#)?;
+    for (name, repos) in &graph.nodes {
+        writeln!(
+            out,
+            r#"    <node id="{}"><data key="repos">{}</data></node>"#,
+            name, repos
+        )?;
+    }
+    for ((from, to), repos) in &graph.edges {
+        writeln!(
+            out,
+            r#"    <edge source="{}" target="{}"><data key="repos">{}</data></edge>"#,
+            from, to, repos
+        )?;
+    }
+    writeln!(out, "  </graph>")?;
+    writeln!(out, "</graphml>")?;
+    Ok(())
+}
@@ -0,0 +1,557 @@
+// Copyright (c) 2018 Pietro Albini <pietro@pietroalbini.org>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies
+// of the Software, and to permit persons to whom the Software is furnished to do
+// so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Optional encryption at rest of the repositories CSV, with ChaCha20-Poly1305.
+//!
+//! An encrypted file starts with `MAGIC`, followed by frames: the length of the ciphertext (as a
+//! little endian `u32`), the flags of the frame, a random nonce and the ciphertext with its
+//! authentication tag. Each write appends frames, splitting big writes in frames of `FRAME_SIZE`
+//! bytes of plaintext, and flags its last frame as final.
+//!
+//! The position of each frame in the file and its flags are authenticated with it, so frames
+//! dropped, reordered or copied from another file fail to decrypt, and so does a file whose last
+//! frame isn't final. Only truncating a file exactly at the end of a previous write can't be
+//! detected, as the file is append-only.
+
+use prelude::*;
+use std::fs::File;
+use std::io::{self, BufReader, Cursor, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// Header identifying the encrypted files, which is also authenticated with every frame.
+static MAGIC: &[u8] = b"rust-repos encrypted v2\n";
+/// Flag of the last frame written by each write.
+const FINAL_FRAME: u8 = 1;
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+/// Maximum number of bytes of plaintext encrypted in a single frame.
+const FRAME_SIZE: usize = 1024 * 1024;
+
+/// A 256-bit key, provided in hexadecimal.
+#[derive(Clone)]
+#[cfg_attr(not(feature = "encryption"), allow(dead_code))]
+pub struct Key([u8; KEY_LEN]);
+
+impl Key {
+    pub fn parse(hex: &str) -> Fallible<Self> {
+        let hex = hex.trim();
+        if hex.len() != KEY_LEN * 2 || !hex.is_ascii() {
+            bail!(
+                "the encryption key must be {} hexadecimal characters",
+                KEY_LEN * 2
+            );
+        }
+        let mut key = [0; KEY_LEN];
+        for (i, byte) in key.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)
+                .map_err(|_| err_msg("the encryption key is not hexadecimal"))?;
+        }
+        Ok(Key(key))
+    }
+}
+
+/// Data authenticated with a frame besides its ciphertext: the header of the file, and the
+/// position and flags of the frame.
+#[cfg_attr(not(feature = "encryption"), allow(dead_code))]
+fn associated_data(index: u64, flags: u8) -> Vec<u8> {
+    let mut aad = MAGIC.to_vec();
+    aad.extend_from_slice(&index.to_le_bytes());
+    aad.push(flags);
+    aad
+}
+
+#[cfg(feature = "encryption")]
+fn seal(key: &Key, index: u64, flags: u8, plaintext: &[u8]) -> Fallible<Vec<u8>> {
+    use openssl::rand::rand_bytes;
+    use openssl::symm::{encrypt_aead, Cipher};
+
+    let mut nonce = [0; NONCE_LEN];
+    rand_bytes(&mut nonce)?;
+    let mut tag = [0; TAG_LEN];
+    let ciphertext = encrypt_aead(
+        Cipher::chacha20_poly1305(),
+        &key.0,
+        Some(&nonce),
+        &associated_data(index, flags),
+        plaintext,
+        &mut tag,
+    )?;
+
+    let mut frame = Vec::with_capacity(5 + NONCE_LEN + ciphertext.len() + TAG_LEN);
+    frame.extend_from_slice(&((ciphertext.len() + TAG_LEN) as u32).to_le_bytes());
+    frame.push(flags);
+    frame.extend_from_slice(&nonce);
+    frame.extend_from_slice(&ciphertext);
+    frame.extend_from_slice(&tag);
+    Ok(frame)
+}
+
+#[cfg(feature = "encryption")]
+fn unseal(key: &Key, index: u64, flags: u8, nonce: &[u8], sealed: &[u8]) -> Fallible<Vec<u8>> {
+    use openssl::symm::{decrypt_aead, Cipher};
+
+    let (ciphertext, tag) = sealed.split_at(sealed.len() - TAG_LEN);
+    decrypt_aead(
+        Cipher::chacha20_poly1305(),
+        &key.0,
+        Some(nonce),
+        &associated_data(index, flags),
+        ciphertext,
+        tag,
+    )
+    .map_err(|_| err_msg("failed to decrypt the data (wrong key or corrupted file)"))
+}
+
+#[cfg(not(feature = "encryption"))]
+fn seal(_key: &Key, _index: u64, _flags: u8, _plaintext: &[u8]) -> Fallible<Vec<u8>> {
+    bail!("rust-repos was built without the encryption feature");
+}
+
+#[cfg(not(feature = "encryption"))]
+fn unseal(_key: &Key, _index: u64, _flags: u8, _nonce: &[u8], _sealed: &[u8]) -> Fallible<Vec<u8>> {
+    bail!("rust-repos was built without the encryption feature");
+}
+
+/// Header of a frame: the length of its ciphertext and its flags.
+fn read_frame_header<R: Read>(inner: &mut R) -> io::Result<Option<(usize, u8)>> {
+    let mut header = [0; 5];
+    let mut read = 0;
+    while read < header.len() {
+        match inner.read(&mut header[read..])? {
+            0 if read == 0 => return Ok(None),
+            0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+            n => read += n,
+        }
+    }
+    let len = u32::from_le_bytes([header[0], header[1], header[2], header[3]]) as usize;
+    let flags = header[4];
+    if len < TAG_LEN || flags & !FINAL_FRAME != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "invalid encrypted frame",
+        ));
+    }
+    Ok(Some((len, flags)))
+}
+
+fn truncated() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        "the encrypted file is truncated (its last frame is missing)",
+    )
+}
+
+/// Open a file for reading, decrypting it when a key is provided. Files that are not encrypted
+/// are only read without a key, so a plaintext file can't be passed off as an encrypted one.
+pub fn reader(path: &Path, key: Option<&Key>) -> Fallible<Box<dyn Read + Send>> {
+    let mut file = BufReader::new(File::open(path)?);
+    let mut prefix = Vec::with_capacity(MAGIC.len());
+    (&mut file)
+        .take(MAGIC.len() as u64)
+        .read_to_end(&mut prefix)?;
+    if prefix != MAGIC {
+        if key.is_some() {
+            bail!(
+                "{} is not encrypted, but a key was provided in RUST_REPOS_ENCRYPTION_KEY or \
+                 RUST_REPOS_ENCRYPTION_KEY_FILE",
+                path.display()
+            );
+        }
+        return Ok(Box::new(Cursor::new(prefix).chain(file)));
+    }
+
+    match key {
+        Some(key) => Ok(Box::new(Decryptor {
+            inner: file,
+            key: key.clone(),
+            plaintext: Cursor::new(Vec::new()),
+            next_index: 0,
+            last_flags: None,
+        })),
+        None => bail!(
+            "{} is encrypted, but no key was provided in RUST_REPOS_ENCRYPTION_KEY or \
+             RUST_REPOS_ENCRYPTION_KEY_FILE",
+            path.display()
+        ),
+    }
+}
+
+/// Whether a file was written encrypted.
+pub fn is_encrypted(path: &Path) -> Fallible<bool> {
+    let mut prefix = Vec::with_capacity(MAGIC.len());
+    File::open(path)?
+        .take(MAGIC.len() as u64)
+        .read_to_end(&mut prefix)?;
+    Ok(prefix == MAGIC)
+}
+
+/// Number of frames of an encrypted file, which is the position of the next frame appended to it.
+/// Only the headers of the frames are read, and the last one must be final.
+fn count_frames(path: &Path) -> Fallible<u64> {
+    let mut file = BufReader::new(File::open(path)?);
+    let len = file.get_ref().metadata()?.len();
+    skip_frames(&mut file, len)
+}
+
+/// Skip the frames of an encrypted file up to `end`, returning how many there are. Only the
+/// headers of the frames are read, and `end` must be the end of a final frame.
+fn skip_frames(file: &mut BufReader<File>, end: u64) -> Fallible<u64> {
+    file.seek(SeekFrom::Start(MAGIC.len() as u64))?;
+    let mut frames = 0;
+    let mut last_flags = None;
+    while file.stream_position()? < end {
+        let (len, flags) = read_frame_header(file)?.ok_or_else(truncated)?;
+        file.seek_relative((NONCE_LEN + len) as i64)?;
+        frames += 1;
+        last_flags = Some(flags);
+    }
+    // Seeking past the end doesn't fail, so check the last frame is complete
+    if last_flags != Some(FINAL_FRAME) || file.stream_position()? != end {
+        return Err(truncated().into());
+    }
+    Ok(frames)
+}
+
+/// Open a file for reading from `offset`, which must be the size the file had at the end of a
+/// previous write, decrypting it when a key is provided.
+pub fn reader_at(path: &Path, key: Option<&Key>, offset: u64) -> Fallible<Box<dyn Read + Send>> {
+    let encrypted = is_encrypted(path)?;
+    let mut file = BufReader::new(File::open(path)?);
+    match (key, encrypted) {
+        (None, false) => {
+            file.seek(SeekFrom::Start(offset))?;
+            Ok(Box::new(file))
+        }
+        (Some(key), true) => {
+            let next_index = skip_frames(&mut file, offset)
+                .with_context(|_| format!("{} is not a write boundary", offset))?;
+            Ok(Box::new(Decryptor {
+                inner: file,
+                key: key.clone(),
+                plaintext: Cursor::new(Vec::new()),
+                next_index,
+                last_flags: Some(FINAL_FRAME),
+            }))
+        }
+        // Let `reader` explain what's wrong
+        _ => reader(path, key),
+    }
+}
+
+struct Decryptor<R: Read> {
+    inner: R,
+    key: Key,
+    plaintext: Cursor<Vec<u8>>,
+    /// Position of the next frame in the file.
+    next_index: u64,
+    /// Flags of the last frame decrypted, if any.
+    last_flags: Option<u8>,
+}
+
+impl<R: Read> Decryptor<R> {
+    /// Decrypt the next frame, returning `false` at the end of the file.
+    fn next_frame(&mut self) -> io::Result<bool> {
+        let (len, flags) = match read_frame_header(&mut self.inner)? {
+            Some(header) => header,
+            None if self.last_flags == Some(FINAL_FRAME) => return Ok(false),
+            None => return Err(truncated()),
+        };
+
+        let mut nonce = [0; NONCE_LEN];
+        self.inner.read_exact(&mut nonce)?;
+        let mut sealed = vec![0; len];
+        self.inner.read_exact(&mut sealed)?;
+        let plaintext = unseal(&self.key, self.next_index, flags, &nonce, &sealed)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))?;
+        self.plaintext = Cursor::new(plaintext);
+        self.next_index += 1;
+        self.last_flags = Some(flags);
+        Ok(true)
+    }
+}
+
+impl<R: Read> Read for Decryptor<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let read = self.plaintext.read(buf)?;
+            if read > 0 || buf.is_empty() || !self.next_frame()? {
+                return Ok(read);
+            }
+        }
+    }
+}
+
+/// Writer encrypting what's written to it when a key is provided, and passing it through
+/// otherwise. `finish` must be called to write the last frame.
+pub struct Writer<W: Write> {
+    inner: W,
+    key: Option<Key>,
+    buffer: Vec<u8>,
+    /// Position of the next frame in the file.
+    next_index: u64,
+}
+
+impl<W: Write> Writer<W> {
+    /// Wrap a writer at the start of a new file, writing the header first.
+    pub fn new(mut inner: W, key: Option<&Key>) -> Fallible<Self> {
+        if key.is_some() {
+            inner.write_all(MAGIC)?;
+        }
+        Ok(Writer {
+            inner,
+            key: key.cloned(),
+            buffer: Vec::new(),
+            next_index: 0,
+        })
+    }
+
+    /// Wrap a writer appending to the existing file at `path`, which must already be encrypted
+    /// when a key is provided.
+    pub fn append(inner: W, key: Option<&Key>, path: &Path) -> Fallible<Self> {
+        let next_index = match key {
+            Some(_) => count_frames(path)
+                .with_context(|_| format!("failed to append to {}", path.display()))?,
+            None => 0,
+        };
+        Ok(Writer {
+            inner,
+            key: key.cloned(),
+            buffer: Vec::new(),
+            next_index,
+        })
+    }
+
+    fn write_frame(&mut self, flags: u8) -> io::Result<()> {
+        if let Some(key) = &self.key {
+            let frame = seal(key, self.next_index, flags, &self.buffer)
+                .map_err(|err| io::Error::other(err.to_string()))?;
+            self.inner.write_all(&frame)?;
+            self.buffer.clear();
+            self.next_index += 1;
+        }
+        Ok(())
+    }
+
+    /// Write the last frame, flagged as final even if it's empty.
+    pub fn finish(mut self) -> Fallible<W> {
+        self.write_frame(FINAL_FRAME)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for Writer<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.key.is_none() {
+            return self.inner.write(buf);
+        }
+        let len = buf.len().min(FRAME_SIZE - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..len]);
+        if self.buffer.len() >= FRAME_SIZE {
+            self.write_frame(0)?;
+        }
+        Ok(len)
+    }
+
+    /// Frames are only written once full or when finishing, to avoid tiny frames.
+    fn flush(&mut self) -> io::Result<()> {
+        if self.key.is_none() {
+            self.inner.flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// Copy a possibly encrypted file to `output` in plaintext.
+pub fn decrypt(path: &Path, key: Option<&Key>, output: &mut dyn Write) -> Fallible<()> {
+    io::copy(&mut reader(path, key)?, output)?;
+    output.flush()?;
+    Ok(())
+}
+
+#[cfg(all(test, feature = "encryption"))]
+mod tests {
+    use super::{reader, reader_at, Key, Writer, MAGIC, NONCE_LEN, TAG_LEN};
+    use std::fs::{self, OpenOptions};
+    use std::io::{Read, Write};
+    use std::path::{Path, PathBuf};
+
+    fn key() -> Key {
+        Key::parse(&"42".repeat(32)).unwrap()
+    }
+
+    fn temp_file(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "rust-repos-encryption-{}-{}",
+            std::process::id(),
+            name
+        ))
+    }
+
+    /// Write each of the contents in a separate session, like the CSV appends.
+    fn write(name: &str, contents: &[&str]) -> PathBuf {
+        let path = temp_file(name);
+        let _ = fs::remove_file(&path);
+        for content in contents {
+            let exists = path.exists();
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .unwrap();
+            let mut writer = if exists {
+                Writer::append(file, Some(&key()), &path).unwrap()
+            } else {
+                Writer::new(file, Some(&key())).unwrap()
+            };
+            writer.write_all(content.as_bytes()).unwrap();
+            writer.finish().unwrap();
+        }
+        path
+    }
+
+    fn read(path: &Path) -> Result<String, String> {
+        let mut content = String::new();
+        reader(path, Some(&key()))
+            .map_err(|err| err.to_string())?
+            .read_to_string(&mut content)
+            .map_err(|err| err.to_string())?;
+        Ok(content)
+    }
+
+    /// Offsets of the frames of an encrypted file.
+    fn frames(bytes: &[u8]) -> Vec<(usize, usize)> {
+        let mut frames = Vec::new();
+        let mut pos = MAGIC.len();
+        while pos < bytes.len() {
+            let len =
+                u32::from_le_bytes([bytes[pos], bytes[pos + 1], bytes[pos + 2], bytes[pos + 3]])
+                    as usize;
+            let end = pos + 5 + NONCE_LEN + len;
+            frames.push((pos, end));
+            pos = end;
+        }
+        frames
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let path = write("round-trip", &["id,name\n", "1,foo/bar\n", "", "2,baz\n"]);
+        assert_eq!(read(&path).unwrap(), "id,name\n1,foo/bar\n2,baz\n");
+        assert!(fs::read(&path).unwrap().starts_with(MAGIC));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_reordered_and_dropped_frames() {
+        let path = write("reordered", &["first\n", "second\n", "third\n"]);
+        let bytes = fs::read(&path).unwrap();
+        let frames = frames(&bytes);
+        assert_eq!(frames.len(), 3);
+        assert!(frames.iter().all(|(start, end)| end - start > TAG_LEN));
+
+        // Swapping the first two frames
+        let (first, second) = (frames[0], frames[1]);
+        let mut reordered = bytes[..first.0].to_vec();
+        reordered.extend_from_slice(&bytes[second.0..second.1]);
+        reordered.extend_from_slice(&bytes[first.0..first.1]);
+        reordered.extend_from_slice(&bytes[second.1..]);
+        fs::write(&path, &reordered).unwrap();
+        assert!(read(&path).is_err());
+
+        // Dropping the second frame
+        let mut dropped = bytes[..second.0].to_vec();
+        dropped.extend_from_slice(&bytes[second.1..]);
+        fs::write(&path, &dropped).unwrap();
+        assert!(read(&path).is_err());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_truncated_file() {
+        let big = "x".repeat(super::FRAME_SIZE + 10);
+        let path = write("truncated", &[&big]);
+        let bytes = fs::read(&path).unwrap();
+        let frames = frames(&bytes);
+        assert_eq!(frames.len(), 2);
+        assert_eq!(read(&path).unwrap(), big);
+
+        // Without the final frame
+        fs::write(&path, &bytes[..frames[1].0]).unwrap();
+        assert!(read(&path).unwrap_err().contains("truncated"));
+        let file = OpenOptions::new().append(true).open(&path).unwrap();
+        assert!(Writer::append(file, Some(&key()), &path).is_err());
+
+        // In the middle of a frame
+        fs::write(&path, &bytes[..bytes.len() - 1]).unwrap();
+        assert!(read(&path).is_err());
+        let file = OpenOptions::new().append(true).open(&path).unwrap();
+        assert!(Writer::append(file, Some(&key()), &path).is_err());
+
+        // Without any frame
+        fs::write(&path, MAGIC).unwrap();
+        assert!(read(&path).unwrap_err().contains("truncated"));
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_plaintext_file_with_key() {
+        let path = temp_file("plaintext");
+        fs::write(&path, "id,name\n1,foo/bar\n").unwrap();
+        assert!(read(&path).unwrap_err().contains("not encrypted"));
+
+        let mut content = String::new();
+        reader(&path, None)
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        assert_eq!(content, "id,name\n1,foo/bar\n");
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_reader_at() {
+        let path = write("reader-at", &["id,name\n1,foo/bar\n"]);
+        let offset = fs::metadata(&path).unwrap().len();
+        let file = OpenOptions::new().append(true).open(&path).unwrap();
+        let mut writer = Writer::append(file, Some(&key()), &path).unwrap();
+        writer.write_all(b"2,baz\n").unwrap();
+        writer.finish().unwrap();
+
+        let mut content = String::new();
+        reader_at(&path, Some(&key()), offset)
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        assert_eq!(content, "2,baz\n");
+
+        // Not the end of a write
+        assert!(reader_at(&path, Some(&key()), offset - 1).is_err());
+        assert!(reader_at(&path, Some(&key()), offset + 1).is_err());
+        // A file can't be read in plaintext, even from an offset
+        assert!(reader_at(&path, None, offset).is_err());
+
+        fs::remove_file(&path).unwrap();
+    }
+}
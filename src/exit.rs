@@ -0,0 +1,91 @@
+// Copyright (c) 2018 Pietro Albini <pietro@pietroalbini.org>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies
+// of the Software, and to permit persons to whom the Software is furnished to do
+// so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use prelude::*;
+
+pub const EXIT_SUCCESS: i32 = 0;
+pub const EXIT_FAILURE: i32 = 1;
+pub const EXIT_RATE_LIMITED: i32 = 2;
+pub const EXIT_TOKEN_FAILURE: i32 = 3;
+pub const EXIT_STORAGE_ERROR: i32 = 4;
+pub const EXIT_TIMEOUT: i32 = 5;
+pub const EXIT_BUDGET_EXHAUSTED: i32 = 6;
+
+/// Classes of errors that need to be reported with a distinct exit code, from the least to the
+/// most severe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Fail)]
+pub enum ErrorKind {
+    #[fail(display = "the GitHub API rate limit is exhausted")]
+    RateLimitExhausted,
+    #[fail(display = "the GitHub API token was rejected")]
+    TokenFailure,
+    #[fail(display = "failed to access the data directory")]
+    Storage,
+}
+
+impl ErrorKind {
+    fn exit_code(self) -> i32 {
+        match self {
+            ErrorKind::RateLimitExhausted => EXIT_RATE_LIMITED,
+            ErrorKind::TokenFailure => EXIT_TOKEN_FAILURE,
+            ErrorKind::Storage => EXIT_STORAGE_ERROR,
+        }
+    }
+}
+
+/// How a scraping run ended without errors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// All the repositories up to the newest one were scraped.
+    CaughtUp,
+    Interrupted,
+    TimedOut,
+    /// The remaining rate limit of the tokens dropped below the configured floor.
+    BudgetExhausted,
+}
+
+impl Outcome {
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Outcome::CaughtUp | Outcome::Interrupted => EXIT_SUCCESS,
+            Outcome::TimedOut => EXIT_TIMEOUT,
+            Outcome::BudgetExhausted => EXIT_BUDGET_EXHAUSTED,
+        }
+    }
+}
+
+/// Find the class of an error, looking for an `ErrorKind` in its chain of causes.
+pub fn error_kind(err: &Error) -> Option<ErrorKind> {
+    for cause in err.iter_chain() {
+        if let Some(kind) = cause.downcast_ref::<ErrorKind>() {
+            return Some(*kind);
+        } else if let Some(context) = cause.downcast_ref::<Context<ErrorKind>>() {
+            return Some(*context.get_context());
+        }
+    }
+    None
+}
+
+/// Find the exit code to use for an error.
+pub fn exit_code(err: &Error) -> i32 {
+    error_kind(err)
+        .map(ErrorKind::exit_code)
+        .unwrap_or(EXIT_FAILURE)
+}
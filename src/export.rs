@@ -0,0 +1,312 @@
+// Copyright (c) 2018 Pietro Albini <pietro@pietroalbini.org>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies
+// of the Software, and to permit persons to whom the Software is furnished to do
+// so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use browser;
+use config::Config;
+use csv;
+use data::{ColumnType, Data, Repo};
+use http::{self, ResponseExt};
+use prelude::*;
+use reqwest::blocking::Body;
+use serde_json;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+
+static PLATFORM: &str = "github";
+
+/// Databases the dataset can be loaded into, or static site it can be browsed with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    ClickHouse,
+    BigQuery,
+    HtmlBrowser,
+}
+
+impl Target {
+    fn name(self) -> &'static str {
+        match self {
+            Target::ClickHouse => "clickhouse",
+            Target::BigQuery => "bigquery",
+            Target::HtmlBrowser => "html-browser",
+        }
+    }
+}
+
+impl FromStr for Target {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Fallible<Self> {
+        match s {
+            "clickhouse" => Ok(Target::ClickHouse),
+            "bigquery" => Ok(Target::BigQuery),
+            "html-browser" => Ok(Target::HtmlBrowser),
+            other => bail!("unknown export target: {}", other),
+        }
+    }
+}
+
+fn clickhouse_type(kind: ColumnType) -> &'static str {
+    match kind {
+        ColumnType::String => "String",
+        ColumnType::Bool => "Bool",
+        ColumnType::OptionalBool => "Nullable(Bool)",
+        ColumnType::OptionalTimestamp => "Nullable(DateTime('UTC'))",
+        ColumnType::OptionalString => "Nullable(String)",
+        ColumnType::OptionalInteger => "Nullable(UInt64)",
+    }
+}
+
+fn bigquery_type(kind: ColumnType) -> (&'static str, &'static str) {
+    match kind {
+        ColumnType::String => ("STRING", "REQUIRED"),
+        ColumnType::Bool => ("BOOLEAN", "REQUIRED"),
+        ColumnType::OptionalBool => ("BOOLEAN", "NULLABLE"),
+        ColumnType::OptionalTimestamp => ("TIMESTAMP", "NULLABLE"),
+        ColumnType::OptionalString => ("STRING", "NULLABLE"),
+        ColumnType::OptionalInteger => ("INTEGER", "NULLABLE"),
+    }
+}
+
+/// Load the repositories checked since the previous export into the target database, creating
+/// its table if it doesn't exist yet. The HTML browser is instead written to `output` as a whole.
+pub fn export(data: &Data, config: &Config, target: Target, output: &Path) -> Fallible<()> {
+    if target == Target::HtmlBrowser {
+        return browser::write(data, output);
+    }
+
+    let watermark = data.get_export_watermark(PLATFORM, target.name())?;
+    match &watermark {
+        Some(watermark) => info!("exporting repositories checked since {}", watermark),
+        None => info!("exporting all the repositories"),
+    }
+
+    let rows_file = config
+        .data_dir
+        .join(format!("export-{}.tmp", target.name()));
+    let result =
+        write_rows(data, target, watermark.as_ref(), &rows_file).and_then(|(count, newest)| {
+            if count == 0 {
+                info!("no new repositories to export");
+                return Ok(());
+            }
+
+            match target {
+                Target::ClickHouse => load_clickhouse(config, &rows_file)?,
+                Target::BigQuery => load_bigquery(config, &rows_file)?,
+                Target::HtmlBrowser => unreachable!("the HTML browser is not a database"),
+            }
+            info!("exported {} repositories to {}", count, target.name());
+
+            // Rows scraped by older versions lack the timestamp, and are only loaded by the
+            // first export
+            if let Some(newest) = newest {
+                data.set_export_watermark(PLATFORM, target.name(), &newest)?;
+            }
+            Ok(())
+        });
+
+    if rows_file.exists() {
+        let _ = fs::remove_file(&rows_file);
+    }
+    result
+}
+
+/// Writer of the rows in the format expected by each target.
+enum RowWriter {
+    Csv(Box<csv::Writer<BufWriter<File>>>, csv::ByteRecord),
+    Json(BufWriter<File>),
+}
+
+impl RowWriter {
+    fn create(target: Target, path: &Path) -> Fallible<Self> {
+        let file = BufWriter::new(File::create(path).context(ErrorKind::Storage)?);
+        Ok(match target {
+            Target::ClickHouse => {
+                let mut csv = csv::Writer::from_writer(file);
+                csv.write_record(Repo::columns())?;
+                RowWriter::Csv(Box::new(csv), csv::ByteRecord::new())
+            }
+            Target::BigQuery => RowWriter::Json(file),
+            Target::HtmlBrowser => unreachable!("the HTML browser is not a database"),
+        })
+    }
+
+    fn write(&mut self, repo: &Repo) -> Fallible<()> {
+        match self {
+            RowWriter::Csv(csv, record) => {
+                repo.write_record(record);
+                csv.write_byte_record(record)?;
+            }
+            RowWriter::Json(file) => {
+                serde_json::to_writer(&mut *file, repo)?;
+                file.write_all(b"\n")?;
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Fallible<()> {
+        match self {
+            RowWriter::Csv(mut csv, _) => csv.flush()?,
+            RowWriter::Json(mut file) => file.flush()?,
+        }
+        Ok(())
+    }
+}
+
+/// Write the rows to export, returning how many were written and the most recent
+/// `last_checked_at` among them.
+fn write_rows(
+    data: &Data,
+    target: Target,
+    watermark: Option<&String>,
+    path: &Path,
+) -> Fallible<(usize, Option<String>)> {
+    let mut writer = RowWriter::create(target, path)?;
+
+    let mut count = 0;
+    let mut newest: Option<String> = None;
+    for repo in data.repos(PLATFORM)? {
+        let repo = repo?;
+        let is_new = match (watermark, &repo.last_checked_at) {
+            (None, _) => true,
+            // Repositories checked in the same second as the watermark, but after the previous
+            // export read the CSV, would be lost with a strict comparison. The ones already loaded
+            // are loaded again, and replaced by ReplacingMergeTree.
+            (Some(watermark), Some(checked)) => checked >= watermark,
+            (Some(_), None) => false,
+        };
+        if !is_new {
+            continue;
+        }
+
+        writer.write(&repo)?;
+        count += 1;
+        if repo.last_checked_at > newest {
+            newest = repo.last_checked_at;
+        }
+    }
+
+    writer.finish()?;
+    Ok((count, newest))
+}
+
+fn load_clickhouse(config: &Config, rows_file: &Path) -> Fallible<()> {
+    let url = match &config.clickhouse_url {
+        Some(url) => url,
+        None => bail!("missing RUST_REPOS_CLICKHOUSE_URL"),
+    };
+    // The table name goes into the queries as is
+    let table = &config.clickhouse_table;
+    if table.is_empty()
+        || !table
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+    {
+        bail!("invalid ClickHouse table name: {}", table);
+    }
+
+    let columns = Repo::schema()
+        .iter()
+        .map(|column| format!("{} {}", column.name, clickhouse_type(column.kind)))
+        .collect::<Vec<_>>();
+    clickhouse_query(
+        config,
+        url,
+        &format!(
+            "CREATE TABLE IF NOT EXISTS {} ({}) ENGINE = ReplacingMergeTree ORDER BY id",
+            table,
+            columns.join(", ")
+        ),
+        None,
+    )?;
+
+    // Later rows for the same repository replace the earlier ones when ClickHouse merges parts
+    clickhouse_query(
+        config,
+        url,
+        &format!("INSERT INTO {} FORMAT CSVWithNames", table),
+        Some(File::open(rows_file)?),
+    )
+}
+
+fn clickhouse_query(config: &Config, url: &str, query: &str, data: Option<File>) -> Fallible<()> {
+    debug!("running ClickHouse query: {}", query);
+
+    let mut req = http::client(config)?
+        .post(url)
+        .query(&[("query", query), ("date_time_input_format", "best_effort")]);
+    if let Some(user) = &config.clickhouse_user {
+        req = req.header("X-ClickHouse-User", user.as_str());
+    }
+    if let Some(password) = &config.clickhouse_password {
+        req = req.header("X-ClickHouse-Key", password.as_str());
+    }
+    if let Some(data) = data {
+        req = req.body(Body::from(data));
+    }
+
+    let resp = req.send()?.decompressed()?;
+    if !resp.status().is_success() {
+        bail!(
+            "ClickHouse returned {}: {}",
+            resp.status(),
+            resp.text()?.trim()
+        );
+    }
+    Ok(())
+}
+
+/// Load the rows with the `bq` command line tool, which creates the table from the schema if it
+/// doesn't exist and appends to it otherwise.
+fn load_bigquery(config: &Config, rows_file: &Path) -> Fallible<()> {
+    let table = match &config.bigquery_table {
+        Some(table) => table,
+        None => bail!("missing RUST_REPOS_BIGQUERY_TABLE"),
+    };
+
+    let schema = Repo::schema()
+        .iter()
+        .map(|column| {
+            let (kind, mode) = bigquery_type(column.kind);
+            json!({ "name": column.name, "type": kind, "mode": mode })
+        })
+        .collect::<Vec<_>>();
+    let schema_file = PathBuf::from(format!("{}.schema.json", rows_file.to_string_lossy()));
+    fs::write(&schema_file, serde_json::to_vec(&schema)?).context(ErrorKind::Storage)?;
+
+    let status = process::Command::new("bq")
+        .arg("load")
+        .arg("--source_format=NEWLINE_DELIMITED_JSON")
+        .arg(format!("--schema={}", schema_file.to_string_lossy()))
+        .arg(table)
+        .arg(rows_file)
+        .status();
+    let _ = fs::remove_file(&schema_file);
+
+    let status = status.context("failed to run the bq command line tool")?;
+    if !status.success() {
+        bail!("bq load failed with {}", status);
+    }
+    Ok(())
+}
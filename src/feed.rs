@@ -0,0 +1,114 @@
+// Copyright (c) 2018 Pietro Albini <pietro@pietroalbini.org>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies
+// of the Software, and to permit persons to whom the Software is furnished to do
+// so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Atom feed of the repositories discovered most recently. A repository is discovered when its
+//! first row is written, so the filters are matched against the data known at that time.
+
+use data::{Data, Repo};
+use prelude::*;
+use query::Filter;
+use render::escape_html;
+use std::collections::{HashSet, VecDeque};
+use std::time::SystemTime;
+use utils::format_timestamp;
+
+static PLATFORM: &str = "github";
+
+/// Number of repositories in the feed when no limit is provided.
+pub const DEFAULT_LIMIT: usize = 50;
+
+struct Entry {
+    name: String,
+    description: Option<String>,
+    categories: Option<String>,
+    discovered_at: String,
+}
+
+/// Call `f` with the first row of each repository matching the filter and the time it was
+/// discovered at, in the order they were discovered. Rows written by older versions of the
+/// scraper lack the time they were checked at, and are ignored.
+pub fn discoveries<F>(data: &Data, filter: Option<&Filter>, mut f: F) -> Fallible<()>
+where
+    F: FnMut(Repo, String),
+{
+    let mut seen = HashSet::new();
+    for repo in data.repos(PLATFORM)? {
+        let repo = repo?;
+        if !seen.insert(repo.id.clone()) {
+            continue;
+        }
+        let discovered_at = match &repo.last_checked_at {
+            Some(time) => time.clone(),
+            None => continue,
+        };
+        if let Some(filter) = filter {
+            if !filter.matches(&repo)? {
+                continue;
+            }
+        }
+        f(repo, discovered_at);
+    }
+    Ok(())
+}
+
+/// Render the feed of the `limit` repositories matching the filter discovered last.
+pub fn render(data: &Data, filter: Option<&Filter>, limit: usize) -> Fallible<String> {
+    let mut entries = VecDeque::with_capacity(limit + 1);
+    discoveries(data, filter, |repo, discovered_at| {
+        entries.push_back(Entry {
+            name: repo.name,
+            description: repo.description,
+            categories: repo.categories,
+            discovered_at,
+        });
+        if entries.len() > limit {
+            entries.pop_front();
+        }
+    })?;
+
+    let updated = entries
+        .back()
+        .map(|entry| entry.discovered_at.clone())
+        .unwrap_or_else(|| format_timestamp(SystemTime::now()));
+    let mut feed = String::new();
+    feed += "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n";
+    feed += "<feed xmlns=\"http://www.w3.org/2005/Atom\">\n";
+    feed += "  <title>New Rust repositories on GitHub</title>\n";
+    feed += "  <id>tag:rust-repos,2018:github</id>\n";
+    feed += &format!("  <updated>{}</updated>\n", updated);
+    feed += "  <author><name>rust-repos</name></author>\n";
+    for entry in entries.iter().rev() {
+        let url = format!("https://github.com/{}", escape_html(&entry.name));
+        feed += "  <entry>\n";
+        feed += &format!("    <title>{}</title>\n", escape_html(&entry.name));
+        feed += &format!("    <id>{}</id>\n", url);
+        feed += &format!("    <link href=\"{}\"/>\n", url);
+        feed += &format!("    <updated>{}</updated>\n", entry.discovered_at);
+        if let Some(description) = &entry.description {
+            feed += &format!("    <summary>{}</summary>\n", escape_html(description));
+        }
+        for category in entry.categories.iter().flat_map(|c| c.split_whitespace()) {
+            feed += &format!("    <category term=\"{}\"/>\n", escape_html(category));
+        }
+        feed += "  </entry>\n";
+    }
+    feed += "</feed>\n";
+    Ok(feed)
+}
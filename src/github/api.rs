@@ -19,31 +19,131 @@
 // SOFTWARE.
 
 use config::Config;
+use github::{drift, graphql};
+use http::{self, is_timeout, ResponseExt, ResponseTooLarge, RetryRequest};
 use prelude::*;
+use recorder::{Recorder, Replayer};
 use reqwest::blocking::{Client, RequestBuilder, Response};
 use reqwest::{header, Method, StatusCode};
 use serde::{de::DeserializeOwned, Serialize};
+use serde_json;
 use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io::{self, Read};
 use std::sync::{
     atomic::{AtomicBool, AtomicUsize, Ordering},
-    Arc,
+    Arc, Mutex,
 };
 use std::time::Duration;
+use summary::Summary;
 
-static USER_AGENT: &str = "rust-repos (https://github.com/rust-ops/rust-repos)";
+/// Remaining GraphQL points below which the scraper slows down.
+const LOW_GRAPHQL_QUOTA: u64 = 100;
 
 static GRAPHQL_QUERY_REPOSITORIES: &str = "
-query($ids: [ID!]!) {
+query(
+    $ids: [ID!]!,
+    $details: Boolean!,
+    $counts: Boolean!,
+    $ci: Boolean!,
+    $activity: Boolean!,
+    $since: GitTimestamp,
+) {
     nodes(ids: $ids) {
         ... on Repository {
             id
+            databaseId
             nameWithOwner
+            description
+            stargazerCount
+            isArchived
+            isEmpty
+            isDisabled
+            licenseInfo {
+                spdxId
+            }
             defaultBranchRef {
-                name
+                target {
+                    oid
+                }
+            }
+            templateRepository {
+                nameWithOwner
             }
             languages(first: 100, orderBy: { field: SIZE, direction: DESC }) {
+                totalSize
+                edges {
+                    size
+                    node {
+                        name
+                    }
+                }
+            }
+            repositoryTopics(first: 20) {
                 nodes {
-                    name
+                    topic {
+                        name
+                    }
+                }
+            }
+            # Details about the content of the repository, skipped in discovery-only mode
+            ... on Repository @include(if: $details) {
+                rustToolchainToml: object(expression: \"HEAD:rust-toolchain.toml\") {
+                    ...BlobText
+                }
+                rustToolchain: object(expression: \"HEAD:rust-toolchain\") {
+                    ...BlobText
+                }
+                rootTree: object(expression: \"HEAD:\") {
+                    ...TreeEntries
+                }
+                srcTree: object(expression: \"HEAD:src\") {
+                    ...TreeEntries
+                }
+                cargoToml: object(expression: \"HEAD:Cargo.toml\") {
+                    ...BlobText
+                }
+                fundingFile: object(expression: \"HEAD:.github/FUNDING.yml\") {
+                    __typename
+                }
+                fundingLinks {
+                    platform
+                }
+            }
+            # Issue and pull request counts, expensive so only loaded when configured
+            ... on Repository @include(if: $counts) {
+                openIssues: issues(states: OPEN) {
+                    totalCount
+                }
+                pullRequests {
+                    totalCount
+                }
+            }
+            # Outcome of the CI of the default branch, only loaded when configured
+            ... on Repository @include(if: $ci) {
+                ciRef: defaultBranchRef {
+                    target {
+                        ... on Commit {
+                            statusCheckRollup {
+                                state
+                            }
+                        }
+                    }
+                }
+            }
+            # Activity metrics, only loaded by the enrich subcommand
+            ... on Repository @include(if: $activity) {
+                mentionableUsers {
+                    totalCount
+                }
+                activityRef: defaultBranchRef {
+                    target {
+                        ... on Commit {
+                            history(since: $since) {
+                                totalCount
+                            }
+                        }
+                    }
                 }
             }
         }
@@ -51,116 +151,278 @@ query($ids: [ID!]!) {
 
     rateLimit {
         cost
+        remaining
+    }
+}
+
+fragment BlobText on Blob {
+    text
+}
+
+fragment TreeEntries on Tree {
+    entries {
+        name
+        type
     }
 }
 ";
 
-#[derive(Fail, Debug)]
-#[fail(display = "internal github error: {:?}", _0)]
-struct RetryRequest(StatusCode);
-
-trait ResponseExt {
-    fn handle_errors(self) -> Fallible<Self>
-    where
-        Self: Sized;
-}
-
-impl ResponseExt for Response {
-    fn handle_errors(self) -> Fallible<Self> {
-        let status = self.status();
-        match status {
-            StatusCode::INTERNAL_SERVER_ERROR
-            | StatusCode::BAD_GATEWAY
-            | StatusCode::SERVICE_UNAVAILABLE
-            | StatusCode::GATEWAY_TIMEOUT => Err(RetryRequest(status).into()),
-            _ => Ok(self),
+static GRAPHQL_QUERY_SEARCH: &str = "
+query($query: String!, $cursor: String) {
+    search(query: $query, type: REPOSITORY, first: 100, after: $cursor) {
+        repositoryCount
+        pageInfo {
+            hasNextPage
+            endCursor
+        }
+        nodes {
+            ... on Repository {
+                id
+                databaseId
+                isFork
+            }
         }
     }
+
+    rateLimit {
+        cost
+        remaining
+    }
+}
+";
+
+/// The token used by a request was rejected and quarantined, so it should be sent again.
+#[derive(Fail, Debug)]
+#[fail(display = "the token was rejected by GitHub")]
+struct TokenQuarantined;
+
+/// Optional parts of the repositories query, not needed while scraping.
+#[derive(Default)]
+pub struct QueryExtras {
+    /// Load the number of open issues and of pull requests.
+    pub counts: bool,
+    /// Load the combined CI status of the default branch.
+    pub ci_status: bool,
+    /// Load the activity metrics, counting the commits made after this timestamp.
+    pub activity_since: Option<String>,
 }
 
 pub struct GitHubApi<'conf> {
     config: &'conf Config,
+    summary: &'conf Summary,
     client: Client,
     slow_down: Arc<AtomicBool>,
     concurrent_requests: Arc<AtomicUsize>,
+    next_token: AtomicUsize,
+    /// Identifiers of the tokens, safe to log.
+    token_ids: Vec<String>,
+    /// Tokens rejected by GitHub, which are not used anymore.
+    quarantined: Vec<AtomicBool>,
+    /// Remaining rate limit of each token as of its latest response, by rate limit resource.
+    rate_limits: Mutex<HashMap<String, Vec<Option<u64>>>>,
+    recorder: Option<Recorder>,
+    replayer: Option<Replayer>,
+    /// Minified GraphQL documents, computed once instead of for every request.
+    query_repositories: String,
+    query_search: String,
 }
 
 impl<'conf> GitHubApi<'conf> {
-    pub fn new(config: &'conf Config) -> Self {
-        GitHubApi {
+    pub fn new(config: &'conf Config, summary: &'conf Summary) -> Fallible<Self> {
+        config.require_api_access()?;
+
+        Ok(GitHubApi {
             config,
-            client: Client::new(),
+            summary,
+            client: http::client(config)?,
             slow_down: Arc::new(AtomicBool::new(false)),
             concurrent_requests: Arc::new(AtomicUsize::new(0)),
+            next_token: AtomicUsize::new(0),
+            token_ids: config
+                .github_tokens
+                .iter()
+                .map(|token| http::token_id(token))
+                .collect(),
+            quarantined: config
+                .github_tokens
+                .iter()
+                .map(|_| AtomicBool::new(false))
+                .collect(),
+            rate_limits: Mutex::new(HashMap::new()),
+            recorder: match &config.record_http {
+                Some(dir) => Some(Recorder::new(dir, config.github_tokens.clone())?),
+                None => None,
+            },
+            replayer: match &config.replay_http {
+                Some(dir) => Some(Replayer::load(dir, config.github_tokens.clone())?),
+                None => None,
+            },
+            query_repositories: graphql::minify(GRAPHQL_QUERY_REPOSITORIES),
+            query_search: graphql::minify(GRAPHQL_QUERY_SEARCH),
+        })
+    }
+
+    /// Pick the token to use for the next request, cycling through all the configured ones that
+    /// were not quarantined.
+    fn token(&self) -> &str {
+        let tokens = &self.config.github_tokens;
+        for _ in 0..tokens.len() {
+            let index = self.next_token.fetch_add(1, Ordering::SeqCst) % tokens.len();
+            if !self.quarantined[index].load(Ordering::SeqCst) {
+                return &tokens[index];
+            }
         }
+        // All the tokens were rejected, the request will fail with a token failure
+        &tokens[0]
     }
 
-    fn retry<T, F: Fn() -> Fallible<T>>(&self, f: F) -> Fallible<T> {
-        let mut wait = Duration::from_secs(10);
-        let mut first = true;
+    fn record_rate_limit(&self, index: usize, response: &Response) {
+        let header = |name| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+        };
+        let remaining = header("x-ratelimit-remaining").and_then(|value| value.parse().ok());
+        if let (Some(resource), Some(remaining)) = (header("x-ratelimit-resource"), remaining) {
+            let mut rate_limits = self.rate_limits.lock().unwrap();
+            let tokens = rate_limits
+                .entry(resource.to_string())
+                .or_insert_with(|| vec![None; self.config.github_tokens.len()]);
+            tokens[index] = Some(remaining);
+        }
+    }
+
+    /// The rate limit resource (like `core` or `graphql`) whose remaining requests, summed across
+    /// all the usable tokens, dropped below `RUST_REPOS_RATE_LIMIT_FLOOR`, with that sum. Resources
+    /// not used yet by all the tokens are not considered.
+    pub fn below_rate_limit_floor(&self) -> Option<(String, u64)> {
+        let floor = self.config.rate_limit_floor?;
+        let rate_limits = self.rate_limits.lock().unwrap();
+        for (resource, tokens) in rate_limits.iter() {
+            let total = tokens
+                .iter()
+                .zip(&self.quarantined)
+                .filter(|(_, quarantined)| !quarantined.load(Ordering::SeqCst))
+                .map(|(remaining, _)| *remaining)
+                .sum::<Option<u64>>();
+            if let Some(total) = total {
+                if total < floor {
+                    return Some((resource.clone(), total));
+                }
+            }
+        }
+        None
+    }
 
-        loop {
+    /// Stop using a token rejected by GitHub, returning whether other tokens are still usable.
+    /// Many requests can be rejected with the same token at once: all of them are sent again as
+    /// long as a token is left, whichever of them removed it from the pool.
+    fn quarantine(&self, index: usize) -> bool {
+        let already_quarantined = self.quarantined[index].swap(true, Ordering::SeqCst);
+        let usable = self
+            .quarantined
+            .iter()
+            .filter(|quarantined| !quarantined.load(Ordering::SeqCst))
+            .count();
+        if !already_quarantined {
+            error!(
+                "GitHub rejected token {}, removing it from the pool ({} usable tokens left)",
+                self.token_ids[index], usable
+            );
+            Summary::incr(&self.summary.tokens_quarantined, 1);
+        }
+        usable > 0
+    }
+
+    fn retry<T, F: Fn() -> Fallible<T>>(&self, f: F) -> Fallible<T> {
+        let call = || {
             let concurrent = self.concurrent_requests.fetch_add(1, Ordering::SeqCst);
             debug!(
                 "currently making {} concurrent requests to the GitHub API",
                 concurrent + 1
             );
-            let res = f();
+            let mut res = f();
+            // Retry right away with another token if the one used was quarantined
+            while res
+                .as_ref()
+                .err()
+                .and_then(|err| err.downcast_ref::<TokenQuarantined>())
+                .is_some()
+            {
+                Summary::incr(&self.summary.api_calls, 1);
+                res = f();
+            }
             self.concurrent_requests.fetch_sub(1, Ordering::SeqCst);
+            Summary::incr(&self.summary.api_calls, 1);
+            res
+        };
 
-            match res {
-                Ok(res) => return Ok(res),
-                Err(err) => {
-                    let mut retry = false;
-                    if let Some(error) = err.downcast_ref::<RetryRequest>() {
-                        warn!(
-                            "API call to GitHub returned status code {}, retrying in {} seconds",
-                            error.0,
-                            wait.as_secs()
-                        );
-                        retry = true;
-                    } else if let Some(error) = err.downcast_ref::<reqwest::Error>() {
-                        if error.is_timeout() {
-                            warn!(
-                                "API call to GitHub timed out, retrying in {} seconds",
-                                wait.as_secs()
-                            );
-                            retry = true;
-                        }
-                    } else if let Some(error) = err.downcast_ref::<std::io::Error>() {
-                        if error.kind() == std::io::ErrorKind::ConnectionReset {
-                            warn!(
-                                "connection to the API reset by peer, retrying in {} seconds",
-                                wait.as_secs()
-                            );
-                            retry = true;
-                        }
-                    }
-
-                    if !retry {
-                        return Err(err);
-                    }
+        http::retry(
+            "GitHub",
+            &self.config.github_retry,
+            self.summary,
+            call,
+            |retries| {
+                // Slow down only once per API call
+                if retries == 0 {
+                    self.slow_down.store(true, Ordering::SeqCst);
                 }
-            }
+            },
+        )
+    }
 
-            // Slow down only once per API call
-            if first {
-                self.slow_down.store(true, Ordering::SeqCst);
+    fn send(&self, request: RequestBuilder) -> Fallible<Response> {
+        let request = request.build()?;
+        let token = request
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| {
+                self.config
+                    .github_tokens
+                    .iter()
+                    .position(|token| value == format!("token {}", token))
+            });
+
+        let token_id = token.map(|index| self.token_ids[index].as_str());
+        let response = http::instrumented(self.summary, token_id, request, |request| {
+            if let Some(replayer) = &self.replayer {
+                replayer.execute(request)
+            } else if let Some(recorder) = &self.recorder {
+                recorder.execute(&self.client, request)
+            } else {
+                http::execute(&self.client, request)
             }
+        })?;
 
-            ::std::thread::sleep(wait);
+        if let Some(index) = token {
+            self.record_rate_limit(index, &response);
+        }
 
-            // Stop doubling the time after a few increments, to avoid waiting too long
-            // This is still a request every ~10 minutes
-            if wait.as_secs() < 640 {
-                wait *= 2;
+        // Bad credentials are reported as a token failure once no other token is left. They're
+        // usually a 401, but some endpoints answer with a 403 with the same message instead.
+        let (response, rejected) = match response.status() {
+            StatusCode::UNAUTHORIZED => (response, true),
+            StatusCode::FORBIDDEN => has_bad_credentials(response)?,
+            _ => (response, false),
+        };
+        if rejected {
+            if let Some(index) = token {
+                if self.quarantine(index) {
+                    return Err(TokenQuarantined.into());
+                }
+                return Err(ErrorKind::TokenFailure.into());
             }
-
-            first = false;
         }
+        Ok(response)
     }
 
     fn build_request(&self, method: Method, url: &str) -> RequestBuilder {
+        self.build_request_with_token(method, url, self.token())
+    }
+
+    fn build_request_with_token(&self, method: Method, url: &str, token: &str) -> RequestBuilder {
         let url = if !url.starts_with("https://") {
             Cow::Owned(format!("https://api.github.com/{}", url))
         } else {
@@ -169,24 +431,21 @@ impl<'conf> GitHubApi<'conf> {
 
         self.client
             .request(method, url.as_ref())
-            .header(
-                header::AUTHORIZATION,
-                format!("token {}", self.config.github_token),
-            )
-            .header(header::USER_AGENT, USER_AGENT)
+            .header(header::AUTHORIZATION, format!("token {}", token))
+            .header(header::USER_AGENT, self.config.github_user_agent.as_str())
     }
 
     fn graphql<T: DeserializeOwned, V: Serialize>(&self, query: &str, variables: V) -> Fallible<T> {
         self.retry(|| {
-            let resp: GraphResponse<T> = self
-                .build_request(Method::POST, "graphql")
-                .json(&json!({
+            let body = self
+                .send(self.build_request(Method::POST, "graphql").json(&json!({
                     "query": query,
                     "variables": variables,
-                }))
-                .send()?
+                })))?
                 .handle_errors()?
-                .json()?;
+                .bytes()?;
+            drift::graphql_response(self.summary, &body);
+            let resp: GraphResponse<T> = serde_json::from_slice(&body)?;
 
             if let Some(data) = resp.data {
                 if let Some(errors) = resp.errors {
@@ -204,6 +463,13 @@ impl<'conf> GitHubApi<'conf> {
 
                 Ok(data)
             } else if let Some(mut errors) = resp.errors {
+                if errors
+                    .iter()
+                    .any(|error| error.type_.as_deref() == Some("RATE_LIMITED"))
+                {
+                    return Err(ErrorKind::RateLimitExhausted.into());
+                }
+
                 Err(err_msg(errors.pop().unwrap().message)
                     .context("GitHub GraphQL call failed")
                     .into())
@@ -223,15 +489,16 @@ impl<'conf> GitHubApi<'conf> {
     }
 
     pub fn scrape_repositories(&self, since: usize) -> Fallible<Vec<Option<RestRepository>>> {
-        self.retry(|| {
+        let repos = self.retry(|| {
             let resp = self
-                .build_request(Method::GET, &format!("repositories?since={}", since))
-                .send()?
+                .send(self.build_request(Method::GET, &format!("repositories?since={}", since)))?
                 .handle_errors()?;
 
             let status = resp.status();
             if status == StatusCode::OK {
-                Ok(resp.json()?)
+                let body = resp.bytes()?;
+                drift::rest_repositories(self.summary, &body);
+                Ok(serde_json::from_slice::<Vec<serde_json::Value>>(&body)?)
             } else {
                 let error: GitHubError = resp.json()?;
                 if error.message.contains("abuse") {
@@ -250,56 +517,344 @@ impl<'conf> GitHubApi<'conf> {
                         .into())
                 }
             }
-        })
+        })?;
+        self.parse_entities("REST repository", repos)
+    }
+
+    /// Parse the entities of a response one by one, so that a malformed one is skipped instead
+    /// of failing the whole response, unless strict parsing is enabled.
+    fn parse_entities<T: DeserializeOwned>(
+        &self,
+        what: &str,
+        values: Vec<serde_json::Value>,
+    ) -> Fallible<Vec<Option<T>>> {
+        values
+            .into_iter()
+            .map(|value| self.parse_entity(what, value))
+            .collect()
     }
 
-    pub fn load_repositories(&self, node_ids: &[String]) -> Fallible<Vec<Option<GraphRepository>>> {
+    fn parse_entity<T: DeserializeOwned>(
+        &self,
+        what: &str,
+        value: serde_json::Value,
+    ) -> Fallible<Option<T>> {
+        match serde_json::from_value(value) {
+            Ok(entity) => Ok(entity),
+            Err(err) if self.config.strict_parsing => Err(Error::from(err)
+                .context(format!("malformed {} in a GitHub API response", what))
+                .into()),
+            Err(err) => {
+                warn!("skipping a malformed {}: {}", what, err);
+                self.summary.record_error(&format!("malformed {}", what));
+                Ok(None)
+            }
+        }
+    }
+
+    /// Account the points spent by a GraphQL query, warning when the quota is running out.
+    fn record_cost(&self, rate_limit: &GraphRateLimit) {
+        Summary::incr(&self.summary.graphql_points, usize::from(rate_limit.cost));
+        if let Some(remaining) = rate_limit.remaining {
+            debug!("{} GraphQL points left", remaining);
+            if remaining < LOW_GRAPHQL_QUOTA {
+                warn!("only {} GraphQL points left", remaining);
+                self.slow_down.store(true, Ordering::SeqCst);
+            }
+        }
+    }
+
+    pub fn load_repositories(
+        &self,
+        node_ids: &[String],
+        extras: &QueryExtras,
+    ) -> Fallible<Vec<Option<GraphRepository>>> {
         let data: GraphRepositories = self.graphql(
-            GRAPHQL_QUERY_REPOSITORIES,
+            &self.query_repositories,
             json!({
                 "ids": node_ids,
+                "details": !self.config.discovery_only,
+                "counts": extras.counts,
+                "ci": extras.ci_status,
+                "activity": extras.activity_since.is_some(),
+                "since": extras.activity_since,
+            }),
+        )?;
+
+        self.record_cost(&data.rate_limit);
+        let nodes = self.parse_entities::<GraphRepository>("GraphQL repository", data.nodes)?;
+        for repo in nodes.iter().flatten() {
+            drift::graphql_repository(self.summary, repo);
+        }
+        Ok(nodes)
+    }
+
+    /// Fetch a page of the repositories matching a search query, along with the total number of
+    /// matches (of which only the first 1000 can be fetched).
+    pub fn search_repositories(&self, query: &str, cursor: Option<&str>) -> Fallible<SearchPage> {
+        let data: GraphSearch = self.graphql(
+            &self.query_search,
+            json!({
+                "query": query,
+                "cursor": cursor,
             }),
         )?;
 
-        assert!(
-            data.rate_limit.cost <= 1,
-            "load repositories query too costly"
+        self.record_cost(&data.rate_limit);
+        Ok(SearchPage {
+            count: data.search.repository_count,
+            repos: self
+                .parse_entities("GraphQL search result", data.search.nodes)?
+                .into_iter()
+                .flatten()
+                .collect(),
+            cursor: if data.search.page_info.has_next_page {
+                data.search.page_info.end_cursor
+            } else {
+                None
+            },
+        })
+    }
+
+    /// Load the profiles of some accounts, returning them in the same order (`None` for the ones
+    /// that don't exist anymore) alongside the cost of the query and the remaining GraphQL quota.
+    pub fn load_owners(&self, logins: &[String]) -> Fallible<(Vec<Option<GraphOwner>>, u64, u64)> {
+        // The owners are queried with aliases, as there is no way to look up a list of logins
+        let mut params = Vec::new();
+        let mut fields = Vec::new();
+        let mut variables = serde_json::Map::new();
+        for (i, login) in logins.iter().enumerate() {
+            params.push(format!("$l{}: String!", i));
+            fields.push(format!(
+                "o{i}: repositoryOwner(login: $l{i}) {{ __typename login \
+                 ... on User {{ company location }} ... on Organization {{ location }} }}",
+                i = i
+            ));
+            variables.insert(format!("l{}", i), login.clone().into());
+        }
+        let query = format!(
+            "query({}) {{ {} rateLimit {{ cost remaining }} }}",
+            params.join(", "),
+            fields.join(" ")
         );
-        Ok(data.nodes)
+
+        let mut data: GraphOwners = self.graphql(&query, variables)?;
+        self.record_cost(&data.rate_limit);
+        let owners = (0..logins.len())
+            .map(|i| match data.owners.remove(&format!("o{}", i)) {
+                Some(owner) => self.parse_entity::<GraphOwner>("GraphQL owner", owner),
+                None => Ok(None),
+            })
+            .collect::<Fallible<Vec<_>>>()?;
+        for owner in owners.iter().flatten() {
+            drift::graphql_owner(self.summary, owner);
+        }
+        Ok((
+            owners,
+            u64::from(data.rate_limit.cost),
+            data.rate_limit.remaining.unwrap_or(0),
+        ))
+    }
+
+    /// Find when a file was first committed to the default branch of each repository. The oldest
+    /// commit is reached by building a history cursor (`<oid> <offset>`) from the number of
+    /// commits touching the file, as the history can't be walked backwards.
+    pub fn first_commits(&self, node_ids: &[String], path: &str) -> Fallible<Vec<Option<String>>> {
+        let mut dates = vec![None; node_ids.len()];
+        let mut oldest = Vec::new();
+
+        let items = node_ids
+            .iter()
+            .map(|id| (id.as_str(), None))
+            .collect::<Vec<_>>();
+        for (i, commit) in self.load_histories(&items, path)?.into_iter().enumerate() {
+            let (oid, history) = match commit.and_then(|c| Some((c.oid?, c.history?))) {
+                Some(found) => found,
+                None => continue,
+            };
+            match history.total_count {
+                // The most recent commit is also the first one
+                1 => dates[i] = history.nodes.into_iter().next().map(|c| c.committed_date),
+                count if count > 1 => oldest.push((i, format!("{} {}", oid, count - 2))),
+                _ => {}
+            }
+        }
+
+        if !oldest.is_empty() {
+            let items = oldest
+                .iter()
+                .map(|(i, cursor)| (node_ids[*i].as_str(), Some(cursor.as_str())))
+                .collect::<Vec<_>>();
+            let histories = self.load_histories(&items, path)?;
+            for ((i, _), commit) in oldest.iter().zip(histories) {
+                dates[*i] = commit
+                    .and_then(|commit| commit.history)
+                    .and_then(|history| history.nodes.into_iter().next())
+                    .map(|commit| commit.committed_date);
+            }
+        }
+
+        Ok(dates)
     }
 
-    pub fn file_exists(&self, repo: &GraphRepository, path: &str) -> Fallible<bool> {
+    /// Load the history of a file in the default branch of some repositories, starting after the
+    /// provided cursors.
+    fn load_histories(
+        &self,
+        items: &[(&str, Option<&str>)],
+        path: &str,
+    ) -> Fallible<Vec<Option<GraphHistoryCommit>>> {
+        let mut params = vec!["$path: String!".to_string()];
+        let mut fields = Vec::new();
+        let mut variables = serde_json::Map::new();
+        variables.insert("path".into(), path.into());
+        for (i, (id, cursor)) in items.iter().enumerate() {
+            params.push(format!("$i{}: ID!", i));
+            variables.insert(format!("i{}", i), (*id).into());
+            let after = match cursor {
+                Some(cursor) => {
+                    params.push(format!("$c{}: String", i));
+                    variables.insert(format!("c{}", i), (*cursor).into());
+                    format!(", after: $c{}", i)
+                }
+                None => String::new(),
+            };
+            fields.push(format!(
+                "r{i}: node(id: $i{i}) {{ ... on Repository {{ defaultBranchRef {{ target {{ \
+                 ... on Commit {{ oid history(path: $path, first: 1{after}) {{ \
+                 totalCount nodes {{ committedDate }} }} }} }} }} }} }}",
+                i = i,
+                after = after
+            ));
+        }
+        let query = format!(
+            "query({}) {{ {} rateLimit {{ cost remaining }} }}",
+            params.join(", "),
+            fields.join(" ")
+        );
+
+        let mut data: GraphHistories = self.graphql(&query, variables)?;
+        self.record_cost(&data.rate_limit);
+        Ok((0..items.len())
+            .map(|i| {
+                data.repos
+                    .remove(&format!("r{}", i))
+                    .and_then(|repo| repo)
+                    .and_then(|repo| repo.default_branch_ref)
+                    .and_then(|ref_| ref_.target)
+            })
+            .collect())
+    }
+
+    /// Fetch the content of a file at a specific revision, returning `None` if it doesn't exist.
+    pub fn file_content(&self, repo: &str, rev: &str, path: &str) -> Fallible<Option<String>> {
         let url = format!(
             "https://raw.githubusercontent.com/{}/{}/{}",
-            repo.name_with_owner,
-            if let Some(ref_) = &repo.default_branch_ref {
-                &ref_.name
-            } else {
-                "master"
-            },
-            path,
+            repo, rev, path
         );
 
         self.retry(|| {
             let resp = self
-                .build_request(Method::GET, &url)
-                .send()?
+                .send(self.build_request(Method::GET, &url))?
                 .handle_errors()?;
             match resp.status() {
-                StatusCode::OK => Ok(true),
-                StatusCode::NOT_FOUND => Ok(false),
+                StatusCode::OK => Ok(Some(resp.text()?)),
+                StatusCode::NOT_FOUND => Ok(None),
                 status => Err(
                     err_msg(format!("GitHub API returned status code {}", status))
-                        .context(format!(
-                            "failed to fetch file {} from repo {}",
-                            path, repo.name_with_owner,
-                        ))
+                        .context(format!("failed to fetch file {} from repo {}", path, repo))
                         .into(),
                 ),
             }
         })
     }
 
+    /// Fetch the paths of all the files in the repository at a specific revision, returning
+    /// `None` if the repository or the revision doesn't exist. Trees bigger than
+    /// `RUST_REPOS_TREE_MAX_SIZE` or slower to fetch than `RUST_REPOS_TREE_TIMEOUT` are returned
+    /// empty and marked as truncated, instead of being retried.
+    pub fn tree(&self, repo: &str, rev: &str) -> Fallible<Option<RepoTree>> {
+        let url = format!("repos/{}/git/trees/{}?recursive=1", repo, rev);
+        let too_big = || {
+            Ok(Some(RepoTree {
+                paths: Vec::new(),
+                truncated: true,
+            }))
+        };
+
+        self.retry(|| {
+            let request = self
+                .build_request(Method::GET, &url)
+                .timeout(Duration::from_secs(self.config.tree_timeout));
+            let resp = match self.send(request) {
+                Ok(resp) => resp.handle_errors()?,
+                Err(ref err)
+                    if is_timeout(err) || err.downcast_ref::<ResponseTooLarge>().is_some() =>
+                {
+                    return too_big()
+                }
+                Err(err) => return Err(err),
+            };
+            match resp.status() {
+                StatusCode::OK => {}
+                // Empty repositories have no tree
+                StatusCode::NOT_FOUND | StatusCode::CONFLICT => return Ok(None),
+                status => {
+                    return Err(
+                        err_msg(format!("GitHub API returned status code {}", status))
+                            .context(format!("failed to fetch the tree of repo {}", repo))
+                            .into(),
+                    )
+                }
+            }
+
+            // Stop reading as soon as the limit is exceeded
+            let limit = self.config.tree_max_size;
+            let mut body = Vec::new();
+            match resp.take(limit + 1).read_to_end(&mut body) {
+                Ok(_) => {}
+                Err(err) if err.kind() == io::ErrorKind::TimedOut => return too_big(),
+                Err(err) => return Err(err.into()),
+            }
+            if body.len() as u64 > limit {
+                return too_big();
+            }
+
+            let tree: RestTree = serde_json::from_slice(&body)?;
+            Ok(Some(RepoTree {
+                paths: tree
+                    .tree
+                    .into_iter()
+                    .filter(|entry| entry.kind == "blob")
+                    .map(|entry| entry.path)
+                    .collect(),
+                truncated: tree.truncated,
+            }))
+        })
+    }
+
+    pub fn rate_limits(&self, token: &str) -> Fallible<RateLimits> {
+        self.retry(|| {
+            let resp = self
+                .send(self.build_request_with_token(Method::GET, "rate_limit", token))?
+                .handle_errors()?;
+
+            let status = resp.status();
+            if status == StatusCode::OK {
+                let limits: RateLimitsResponse = resp.json()?;
+                Ok(limits.resources)
+            } else {
+                let error: GitHubError = resp.json()?;
+                Err(err_msg(error.message)
+                    .context(format!(
+                        "GitHub API call failed with status code: {}",
+                        status
+                    ))
+                    .into())
+            }
+        })
+    }
+
     pub fn should_slow_down(&self) -> bool {
         self.slow_down.swap(false, Ordering::SeqCst)
     }
@@ -315,11 +870,50 @@ struct GitHubError {
 #[derive(Deserialize)]
 pub struct RestRepository {
     pub id: usize,
-    pub full_name: String,
     pub node_id: String,
+    pub full_name: String,
     pub fork: bool,
 }
 
+/// Files of a repository at a specific revision.
+pub struct RepoTree {
+    pub paths: Vec<String>,
+    /// Whether some files are missing, because GitHub truncated the tree or it exceeded the
+    /// configured limits.
+    pub truncated: bool,
+}
+
+#[derive(Deserialize)]
+struct RestTree {
+    tree: Vec<RestTreeEntry>,
+    truncated: bool,
+}
+
+#[derive(Deserialize)]
+struct RestTreeEntry {
+    path: String,
+    #[serde(rename = "type")]
+    kind: String,
+}
+
+#[derive(Deserialize)]
+struct RateLimitsResponse {
+    resources: RateLimits,
+}
+
+#[derive(Deserialize)]
+pub struct RateLimits {
+    pub core: RateLimit,
+    pub graphql: RateLimit,
+}
+
+#[derive(Deserialize)]
+pub struct RateLimit {
+    pub limit: u64,
+    pub remaining: u64,
+    pub reset: u64,
+}
+
 #[derive(Deserialize)]
 struct GraphResponse<T> {
     data: Option<T>,
@@ -330,27 +924,335 @@ struct GraphResponse<T> {
 #[derive(Deserialize)]
 struct GraphRateLimit {
     cost: u16,
+    #[serde(default)]
+    remaining: Option<u64>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GraphOwners {
+    rate_limit: GraphRateLimit,
+    #[serde(flatten)]
+    owners: HashMap<String, serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GraphHistories {
+    rate_limit: GraphRateLimit,
+    #[serde(flatten)]
+    repos: HashMap<String, Option<GraphHistoryRepository>>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GraphHistoryRepository {
+    default_branch_ref: Option<GraphHistoryRef>,
+}
+
+#[derive(Deserialize)]
+struct GraphHistoryRef {
+    target: Option<GraphHistoryCommit>,
+}
+
+/// Missing fields when the default branch doesn't point to a commit.
+#[derive(Deserialize)]
+struct GraphHistoryCommit {
+    oid: Option<String>,
+    history: Option<GraphHistory>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GraphHistory {
+    total_count: u64,
+    nodes: Vec<GraphCommit>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GraphCommit {
+    committed_date: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GraphOwner {
+    #[serde(rename = "__typename")]
+    pub kind: String,
+    pub login: String,
+    #[serde(default)]
+    pub company: Option<String>,
+    #[serde(default)]
+    pub location: Option<String>,
 }
 
 #[derive(Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct GraphRepositories {
-    nodes: Vec<Option<GraphRepository>>,
+    nodes: Vec<serde_json::Value>,
     rate_limit: GraphRateLimit,
 }
 
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GraphSearch {
+    search: GraphSearchResults,
+    rate_limit: GraphRateLimit,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GraphSearchResults {
+    repository_count: u64,
+    page_info: GraphPageInfo,
+    nodes: Vec<serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GraphPageInfo {
+    has_next_page: bool,
+    end_cursor: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GraphSearchRepository {
+    pub id: String,
+    pub database_id: Option<u64>,
+    pub is_fork: bool,
+}
+
+/// A page of search results, with the cursor of the next one if there are more.
+pub struct SearchPage {
+    pub count: u64,
+    pub repos: Vec<GraphSearchRepository>,
+    pub cursor: Option<String>,
+}
+
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct GraphRepository {
     pub id: String,
+    pub database_id: Option<u64>,
     pub name_with_owner: String,
+    pub description: Option<String>,
+    pub stargazer_count: u64,
+    pub is_archived: bool,
+    pub is_empty: bool,
+    pub is_disabled: bool,
+    pub license_info: Option<GraphLicense>,
     pub default_branch_ref: Option<GraphRef>,
+    pub template_repository: Option<GraphTemplate>,
     pub languages: GraphLanguages,
+    pub repository_topics: Option<GraphTopics>,
+    pub rust_toolchain_toml: Option<GraphBlob>,
+    pub rust_toolchain: Option<GraphBlob>,
+    pub root_tree: Option<GraphTree>,
+    pub src_tree: Option<GraphTree>,
+    pub cargo_toml: Option<GraphBlob>,
+    pub funding_file: Option<serde_json::Value>,
+    pub funding_links: Option<Vec<serde_json::Value>>,
+    pub open_issues: Option<GraphCount>,
+    pub pull_requests: Option<GraphCount>,
+    pub ci_ref: Option<GraphCiRef>,
+    pub mentionable_users: Option<GraphCount>,
+    pub activity_ref: Option<GraphActivityRef>,
+}
+
+impl GraphRepository {
+    /// Whether sponsorship is configured, either with a `FUNDING.yml` in the repository or with
+    /// funding links inherited from the owner. `None` if the details weren't loaded.
+    pub fn has_funding(&self) -> Option<bool> {
+        let links = self.funding_links.as_ref()?;
+        Some(!links.is_empty() || self.funding_file.is_some())
+    }
+
+    /// Names of the files at the root of the default branch.
+    pub fn root_files(&self) -> impl Iterator<Item = &str> {
+        self.root_tree
+            .iter()
+            .flat_map(|tree| tree.entries.iter().flatten())
+            .filter(|entry| entry.kind == "blob")
+            .map(|entry| entry.name.as_str())
+    }
+
+    /// Whether a file exists at the root of the default branch.
+    pub fn has_root_file(&self, name: &str) -> bool {
+        has_entry(self.root_tree.as_ref(), name, "blob")
+    }
+
+    /// Whether an entry of the given kind (`blob` or `tree`) exists in the `src` directory.
+    pub fn has_src_entry(&self, name: &str, kind: &str) -> bool {
+        has_entry(self.src_tree.as_ref(), name, kind)
+    }
+
+    /// Combined outcome of the CI of the default branch (`pass`, `fail`, `pending` or `none`),
+    /// if loaded.
+    pub fn ci_status(&self) -> Option<&'static str> {
+        let target = self.ci_ref.as_ref()?.target.as_ref();
+        let state = target
+            .and_then(|target| target.status_check_rollup.as_ref())
+            .map(|rollup| rollup.state.as_str());
+        Some(match state {
+            Some("SUCCESS") => "pass",
+            Some("FAILURE") | Some("ERROR") => "fail",
+            Some(_) => "pending",
+            None => "none",
+        })
+    }
+
+    /// Number of commits in the default branch since the activity cutoff, if loaded.
+    pub fn recent_commits(&self) -> Option<u64> {
+        self.activity_ref
+            .as_ref()
+            .and_then(|ref_| ref_.target.as_ref())
+            .and_then(|target| target.history.as_ref())
+            .map(|history| history.total_count)
+    }
+
+    pub fn topics(&self) -> impl Iterator<Item = &str> {
+        self.repository_topics
+            .iter()
+            .flat_map(|topics| topics.nodes.iter().flatten())
+            .map(|node| node.topic.name.as_str())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GraphCount {
+    pub total_count: u64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GraphCiRef {
+    pub target: Option<GraphCiCommit>,
+}
+
+/// The rollup is missing when no status or check was reported.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GraphCiCommit {
+    pub status_check_rollup: Option<GraphStatusRollup>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GraphStatusRollup {
+    pub state: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GraphActivityRef {
+    pub target: Option<GraphActivityCommit>,
+}
+
+/// The history is missing when the default branch doesn't point to a commit.
+#[derive(Debug, Deserialize)]
+pub struct GraphActivityCommit {
+    pub history: Option<GraphCount>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GraphTopics {
+    pub nodes: Vec<Option<GraphTopicNode>>,
 }
 
 #[derive(Debug, Deserialize)]
+pub struct GraphTopicNode {
+    pub topic: GraphTopic,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GraphTopic {
+    pub name: String,
+}
+
+/// Whether a response says the credentials of the request are invalid, returning it with its
+/// body still readable.
+fn has_bad_credentials(response: Response) -> Fallible<(Response, bool)> {
+    let (body, response) = response.buffered()?;
+    let rejected = String::from_utf8_lossy(&body).contains("Bad credentials");
+    Ok((response, rejected))
+}
+
+fn has_entry(tree: Option<&GraphTree>, name: &str, kind: &str) -> bool {
+    tree.and_then(|tree| tree.entries.as_ref())
+        .map(|entries| {
+            entries
+                .iter()
+                .any(|entry| entry.name == name && entry.kind == kind)
+        })
+        .unwrap_or(false)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GraphTree {
+    /// Missing when the object is not a tree.
+    pub entries: Option<Vec<GraphTreeEntry>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GraphTreeEntry {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GraphLicense {
+    pub spdx_id: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GraphBlob {
+    pub text: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct GraphLanguages {
-    pub nodes: Vec<Option<GraphLanguage>>,
+    pub total_size: u64,
+    pub edges: Vec<Option<GraphLanguageEdge>>,
+}
+
+impl GraphLanguages {
+    /// Bytes of code written in a language, or `None` if the language is not used.
+    pub fn size_of(&self, name: &str) -> Option<u64> {
+        self.edges
+            .iter()
+            .flatten()
+            .find(|edge| edge.node.name == name)
+            .map(|edge| edge.size)
+    }
+
+    /// Percentage of the code written in a language, or `None` if the language is not used.
+    pub fn share_of(&self, name: &str) -> Option<u64> {
+        let size = self.size_of(name)?;
+        if self.total_size == 0 {
+            return None;
+        }
+        Some((size * 100 + self.total_size / 2) / self.total_size)
+    }
+
+    /// Whether a language is the one with the most code in the repository.
+    pub fn is_top(&self, name: &str) -> bool {
+        // The languages are loaded sorted by size
+        self.edges
+            .iter()
+            .flatten()
+            .next()
+            .map(|edge| edge.node.name == name)
+            .unwrap_or(false)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GraphLanguageEdge {
+    pub size: u64,
+    pub node: GraphLanguage,
 }
 
 #[derive(Debug, Deserialize)]
@@ -358,14 +1260,87 @@ pub struct GraphLanguage {
     pub name: String,
 }
 
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GraphTemplate {
+    pub name_with_owner: String,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct GraphRef {
-    pub name: String,
+    pub target: Option<GraphObject>,
 }
 
 #[derive(Debug, Deserialize)]
-#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
-pub enum GitHubErrorType {
-    NotFound,
-    Other(String),
+pub struct GraphObject {
+    pub oid: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GitHubApi;
+    use config::Config;
+    use serde_json;
+    use std::fs;
+    use std::sync::atomic::Ordering;
+    use summary::Summary;
+
+    #[test]
+    fn test_replay_page() {
+        let dir =
+            ::std::env::temp_dir().join(format!("rust-repos-api-{}-replay", ::std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        // Stored like the recorder does, with the token redacted
+        let exchange = json!({
+            "request": {
+                "method": "GET",
+                "url": "https://api.github.com/repositories?since=41",
+                "headers": {"authorization": "[REDACTED]"},
+                "body": null,
+            },
+            "response": {
+                "status": 200,
+                "headers": {"content-type": "application/json"},
+                "body": serde_json::to_string(&json!([
+                    {"id": 42, "node_id": "MDEwOlJlcG9zaXRvcnk0Mg==", "full_name": "foo/bar", "fork": false},
+                    {"id": 43, "node_id": "MDEwOlJlcG9zaXRvcnk0Mw==", "full_name": "foo/baz", "fork": true},
+                ])).unwrap(),
+            },
+        });
+        fs::write(
+            dir.join("00000000.json"),
+            serde_json::to_vec(&exchange).unwrap(),
+        )
+        .unwrap();
+
+        let mut config = Config::from_env(dir.clone()).unwrap();
+        config.github_tokens = vec!["secret".into()];
+        config.contact = Some("test@example.com".into());
+        config.replay_http = Some(dir.clone());
+        let summary = Summary::default();
+        let api = GitHubApi::new(&config, &summary).unwrap();
+
+        let repos = api
+            .scrape_repositories(41)
+            .unwrap()
+            .into_iter()
+            .flatten()
+            .map(|repo| (repo.id, repo.full_name, repo.fork))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            repos,
+            vec![
+                (42, "foo/bar".to_string(), false),
+                (43, "foo/baz".to_string(), true),
+            ]
+        );
+        assert_eq!(summary.api_calls.load(Ordering::SeqCst), 1);
+
+        // Only the recorded requests can be replayed
+        assert!(api.scrape_repositories(43).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
 }
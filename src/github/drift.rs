@@ -0,0 +1,227 @@
+// Copyright (c) 2018 Pietro Albini <pietro@pietroalbini.org>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies
+// of the Software, and to permit persons to whom the Software is furnished to do
+// so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Detection of changes in the shape of the GitHub API responses.
+//!
+//! The responses are parsed leniently, ignoring the fields the scraper doesn't use, so a change
+//! made by GitHub could silently break some columns. The probes here parse the responses again
+//! with `deny_unknown_fields` and closed enums, and record everything unexpected in the summary,
+//! without ever failing the request.
+
+use github::api::{GraphOwner, GraphRepository};
+use serde::de::{DeserializeOwned, IgnoredAny, IntoDeserializer};
+use serde_json;
+use summary::Summary;
+
+/// Repository returned by the REST API when listing all the repositories.
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+#[allow(dead_code)]
+struct RestRepository {
+    id: IgnoredAny,
+    node_id: IgnoredAny,
+    name: IgnoredAny,
+    full_name: IgnoredAny,
+    private: IgnoredAny,
+    owner: Option<RestOwner>,
+    html_url: IgnoredAny,
+    description: IgnoredAny,
+    fork: IgnoredAny,
+    url: IgnoredAny,
+    forks_url: IgnoredAny,
+    keys_url: IgnoredAny,
+    collaborators_url: IgnoredAny,
+    teams_url: IgnoredAny,
+    hooks_url: IgnoredAny,
+    issue_events_url: IgnoredAny,
+    events_url: IgnoredAny,
+    assignees_url: IgnoredAny,
+    branches_url: IgnoredAny,
+    tags_url: IgnoredAny,
+    blobs_url: IgnoredAny,
+    git_tags_url: IgnoredAny,
+    git_refs_url: IgnoredAny,
+    trees_url: IgnoredAny,
+    statuses_url: IgnoredAny,
+    languages_url: IgnoredAny,
+    stargazers_url: IgnoredAny,
+    contributors_url: IgnoredAny,
+    subscribers_url: IgnoredAny,
+    subscription_url: IgnoredAny,
+    commits_url: IgnoredAny,
+    git_commits_url: IgnoredAny,
+    comments_url: IgnoredAny,
+    issue_comment_url: IgnoredAny,
+    contents_url: IgnoredAny,
+    compare_url: IgnoredAny,
+    merges_url: IgnoredAny,
+    archive_url: IgnoredAny,
+    downloads_url: IgnoredAny,
+    issues_url: IgnoredAny,
+    pulls_url: IgnoredAny,
+    milestones_url: IgnoredAny,
+    notifications_url: IgnoredAny,
+    labels_url: IgnoredAny,
+    releases_url: IgnoredAny,
+    deployments_url: IgnoredAny,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+#[allow(dead_code)]
+struct RestOwner {
+    login: IgnoredAny,
+    id: IgnoredAny,
+    node_id: IgnoredAny,
+    avatar_url: IgnoredAny,
+    gravatar_id: IgnoredAny,
+    url: IgnoredAny,
+    html_url: IgnoredAny,
+    followers_url: IgnoredAny,
+    following_url: IgnoredAny,
+    gists_url: IgnoredAny,
+    starred_url: IgnoredAny,
+    subscriptions_url: IgnoredAny,
+    organizations_url: IgnoredAny,
+    repos_url: IgnoredAny,
+    events_url: IgnoredAny,
+    received_events_url: IgnoredAny,
+    #[serde(rename = "type")]
+    kind: Option<OwnerKind>,
+    user_view_type: IgnoredAny,
+    site_admin: IgnoredAny,
+}
+
+/// Envelope of the GraphQL responses.
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+#[allow(dead_code)]
+struct GraphResponse {
+    data: IgnoredAny,
+    errors: Option<Vec<GraphError>>,
+    extensions: IgnoredAny,
+    message: IgnoredAny,
+    documentation_url: IgnoredAny,
+    status: IgnoredAny,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+#[allow(dead_code)]
+struct GraphError {
+    #[serde(rename = "type")]
+    kind: Option<GraphErrorKind>,
+    message: IgnoredAny,
+    path: IgnoredAny,
+    locations: IgnoredAny,
+    extensions: IgnoredAny,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+enum GraphErrorKind {
+    NotFound,
+    RateLimited,
+    Forbidden,
+    Internal,
+    MaxNodeLimitExceeded,
+    ExcessiveComplexity,
+    Unprocessable,
+}
+
+#[derive(Deserialize)]
+enum OwnerKind {
+    User,
+    Organization,
+    Bot,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum TreeEntryKind {
+    Blob,
+    Tree,
+    Commit,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+enum StatusState {
+    Success,
+    Failure,
+    Error,
+    Pending,
+    Expected,
+}
+
+/// Check the repositories of a page of the REST API.
+pub fn rest_repositories(summary: &Summary, body: &[u8]) {
+    probe::<Vec<Option<RestRepository>>>(summary, "REST repository", body);
+}
+
+/// Check the envelope of a GraphQL response.
+pub fn graphql_response(summary: &Summary, body: &[u8]) {
+    probe::<GraphResponse>(summary, "GraphQL response", body);
+}
+
+/// Check the values of the enums of a repository loaded with GraphQL.
+pub fn graphql_repository(summary: &Summary, repo: &GraphRepository) {
+    let trees = repo.root_tree.iter().chain(repo.src_tree.iter());
+    for entry in trees.flat_map(|tree| tree.entries.iter().flatten()) {
+        probe_value::<TreeEntryKind>(summary, "GraphQL tree entry type", &entry.kind);
+    }
+    let rollup = repo
+        .ci_ref
+        .as_ref()
+        .and_then(|ref_| ref_.target.as_ref())
+        .and_then(|target| target.status_check_rollup.as_ref());
+    if let Some(rollup) = rollup {
+        probe_value::<StatusState>(summary, "GraphQL status state", &rollup.state);
+    }
+}
+
+/// Check the values of the enums of an owner loaded with GraphQL.
+pub fn graphql_owner(summary: &Summary, owner: &GraphOwner) {
+    probe_value::<OwnerKind>(summary, "GraphQL owner type", &owner.kind);
+}
+
+fn probe<T: DeserializeOwned>(summary: &Summary, what: &str, body: &[u8]) {
+    if let Err(err) = serde_json::from_slice::<T>(body) {
+        record(summary, what, &err.to_string());
+    }
+}
+
+fn probe_value<T: DeserializeOwned>(summary: &Summary, what: &str, value: &str) {
+    let deserializer: serde::de::value::StrDeserializer<serde::de::value::Error> =
+        value.into_deserializer();
+    if let Err(err) = T::deserialize(deserializer) {
+        record(summary, what, &err.to_string());
+    }
+}
+
+/// Record the unexpected field or value mentioned by a deserialization error, warning the first
+/// time it's seen.
+fn record(summary: &Summary, what: &str, error: &str) {
+    let unexpected = error.split('`').nth(1).unwrap_or(error);
+    let drift = format!("{}: {}", what, unexpected);
+    if summary.record_drift(&drift) {
+        warn!("the GitHub API changed, unexpected {}", drift);
+    }
+}
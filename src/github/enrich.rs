@@ -0,0 +1,303 @@
+// Copyright (c) 2018 Pietro Albini <pietro@pietroalbini.org>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies
+// of the Software, and to permit persons to whom the Software is furnished to do
+// so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use config::Config;
+use data::{Data, Repo};
+use github::api::{GitHubApi, QueryExtras};
+use github::build_repo;
+use prelude::*;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, SystemTime};
+use summary::Summary;
+use utils::format_timestamp;
+
+/// Number of repositories loaded with a single GraphQL query.
+const ENRICH_BATCH: usize = 100;
+/// Commits made in this many days are counted in the `recent_commits` column.
+const RECENT_COMMITS_DAYS: u64 = 90;
+
+/// Columns that can be backfilled by the `enrich` subcommand.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    IsArchived,
+    IsEmpty,
+    IsDisabled,
+    Description,
+    Stars,
+    HeadSha,
+    Toolchain,
+    UsesNightly,
+    RustVersion,
+    Edition,
+    License,
+    RustAdoptedAt,
+    OpenIssues,
+    PullRequests,
+    CiStatus,
+    Readme,
+    HasFunding,
+    HasCodeOfConduct,
+    HasContributing,
+    CrateKind,
+    Template,
+    RustShare,
+    RustIsTopLanguage,
+    Categories,
+    Keywords,
+    Contributors,
+    RecentCommits,
+    Manifests,
+    TreeTruncated,
+}
+
+impl Field {
+    fn is_missing(self, repo: &Repo) -> bool {
+        match self {
+            Field::IsArchived => repo.is_archived.is_none(),
+            Field::IsEmpty => repo.is_empty.is_none(),
+            Field::IsDisabled => repo.is_disabled.is_none(),
+            Field::Description => repo.description.is_none(),
+            Field::Stars => repo.stars.is_none(),
+            Field::HeadSha => repo.head_sha.is_none(),
+            Field::Toolchain => repo.toolchain.is_none(),
+            Field::UsesNightly => repo.uses_nightly.is_none(),
+            Field::RustVersion => repo.rust_version.is_none(),
+            Field::Edition => repo.edition.is_none(),
+            Field::License => repo.license.is_none(),
+            Field::RustAdoptedAt => repo.rust_adopted_at.is_none(),
+            Field::OpenIssues => repo.open_issues.is_none(),
+            Field::PullRequests => repo.pull_requests.is_none(),
+            Field::CiStatus => repo.ci_status.is_none(),
+            Field::Readme => repo.readme.is_none(),
+            Field::HasFunding => repo.has_funding.is_none(),
+            Field::HasCodeOfConduct => repo.has_code_of_conduct.is_none(),
+            Field::HasContributing => repo.has_contributing.is_none(),
+            Field::CrateKind => repo.crate_kind.is_none(),
+            Field::Template => repo.template.is_none(),
+            Field::RustShare => repo.rust_share.is_none(),
+            Field::RustIsTopLanguage => repo.rust_is_top_language.is_none(),
+            Field::Categories => repo.categories.is_none(),
+            Field::Keywords => repo.keywords.is_none(),
+            Field::Contributors => repo.contributors.is_none(),
+            Field::RecentCommits => repo.recent_commits.is_none(),
+            Field::Manifests => repo.manifests.is_none(),
+            Field::TreeTruncated => repo.tree_truncated.is_none(),
+        }
+    }
+}
+
+impl FromStr for Field {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Fallible<Self> {
+        Ok(match s {
+            "is_archived" => Field::IsArchived,
+            "is_empty" => Field::IsEmpty,
+            "is_disabled" => Field::IsDisabled,
+            "description" => Field::Description,
+            "stars" => Field::Stars,
+            "head_sha" => Field::HeadSha,
+            "toolchain" => Field::Toolchain,
+            "uses_nightly" => Field::UsesNightly,
+            "rust_version" => Field::RustVersion,
+            "edition" => Field::Edition,
+            "license" => Field::License,
+            "rust_adopted_at" => Field::RustAdoptedAt,
+            "open_issues" => Field::OpenIssues,
+            "pull_requests" => Field::PullRequests,
+            "ci_status" => Field::CiStatus,
+            "readme" => Field::Readme,
+            "has_funding" => Field::HasFunding,
+            "has_code_of_conduct" => Field::HasCodeOfConduct,
+            "has_contributing" => Field::HasContributing,
+            "crate_kind" => Field::CrateKind,
+            "template" => Field::Template,
+            "rust_share" => Field::RustShare,
+            "rust_is_top_language" => Field::RustIsTopLanguage,
+            "categories" => Field::Categories,
+            "keywords" => Field::Keywords,
+            "contributors" => Field::Contributors,
+            "recent_commits" => Field::RecentCommits,
+            "manifests" => Field::Manifests,
+            "tree_truncated" => Field::TreeTruncated,
+            other => bail!("column {} can't be enriched", other),
+        })
+    }
+}
+
+/// Load again the stored repositories missing any of the `fields`, storing a fresh row for each
+/// of them, until `budget` GraphQL points are spent. Repositories that were deleted in the
+/// meantime keep their old row.
+pub fn enrich(
+    data: &Data,
+    config: &Config,
+    summary: &Summary,
+    fields: &[Field],
+    budget: Option<u64>,
+    should_stop: &AtomicBool,
+) -> Fallible<()> {
+    let gh = GitHubApi::new(config, summary)?;
+
+    // Only the latest row of each repository matters
+    let mut latest = HashMap::new();
+    let mut order = Vec::new();
+    for repo in data.repos("github")? {
+        let repo = repo?;
+        if !latest.contains_key(&repo.id) {
+            order.push(repo.id.clone());
+        }
+        latest.insert(repo.id.clone(), repo);
+    }
+    let ids = order
+        .into_iter()
+        .filter(|id| fields.iter().any(|field| field.is_missing(&latest[id])))
+        .collect::<Vec<_>>();
+    info!("enriching {} repositories", ids.len());
+
+    let extras = QueryExtras {
+        counts: config.issue_counts
+            || fields.contains(&Field::OpenIssues)
+            || fields.contains(&Field::PullRequests),
+        ci_status: config.ci_status || fields.contains(&Field::CiStatus),
+        activity_since: if fields.contains(&Field::Contributors)
+            || fields.contains(&Field::RecentCommits)
+        {
+            let cutoff = SystemTime::now() - Duration::from_secs(RECENT_COMMITS_DAYS * 24 * 3600);
+            Some(format_timestamp(cutoff))
+        } else {
+            None
+        },
+    };
+
+    let trees = fields.contains(&Field::Manifests) || fields.contains(&Field::TreeTruncated);
+
+    let spent = || summary.graphql_points.load(Ordering::SeqCst) as u64;
+    let mut enriched = 0;
+    for chunk in ids.chunks(ENRICH_BATCH) {
+        if should_stop.load(Ordering::SeqCst) {
+            break;
+        }
+        if let Some(budget) = budget {
+            if spent() >= budget {
+                warn!("the budget of {} points was spent, stopping", budget);
+                break;
+            }
+        }
+
+        let repos = gh.load_repositories(chunk, &extras)?;
+        // Walking the history is expensive, so it's only done when explicitly requested
+        let adopted_at = if fields.contains(&Field::RustAdoptedAt) {
+            Some(gh.first_commits(chunk, "Cargo.toml")?)
+        } else {
+            None
+        };
+
+        let mut rows = Vec::new();
+        for (i, repo) in repos.into_iter().enumerate() {
+            let repo = match repo {
+                Some(repo) => repo,
+                None => continue,
+            };
+            // Keep the columns only filled by this subcommand if they weren't loaded again
+            let old = &latest[&chunk[i]];
+            let mut row = build_repo(&repo, config, false);
+            row.rust_adopted_at = match &adopted_at {
+                Some(dates) => dates[i].clone(),
+                None => old.rust_adopted_at.clone(),
+            };
+            row.readme_excerpt = old.readme_excerpt.clone();
+            if !extras.counts {
+                row.open_issues = old.open_issues;
+                row.pull_requests = old.pull_requests;
+            }
+            if !extras.ci_status {
+                row.ci_status = old.ci_status.clone();
+            }
+            if extras.activity_since.is_none() {
+                row.contributors = old.contributors;
+                row.recent_commits = old.recent_commits;
+            }
+            if trees {
+                load_tree(&gh, config, summary, &mut row);
+            } else {
+                row.manifests = old.manifests;
+                row.tree_truncated = old.tree_truncated;
+            }
+            rows.push(row);
+        }
+        let stored = rows.len();
+        data.store_repos("github", rows)?;
+        Summary::incr(&summary.repos_seen, chunk.len());
+        Summary::incr(&summary.repos_stored, stored);
+        enriched += stored;
+    }
+    data.flush()?;
+
+    info!(
+        "enriched {} repositories, spending {} GraphQL points",
+        enriched,
+        spent()
+    );
+    Ok(())
+}
+
+/// Count the manifests in the whole file tree of the repository, ignoring the vendored ones.
+/// Failures and oversized trees only affect the repository itself: the columns are left empty
+/// or the tree marked as truncated.
+fn load_tree(gh: &GitHubApi, config: &Config, summary: &Summary, row: &mut Repo) {
+    let head_sha = match &row.head_sha {
+        Some(sha) => sha.clone(),
+        None => return,
+    };
+    let tree = match gh.tree(&row.name, &head_sha) {
+        Ok(Some(tree)) => tree,
+        Ok(None) => return,
+        Err(err) => {
+            warn!("failed to load the tree of {}: {}", row.name, err);
+            summary.record_error("tree");
+            return;
+        }
+    };
+
+    if tree.truncated {
+        warn!("the tree of {} is too big, it was truncated", row.name);
+    }
+    // Nothing is known about the trees exceeding the limits
+    if !tree.truncated || !tree.paths.is_empty() {
+        let manifests = tree
+            .paths
+            .iter()
+            .filter(|path| path.rsplit('/').next() == Some("Cargo.toml"))
+            .filter(|path| !is_vendored(path, &config.vendored_dirs))
+            .count();
+        row.manifests = Some(manifests as u64);
+    }
+    row.tree_truncated = Some(tree.truncated);
+}
+
+/// Whether the file is inside one of the directories containing third-party code, at any depth.
+fn is_vendored(path: &str, vendored_dirs: &[String]) -> bool {
+    let mut dirs = path.split('/');
+    // The last component is the file name
+    dirs.next_back();
+    dirs.any(|dir| vendored_dirs.iter().any(|vendored| vendored == dir))
+}
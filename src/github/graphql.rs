@@ -0,0 +1,69 @@
+// Copyright (c) 2018 Pietro Albini <pietro@pietroalbini.org>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies
+// of the Software, and to permit persons to whom the Software is furnished to do
+// so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Minification of the GraphQL documents sent to GitHub.
+
+/// Whether a character can be part of a name, which needs whitespace to be separated from the
+/// next name.
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// Remove the comments and the insignificant whitespace and commas from a GraphQL document,
+/// leaving the strings untouched.
+pub fn minify(document: &str) -> String {
+    let mut minified = String::with_capacity(document.len());
+    let mut chars = document.chars().peekable();
+    let mut separate = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '#' => {
+                while chars.peek().is_some_and(|&c| c != '\n') {
+                    chars.next();
+                }
+                separate = true;
+            }
+            c if c.is_whitespace() || c == ',' => separate = true,
+            '"' => {
+                minified.push(c);
+                while let Some(c) = chars.next() {
+                    minified.push(c);
+                    match c {
+                        '\\' => minified.extend(chars.next()),
+                        '"' => break,
+                        _ => {}
+                    }
+                }
+                separate = false;
+            }
+            c => {
+                if separate
+                    && is_name_char(c)
+                    && minified.chars().next_back().is_some_and(is_name_char)
+                {
+                    minified.push(' ');
+                }
+                minified.push(c);
+                separate = false;
+            }
+        }
+    }
+    minified
+}
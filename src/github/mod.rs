@@ -19,54 +19,371 @@
 // SOFTWARE.
 
 mod api;
+mod drift;
+mod enrich;
+mod graphql;
+mod popular;
 
+use canonical::RepoName;
 use config::Config;
-use crossbeam_utils::thread::scope;
-use data::{Data, Repo};
-use github::api::GitHubApi;
+use crossbeam_utils::thread::{scope, ScopedJoinHandle};
+use data::{Data, Owner, Repo};
+use denylist::Denylist;
+use exit::Outcome;
+use github::api::{GitHubApi, GraphRepository, QueryExtras, RestRepository};
+use http;
+use lockfile;
+use manifest;
 use prelude::*;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::time::{Duration, Instant};
-use utils::wrap_thread;
+use readme;
+use seed::Seed;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use summary::Summary;
+use systemd;
+use toolchain;
+use utils::{format_timestamp, panic_message, wrap_thread};
+
+pub use self::enrich::{enrich, Field};
+pub use self::popular::{scrape_popular, DEFAULT_MIN_STARS};
 
 static WANTED_LANG: &str = "Rust";
 
-fn load_thread(api: &GitHubApi, data: &Data, to_load: Vec<String>) -> Fallible<()> {
+/// Number of consecutive pages without repositories to load after which the scraper considers
+/// itself in a sparse region of the ID space, and stops pausing between pages.
+const SPARSE_PAGES_THRESHOLD: usize = 3;
+/// Maximum number of pages fetched without pausing while in a sparse region, to stay within the
+/// REST API rate limits.
+const SPARSE_BURST_PAGES: usize = 20;
+/// Minimum time between the flushes saving the processed ID while scraping.
+const PROCESSED_FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// GraphQL points spent fetching owners when no budget is provided.
+pub const DEFAULT_OWNERS_BUDGET: u64 = 100;
+/// Number of owner profiles fetched with a single GraphQL query.
+const OWNERS_BATCH: usize = 50;
+/// How long fetched owner profiles are reused before being fetched again.
+const OWNERS_CACHE_DAYS: u64 = 30;
+/// GraphQL points that fetching owners never spends, to avoid starving the scraper.
+const OWNERS_RESERVED_POINTS: u64 = 1000;
+
+/// Number of lockfiles fetched in parallel.
+const LOCKFILES_BATCH: usize = 16;
+
+fn skip_reason(
+    config: &Config,
+    denylist: &Denylist,
+    repo: &GraphRepository,
+) -> Option<&'static str> {
+    if denylist.contains_repo(&repo.name_with_owner) {
+        Some("owned by a denylisted account")
+    } else if config.skip_archived && repo.is_archived {
+        Some("archived")
+    } else if config.skip_empty && repo.is_empty {
+        Some("empty")
+    } else if config.skip_disabled && repo.is_disabled {
+        Some("disabled")
+    } else if repo.languages.size_of(WANTED_LANG).unwrap_or(0) < config.min_rust_bytes {
+        Some("too small")
+    } else if repo
+        .topics()
+        .any(|topic| config.exclude_topics.iter().any(|t| t == topic))
+    {
+        Some("tagged with an excluded topic")
+    } else if !config.include_topics.is_empty()
+        && !repo
+            .topics()
+            .any(|topic| config.include_topics.iter().any(|t| t == topic))
+    {
+        Some("not tagged with any of the included topics")
+    } else {
+        None
+    }
+}
+
+/// Load a batch of repositories, logging the errors. Returns whether the batch was handled: either
+/// stored, or queued to be loaded again by the next run when page retries are disabled.
+fn load_thread(
+    api: &GitHubApi,
+    data: &Data,
+    config: &Config,
+    denylist: &Denylist,
+    summary: &Summary,
+    to_load: Vec<String>,
+) -> bool {
+    let mut handled = false;
+    wrap_thread(|| {
+        // A panic while loading a batch must not bring down the whole scraper: the batch is
+        // instead queued to be loaded again on the next run.
+        let ids = to_load.clone();
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            load_repos(api, data, config, denylist, summary, to_load)
+        }));
+
+        match result {
+            Ok(Ok(())) => {
+                data.remove_from_retry_queue("github", &ids);
+                handled = true;
+                Ok(())
+            }
+            Ok(Err(err)) => {
+                summary.record_error("failed batch");
+                summary.record_error_kind(&err);
+                handled = queue_failed(data, config, &ids)?;
+                data.store_error("github", "failed batch", &ids, &err.to_string())?;
+                Err(err)
+            }
+            Err(payload) => {
+                let message = panic_message(&*payload);
+                error!(
+                    "loading a batch of {} repositories panicked: {}",
+                    ids.len(),
+                    message
+                );
+                summary.record_error("panicked batch");
+                handled = queue_failed(data, config, &ids)?;
+                data.store_error("github", "panicked batch", &ids, message)?;
+                Ok(())
+            }
+        }
+    });
+    handled
+}
+
+/// Queue a batch that failed to load to be loaded again by the next run, returning whether it was
+/// queued. With page retries the page is fetched again instead, without moving past it. Batches
+/// from the retry queue are never lost either way, as they're only removed from it once loaded.
+fn queue_failed(data: &Data, config: &Config, ids: &[String]) -> Fallible<bool> {
+    if config.page_retries.is_some() {
+        return Ok(false);
+    }
+    data.add_to_retry_queue("github", ids)?;
+    Ok(true)
+}
+
+/// Batches of the repositories in the retry queue, except the ones `stored` after the last saved
+/// progress, which are removed from it.
+fn retry_batches(data: &Data, stored: &HashSet<String>) -> Fallible<Vec<Vec<String>>> {
+    let (already_stored, retry_queue): (Vec<_>, Vec<_>) = data
+        .retry_queue("github")?
+        .into_iter()
+        .partition(|id| stored.contains(id));
+    data.remove_from_retry_queue("github", &already_stored);
+    if !retry_queue.is_empty() {
+        info!(
+            "loading {} repositories from the retry queue",
+            retry_queue.len()
+        );
+    }
+    Ok(retry_queue
+        .chunks(100)
+        .map(|chunk| chunk.to_vec())
+        .collect())
+}
+
+/// Convert a repository loaded from the GraphQL API into the row to store. Its content is left
+/// unknown in discovery-only mode.
+fn build_repo(repo: &GraphRepository, config: &Config, discovery_only: bool) -> Repo {
+    let has_cargo_toml = repo.has_root_file("Cargo.toml");
+    let has_cargo_lock = repo.has_root_file("Cargo.lock");
+
+    let toolchain = repo
+        .rust_toolchain_toml
+        .as_ref()
+        .or(repo.rust_toolchain.as_ref())
+        .and_then(|blob| blob.text.as_ref())
+        .and_then(|text| toolchain::parse_channel(text));
+    let manifest = repo
+        .cargo_toml
+        .as_ref()
+        .and_then(|blob| blob.text.as_ref())
+        .map(|text| manifest::parse(text))
+        .unwrap_or_default();
+    let crate_kind = manifest.crate_kind(
+        repo.has_src_entry("lib.rs", "blob"),
+        repo.has_src_entry("main.rs", "blob") || repo.has_src_entry("bin", "tree"),
+    );
+    let uses_nightly = !manifest.cargo_features.is_empty()
+        || toolchain
+            .as_ref()
+            .map(|channel| toolchain::category(channel).starts_with("nightly"))
+            .unwrap_or(false);
+
+    Repo {
+        id: repo.id.clone(),
+        name: repo.name_with_owner.clone(),
+        has_cargo_toml,
+        has_cargo_lock,
+        is_archived: Some(repo.is_archived),
+        is_empty: Some(repo.is_empty),
+        is_disabled: Some(repo.is_disabled),
+        last_checked_at: Some(format_timestamp(SystemTime::now())),
+        description: repo.description.clone(),
+        stars: Some(repo.stargazer_count),
+        toolchain,
+        // Left empty until the `enrich` pass looks at the content
+        uses_nightly: if discovery_only {
+            None
+        } else {
+            Some(uses_nightly)
+        },
+        rust_version: manifest.rust_version,
+        edition: manifest.edition,
+        license: repo
+            .license_info
+            .as_ref()
+            .and_then(|license| license.spdx_id.clone()),
+        head_sha: repo
+            .default_branch_ref
+            .as_ref()
+            .and_then(|ref_| ref_.target.as_ref())
+            .map(|target| target.oid.clone()),
+        template: repo
+            .template_repository
+            .as_ref()
+            .map(|template| template.name_with_owner.clone()),
+        rust_adopted_at: None,
+        open_issues: repo.open_issues.as_ref().map(|issues| issues.total_count),
+        pull_requests: repo.pull_requests.as_ref().map(|prs| prs.total_count),
+        ci_status: repo.ci_status().map(String::from),
+        readme: if discovery_only {
+            None
+        } else {
+            Some(
+                readme::find(repo.root_files())
+                    .map_or("none", readme::format)
+                    .to_string(),
+            )
+        },
+        readme_excerpt: None,
+        has_funding: repo.has_funding(),
+        has_code_of_conduct: community_file(repo, "code_of_conduct", discovery_only),
+        has_contributing: community_file(repo, "contributing", discovery_only),
+        crate_kind: crate_kind.map(String::from),
+        rust_share: repo.languages.share_of(WANTED_LANG),
+        rust_is_top_language: repo
+            .languages
+            .size_of(WANTED_LANG)
+            .map(|_| repo.languages.is_top(WANTED_LANG)),
+        categories: space_separated(&manifest.categories),
+        keywords: space_separated(&manifest.keywords),
+        contributors: repo
+            .mentionable_users
+            .as_ref()
+            .map(|users| users.total_count),
+        recent_commits: repo.recent_commits(),
+        manifests: None,
+        tree_truncated: None,
+        extra_files: config
+            .extra_files
+            .iter()
+            .map(|name| {
+                if discovery_only {
+                    None
+                } else {
+                    Some(repo.has_root_file(name))
+                }
+            })
+            .collect(),
+    }
+}
+
+/// Join a list of values with spaces, or `None` if the list is empty.
+fn space_separated(values: &[String]) -> Option<String> {
+    if values.is_empty() {
+        None
+    } else {
+        Some(values.join(" "))
+    }
+}
+
+/// Whether a community health file (such as `CODE_OF_CONDUCT.md`) exists at the root of the
+/// repository, with any extension. Left unknown in discovery-only mode.
+fn community_file(repo: &GraphRepository, stem: &str, discovery_only: bool) -> Option<bool> {
+    if discovery_only {
+        return None;
+    }
+    Some(repo.root_files().any(|name| {
+        let name = name.to_lowercase();
+        name == stem || name.split_once('.').map(|(base, _)| base) == Some(stem)
+    }))
+}
+
+fn load_repos(
+    api: &GitHubApi,
+    data: &Data,
+    config: &Config,
+    denylist: &Denylist,
+    summary: &Summary,
+    to_load: Vec<String>,
+) -> Fallible<()> {
     debug!(
         "collected {} non-fork repositories, loading them",
         to_load.len()
     );
 
-    let mut graph_repos = api.load_repositories(&to_load)?;
+    let extras = QueryExtras {
+        counts: config.issue_counts,
+        ci_status: config.ci_status,
+        ..QueryExtras::default()
+    };
+    let mut graph_repos = api.load_repositories(&to_load, &extras)?;
+    let mut rows = Vec::new();
+    let mut non_rust = Vec::new();
     for repo in graph_repos.drain(..).flatten() {
-        let mut found = false;
-        for lang in repo.languages.nodes.iter().filter_map(Option::as_ref) {
-            if lang.name == WANTED_LANG {
-                found = true;
-                break;
+        if repo.languages.size_of(WANTED_LANG).is_none() {
+            non_rust.extend(repo.database_id);
+        } else {
+            if let Some(reason) = skip_reason(config, denylist, &repo) {
+                debug!(
+                    "skipping {}: the repository is {}",
+                    repo.name_with_owner, reason
+                );
+                continue;
             }
-        }
-
-        if found {
-            let has_cargo_toml = api.file_exists(&repo, "Cargo.toml")?;
-            let has_cargo_lock = api.file_exists(&repo, "Cargo.lock")?;
-
-            data.store_repo(
-                "github",
-                Repo {
-                    id: repo.id,
-                    name: repo.name_with_owner.clone(),
-                    has_cargo_toml,
-                    has_cargo_lock,
-                },
-            )?;
 
+            let mut row = build_repo(&repo, config, config.discovery_only);
+            row.name = match RepoName::parse(&row.name) {
+                Ok(name) => name.to_string(),
+                Err(err) => {
+                    warn!("skipping a repository: {}", err);
+                    summary.record_error("invalid name");
+                    continue;
+                }
+            };
+            if config.readme_excerpt && row.description.as_ref().is_none_or(|d| d.is_empty()) {
+                if let (Some(name), Some(sha)) = (readme::find(repo.root_files()), &row.head_sha) {
+                    // The excerpt is optional, so failing to fetch it shouldn't fail the batch
+                    match api.file_content(&row.name, sha, name) {
+                        Ok(content) => {
+                            row.readme_excerpt = content.and_then(|content| {
+                                readme::excerpt(&content, config.readme_excerpt_length)
+                            });
+                        }
+                        Err(err) => {
+                            warn!("failed to fetch the README of {}: {}", row.name, err);
+                            summary.record_error("failed README");
+                            summary.record_error_kind(&err);
+                        }
+                    }
+                }
+            }
             info!(
                 "found {}: Cargo.toml = {:?}, Cargo.lock = {:?}",
-                repo.name_with_owner, has_cargo_toml, has_cargo_lock,
+                row.name, row.has_cargo_toml, row.has_cargo_lock,
             );
+            summary.record_discovery(&row);
+            rows.push(row);
         }
     }
+    let stored = rows.len();
+    data.store_repos("github", rows)?;
+    Summary::incr(&summary.repos_stored, stored);
+    data.record_non_rust("github", &non_rust)?;
 
     // Applease Clippy
     ::std::mem::drop(to_load);
@@ -74,23 +391,179 @@ fn load_thread(api: &GitHubApi, data: &Data, to_load: Vec<String>) -> Fallible<(
     Ok(())
 }
 
-pub fn scrape(data: &Data, config: &Config, should_stop: &AtomicBool) -> Fallible<()> {
+/// Queue the repositories of a page of the REST API to be loaded, skipping the forks and the
+/// repositories known not to use Rust. Returns the batches of 100 repositories ready to load.
+fn queue_page(
+    data: &Data,
+    config: &Config,
+    seed: &Seed,
+    summary: &Summary,
+    repos: Vec<RestRepository>,
+    to_load: &mut Vec<String>,
+) -> Fallible<Vec<Vec<String>>> {
+    let mut batches = Vec::new();
+    let mut forks = Vec::new();
+    for repo in repos {
+        if repo.fork {
+            forks.push(repo.id as u64);
+            continue;
+        }
+        if !config.recheck_non_rust && data.is_known_non_rust("github", repo.id as u64)? {
+            Summary::incr(&summary.repos_known_non_rust, 1);
+            continue;
+        }
+        if seed.contains(&repo.full_name) {
+            Summary::incr(&summary.repos_seeded, 1);
+            continue;
+        }
+
+        to_load.push(repo.node_id);
+        if to_load.len() == 100 {
+            batches.push(mem::replace(to_load, Vec::with_capacity(100)));
+        }
+    }
+
+    data.record_non_rust("github", &forks)?;
+    Ok(batches)
+}
+
+/// Tracks the ID up to which every repository of the frontier was loaded, while the batches of
+/// the pages are loaded concurrently.
+#[derive(Default)]
+struct Watermark {
+    /// Pages queued and not processed yet, with the ID up to which they cover the repositories
+    /// and the number of their batches still loading.
+    pages: VecDeque<(usize, Arc<AtomicUsize>)>,
+    /// ID of the first repository waiting in the `to_load` vector.
+    pending_from: Option<usize>,
+}
+
+impl Watermark {
+    /// Track a page ending at `page_end` after its batches were queued, returning the counter
+    /// each batch has to decrement when it finishes loading.
+    fn queue(
+        &mut self,
+        page_end: usize,
+        page: &HashMap<String, usize>,
+        to_load: &[String],
+        batches: usize,
+    ) -> Arc<AtomicUsize> {
+        if to_load.is_empty() {
+            self.pending_from = None;
+        } else if batches > 0 || self.pending_from.is_none() {
+            self.pending_from = page.get(&to_load[0]).cloned();
+        }
+        let covered = self.pending_from.map_or(page_end, |id| id - 1);
+        let loading = Arc::new(AtomicUsize::new(batches));
+        self.pages.push_back((covered, loading.clone()));
+        loading
+    }
+
+    /// Forget the pages whose batches all finished loading, returning the ID up to which the
+    /// repositories are now processed, if it moved.
+    fn advance(&mut self) -> Option<usize> {
+        let mut processed = None;
+        while let Some((covered, loading)) = self.pages.front() {
+            if loading.load(Ordering::SeqCst) > 0 {
+                break;
+            }
+            processed = Some(*covered);
+            self.pages.pop_front();
+        }
+        processed
+    }
+}
+
+/// Record the progress of the frontier once the batches of its pages finished loading: the
+/// processed ID, and the coverage of the IDs from `covered_from` up to it. Both are saved by the
+/// next flush. Returns whether the processed ID moved.
+fn advance_frontier(data: &Data, watermark: &mut Watermark, covered_from: &mut usize) -> bool {
+    match watermark.advance() {
+        Some(processed) => {
+            data.set_processed_id("github", processed);
+            if processed >= *covered_from {
+                data.record_coverage("github", *covered_from as u64, processed as u64);
+                *covered_from = processed + 1;
+            }
+            true
+        }
+        None => false,
+    }
+}
+
+/// Record the coverage of the gaps being backfilled, up to the ID their batches finished loading.
+/// The coverage is saved by the next flush.
+fn advance_backfill(data: &Data, progress: &mut [(u64, Watermark)]) {
+    for (first, watermark) in progress {
+        if let Some(processed) = watermark.advance() {
+            if processed as u64 >= *first {
+                data.record_coverage("github", *first, processed as u64);
+                *first = processed as u64 + 1;
+            }
+        }
+    }
+}
+
+pub fn scrape(
+    data: &Data,
+    config: &Config,
+    denylist: &Denylist,
+    summary: &Summary,
+    should_stop: &AtomicBool,
+) -> Fallible<Outcome> {
     info!("started scraping for GitHub repositories");
 
-    let gh = api::GitHubApi::new(config);
+    let gh = api::GitHubApi::new(config, summary)?;
+    let seed = Seed::load(data, "github")?;
     let mut to_load = Vec::with_capacity(100);
+    let mut watermark = Watermark::default();
+    let mut last_id = data.get_resume_id("github")?.unwrap_or(0);
+    let mut covered_from = last_id + 1;
 
     let result = scope(|scope| {
-        let mut last_id = data.get_last_id("github")?.unwrap_or(0);
+        if let Some(seen) = data.get_last_id("github")?.filter(|&seen| seen > last_id) {
+            info!(
+                "resuming from ID {}, the repositories up to ID {} were not all processed",
+                last_id, seen
+            );
+        }
+        // The repositories stored after the processed ID was saved don't need to be stored again
+        let stored = data.reconcile("github")?;
+        if !stored.is_empty() {
+            info!(
+                "{} repositories were stored after the last saved progress, skipping them",
+                stored.len()
+            );
+        }
+
+        let mut last_flush = Instant::now();
         let scrape_start = Instant::now();
+        let mut sparse_pages = 0;
 
+        // Load again the repositories whose loading crashed in previous runs
+        for to_load_now in retry_batches(data, &stored)? {
+            scope.spawn(|_| load_thread(&gh, data, config, denylist, summary, to_load_now));
+        }
+
+        let mut prefetched: Option<ScopedJoinHandle<_>> = None;
+        let mut last_request = Instant::now();
         loop {
             if let Some(timeout) = config.timeout {
                 if scrape_start.elapsed() >= Duration::from_secs(timeout) {
                     info!("timeout reached, stopping the scraping loop");
-                    break;
+                    return Ok(Outcome::TimedOut);
                 }
             }
+            if let Some((resource, remaining)) = gh.below_rate_limit_floor() {
+                info!(
+                    "only {} {} requests left across all the tokens, stopping the scraping loop",
+                    remaining, resource
+                );
+                // The repositories not loaded yet are loaded first by the next run
+                data.add_to_retry_queue("github", &to_load)?;
+                watermark.queue(last_id, &HashMap::new(), &[], 0);
+                return Ok(Outcome::BudgetExhausted);
+            }
 
             // Wait 2 minutes if GitHub is slowing us down
             if gh.should_slow_down() {
@@ -98,50 +571,531 @@ pub fn scrape(data: &Data, config: &Config, should_stop: &AtomicBool) -> Fallibl
                 ::std::thread::sleep(Duration::from_secs(120));
             }
 
-            let start = Instant::now();
+            let mut repos = match prefetched.take() {
+                Some(handle) => handle
+                    .join()
+                    .map_err(|_| err_msg("the page prefetch thread panicked"))??,
+                None => {
+                    debug!("scraping 100 repositories from the REST API");
+                    last_request = Instant::now();
+                    gh.scrape_repositories(last_id)?
+                }
+            };
+            Summary::incr(&summary.pages_fetched, 1);
+            summary.record_progress();
+            systemd::watchdog();
+            Summary::incr(&summary.repos_seen, repos.len());
+            let interrupted = should_stop.load(Ordering::SeqCst);
+            let finished = repos.len() < 100 || interrupted;
 
-            debug!("scraping 100 repositories from the REST API");
+            let found = repos
+                .iter()
+                .flatten()
+                .filter(|repo| !repo.fork && !seed.contains(&repo.full_name))
+                .count();
+            if found == 0 {
+                sparse_pages += 1;
+                if sparse_pages == SPARSE_PAGES_THRESHOLD {
+                    debug!("reached a sparse region of the ID space, speeding up");
+                }
+            } else {
+                sparse_pages = 0;
+            }
 
-            // Load all the non-fork repositories in the to_load vector
-            let mut repos = gh.scrape_repositories(last_id)?;
-            let finished = repos.len() < 100 || should_stop.load(Ordering::SeqCst);
-            for repo in repos.drain(..).flatten() {
-                last_id = repo.id;
-                if repo.fork {
-                    continue;
+            if !finished {
+                // Avoid hammering GitHub too much, except in sparse regions of the ID space:
+                // pages there don't result in any GraphQL call, so short bursts are fine.
+                let bursting = sparse_pages >= SPARSE_PAGES_THRESHOLD
+                    && !(sparse_pages - SPARSE_PAGES_THRESHOLD).is_multiple_of(SPARSE_BURST_PAGES);
+                if !bursting {
+                    if let Some(sleep) = Duration::from_secs(1).checked_sub(last_request.elapsed())
+                    {
+                        ::std::thread::sleep(sleep);
+                    }
                 }
 
-                to_load.push(repo.node_id);
+                // Fetch the next page in the background while this one is processed
+                let since = repos
+                    .iter()
+                    .flatten()
+                    .last()
+                    .map_or(last_id, |repo| repo.id);
+                let gh = &gh;
+                debug!("prefetching 100 repositories from the REST API");
+                last_request = Instant::now();
+                prefetched = Some(scope.spawn(move |_| gh.scrape_repositories(since)));
+            }
 
-                if to_load.len() == 100 {
-                    let to_load_now = to_load.clone();
-                    scope.spawn(|_| wrap_thread(|| load_thread(&gh, data, to_load_now)));
-                    to_load.clear();
+            // Load all the non-fork repositories in the to_load vector
+            let page_start = last_id + 1;
+            let mut repos = repos.drain(..).flatten().collect::<Vec<_>>();
+            if let Some(repo) = repos.last() {
+                last_id = repo.id;
+            }
+            let page = repos
+                .iter()
+                .map(|repo| (repo.node_id.clone(), repo.id))
+                .collect::<HashMap<_, _>>();
+            repos.retain(|repo| !stored.contains(&repo.node_id));
+            if let Some(retries) = config.page_retries {
+                // Load the whole page before moving past it, fetching it again if some of its
+                // batches failed
+                for retry in 0.. {
+                    let mut batches =
+                        queue_page(data, config, &seed, summary, repos, &mut to_load)?;
+                    if !to_load.is_empty() {
+                        batches.push(mem::replace(&mut to_load, Vec::with_capacity(100)));
+                    }
+                    let handles = batches
+                        .into_iter()
+                        .map(|to_load_now| {
+                            scope.spawn(|_| {
+                                load_thread(&gh, data, config, denylist, summary, to_load_now)
+                            })
+                        })
+                        .collect::<Vec<_>>();
+                    let failed = handles
+                        .into_iter()
+                        .map(|handle| handle.join().unwrap())
+                        .filter(|handled| !handled)
+                        .count();
+                    if failed == 0 {
+                        break;
+                    }
+                    if retry == retries {
+                        bail!(
+                            "{} batches of the page after ID {} failed to load after {} retries, \
+                             stopping without skipping them",
+                            failed,
+                            page_start - 1,
+                            retries
+                        );
+                    }
+                    warn!(
+                        "{} batches of the page after ID {} failed to load, fetching the page \
+                         again (retry {} of {})",
+                        failed,
+                        page_start - 1,
+                        retry + 1,
+                        retries
+                    );
+                    repos = gh
+                        .scrape_repositories(page_start - 1)?
+                        .into_iter()
+                        .flatten()
+                        .filter(|repo| !stored.contains(&repo.node_id))
+                        .collect();
+                }
+                watermark.queue(last_id, &page, &to_load, 0);
+            } else {
+                let batches = queue_page(data, config, &seed, summary, repos, &mut to_load)?;
+                let loading = watermark.queue(last_id, &page, &to_load, batches.len());
+                for to_load_now in batches {
+                    let loading = loading.clone();
+                    let gh = &gh;
+                    scope.spawn(move |_| {
+                        // A batch neither stored nor queued holds back the processed ID
+                        if load_thread(gh, data, config, denylist, summary, to_load_now) {
+                            loading.fetch_sub(1, Ordering::SeqCst);
+                        }
+                    });
                 }
             }
 
             data.set_last_id("github", last_id)?;
 
+            // Save the processed ID from time to time, so that a crash loses little progress
+            if advance_frontier(data, &mut watermark, &mut covered_from)
+                && last_flush.elapsed() >= PROCESSED_FLUSH_INTERVAL
+            {
+                data.flush()?;
+                last_flush = Instant::now();
+            }
+
             if finished {
                 // Ensure all the remaining repositories are loaded
                 if !to_load.is_empty() {
-                    let to_load_now = to_load.clone();
-                    scope.spawn(|_| wrap_thread(|| load_thread(&gh, data, to_load_now)));
+                    let to_load_now = mem::replace(&mut to_load, Vec::with_capacity(100));
+                    let loading = watermark.queue(last_id, &page, &[], 1);
+                    let gh = &gh;
+                    scope.spawn(move |_| {
+                        if load_thread(gh, data, config, denylist, summary, to_load_now) {
+                            loading.fetch_sub(1, Ordering::SeqCst);
+                        }
+                    });
                 }
 
-                break;
-            }
+                if interrupted {
+                    return Ok(Outcome::Interrupted);
+                }
+                info!("caught up with the newest repository (ID {})", last_id);
 
-            // Avoid hammering GitHub too much
-            if let Some(sleep) = Duration::from_secs(1).checked_sub(start.elapsed()) {
-                ::std::thread::sleep(sleep);
+                // In follow mode, keep polling the frontier for new repositories from time to time
+                match config.follow_interval {
+                    Some(interval) => {
+                        info!("checking for new repositories in {} seconds", interval);
+                        let wait_start = Instant::now();
+                        while wait_start.elapsed() < Duration::from_secs(interval) {
+                            if should_stop.load(Ordering::SeqCst) {
+                                return Ok(Outcome::Interrupted);
+                            }
+                            ::std::thread::sleep(Duration::from_secs(1));
+                            systemd::watchdog();
+                            // Make the repositories loaded meanwhile visible to readers
+                            data.flush()?;
+                        }
+                    }
+                    None => return Ok(Outcome::CaughtUp),
+                }
             }
         }
-
-        Ok(())
     })
     .unwrap();
 
+    // All the batches finished loading when the scope ended
+    advance_frontier(data, &mut watermark, &mut covered_from);
+
     info!("finished scraping for GitHub repositories");
     result
 }
+
+/// Scrape the ranges of the ID space skipped by previous runs (for example because the last
+/// scraped ID was changed by hand), without moving the last scraped ID.
+pub fn backfill(
+    data: &Data,
+    config: &Config,
+    denylist: &Denylist,
+    summary: &Summary,
+    should_stop: &AtomicBool,
+) -> Fallible<Outcome> {
+    let gaps = data.coverage_gaps("github")?;
+    if gaps.is_empty() {
+        info!("no gaps found in the scraped ID space");
+        return Ok(Outcome::CaughtUp);
+    }
+    info!(
+        "backfilling {} gaps in the scraped ID space, totalling {} IDs",
+        gaps.len(),
+        gaps.iter()
+            .map(|(first, last)| last - first + 1)
+            .sum::<u64>()
+    );
+
+    let gh = api::GitHubApi::new(config, summary)?;
+    let seed = Seed::load(data, "github")?;
+    let mut to_load = Vec::with_capacity(100);
+    // Progress of each gap, whose coverage is recorded once the batches of its pages are loaded
+    let mut progress: Vec<(u64, Watermark)> = Vec::new();
+
+    let result = scope(|scope| {
+        let mut last_flush = Instant::now();
+        for (first, last) in gaps {
+            debug!("backfilling IDs from {} to {}", first, last);
+            progress.push((first, Watermark::default()));
+            let mut since = first - 1;
+            while since < last {
+                let stop = if should_stop.load(Ordering::SeqCst) {
+                    Some(Outcome::Interrupted)
+                } else if let Some((resource, remaining)) = gh.below_rate_limit_floor() {
+                    info!(
+                        "only {} {} requests left across all the tokens, stopping the backfill",
+                        remaining, resource
+                    );
+                    Some(Outcome::BudgetExhausted)
+                } else {
+                    None
+                };
+                if let Some(outcome) = stop {
+                    // Load the remaining repositories, covering the gap up to the last page
+                    if !to_load.is_empty() {
+                        let to_load_now = mem::replace(&mut to_load, Vec::with_capacity(100));
+                        let watermark = &mut progress.last_mut().unwrap().1;
+                        let loading = watermark.queue(since as usize, &HashMap::new(), &[], 1);
+                        let gh = &gh;
+                        scope.spawn(move |_| {
+                            if load_thread(gh, data, config, denylist, summary, to_load_now) {
+                                loading.fetch_sub(1, Ordering::SeqCst);
+                            }
+                        });
+                    }
+                    return Ok(outcome);
+                }
+
+                let page = gh.scrape_repositories(since as usize)?;
+                Summary::incr(&summary.pages_fetched, 1);
+                summary.record_progress();
+                systemd::watchdog();
+
+                // Pages extending past the gap are only covered until its end
+                let full = page.len() == 100;
+                let repos = page
+                    .into_iter()
+                    .flatten()
+                    .filter(|repo| repo.id as u64 <= last)
+                    .collect::<Vec<_>>();
+                Summary::incr(&summary.repos_seen, repos.len());
+                let end = match repos.last() {
+                    Some(repo) if full && (repo.id as u64) < last => repo.id as u64,
+                    _ => last,
+                };
+
+                let ids = repos
+                    .iter()
+                    .map(|repo| (repo.node_id.clone(), repo.id))
+                    .collect::<HashMap<_, _>>();
+                let mut batches = queue_page(data, config, &seed, summary, repos, &mut to_load)?;
+                // The repositories left at the end of a gap are not covered by the next pages
+                if end == last && !to_load.is_empty() {
+                    batches.push(mem::replace(&mut to_load, Vec::with_capacity(100)));
+                }
+                let watermark = &mut progress.last_mut().unwrap().1;
+                let loading = watermark.queue(end as usize, &ids, &to_load, batches.len());
+                for to_load_now in batches {
+                    let loading = loading.clone();
+                    let gh = &gh;
+                    scope.spawn(move |_| {
+                        if load_thread(gh, data, config, denylist, summary, to_load_now) {
+                            loading.fetch_sub(1, Ordering::SeqCst);
+                        }
+                    });
+                }
+                since = end;
+
+                advance_backfill(data, &mut progress);
+                if last_flush.elapsed() >= PROCESSED_FLUSH_INTERVAL {
+                    data.flush()?;
+                    last_flush = Instant::now();
+                }
+
+                ::std::thread::sleep(Duration::from_secs(1));
+            }
+        }
+        Ok(Outcome::CaughtUp)
+    })
+    .unwrap();
+
+    // Ensure all the remaining repositories are loaded, even when failing
+    if !to_load.is_empty() {
+        load_thread(&gh, data, config, denylist, summary, to_load);
+    }
+    // All the batches finished loading when the scope ended
+    advance_backfill(data, &mut progress);
+
+    info!("finished backfilling the gaps in the scraped ID space");
+    result
+}
+
+/// Fetch and parse the `Cargo.lock` of the stored repositories, at the commit they were scraped
+/// at, resuming from the last repository processed by the previous run.
+pub fn fetch_lockfiles(
+    data: &Data,
+    config: &Config,
+    summary: &Summary,
+    should_stop: &AtomicBool,
+) -> Fallible<()> {
+    let gh = api::GitHubApi::new(config, summary)?;
+    let mut position = data.get_lockfile_position("github")?;
+    info!("fetching lockfiles, starting from row {}", position);
+
+    let mut repos = data.repos("github")?.skip(position).peekable();
+    while repos.peek().is_some() {
+        if should_stop.load(Ordering::SeqCst) {
+            break;
+        }
+
+        let mut batch = Vec::with_capacity(LOCKFILES_BATCH);
+        for repo in repos.by_ref().take(LOCKFILES_BATCH) {
+            batch.push(repo?);
+        }
+        position += batch.len();
+
+        let results = scope(|scope| {
+            let handles = batch
+                .iter()
+                .filter(|repo| repo.has_cargo_lock)
+                .filter_map(|repo| repo.head_sha.as_ref().map(|sha| (repo, sha)))
+                .map(|(repo, sha)| {
+                    let gh = &gh;
+                    scope.spawn(move |_| {
+                        let content = gh.file_content(&repo.name, sha, "Cargo.lock")?;
+                        Ok(content.map(|content| lockfile::parse(&repo.id, &content)))
+                    })
+                })
+                .collect::<Vec<_>>();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap())
+                .collect::<Fallible<Vec<_>>>()
+        })
+        .map_err(|_| err_msg("a lockfile thread panicked"))??;
+
+        let packages = results.into_iter().flatten().flatten().collect::<Vec<_>>();
+        data.store_locked_packages("github", &packages)?;
+        data.set_lockfile_position("github", position)?;
+        Summary::incr(&summary.repos_seen, batch.len());
+    }
+
+    info!("fetched lockfiles up to row {}", position);
+    Ok(())
+}
+
+/// Fetch the profiles of the owners of a random sample of repositories, without spending more
+/// than `budget` GraphQL points. Profiles fetched recently are reused.
+pub fn fetch_owners(
+    data: &Data,
+    config: &Config,
+    summary: &Summary,
+    sample_size: usize,
+    seed: u64,
+    budget: u64,
+) -> Fallible<()> {
+    let gh = api::GitHubApi::new(config, summary)?;
+
+    let now = SystemTime::now();
+    let fresh_after = format_timestamp(now - Duration::from_secs(OWNERS_CACHE_DAYS * 24 * 3600));
+    let cached = data.owners("github")?;
+
+    let mut logins = data
+        .sample("github", sample_size, seed, |_| String::new())?
+        .into_iter()
+        .filter_map(|repo| repo.name.split('/').next().map(String::from))
+        .filter(|login| {
+            cached
+                .get(&login.to_lowercase())
+                .map(|owner| owner.fetched_at < fresh_after)
+                .unwrap_or(true)
+        })
+        .collect::<Vec<_>>();
+    logins.sort();
+    logins.dedup();
+    info!(
+        "fetching {} owner profiles with a budget of {} points",
+        logins.len(),
+        budget
+    );
+
+    let mut spent = 0;
+    for chunk in logins.chunks(OWNERS_BATCH) {
+        if spent >= budget {
+            warn!("the budget of {} points was spent, stopping", budget);
+            break;
+        }
+
+        let (profiles, cost, remaining) = gh.load_owners(chunk)?;
+        spent += cost;
+
+        let fetched_at = format_timestamp(SystemTime::now());
+        let owners = chunk
+            .iter()
+            .zip(profiles)
+            .map(|(login, profile)| match profile {
+                Some(profile) => Owner {
+                    login: profile.login,
+                    kind: Some(profile.kind),
+                    company: profile.company,
+                    location: profile.location,
+                    fetched_at: fetched_at.clone(),
+                },
+                None => Owner {
+                    login: login.clone(),
+                    kind: None,
+                    company: None,
+                    location: None,
+                    fetched_at: fetched_at.clone(),
+                },
+            })
+            .collect::<Vec<_>>();
+        data.store_owners("github", &owners)?;
+
+        // Leave most of the quota to the scraper
+        if remaining < OWNERS_RESERVED_POINTS {
+            warn!("only {} GraphQL points left, stopping", remaining);
+            break;
+        }
+    }
+
+    info!("spent {} GraphQL points fetching owners", spent);
+    Ok(())
+}
+
+pub fn tokens_status(config: &Config, summary: &Summary) -> Fallible<()> {
+    let gh = api::GitHubApi::new(config, summary)?;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+
+    for (i, token) in config.github_tokens.iter().enumerate() {
+        // Never print any part of the token, only its hashed identifier
+        println!("token #{} (identifier {}):", i + 1, http::token_id(token));
+
+        match gh.rate_limits(token) {
+            Ok(limits) => {
+                for (name, limit) in &[("REST", &limits.core), ("GraphQL", &limits.graphql)] {
+                    println!(
+                        "  {:<8} {:>5}/{:<5} remaining, resets in {} minutes",
+                        name,
+                        limit.remaining,
+                        limit.limit,
+                        limit.reset.saturating_sub(now) / 60,
+                    );
+                }
+            }
+            Err(err) => println!("  failed to fetch the rate limits: {}", err),
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{load_thread, retry_batches};
+    use config::Config;
+    use data::Data;
+    use denylist::Denylist;
+    use github::api::GitHubApi;
+    use std::fs;
+    use summary::Summary;
+
+    #[test]
+    fn test_failed_retry_batch_stays_queued() {
+        let dir = ::std::env::temp_dir()
+            .join(format!("rust-repos-github-{}-retry", ::std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let replay = dir.join("replay");
+        fs::create_dir_all(&replay).unwrap();
+
+        // Nothing was recorded, so loading the batch fails
+        let mut config = Config::from_env(dir.clone()).unwrap();
+        config.github_tokens = vec!["secret".into()];
+        config.contact = Some("test@example.com".into());
+        config.replay_http = Some(replay);
+        config.page_retries = Some(3);
+        let summary = Summary::default();
+        let api = GitHubApi::new(&config, &summary).unwrap();
+        let data = Data::new(&config).unwrap();
+        let denylist = Denylist::load(&config).unwrap();
+
+        // The second repository was stored after the last saved progress
+        let ids = vec!["MDEwOlJlcG9zaXRvcnkx".to_string()];
+        let stored = "MDEwOlJlcG9zaXRvcnky".to_string();
+        data.add_to_retry_queue("github", &[ids[0].clone(), stored.clone()])
+            .unwrap();
+
+        let batches = retry_batches(&data, &vec![stored].into_iter().collect()).unwrap();
+        assert_eq!(batches, vec![ids.clone()]);
+        for batch in batches {
+            assert!(!load_thread(
+                &api, &data, &config, &denylist, &summary, batch
+            ));
+        }
+        data.flush().unwrap();
+        assert_eq!(data.retry_queue("github").unwrap(), ids);
+
+        // Once loaded, the repositories leave the queue with the next flush
+        data.remove_from_retry_queue("github", &ids);
+        assert_eq!(data.retry_queue("github").unwrap(), ids);
+        data.flush().unwrap();
+        assert!(data.retry_queue("github").unwrap().is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
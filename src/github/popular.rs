@@ -0,0 +1,212 @@
+// Copyright (c) 2018 Pietro Albini <pietro@pietroalbini.org>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies
+// of the Software, and to permit persons to whom the Software is furnished to do
+// so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Discovery of the popular Rust repositories through the search API. A search returns at most
+//! 1000 results, so it's split by ranges of stars, and then by creation date windows, until each
+//! partition fits. The partitions left and the cursor of the current one are persisted in the
+//! state, letting the next run resume where the previous one stopped.
+
+use config::Config;
+use data::{Data, SearchPartition, SearchProgress};
+use denylist::Denylist;
+use exit::Outcome;
+use github::api::GitHubApi;
+use github::load_thread;
+use prelude::*;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use summary::Summary;
+use systemd;
+use utils::format_timestamp;
+
+/// Stars a repository needs to be considered popular when no minimum is provided.
+pub const DEFAULT_MIN_STARS: u64 = 100;
+/// Maximum number of results returned by a search query.
+const RESULTS_CAP: u64 = 1000;
+/// First day of the creation date windows (2008-01-01, when GitHub launched).
+const FIRST_DAY: u64 = 13_879;
+
+/// Search qualifiers matching the repositories of a partition.
+fn search_query(partition: &SearchPartition) -> String {
+    let mut query = format!("language:rust stars:{}", partition.min_stars);
+    match partition.max_stars {
+        Some(max) => query.push_str(&format!("..{}", max)),
+        None => query.push_str("..*"),
+    }
+    if let Some((first, last)) = partition.created {
+        query.push_str(&format!(" created:{}..{}", day(first), day(last)));
+    }
+    query
+}
+
+fn day(days: u64) -> String {
+    format_timestamp(UNIX_EPOCH + Duration::from_secs(days * 86_400))[..10].to_string()
+}
+
+/// Split a partition in two, halving its range of stars or, when it's a single value, its window
+/// of creation dates. Returns `None` when the partition can't be split further.
+fn split(partition: &SearchPartition, today: u64) -> Option<Vec<SearchPartition>> {
+    let (min, max) = (partition.min_stars, partition.max_stars);
+    let stars = |min_stars, max_stars| SearchPartition {
+        min_stars,
+        max_stars,
+        created: None,
+    };
+    match max {
+        // The number of repositories drops quickly with the stars, so the unbounded end of the
+        // range is split geometrically
+        None => {
+            let mid = (min * 2).max(min + 1);
+            Some(vec![stars(min, Some(mid - 1)), stars(mid, None)])
+        }
+        Some(max) if max > min => {
+            let mid = min + (max - min) / 2;
+            Some(vec![stars(min, Some(mid)), stars(mid + 1, Some(max))])
+        }
+        Some(_) => {
+            let (first, last) = partition.created.unwrap_or((FIRST_DAY, today));
+            if first >= last {
+                return None;
+            }
+            let mid = first + (last - first) / 2;
+            let window = |created| SearchPartition {
+                created: Some(created),
+                ..partition.clone()
+            };
+            Some(vec![window((first, mid)), window((mid + 1, last))])
+        }
+    }
+}
+
+fn save_progress(data: &Data, progress: Option<&SearchProgress>) -> Fallible<()> {
+    data.edit_forge_state("github", |state| {
+        state.popular_search = progress.cloned();
+    })
+}
+
+/// Find and load all the Rust repositories with at least `min_stars` stars, resuming the search
+/// of the previous run if it didn't complete.
+pub fn scrape_popular(
+    data: &Data,
+    config: &Config,
+    denylist: &Denylist,
+    summary: &Summary,
+    should_stop: &AtomicBool,
+    min_stars: u64,
+) -> Fallible<Outcome> {
+    let gh = GitHubApi::new(config, summary)?;
+    let today = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() / 86_400;
+
+    let mut progress = match data.forge_state("github")?.popular_search {
+        Some(progress) if progress.min_stars == min_stars => {
+            info!(
+                "resuming the search of popular repositories ({} partitions left)",
+                progress.partitions.len()
+            );
+            progress
+        }
+        _ => {
+            info!("searching repositories with at least {} stars", min_stars);
+            SearchProgress {
+                min_stars,
+                partitions: vec![SearchPartition {
+                    min_stars,
+                    max_stars: None,
+                    created: None,
+                }],
+                cursor: None,
+            }
+        }
+    };
+
+    while let Some(partition) = progress.partitions.first().cloned() {
+        if should_stop.load(Ordering::SeqCst) {
+            return Ok(Outcome::Interrupted);
+        }
+        if let Some((resource, remaining)) = gh.below_rate_limit_floor() {
+            info!(
+                "only {} {} requests left across all the tokens, stopping the search",
+                remaining, resource
+            );
+            return Ok(Outcome::BudgetExhausted);
+        }
+
+        let query = search_query(&partition);
+        let page = gh.search_repositories(&query, progress.cursor.as_deref())?;
+        Summary::incr(&summary.pages_fetched, 1);
+        summary.record_progress();
+        systemd::watchdog();
+
+        if progress.cursor.is_none() && page.count > RESULTS_CAP {
+            if let Some(partitions) = split(&partition, today) {
+                debug!(
+                    "splitting `{}`, which matches {} repositories",
+                    query, page.count
+                );
+                progress.partitions.splice(0..1, partitions);
+                save_progress(data, Some(&progress))?;
+                continue;
+            }
+            warn!(
+                "`{}` matches {} repositories, only the first {} will be found",
+                query, page.count, RESULTS_CAP
+            );
+        }
+
+        Summary::incr(&summary.repos_seen, page.repos.len());
+        let mut forks = Vec::new();
+        let mut to_load = Vec::new();
+        for repo in page.repos {
+            if repo.is_fork {
+                forks.extend(repo.database_id);
+            } else {
+                to_load.push(repo.id);
+            }
+        }
+        data.record_non_rust("github", &forks)?;
+        if !to_load.is_empty() && !load_thread(&gh, data, config, denylist, summary, to_load) {
+            bail!(
+                "failed to load the repositories found by `{}`, stopping without skipping them",
+                query
+            );
+        }
+
+        match page.cursor {
+            Some(cursor) => progress.cursor = Some(cursor),
+            None => {
+                debug!("covered `{}`", query);
+                progress.partitions.remove(0);
+                progress.cursor = None;
+            }
+        }
+        // Moving past the page is saved only once its repositories are written to disk
+        data.flush()?;
+        save_progress(data, Some(&progress))?;
+
+        ::std::thread::sleep(Duration::from_secs(1));
+    }
+
+    save_progress(data, None)?;
+    info!(
+        "found all the repositories with at least {} stars",
+        min_stars
+    );
+    Ok(Outcome::CaughtUp)
+}
@@ -0,0 +1,230 @@
+// Copyright (c) 2018 Pietro Albini <pietro@pietroalbini.org>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies
+// of the Software, and to permit persons to whom the Software is furnished to do
+// so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use config::Config;
+use data::Data;
+use feed;
+use prelude::*;
+use query::Filter;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use summary::{Summary, LATENCY_BUCKETS_MS};
+
+static PROBE_FILE: &str = ".health-probe";
+
+/// Minimal HTTP server answering `GET /healthz`, meant to be used by supervisors' liveness probes,
+/// `GET /metrics` with the request metrics in the Prometheus text format, and `GET /feed.atom`
+/// with the feed of the repositories discovered last.
+struct HealthServer {
+    summary: Arc<Summary>,
+    data: Data,
+    feed_filter: Option<Filter>,
+    feed_limit: usize,
+    data_dir: PathBuf,
+    max_stall: Duration,
+    started_at: Instant,
+}
+
+pub fn spawn(config: &Config, data: Data, summary: Arc<Summary>) -> Fallible<()> {
+    let addr = match &config.health_addr {
+        Some(addr) => addr,
+        None => return Ok(()),
+    };
+
+    let listener = TcpListener::bind(addr.as_str())
+        .with_context(|_| format!("failed to bind the health check server to {}", addr))?;
+    info!("health check available at http://{}/healthz", addr);
+
+    let feed_filter = match &config.feed_where {
+        Some(expr) => Some(Filter::parse(expr).context("invalid RUST_REPOS_FEED_WHERE")?),
+        None => None,
+    };
+    let server = HealthServer {
+        summary,
+        data,
+        feed_filter,
+        feed_limit: config.feed_limit,
+        data_dir: config.data_dir.clone(),
+        max_stall: Duration::from_secs(config.health_max_stall),
+        started_at: Instant::now(),
+    };
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let result = stream
+                .map_err(Error::from)
+                .and_then(|stream| server.handle(stream));
+            if let Err(err) = result {
+                warn!("failed to answer a health check request: {}", err);
+            }
+        }
+    });
+
+    Ok(())
+}
+
+impl HealthServer {
+    fn handle(&self, mut stream: TcpStream) -> Fallible<()> {
+        stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+
+        let mut request_line = String::new();
+        BufReader::new(&stream).read_line(&mut request_line)?;
+        let mut parts = request_line.split_whitespace();
+
+        let mut content_type = "text/plain";
+        let (status, body) = match (parts.next(), parts.next()) {
+            (Some("GET"), Some("/healthz")) => match self.check() {
+                Ok(()) => ("200 OK", "ok\n".to_string()),
+                Err(err) => ("503 Service Unavailable", format!("{}\n", err)),
+            },
+            (Some("GET"), Some("/metrics")) => ("200 OK", self.metrics()),
+            (Some("GET"), Some("/feed.atom")) => {
+                match feed::render(&self.data, self.feed_filter.as_ref(), self.feed_limit) {
+                    Ok(feed) => {
+                        content_type = "application/atom+xml";
+                        ("200 OK", feed)
+                    }
+                    Err(err) => ("500 Internal Server Error", format!("{}\n", err)),
+                }
+            }
+            _ => ("404 Not Found", "not found\n".to_string()),
+        };
+
+        write!(
+            stream,
+            "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n\
+             Connection: close\r\n\r\n{}",
+            status,
+            content_type,
+            body.len(),
+            body
+        )?;
+        Ok(())
+    }
+
+    fn metrics(&self) -> String {
+        let mut out = String::new();
+        let get = |counter: &AtomicUsize| counter.load(Ordering::SeqCst);
+        for (name, value) in &[
+            ("pages_fetched", get(&self.summary.pages_fetched)),
+            ("repos_seen", get(&self.summary.repos_seen)),
+            ("repos_stored", get(&self.summary.repos_stored)),
+            ("graphql_points", get(&self.summary.graphql_points)),
+            ("tokens_quarantined", get(&self.summary.tokens_quarantined)),
+            ("errors", self.summary.errors_count()),
+            ("schema_drift", self.summary.schema_drift_count()),
+        ] {
+            out += &format!(
+                "# TYPE rust_repos_{0}_total counter\nrust_repos_{0}_total {1}\n",
+                name, value
+            );
+        }
+
+        let endpoints = self.summary.endpoints();
+        out += "# TYPE rust_repos_http_request_duration_seconds histogram\n";
+        for (endpoint, stats) in &endpoints {
+            let mut cumulative = 0;
+            for (i, count) in stats.latency_buckets.iter().enumerate() {
+                cumulative += count;
+                let le = match LATENCY_BUCKETS_MS.get(i) {
+                    Some(bound) => format!("{}", *bound as f64 / 1000.0),
+                    None => "+Inf".to_string(),
+                };
+                out += &format!(
+                    "rust_repos_http_request_duration_seconds_bucket{{endpoint=\"{}\",le=\"{}\"}} {}\n",
+                    endpoint, le, cumulative
+                );
+            }
+            out += &format!(
+                "rust_repos_http_request_duration_seconds_sum{{endpoint=\"{}\"}} {}\n",
+                endpoint,
+                stats.latency_total_ms as f64 / 1000.0
+            );
+            out += &format!(
+                "rust_repos_http_request_duration_seconds_count{{endpoint=\"{}\"}} {}\n",
+                endpoint, stats.requests
+            );
+        }
+        out += "# TYPE rust_repos_http_responses_total counter\n";
+        for (endpoint, stats) in &endpoints {
+            for (status, count) in &stats.statuses {
+                out += &format!(
+                    "rust_repos_http_responses_total{{endpoint=\"{}\",status=\"{}\"}} {}\n",
+                    endpoint, status, count
+                );
+            }
+        }
+        out += "# TYPE rust_repos_http_rate_limit_remaining gauge\n";
+        for (endpoint, stats) in &endpoints {
+            if let Some(remaining) = stats.rate_limit_remaining {
+                out += &format!(
+                    "rust_repos_http_rate_limit_remaining{{endpoint=\"{}\"}} {}\n",
+                    endpoint, remaining
+                );
+            }
+        }
+
+        let tokens = self.summary.tokens();
+        out += "# TYPE rust_repos_token_responses_total counter\n";
+        for (token, stats) in &tokens {
+            for (status, count) in &stats.statuses {
+                out += &format!(
+                    "rust_repos_token_responses_total{{token=\"{}\",status=\"{}\"}} {}\n",
+                    token, status, count
+                );
+            }
+        }
+        out += "# TYPE rust_repos_token_rate_limit_remaining gauge\n";
+        for (token, stats) in &tokens {
+            if let Some(remaining) = stats.rate_limit_remaining {
+                out += &format!(
+                    "rust_repos_token_rate_limit_remaining{{token=\"{}\"}} {}\n",
+                    token, remaining
+                );
+            }
+        }
+        out
+    }
+
+    fn check(&self) -> Fallible<()> {
+        let stalled_for = self
+            .summary
+            .since_last_progress()
+            .unwrap_or_else(|| self.started_at.elapsed());
+        if stalled_for > self.max_stall {
+            bail!(
+                "no progress made in the last {} seconds",
+                stalled_for.as_secs()
+            );
+        }
+
+        let probe = self.data_dir.join(PROBE_FILE);
+        fs::write(&probe, b"")
+            .and_then(|()| fs::remove_file(&probe))
+            .context("the data directory is not writable")?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,100 @@
+// Copyright (c) 2018 Pietro Albini <pietro@pietroalbini.org>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies
+// of the Software, and to permit persons to whom the Software is furnished to do
+// so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Export of the successive versions of the records of the repositories, for longitudinal
+//! studies of the dataset without diffing snapshots.
+
+use csv;
+use data::{Data, Repo, RepoVersion};
+use prelude::*;
+use query::Filter;
+use std::io::Write;
+
+static PLATFORM: &str = "github";
+
+/// Turn the `--as-of` argument (a date or an RFC 3339 timestamp in UTC) into a timestamp
+/// comparable with the stored ones. Dates include the whole day.
+pub fn parse_as_of(input: &str) -> Fallible<String> {
+    let valid_date = |date: &str| {
+        date.len() == 10
+            && date.char_indices().all(|(i, c)| {
+                if i == 4 || i == 7 {
+                    c == '-'
+                } else {
+                    c.is_ascii_digit()
+                }
+            })
+    };
+    if valid_date(input) {
+        Ok(format!("{}T23:59:59Z", input))
+    } else if input.len() == 20 && valid_date(&input[..10]) && input.ends_with('Z') {
+        Ok(input.to_string())
+    } else {
+        bail!(
+            "invalid date: {} (expected YYYY-MM-DD or YYYY-MM-DDTHH:MM:SSZ)",
+            input
+        );
+    }
+}
+
+/// Write the versions of the repositories matching the filter as CSV, either all of them or
+/// only the ones current at the `as_of` timestamp.
+pub fn write(
+    data: &Data,
+    filter: Option<&Filter>,
+    as_of: Option<&str>,
+    out: &mut dyn Write,
+) -> Fallible<()> {
+    let versions = match as_of {
+        Some(timestamp) => data.as_of(PLATFORM, timestamp)?,
+        None => data.versions(PLATFORM)?,
+    };
+
+    let mut csv = csv::Writer::from_writer(out);
+    let mut header = vec!["valid_from", "valid_to"];
+    header.extend(Repo::columns());
+    csv.write_record(&header)?;
+
+    let mut record = csv::ByteRecord::new();
+    let mut written = 0;
+    for RepoVersion {
+        repo,
+        valid_from,
+        valid_to,
+    } in &versions
+    {
+        if let Some(filter) = filter {
+            if !filter.matches(repo)? {
+                continue;
+            }
+        }
+        repo.write_record(&mut record);
+        let mut row = csv::ByteRecord::new();
+        row.push_field(valid_from.as_deref().unwrap_or("").as_bytes());
+        row.push_field(valid_to.as_deref().unwrap_or("").as_bytes());
+        row.extend(record.iter());
+        csv.write_byte_record(&row)?;
+        written += 1;
+    }
+    csv.flush()?;
+
+    info!("wrote {} versions of the repositories", written);
+    Ok(())
+}
@@ -0,0 +1,542 @@
+// Copyright (c) 2018 Pietro Albini <pietro@pietroalbini.org>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies
+// of the Software, and to permit persons to whom the Software is furnished to do
+// so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! HTTP infrastructure shared by the clients of all the APIs: client construction, retries with
+//! backoff, decompression of the responses and detection of the responses signalling server
+//! errors or exhausted rate limits.
+
+use config::{Config, RetryPolicy};
+use http_types;
+use miniz_oxide::inflate;
+use prelude::*;
+use reqwest::blocking::{Client, ClientBuilder, Request, Response};
+use reqwest::header::{self, HeaderMap, HeaderValue};
+use reqwest::{Proxy, StatusCode};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use summary::Summary;
+use utils::Rng;
+
+/// Encodings the responses can be compressed with, see `ResponseExt::decompressed`.
+static ACCEPT_ENCODING: &str = "gzip, deflate";
+/// Size above which a decompressed response is rejected, to bound the memory used by responses
+/// compressing very well.
+const MAX_DECOMPRESSED_SIZE: usize = 256 * 1024 * 1024;
+
+/// Build an HTTP client with the timeouts, connection pool and proxy configured by the user,
+/// sending the configured user agent unless a request overrides it.
+///
+/// HTTP/2 is negotiated with ALPN when the server supports it, which allows all the concurrent
+/// requests to a host to share a single connection instead of paying a TLS handshake each.
+/// Compressed responses are requested unless disabled, and must be decoded with
+/// `ResponseExt::decompressed`.
+///
+/// The TLS backend is chosen with the Cargo features, see `tls_backend`.
+pub fn client(config: &Config) -> Fallible<Client> {
+    let mut headers = HeaderMap::new();
+    if config.http_compression {
+        headers.insert(
+            header::ACCEPT_ENCODING,
+            HeaderValue::from_static(ACCEPT_ENCODING),
+        );
+    }
+    let mut builder = Client::builder()
+        .default_headers(headers)
+        .user_agent(config.user_agent.as_str())
+        .timeout(Duration::from_secs(config.http_timeout))
+        .connect_timeout(Duration::from_secs(config.http_connect_timeout))
+        .pool_idle_timeout(Duration::from_secs(config.http_pool_idle_timeout))
+        .tcp_keepalive(config.http_tcp_keepalive.map(Duration::from_secs));
+    if let Some(size) = config.http_pool_size {
+        builder = builder.pool_max_idle_per_host(size);
+    }
+    if config.http1_only {
+        builder = builder.http1_only();
+    }
+    if let Some(proxy) = &config.http_proxy {
+        builder = builder.proxy(Proxy::all(proxy.as_str()).context("invalid HTTP proxy")?);
+    }
+    Ok(tls_backend(builder)?.build()?)
+}
+
+/// Select the TLS backend enabled with the Cargo features.
+#[cfg(feature = "native-tls")]
+fn tls_backend(builder: ClientBuilder) -> Fallible<ClientBuilder> {
+    Ok(builder.use_native_tls())
+}
+
+/// Name under which the requests are grouped in the metrics: the method, the host and the first
+/// component of the path (like `GET api.github.com/repos`), to avoid one group per repository.
+fn endpoint(request: &Request) -> String {
+    let url = request.url();
+    let section = url
+        .path_segments()
+        .and_then(|mut segments| segments.next())
+        .unwrap_or("");
+    format!(
+        "{} {}/{}",
+        request.method(),
+        url.host_str().unwrap_or(""),
+        section
+    )
+}
+
+/// Identifier of a token that can be logged without revealing it: the beginning of its hash,
+/// which is the same across runs.
+pub fn token_id(token: &str) -> String {
+    let mut hasher = DefaultHasher::new();
+    token.hash(&mut hasher);
+    format!("{:08x}", hasher.finish() >> 32)
+}
+
+/// Send a request with `send`, recording its latency, the status code of its response and the
+/// remaining rate limit in the summary, attributed to the identifier of the token it uses.
+pub fn instrumented<F>(
+    summary: &Summary,
+    token: Option<&str>,
+    request: Request,
+    send: F,
+) -> Fallible<Response>
+where
+    F: FnOnce(Request) -> Fallible<Response>,
+{
+    let endpoint = endpoint(&request);
+    let with_token = token.map_or(String::new(), |token| format!(" with token {}", token));
+    let start = Instant::now();
+    let result = send(request);
+    let elapsed = start.elapsed();
+
+    match &result {
+        Ok(response) => {
+            let remaining = response
+                .headers()
+                .get("x-ratelimit-remaining")
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse().ok());
+            debug!(
+                "{}{} returned {} in {} ms",
+                endpoint,
+                with_token,
+                response.status().as_u16(),
+                elapsed.as_millis()
+            );
+            summary.record_response(&format!("{:?}", response.version()), elapsed);
+            summary.record_request(
+                &endpoint,
+                token,
+                response.status().as_str(),
+                elapsed,
+                remaining,
+            );
+        }
+        Err(err) => {
+            debug!(
+                "{}{} failed after {} ms: {}",
+                endpoint,
+                with_token,
+                elapsed.as_millis(),
+                err
+            );
+            summary.record_request(&endpoint, token, "error", elapsed, None);
+        }
+    }
+    result
+}
+
+/// Execute a request with the client, decompressing its response.
+pub fn execute(client: &Client, request: Request) -> Fallible<Response> {
+    client.execute(request)?.decompressed()
+}
+
+#[derive(Fail, Debug)]
+#[fail(display = "internal server error: {:?}", _0)]
+pub struct RetryRequest(pub StatusCode);
+
+#[derive(Fail, Debug)]
+#[fail(display = "the decompressed response is larger than {} bytes", _0)]
+pub struct ResponseTooLarge(pub usize);
+
+/// Decode a body compressed with one of the `ACCEPT_ENCODING` encodings, rejecting it once it's
+/// larger than `limit` bytes.
+fn decode(encoding: &str, body: &[u8], limit: usize) -> Fallible<Vec<u8>> {
+    match encoding {
+        "identity" => Ok(body.to_vec()),
+        "gzip" | "x-gzip" => gunzip(body, limit),
+        "deflate" => inflate(
+            inflate::decompress_to_vec_zlib_with_limit(body, limit + 1),
+            limit,
+        ),
+        other => bail!("unsupported content encoding: {}", other),
+    }
+}
+
+/// Decompress a gzip member, skipping its header and ignoring its trailer.
+fn gunzip(body: &[u8], limit: usize) -> Fallible<Vec<u8>> {
+    const FHCRC: u8 = 0x02;
+    const FEXTRA: u8 = 0x04;
+    const FNAME: u8 = 0x08;
+    const FCOMMENT: u8 = 0x10;
+
+    if body.len() < 10 || body[..3] != [0x1f, 0x8b, 0x08] {
+        bail!("invalid gzip header");
+    }
+    let flags = body[3];
+    let mut rest = &body[10..];
+    if flags & FEXTRA != 0 {
+        if rest.len() < 2 {
+            bail!("invalid gzip header");
+        }
+        let len = usize::from(rest[0]) | usize::from(rest[1]) << 8;
+        rest = rest
+            .get(2 + len..)
+            .ok_or_else(|| err_msg("invalid gzip header"))?;
+    }
+    for flag in &[FNAME, FCOMMENT] {
+        if flags & flag != 0 {
+            let end = rest
+                .iter()
+                .position(|&byte| byte == 0)
+                .ok_or_else(|| err_msg("invalid gzip header"))?;
+            rest = &rest[end + 1..];
+        }
+    }
+    if flags & FHCRC != 0 {
+        rest = rest
+            .get(2..)
+            .ok_or_else(|| err_msg("invalid gzip header"))?;
+    }
+    inflate(
+        inflate::decompress_to_vec_with_limit(rest, limit + 1),
+        limit,
+    )
+}
+
+fn inflate(result: Result<Vec<u8>, inflate::TINFLStatus>, limit: usize) -> Fallible<Vec<u8>> {
+    match result {
+        Ok(body) if body.len() > limit => Err(ResponseTooLarge(limit).into()),
+        Ok(body) => Ok(body),
+        Err(inflate::TINFLStatus::HasMoreOutput) => Err(ResponseTooLarge(limit).into()),
+        Err(status) => bail!("failed to decompress the response: {:?}", status),
+    }
+}
+
+pub trait ResponseExt {
+    fn handle_errors(self) -> Fallible<Self>
+    where
+        Self: Sized;
+
+    /// Decode the body of the response according to its `Content-Encoding`.
+    fn decompressed(self) -> Fallible<Self>
+    where
+        Self: Sized;
+
+    /// Read the whole body, returning it with a copy of the response whose body can still be read.
+    fn buffered(self) -> Fallible<(Vec<u8>, Self)>
+    where
+        Self: Sized;
+}
+
+impl ResponseExt for Response {
+    fn decompressed(self) -> Fallible<Self> {
+        let encoding = match self.headers().get(header::CONTENT_ENCODING) {
+            Some(encoding) => encoding.to_str()?.trim().to_lowercase(),
+            None => return Ok(self),
+        };
+        let status = self.status();
+        let version = self.version();
+        let mut headers = self.headers().clone();
+        let body = self.bytes()?;
+        let body = decode(&encoding, &body, MAX_DECOMPRESSED_SIZE)?;
+
+        headers.remove(header::CONTENT_ENCODING);
+        headers.remove(header::CONTENT_LENGTH);
+        let mut rebuilt = http_types::Response::builder()
+            .status(status)
+            .version(version)
+            .body(body)?;
+        *rebuilt.headers_mut() = headers;
+        Ok(rebuilt.into())
+    }
+
+    fn buffered(self) -> Fallible<(Vec<u8>, Self)> {
+        let status = self.status();
+        let version = self.version();
+        let headers = self.headers().clone();
+        let body = self.bytes()?.to_vec();
+        let mut rebuilt = http_types::Response::builder()
+            .status(status)
+            .version(version)
+            .body(body.clone())?;
+        *rebuilt.headers_mut() = headers;
+        Ok((body, rebuilt.into()))
+    }
+
+    fn handle_errors(self) -> Fallible<Self> {
+        let status = self.status();
+        let rate_limited = self
+            .headers()
+            .get("x-ratelimit-remaining")
+            .map(|remaining| remaining == "0")
+            .unwrap_or(false);
+        match status {
+            StatusCode::UNAUTHORIZED => Err(ErrorKind::TokenFailure.into()),
+            StatusCode::FORBIDDEN | StatusCode::TOO_MANY_REQUESTS if rate_limited => {
+                Err(ErrorKind::RateLimitExhausted.into())
+            }
+            StatusCode::INTERNAL_SERVER_ERROR
+            | StatusCode::BAD_GATEWAY
+            | StatusCode::SERVICE_UNAVAILABLE
+            | StatusCode::GATEWAY_TIMEOUT => Err(RetryRequest(status).into()),
+            _ => Ok(self),
+        }
+    }
+}
+
+/// Whether a request failed because it took too long.
+pub fn is_timeout(err: &Error) -> bool {
+    err.downcast_ref::<reqwest::Error>()
+        .map(|error| error.is_timeout())
+        .unwrap_or(false)
+        || err
+            .downcast_ref::<io::Error>()
+            .map(|error| error.kind() == io::ErrorKind::TimedOut)
+            .unwrap_or(false)
+}
+
+/// Randomly add or remove up to `jitter` times the backoff from it.
+fn jittered(backoff: u64, jitter: f64) -> Duration {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.subsec_nanos())
+        .unwrap_or(0);
+    let random = Rng::new(u64::from(nanos)).below(2001) as f64 / 1000.0 - 1.0;
+    Duration::from_secs_f64(backoff as f64 * (1.0 + jitter * random))
+}
+
+/// Call `f` until it succeeds or fails with an error that can't be retried, waiting more and
+/// more between the attempts according to the policy. `on_retry` is called with the number of
+/// previous retries before waiting.
+pub fn retry<T, F, R>(
+    service: &str,
+    policy: &RetryPolicy,
+    summary: &Summary,
+    f: F,
+    on_retry: R,
+) -> Fallible<T>
+where
+    F: Fn() -> Fallible<T>,
+    R: Fn(u32),
+{
+    let mut backoff = policy.base_backoff;
+    let mut retries = 0;
+
+    loop {
+        let err = match f() {
+            Ok(res) => return Ok(res),
+            Err(err) => err,
+        };
+
+        let (reason, summary_error) = if let Some(error) = err.downcast_ref::<RetryRequest>() {
+            (
+                format!("returned status code {}", error.0),
+                format!("status code {}", error.0.as_u16()),
+            )
+        } else if is_timeout(&err) {
+            ("timed out".into(), "timeout".into())
+        } else if err
+            .downcast_ref::<io::Error>()
+            .map(|error| error.kind() == io::ErrorKind::ConnectionReset)
+            .unwrap_or(false)
+        {
+            (
+                "had its connection reset by peer".into(),
+                "connection reset".into(),
+            )
+        } else {
+            return Err(err);
+        };
+        summary.record_error(&summary_error);
+
+        if policy.max_retries == Some(retries) {
+            warn!(
+                "API call to {} {}, giving up after {} retries",
+                service, reason, retries
+            );
+            return Err(err);
+        }
+        let wait = jittered(backoff, policy.jitter);
+        warn!(
+            "API call to {} {}, retrying in {} seconds",
+            service,
+            reason,
+            wait.as_secs()
+        );
+        on_retry(retries);
+
+        ::std::thread::sleep(wait);
+
+        // Stop doubling the time at the maximum backoff, to avoid waiting too long
+        backoff = (backoff * 2).min(policy.max_backoff);
+        retries += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, gunzip, ResponseExt, ResponseTooLarge, MAX_DECOMPRESSED_SIZE};
+    use http_types;
+    use miniz_oxide::deflate::{compress_to_vec, compress_to_vec_zlib};
+    use reqwest::blocking::Response;
+    use std::io::Read;
+
+    const FHCRC: u8 = 0x02;
+    const FEXTRA: u8 = 0x04;
+    const FNAME: u8 = 0x08;
+    const FCOMMENT: u8 = 0x10;
+
+    static BODY: &[u8] = b"{\"data\": {\"repository\": null}}";
+
+    /// Header of a gzip member with the given flags, followed by the optional fields.
+    fn header(flags: u8, fields: &[u8]) -> Vec<u8> {
+        let mut header = vec![0x1f, 0x8b, 0x08, flags, 0, 0, 0, 0, 0, 0xff];
+        header.extend_from_slice(fields);
+        header
+    }
+
+    fn gzip(flags: u8, fields: &[u8], body: &[u8]) -> Vec<u8> {
+        let mut member = header(flags, fields);
+        member.extend(compress_to_vec(body, 6));
+        // The CRC32 and size of the trailer are ignored
+        member.extend_from_slice(&[0; 8]);
+        member
+    }
+
+    fn response(encoding: &str, body: Vec<u8>) -> Response {
+        http_types::Response::builder()
+            .header("content-encoding", encoding)
+            .header("content-length", body.len())
+            .body(body)
+            .unwrap()
+            .into()
+    }
+
+    fn decompressed(resp: Response) -> Vec<u8> {
+        let mut resp = resp.decompressed().unwrap();
+        assert!(resp.headers().get("content-encoding").is_none());
+        assert!(resp.headers().get("content-length").is_none());
+        let mut body = Vec::new();
+        resp.read_to_end(&mut body).unwrap();
+        body
+    }
+
+    #[test]
+    fn test_gunzip_headers() {
+        assert_eq!(
+            gunzip(&gzip(0, &[], BODY), MAX_DECOMPRESSED_SIZE).unwrap(),
+            BODY
+        );
+        assert_eq!(
+            gunzip(&gzip(FEXTRA, b"\x03\x00abc", BODY), MAX_DECOMPRESSED_SIZE).unwrap(),
+            BODY
+        );
+        assert_eq!(
+            gunzip(&gzip(FNAME, b"data.json\0", BODY), MAX_DECOMPRESSED_SIZE).unwrap(),
+            BODY
+        );
+        assert_eq!(
+            gunzip(&gzip(FCOMMENT, b"comment\0", BODY), MAX_DECOMPRESSED_SIZE).unwrap(),
+            BODY
+        );
+        assert_eq!(
+            gunzip(&gzip(FHCRC, b"\x12\x34", BODY), MAX_DECOMPRESSED_SIZE).unwrap(),
+            BODY
+        );
+
+        // The fields are in this order when they're all present
+        let all = FEXTRA | FNAME | FCOMMENT | FHCRC;
+        let fields = b"\x01\x00xname\0comment\0\x12\x34";
+        assert_eq!(
+            gunzip(&gzip(all, fields, BODY), MAX_DECOMPRESSED_SIZE).unwrap(),
+            BODY
+        );
+    }
+
+    #[test]
+    fn test_gunzip_truncated_headers() {
+        let mut wrong_magic = gzip(0, &[], BODY);
+        wrong_magic[1] = 0x8c;
+        let mut wrong_method = gzip(0, &[], BODY);
+        wrong_method[2] = 0x07;
+
+        for invalid in &[
+            Vec::new(),
+            header(0, &[])[..9].to_vec(),
+            wrong_magic,
+            wrong_method,
+            header(FEXTRA, b"\x05"),
+            header(FEXTRA, b"\x05\x00abc"),
+            header(FNAME, b"data.json"),
+            header(FCOMMENT, b"comment"),
+            header(FNAME | FCOMMENT, b"data.json\0comment"),
+            header(FHCRC, b"\x12"),
+        ] {
+            assert!(
+                gunzip(invalid, MAX_DECOMPRESSED_SIZE).is_err(),
+                "{:x?} was accepted",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn test_decompressed() {
+        for encoding in &["gzip", "x-gzip", " GZIP "] {
+            assert_eq!(decompressed(response(encoding, gzip(0, &[], BODY))), BODY);
+        }
+        assert_eq!(
+            decompressed(response("deflate", compress_to_vec_zlib(BODY, 6))),
+            BODY
+        );
+        assert_eq!(decompressed(response("identity", BODY.to_vec())), BODY);
+
+        assert!(response("br", BODY.to_vec()).decompressed().is_err());
+        assert!(response("deflate", b"not zlib".to_vec())
+            .decompressed()
+            .is_err());
+        // Raw deflate streams without the zlib wrapper are rejected
+        assert!(response("deflate", compress_to_vec(BODY, 6))
+            .decompressed()
+            .is_err());
+    }
+
+    #[test]
+    fn test_decompressed_size_limit() {
+        let body = vec![b'a'; 1000];
+        let gzip = gzip(0, &[], &body);
+        let zlib = compress_to_vec_zlib(&body, 6);
+        for &(encoding, compressed) in &[("gzip", &gzip), ("deflate", &zlib)] {
+            assert_eq!(decode(encoding, compressed, 1000).unwrap(), body);
+            let err = decode(encoding, compressed, 999).unwrap_err();
+            assert!(err.downcast_ref::<ResponseTooLarge>().is_some());
+        }
+    }
+}
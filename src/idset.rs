@@ -0,0 +1,399 @@
+// Copyright (c) 2018 Pietro Albini <pietro@pietroalbini.org>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies
+// of the Software, and to permit persons to whom the Software is furnished to do
+// so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Compact set of numeric repository IDs, stored like a roaring bitmap: the IDs are grouped by
+//! their upper bits, and each group is either a sorted array (when sparse), a bitmap (when
+//! dense) or a marker (when full).
+
+use prelude::*;
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+
+static MAGIC: &[u8; 8] = b"RRIDSET1";
+
+/// Number of IDs in a group above which it's stored as a bitmap.
+const ARRAY_MAX_LEN: usize = 4096;
+const BITMAP_WORDS: usize = 1 << 16 >> 6;
+const CONTAINER_LEN: u64 = 1 << 16;
+
+enum Container {
+    Array(Vec<u16>),
+    Bitmap(Box<[u64; BITMAP_WORDS]>),
+    Full,
+}
+
+impl Container {
+    fn contains(&self, low: u16) -> bool {
+        match self {
+            Container::Array(values) => values.binary_search(&low).is_ok(),
+            Container::Bitmap(words) => words[low as usize >> 6] & (1 << (low & 63)) != 0,
+            Container::Full => true,
+        }
+    }
+
+    fn to_bitmap(&self) -> Box<[u64; BITMAP_WORDS]> {
+        let mut words = Box::new([0; BITMAP_WORDS]);
+        match self {
+            Container::Array(values) => {
+                for value in values {
+                    words[*value as usize >> 6] |= 1 << (value & 63);
+                }
+            }
+            Container::Bitmap(existing) => words.copy_from_slice(&existing[..]),
+            Container::Full => words.iter_mut().for_each(|word| *word = !0),
+        }
+        words
+    }
+
+    /// Insert all the values between `start` and `end` (inclusive).
+    fn insert_range(&mut self, start: u16, end: u16) {
+        if start == 0 && end == u16::MAX {
+            *self = Container::Full;
+            return;
+        }
+        if let Container::Full = self {
+            return;
+        }
+
+        let mut words = self.to_bitmap();
+        for value in start..=end {
+            words[value as usize >> 6] |= 1 << (value & 63);
+        }
+        *self = if words.iter().all(|word| *word == !0) {
+            Container::Full
+        } else {
+            Container::Bitmap(words)
+        };
+    }
+
+    /// Insert a value, returning whether it was missing.
+    fn insert(&mut self, low: u16) -> bool {
+        let inserted = match self {
+            Container::Array(values) => match values.binary_search(&low) {
+                Ok(_) => false,
+                Err(idx) => {
+                    values.insert(idx, low);
+                    true
+                }
+            },
+            Container::Bitmap(words) => {
+                let word = &mut words[low as usize >> 6];
+                let missing = *word & (1 << (low & 63)) == 0;
+                *word |= 1 << (low & 63);
+                missing
+            }
+            Container::Full => false,
+        };
+
+        let full_array = match self {
+            Container::Array(values) => values.len() > ARRAY_MAX_LEN,
+            _ => false,
+        };
+        if full_array {
+            *self = Container::Bitmap(self.to_bitmap());
+        }
+
+        inserted
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Container::Array(values) => values.len(),
+            Container::Bitmap(words) => words.iter().map(|word| word.count_ones() as usize).sum(),
+            Container::Full => CONTAINER_LEN as usize,
+        }
+    }
+
+    /// Call `f` with the ranges of consecutive values in the container, in order.
+    fn for_each_range<F: FnMut(u16, u16)>(&self, mut f: F) {
+        match self {
+            Container::Full => f(0, u16::MAX),
+            _ => {
+                let mut current: Option<(u16, u16)> = None;
+                for value in 0..=u16::MAX {
+                    if !self.contains(value) {
+                        continue;
+                    }
+                    current = match current {
+                        Some((start, end)) if end + 1 == value => Some((start, value)),
+                        Some((start, end)) => {
+                            f(start, end);
+                            Some((value, value))
+                        }
+                        None => Some((value, value)),
+                    };
+                }
+                if let Some((start, end)) = current {
+                    f(start, end);
+                }
+            }
+        }
+    }
+}
+
+/// Set of numeric IDs, using a few bits per ID when they are dense.
+#[derive(Default)]
+pub struct IdSet {
+    containers: BTreeMap<u64, Container>,
+}
+
+impl IdSet {
+    pub fn contains(&self, id: u64) -> bool {
+        self.containers
+            .get(&(id >> 16))
+            .map(|container| container.contains(id as u16))
+            .unwrap_or(false)
+    }
+
+    /// Insert an ID, returning whether it was missing from the set.
+    pub fn insert(&mut self, id: u64) -> bool {
+        self.containers
+            .entry(id >> 16)
+            .or_insert_with(|| Container::Array(Vec::new()))
+            .insert(id as u16)
+    }
+
+    /// Insert all the IDs between `start` and `end` (inclusive).
+    pub fn insert_range(&mut self, start: u64, end: u64) {
+        if start > end {
+            return;
+        }
+        for key in (start >> 16)..=(end >> 16) {
+            let low = if key == start >> 16 { start as u16 } else { 0 };
+            let high = if key == end >> 16 {
+                end as u16
+            } else {
+                u16::MAX
+            };
+            self.containers
+                .entry(key)
+                .or_insert_with(|| Container::Array(Vec::new()))
+                .insert_range(low, high);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.containers.values().map(Container::len).sum()
+    }
+
+    /// Ranges of consecutive IDs in the set, as (first, last), in order.
+    pub fn ranges(&self) -> Vec<(u64, u64)> {
+        let mut ranges: Vec<(u64, u64)> = Vec::new();
+        for (key, container) in &self.containers {
+            container.for_each_range(|start, end| {
+                let (start, end) = (key << 16 | start as u64, key << 16 | end as u64);
+                match ranges.last_mut() {
+                    Some(last) if last.1 + 1 == start => last.1 = end,
+                    _ => ranges.push((start, end)),
+                }
+            });
+        }
+        ranges
+    }
+
+    /// Ranges of IDs missing from the set between `start` and its highest ID, as (first, last).
+    pub fn gaps(&self, start: u64) -> Vec<(u64, u64)> {
+        let mut gaps = Vec::new();
+        let mut next = start;
+        for (first, last) in self.ranges() {
+            if first > next {
+                gaps.push((next, first - 1));
+            }
+            next = next.max(last + 1);
+        }
+        gaps
+    }
+
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Fallible<()> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&(self.containers.len() as u64).to_le_bytes())?;
+        for (key, container) in &self.containers {
+            writer.write_all(&key.to_le_bytes())?;
+            match container {
+                Container::Array(values) => {
+                    writer.write_all(&[0])?;
+                    writer.write_all(&(values.len() as u32).to_le_bytes())?;
+                    for value in values {
+                        writer.write_all(&value.to_le_bytes())?;
+                    }
+                }
+                Container::Bitmap(words) => {
+                    writer.write_all(&[1])?;
+                    for word in words.iter() {
+                        writer.write_all(&word.to_le_bytes())?;
+                    }
+                }
+                Container::Full => writer.write_all(&[2])?,
+            }
+        }
+        Ok(())
+    }
+
+    pub fn read_from<R: Read>(reader: &mut R) -> Fallible<Self> {
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            bail!("not an ID set file");
+        }
+
+        let mut set = IdSet::default();
+        for _ in 0..read_u64(reader)? {
+            let key = read_u64(reader)?;
+            let mut kind = [0; 1];
+            reader.read_exact(&mut kind)?;
+            let container = match kind[0] {
+                0 => {
+                    let mut len = [0; 4];
+                    reader.read_exact(&mut len)?;
+                    // Bigger arrays are stored as bitmaps, so this is also a corrupted file
+                    let len = u32::from_le_bytes(len) as usize;
+                    if len > ARRAY_MAX_LEN {
+                        bail!("invalid array of {} IDs in the ID set", len);
+                    }
+                    let mut values = Vec::with_capacity(len);
+                    for _ in 0..len {
+                        let mut value = [0; 2];
+                        reader.read_exact(&mut value)?;
+                        let value = u16::from_le_bytes(value);
+                        if values.last().is_some_and(|last| *last >= value) {
+                            bail!("unsorted array in the ID set");
+                        }
+                        values.push(value);
+                    }
+                    Container::Array(values)
+                }
+                1 => {
+                    let mut words = Box::new([0; BITMAP_WORDS]);
+                    for word in words.iter_mut() {
+                        *word = read_u64(reader)?;
+                    }
+                    Container::Bitmap(words)
+                }
+                2 => Container::Full,
+                other => bail!("unknown container kind {} in the ID set", other),
+            };
+            set.containers.insert(key, container);
+        }
+        Ok(set)
+    }
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> Fallible<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{IdSet, ARRAY_MAX_LEN, CONTAINER_LEN};
+
+    fn round_trip(set: &IdSet) -> IdSet {
+        let mut buffer = Vec::new();
+        set.write_to(&mut buffer).unwrap();
+        IdSet::read_from(&mut buffer.as_slice()).unwrap()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut set = IdSet::default();
+        // An array container
+        for id in &[1, 5, 7, CONTAINER_LEN - 1] {
+            set.insert(*id);
+        }
+        // A bitmap container
+        for id in 0..ARRAY_MAX_LEN as u64 + 10 {
+            set.insert(5 * CONTAINER_LEN + id * 3);
+        }
+        // A full container
+        set.insert_range(10 * CONTAINER_LEN, 11 * CONTAINER_LEN - 1);
+
+        let read = round_trip(&set);
+        assert_eq!(read.len(), set.len());
+        assert_eq!(read.ranges(), set.ranges());
+        assert!(read.contains(CONTAINER_LEN - 1));
+        assert!(!read.contains(CONTAINER_LEN));
+        assert!(read.contains(5 * CONTAINER_LEN + 3));
+        assert!(!read.contains(5 * CONTAINER_LEN + 4));
+        assert!(read.contains(10 * CONTAINER_LEN + 1234));
+
+        assert_eq!(round_trip(&IdSet::default()).len(), 0);
+    }
+
+    #[test]
+    fn test_corrupted_file() {
+        let mut set = IdSet::default();
+        set.insert(1);
+        set.insert(2);
+        let mut buffer = Vec::new();
+        set.write_to(&mut buffer).unwrap();
+
+        // Magic, number of containers, key and kind, then the length of the array
+        let len = 8 + 8 + 8 + 1;
+        let mut huge = buffer.clone();
+        huge[len..len + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(IdSet::read_from(&mut huge.as_slice()).is_err());
+
+        let mut unsorted = buffer.clone();
+        unsorted[len + 4..len + 8].copy_from_slice(&[2, 0, 1, 0]);
+        assert!(IdSet::read_from(&mut unsorted.as_slice()).is_err());
+
+        let truncated = &buffer[..buffer.len() - 1];
+        assert!(IdSet::read_from(&mut &truncated[..]).is_err());
+    }
+
+    #[test]
+    fn test_ranges_across_containers() {
+        let mut set = IdSet::default();
+        // Ranges ending and starting at the boundaries of the containers are merged
+        set.insert_range(CONTAINER_LEN - 2, CONTAINER_LEN + 1);
+        set.insert(2 * CONTAINER_LEN - 1);
+        set.insert(2 * CONTAINER_LEN);
+        set.insert_range(3 * CONTAINER_LEN, 4 * CONTAINER_LEN - 1);
+        set.insert(4 * CONTAINER_LEN);
+        assert_eq!(
+            set.ranges(),
+            vec![
+                (CONTAINER_LEN - 2, CONTAINER_LEN + 1),
+                (2 * CONTAINER_LEN - 1, 2 * CONTAINER_LEN),
+                (3 * CONTAINER_LEN, 4 * CONTAINER_LEN),
+            ]
+        );
+        assert_eq!(round_trip(&set).ranges(), set.ranges());
+
+        assert_eq!(
+            set.gaps(0),
+            vec![
+                (0, CONTAINER_LEN - 3),
+                (CONTAINER_LEN + 2, 2 * CONTAINER_LEN - 2),
+                (2 * CONTAINER_LEN + 1, 3 * CONTAINER_LEN - 1),
+            ]
+        );
+        // Gaps before `start` are ignored
+        assert_eq!(
+            set.gaps(CONTAINER_LEN + 5),
+            vec![
+                (CONTAINER_LEN + 5, 2 * CONTAINER_LEN - 2),
+                (2 * CONTAINER_LEN + 1, 3 * CONTAINER_LEN - 1),
+            ]
+        );
+        assert!(set.gaps(4 * CONTAINER_LEN + 1).is_empty());
+    }
+}
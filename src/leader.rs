@@ -0,0 +1,492 @@
+// Copyright (c) 2018 Pietro Albini <pietro@pietroalbini.org>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies
+// of the Software, and to permit persons to whom the Software is furnished to do
+// so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Leader election among replicas of the scraper, so that exactly one of them scrapes at a time
+//! while the others stand by, ready to take over when it fails.
+//!
+//! The leader holds a lease stored as an object at `RUST_REPOS_LEADER_URL`, which it renews in
+//! the background. The lease is only written with conditional requests (`If-None-Match: *` to
+//! create it and `If-Match` with the ETag last seen to update it), which object storage like S3
+//! supports, so two replicas can never both believe to hold it. Requests are authenticated with
+//! a bearer token only: S3 itself needs a signing proxy in front of the bucket.
+//!
+//! A leader stops writing to the data directory as soon as it gives up the lease, before another
+//! replica can take it over, instead of waiting for the scraper to notice it has to stop.
+
+use config::Config;
+use http;
+use prelude::*;
+use reqwest::blocking::{Client, RequestBuilder, Response};
+use reqwest::{header, StatusCode};
+use serde_json;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Serialize, Deserialize)]
+struct Lease {
+    holder: String,
+    /// Unix timestamp after which other replicas can take over.
+    expires_at: u64,
+}
+
+struct LeaseStore {
+    client: Client,
+    url: String,
+    token: Option<String>,
+}
+
+impl LeaseStore {
+    fn request(&self, builder: RequestBuilder) -> Fallible<Response> {
+        let builder = match &self.token {
+            Some(token) => builder.bearer_auth(token),
+            None => builder,
+        };
+        // The client asks for compressed responses, which need to be decoded
+        http::execute(&self.client, builder.build()?)
+    }
+
+    /// Fetch the current lease and its ETag, if any. A lease that can't be parsed is an error, so
+    /// that a garbled response never lets a standby take over.
+    fn read(&self) -> Fallible<Option<(Lease, String)>> {
+        let resp = self.request(self.client.get(&self.url))?;
+        if resp.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        if !resp.status().is_success() {
+            bail!("fetching the leader lease failed with {}", resp.status());
+        }
+        let etag = etag(&resp)?;
+        let lease =
+            serde_json::from_slice(&resp.bytes()?).context("the leader lease can't be parsed")?;
+        Ok(Some((lease, etag)))
+    }
+
+    /// Write the lease if it's still at the ETag (or missing, without an ETag), returning the new
+    /// ETag, or `None` if another replica changed it meanwhile.
+    fn write(&self, lease: &Lease, current: Option<&str>) -> Fallible<Option<String>> {
+        let builder = self.client.put(&self.url).json(lease);
+        let builder = match current {
+            Some(etag) => builder.header(header::IF_MATCH, etag),
+            None => builder.header(header::IF_NONE_MATCH, "*"),
+        };
+        let resp = self.request(builder)?;
+        match resp.status() {
+            StatusCode::PRECONDITION_FAILED | StatusCode::CONFLICT => Ok(None),
+            status if status.is_success() => {
+                if resp.headers().contains_key(header::ETAG) {
+                    return Ok(Some(etag(&resp)?));
+                }
+                // Some servers don't return the ETag when writing
+                match self.read()? {
+                    Some((written, etag)) if written.holder == lease.holder => Ok(Some(etag)),
+                    _ => Ok(None),
+                }
+            }
+            status => bail!("writing the leader lease failed with {}", status),
+        }
+    }
+}
+
+fn etag(resp: &Response) -> Fallible<String> {
+    match resp.headers().get(header::ETAG) {
+        Some(etag) => Ok(etag.to_str()?.to_string()),
+        None => bail!("the leader lease has no ETag, conditional writes are not supported"),
+    }
+}
+
+/// Leadership held by this replica, renewed in the background and released when dropped.
+pub struct Leadership {
+    store: Arc<LeaseStore>,
+    holder: String,
+    /// ETag of the lease, `None` once the leadership is lost.
+    etag: Arc<Mutex<Option<String>>>,
+    /// Set as soon as the leadership is given up, to fence the writes to the data directory.
+    lost: Arc<AtomicBool>,
+}
+
+impl Leadership {
+    /// Flag set once the leadership is lost, after which the data directory must not be written.
+    pub fn lost(&self) -> Arc<AtomicBool> {
+        self.lost.clone()
+    }
+}
+
+impl Drop for Leadership {
+    fn drop(&mut self) {
+        // Let a standby take over right away instead of waiting for the lease to expire
+        if let Some(etag) = self.etag.lock().unwrap().take() {
+            let lease = Lease {
+                holder: self.holder.clone(),
+                expires_at: 0,
+            };
+            match self.store.write(&lease, Some(&etag)) {
+                Ok(_) => info!("released the leadership"),
+                Err(err) => warn!("failed to release the leadership: {}", err),
+            }
+        }
+    }
+}
+
+/// Wait until this replica becomes the leader, returning `None` if it's stopped meanwhile. Once
+/// elected, `should_stop` is set if the leadership is lost.
+pub fn elect(config: &Config, should_stop: &Arc<AtomicBool>) -> Fallible<Option<Leadership>> {
+    let url = match &config.leader_url {
+        Some(url) => url.clone(),
+        None => bail!("missing RUST_REPOS_LEADER_URL"),
+    };
+    let store = Arc::new(LeaseStore {
+        client: http::client(config)?,
+        url,
+        token: config.leader_token.clone(),
+    });
+    let holder = format!(
+        "{}:{}",
+        std::env::var("HOSTNAME").unwrap_or_else(|_| "unknown".into()),
+        std::process::id()
+    );
+    let duration = config.leader_lease;
+
+    let mut standing_by = false;
+    loop {
+        if should_stop.load(Ordering::SeqCst) {
+            return Ok(None);
+        }
+
+        let lease = Lease {
+            holder: holder.clone(),
+            expires_at: unix_now() + duration,
+        };
+        let attempt = store.read().and_then(|current| match current {
+            None => store.write(&lease, None),
+            Some((current, etag)) if current.expires_at <= unix_now() => {
+                store.write(&lease, Some(&etag))
+            }
+            Some((current, _)) => {
+                if !standing_by {
+                    info!("standing by, {} is the leader", current.holder);
+                    standing_by = true;
+                }
+                Ok(None)
+            }
+        });
+        match attempt {
+            Ok(Some(etag)) => {
+                info!("elected as the leader ({})", holder);
+                let leadership = Leadership {
+                    store,
+                    holder,
+                    etag: Arc::new(Mutex::new(Some(etag))),
+                    lost: Arc::new(AtomicBool::new(false)),
+                };
+                spawn_renewal(&leadership, duration, should_stop.clone());
+                return Ok(Some(leadership));
+            }
+            Ok(None) => {}
+            Err(err) => warn!("failed to check the leader lease: {}", err),
+        }
+
+        // Check again a few times per lease, to take over soon after the leader fails
+        for _ in 0..(duration / 3).max(1) {
+            if should_stop.load(Ordering::SeqCst) {
+                return Ok(None);
+            }
+            thread::sleep(Duration::from_secs(1));
+        }
+    }
+}
+
+/// Renew the lease three times per lease duration, stopping the scraper if the leadership is
+/// lost to another replica or if the lease can't be renewed before it expires.
+fn spawn_renewal(leadership: &Leadership, duration: u64, should_stop: Arc<AtomicBool>) {
+    let store = leadership.store.clone();
+    let holder = leadership.holder.clone();
+    let etag = leadership.etag.clone();
+    let lost = leadership.lost.clone();
+    let mut expires_at = unix_now() + duration;
+    thread::spawn(move || loop {
+        thread::sleep(Duration::from_secs((duration / 3).max(1)));
+
+        let mut etag = etag.lock().unwrap();
+        let current = match etag.clone() {
+            Some(current) => current,
+            // Released
+            None => return,
+        };
+        let lease = Lease {
+            holder: holder.clone(),
+            expires_at: unix_now() + duration,
+        };
+        match store.write(&lease, Some(&current)) {
+            Ok(Some(renewed)) => {
+                debug!("renewed the leader lease");
+                *etag = Some(renewed);
+                expires_at = lease.expires_at;
+                continue;
+            }
+            Ok(None) => error!("lost the leadership to another replica, stopping"),
+            Err(err) if unix_now() + duration / 3 < expires_at => {
+                warn!("failed to renew the leader lease: {}", err);
+                continue;
+            }
+            Err(err) => error!(
+                "failed to renew the leader lease before it expires, stopping: {}",
+                err
+            ),
+        }
+        *etag = None;
+        lost.store(true, Ordering::SeqCst);
+        should_stop.store(true, Ordering::SeqCst);
+        return;
+    });
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{elect, Lease, LeaseStore};
+    use config::Config;
+    use data::Data;
+    use http;
+    use serde_json;
+    use std::collections::HashMap;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    /// Object storage holding the lease, supporting conditional writes.
+    #[derive(Default)]
+    struct Stub {
+        /// Body of the lease and its version, used as the ETag.
+        lease: Option<(Vec<u8>, u64)>,
+        versions: u64,
+        /// Whether to return a lease that can't be parsed.
+        garbled: bool,
+        puts: usize,
+    }
+
+    impl Stub {
+        fn set_lease(&mut self, holder: &str, expires_at: u64) {
+            self.versions += 1;
+            let lease = Lease {
+                holder: holder.into(),
+                expires_at,
+            };
+            self.lease = Some((serde_json::to_vec(&lease).unwrap(), self.versions));
+        }
+
+        fn lease(&self) -> Lease {
+            serde_json::from_slice(&self.lease.as_ref().unwrap().0).unwrap()
+        }
+
+        fn handle(
+            &mut self,
+            method: &str,
+            headers: &HashMap<String, String>,
+            body: Vec<u8>,
+        ) -> (&'static str, Option<String>, Vec<u8>) {
+            let etag = self
+                .lease
+                .as_ref()
+                .map(|(_, version)| format!("\"{}\"", version));
+            if method == "GET" {
+                return match &self.lease {
+                    None => ("404 Not Found", None, Vec::new()),
+                    Some(_) if self.garbled => ("200 OK", etag, b"{\"holder\":".to_vec()),
+                    Some((lease, _)) => ("200 OK", etag, lease.clone()),
+                };
+            }
+
+            self.puts += 1;
+            let matches = match (headers.get("if-match"), headers.get("if-none-match")) {
+                (Some(expected), _) => etag.as_ref() == Some(expected),
+                (None, Some(star)) => star == "*" && etag.is_none(),
+                (None, None) => true,
+            };
+            if !matches {
+                return ("412 Precondition Failed", None, Vec::new());
+            }
+            self.versions += 1;
+            self.lease = Some((body, self.versions));
+            ("200 OK", Some(format!("\"{}\"", self.versions)), Vec::new())
+        }
+    }
+
+    /// Serve the stub on a random port, returning the URL of the lease.
+    fn serve(stub: Arc<Mutex<Stub>>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/lease", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                respond(&stub, stream.unwrap());
+            }
+        });
+        url
+    }
+
+    fn respond(stub: &Mutex<Stub>, mut stream: TcpStream) {
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line).unwrap();
+        let mut headers = HashMap::new();
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            match line.trim_end().split_once(':') {
+                Some((name, value)) => {
+                    headers.insert(name.to_lowercase(), value.trim().to_string());
+                }
+                None => break,
+            }
+        }
+        let len = headers
+            .get("content-length")
+            .map_or(0, |len| len.parse().unwrap());
+        let mut body = vec![0; len];
+        reader.read_exact(&mut body).unwrap();
+
+        let method = request_line.split(' ').next().unwrap();
+        let (status, etag, body) = stub.lock().unwrap().handle(method, &headers, body);
+        let etag = etag.map_or(String::new(), |etag| format!("ETag: {}\r\n", etag));
+        write!(
+            stream,
+            "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n{}\r\n",
+            status,
+            body.len(),
+            etag
+        )
+        .unwrap();
+        stream.write_all(&body).unwrap();
+    }
+
+    fn config(name: &str, url: &str) -> Config {
+        let dir = ::std::env::temp_dir().join(format!(
+            "rust-repos-leader-{}-{}",
+            ::std::process::id(),
+            name
+        ));
+        let mut config = Config::from_env(dir).unwrap();
+        config.leader_url = Some(url.to_string());
+        config.leader_lease = 3;
+        config
+    }
+
+    fn store(config: &Config) -> LeaseStore {
+        LeaseStore {
+            client: http::client(config).unwrap(),
+            url: config.leader_url.clone().unwrap(),
+            token: None,
+        }
+    }
+
+    #[test]
+    fn test_take_over_stale_lease() {
+        let stub = Arc::new(Mutex::new(Stub::default()));
+        stub.lock().unwrap().set_lease("other", 1);
+        let config = config("stale", &serve(stub.clone()));
+
+        let leadership = elect(&config, &Arc::new(AtomicBool::new(false)))
+            .unwrap()
+            .unwrap();
+        let lease = stub.lock().unwrap().lease();
+        assert_eq!(lease.holder, leadership.holder);
+        assert!(lease.expires_at > 1);
+
+        // Dropping the leadership releases the lease
+        drop(leadership);
+        assert_eq!(stub.lock().unwrap().lease().expires_at, 0);
+    }
+
+    #[test]
+    fn test_conflicting_write() {
+        let stub = Arc::new(Mutex::new(Stub::default()));
+        stub.lock().unwrap().set_lease("other", 1);
+        let config = config("conflict", &serve(stub.clone()));
+        let store = store(&config);
+        let lease = Lease {
+            holder: "me".into(),
+            expires_at: u64::MAX,
+        };
+
+        // Another replica took the lease after it was read
+        let (_, etag) = store.read().unwrap().unwrap();
+        stub.lock().unwrap().set_lease("another", u64::MAX);
+        assert!(store.write(&lease, Some(&etag)).unwrap().is_none());
+        // Creating a lease that already exists
+        assert!(store.write(&lease, None).unwrap().is_none());
+        assert_eq!(stub.lock().unwrap().lease().holder, "another");
+    }
+
+    #[test]
+    fn test_unreadable_lease() {
+        let stub = Arc::new(Mutex::new(Stub::default()));
+        stub.lock().unwrap().set_lease("other", 1);
+        stub.lock().unwrap().garbled = true;
+        let config = config("unreadable", &serve(stub.clone()));
+        assert!(store(&config).read().is_err());
+
+        // Even if the lease looks expired, it's never taken over
+        let should_stop = Arc::new(AtomicBool::new(false));
+        let stop = should_stop.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(1500));
+            stop.store(true, Ordering::SeqCst);
+        });
+        assert!(elect(&config, &should_stop).unwrap().is_none());
+        assert_eq!(stub.lock().unwrap().puts, 0);
+    }
+
+    #[test]
+    fn test_lost_lease_fences_writes() {
+        let stub = Arc::new(Mutex::new(Stub::default()));
+        let config = config("lost", &serve(stub.clone()));
+        let should_stop = Arc::new(AtomicBool::new(false));
+        let leadership = elect(&config, &should_stop).unwrap().unwrap();
+        let mut data = Data::new(&config).unwrap();
+        data.set_fence(leadership.lost());
+        data.flush().unwrap();
+
+        // Another replica takes over, which is noticed by the next renewal
+        stub.lock().unwrap().set_lease("other", u64::MAX);
+        let start = Instant::now();
+        while !leadership.lost().load(Ordering::SeqCst) {
+            assert!(start.elapsed() < Duration::from_secs(10));
+            thread::sleep(Duration::from_millis(50));
+        }
+        assert!(should_stop.load(Ordering::SeqCst));
+        assert!(data.flush().is_err());
+
+        // The lease of the other replica is left alone
+        let puts = stub.lock().unwrap().puts;
+        drop(leadership);
+        assert_eq!(stub.lock().unwrap().puts, puts);
+        assert_eq!(stub.lock().unwrap().lease().holder, "other");
+    }
+}
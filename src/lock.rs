@@ -0,0 +1,93 @@
+// Copyright (c) 2018 Pietro Albini <pietro@pietroalbini.org>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies
+// of the Software, and to permit persons to whom the Software is furnished to do
+// so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use prelude::*;
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::{Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+static LOCK_FILE: &str = ".lock";
+
+/// Data directories locked by this process, as a second lock on the same directory would fail.
+static HELD: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// Advisory lock preventing multiple instances of the scraper from using the same data directory
+/// at the same time. The lock is released when this struct is dropped.
+///
+/// The lock is held on the file itself (with `flock` or `LockFileEx`), which is never deleted:
+/// the operating system releases it when the process exits, even if it crashed, so there are no
+/// stale locks to detect.
+pub struct DataDirLock {
+    // The lock is released when the file is closed
+    _file: File,
+    data_dir: PathBuf,
+}
+
+impl DataDirLock {
+    pub fn acquire(data_dir: &Path) -> Fallible<Self> {
+        let path = data_dir.join(LOCK_FILE);
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => bail!(
+                "the data directory is already in use by another process (holding {})",
+                path.to_string_lossy()
+            ),
+            Err(TryLockError::Error(err)) => return Err(err.into()),
+        }
+
+        // The PID is only informative, to find which process holds the lock
+        file.set_len(0)?;
+        file.seek(SeekFrom::Start(0))?;
+        writeln!(file, "{}", std::process::id())?;
+        file.flush()?;
+
+        let data_dir = data_dir.canonicalize()?;
+        HELD.lock().unwrap().push(data_dir.clone());
+        Ok(DataDirLock {
+            _file: file,
+            data_dir,
+        })
+    }
+
+    /// Whether this process already holds the lock of the data directory.
+    pub fn is_held(data_dir: &Path) -> bool {
+        match data_dir.canonicalize() {
+            Ok(data_dir) => HELD.lock().unwrap().contains(&data_dir),
+            Err(_) => false,
+        }
+    }
+}
+
+impl Drop for DataDirLock {
+    fn drop(&mut self) {
+        let mut held = HELD.lock().unwrap();
+        if let Some(pos) = held.iter().position(|dir| *dir == self.data_dir) {
+            held.remove(pos);
+        }
+    }
+}
@@ -0,0 +1,82 @@
+// Copyright (c) 2018 Pietro Albini <pietro@pietroalbini.org>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies
+// of the Software, and to permit persons to whom the Software is furnished to do
+// so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use data::LockedPackage;
+
+/// Extract the packages from the content of a `Cargo.lock`.
+///
+/// This only understands the `[[package]]` tables, which is enough for all the lockfile formats
+/// Cargo ever produced, and avoids parsing the whole TOML. Dependencies are recorded by name, as
+/// entries are either `name` or `name version (source)` depending on the lockfile version.
+pub fn parse(repo_id: &str, content: &str) -> Vec<LockedPackage> {
+    let mut packages = Vec::new();
+    let mut current: Option<LockedPackage> = None;
+
+    let mut lines = content.lines();
+    while let Some(line) = lines.next() {
+        let line = line.trim();
+        if line.starts_with('[') {
+            packages.extend(current.take().filter(|package| !package.name.is_empty()));
+            if line == "[[package]]" {
+                current = Some(LockedPackage {
+                    repo_id: repo_id.to_string(),
+                    name: String::new(),
+                    version: String::new(),
+                    source: None,
+                    dependencies: String::new(),
+                });
+            }
+            continue;
+        }
+
+        if let Some(package) = &mut current {
+            let mut parts = line.splitn(2, '=');
+            let (key, value) = match (parts.next(), parts.next()) {
+                (Some(key), Some(value)) => (key.trim(), value.trim().trim_matches('"')),
+                _ => continue,
+            };
+            match key {
+                "dependencies" => {
+                    let mut array = value.to_string();
+                    while !array.contains(']') {
+                        match lines.next() {
+                            Some(next) => array.push_str(next),
+                            None => break,
+                        }
+                    }
+                    package.dependencies = array
+                        .split('"')
+                        .skip(1)
+                        .step_by(2)
+                        .filter_map(|dep| dep.split_whitespace().next())
+                        .collect::<Vec<_>>()
+                        .join(" ");
+                }
+                "name" => package.name = value.to_string(),
+                "version" => package.version = value.to_string(),
+                "source" => package.source = Some(value.to_string()),
+                _ => {}
+            }
+        }
+    }
+
+    packages.extend(current.filter(|package| !package.name.is_empty()));
+    packages
+}
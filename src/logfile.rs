@@ -0,0 +1,130 @@
+// Copyright (c) 2018 Pietro Albini <pietro@pietroalbini.org>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies
+// of the Software, and to permit persons to whom the Software is furnished to do
+// so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use env_logger;
+use prelude::*;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+use utils::format_timestamp;
+
+static PREFIX: &str = "rust-repos.";
+static SUFFIX: &str = ".log";
+
+/// Log file rotated every day, keeping only the files of the most recent days.
+pub struct RotatingFile {
+    dir: PathBuf,
+    retention: usize,
+    date: String,
+    file: File,
+}
+
+impl RotatingFile {
+    pub fn open(dir: PathBuf, retention: usize) -> Fallible<Self> {
+        if retention == 0 {
+            bail!("at least one log file must be kept");
+        }
+        fs::create_dir_all(&dir)?;
+
+        let date = today();
+        let file = open_log(&dir, &date)?;
+        let rotating = RotatingFile {
+            dir,
+            retention,
+            date,
+            file,
+        };
+        rotating.remove_old()?;
+        Ok(rotating)
+    }
+
+    fn rotate(&mut self, date: String) -> io::Result<()> {
+        self.file.flush()?;
+        self.file = open_log(&self.dir, &date)?;
+        self.date = date;
+        self.remove_old()
+    }
+
+    /// Remove the log files past the retention limit. The names contain the date, so sorting
+    /// them also sorts them by age.
+    fn remove_old(&self) -> io::Result<()> {
+        let mut logs = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let name = entry?.file_name().to_string_lossy().to_string();
+            if name.starts_with(PREFIX) && name.ends_with(SUFFIX) {
+                logs.push(name);
+            }
+        }
+        logs.sort();
+
+        let excess = logs.len().saturating_sub(self.retention);
+        for name in &logs[..excess] {
+            fs::remove_file(self.dir.join(name))?;
+        }
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let date = today();
+        if date != self.date {
+            self.rotate(date)?;
+        }
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Configure the logger to also write every line to the log files, in the same format used on
+/// stderr.
+pub fn install(logger: &mut env_logger::Builder, file: RotatingFile) {
+    let file = Mutex::new(file);
+    logger.format(move |buf, record| {
+        let line = format!(
+            "[{} {:<5} {}] {}\n",
+            buf.timestamp(),
+            record.level(),
+            record.target(),
+            record.args()
+        );
+        // Failing to write the log file must not prevent logging to stderr
+        if let Err(err) = file.lock().unwrap().write_all(line.as_bytes()) {
+            eprintln!("failed to write to the log file: {}", err);
+        }
+        buf.write_all(line.as_bytes())
+    });
+}
+
+fn open_log(dir: &Path, date: &str) -> io::Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(dir.join(format!("{}{}{}", PREFIX, date, SUFFIX)))
+}
+
+fn today() -> String {
+    format_timestamp(SystemTime::now())[..10].to_string()
+}
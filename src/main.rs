@@ -18,14 +18,34 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+// failure_derive generates its impls inside anonymous constants
+#![allow(non_local_definitions)]
+// The configuration and the helpers of the optional features are kept in minimal builds
+#![cfg_attr(not(all(feature = "export", feature = "monitoring")), allow(dead_code))]
+
+// Every HTTP request needs TLS, so a build without a backend would be useless
+#[cfg(not(feature = "native-tls"))]
+compile_error!("a TLS backend is required, enable the `native-tls` feature");
+
+#[cfg(feature = "monitoring")]
+extern crate base64;
 extern crate crossbeam_utils;
 extern crate csv;
 extern crate ctrlc;
 extern crate env_logger;
+#[cfg(unix)]
+extern crate libc;
 #[macro_use]
 extern crate failure;
+// Renamed to leave the name to the module with the shared HTTP infrastructure
+extern crate http as http_types;
 #[macro_use]
 extern crate log;
+extern crate miniz_oxide;
+#[cfg(feature = "native-tls")]
+extern crate native_tls;
+#[cfg(feature = "encryption")]
+extern crate openssl;
 extern crate reqwest;
 extern crate serde;
 #[macro_use]
@@ -33,14 +53,62 @@ extern crate serde_derive;
 #[macro_use]
 extern crate serde_json;
 
+mod advisories;
+#[cfg(feature = "monitoring")]
+mod alert;
+#[cfg(feature = "export")]
+mod browser;
+mod canonical;
+mod cli;
+mod clone;
 mod config;
+mod crater;
+mod crates;
+mod csv_reader;
 mod data;
+mod denylist;
+mod depgraph;
+mod encryption;
+mod exit;
+#[cfg(feature = "export")]
+mod export;
+mod feed;
 mod github;
+#[cfg(feature = "monitoring")]
+mod health;
+mod history;
+mod http;
+mod idset;
+mod leader;
+mod lock;
+mod lockfile;
+mod logfile;
+mod manifest;
+#[cfg(feature = "monitoring")]
+mod notify;
 mod prelude;
+mod query;
+mod readme;
+mod recorder;
+mod render;
+mod report;
+mod sample;
+mod sbom;
+mod search;
+mod seed;
+#[cfg(feature = "monitoring")]
+mod smtp;
+mod state;
+mod stats;
+mod summary;
+mod systemd;
+mod toolchain;
 mod utils;
+mod version;
 
-use config::Config;
+use cli::{Command, Options};
 use prelude::*;
+use std::io::{self, Write};
 use std::path::PathBuf;
 use std::sync::{
     atomic::{AtomicBool, Ordering},
@@ -48,45 +116,380 @@ use std::sync::{
 };
 use std::time::Instant;
 
-fn app() -> Fallible<()> {
-    // Get the GitHub token from the environment
-    let github_token =
-        std::env::var("GITHUB_TOKEN").context("failed to get the GitHub API token")?;
-
-    let timeout = if let Ok(var) = std::env::var("RUST_REPOS_TIMEOUT") {
-        Some(
-            var.parse::<u64>()
-                .context("failed to parse RUST_REPOS_TIMEOUT")?,
-        )
-    } else {
-        None
-    };
+fn app() -> Fallible<i32> {
+    let (options, command) = cli::parse_args()?;
+    match command {
+        Command::Scrape {
+            data_dir,
+            discovery_only,
+            backfill,
+        } => {
+            let mode = if backfill {
+                ScrapeMode::Backfill
+            } else {
+                ScrapeMode::Frontier
+            };
+            Ok(scrape(&options, data_dir, discovery_only, mode)?.exit_code())
+        }
+        Command::Popular {
+            data_dir,
+            min_stars,
+        } => Ok(scrape(&options, data_dir, false, ScrapeMode::Popular(min_stars))?.exit_code()),
+        Command::Canonicalize(data_dir) => {
+            let config = options.load_config(data_dir)?;
+            let data = data::Data::new(&config)?;
+            canonical::run(&data)?;
+            Ok(exit::EXIT_SUCCESS)
+        }
+        Command::TokensStatus => {
+            // The data directory is not used when checking the tokens
+            let config = options.load_config(PathBuf::new())?;
+            github::tokens_status(&config, &summary::Summary::default())?;
+            Ok(exit::EXIT_SUCCESS)
+        }
+        Command::Schema => {
+            let stdout = std::io::stdout();
+            serde_json::to_writer_pretty(stdout.lock(), data::Repo::schema())?;
+            println!();
+            Ok(exit::EXIT_SUCCESS)
+        }
+        #[cfg(feature = "export")]
+        Command::Export {
+            target,
+            data_dir,
+            output,
+        } => {
+            let config = options.load_config(data_dir)?;
+            let _lock = lock::DataDirLock::acquire(&config.data_dir).context(ErrorKind::Storage)?;
+            let data = data::Data::new(&config)?;
+            export::export(&data, &config, target, &output)?;
+            Ok(exit::EXIT_SUCCESS)
+        }
+        Command::Query {
+            data_dir,
+            sql,
+            format,
+        } => {
+            let config = options.load_config(data_dir)?;
+            let data = data::Data::new(&config)?;
+            query::run(&data, &sql, format)?;
+            Ok(exit::EXIT_SUCCESS)
+        }
+        Command::Sample {
+            data_dir,
+            size,
+            seed,
+            stratify_by,
+        } => {
+            let config = options.load_config(data_dir)?;
+            let data = data::Data::new(&config)?;
+            sample::run(&data, size, seed, stratify_by.as_deref())?;
+            Ok(exit::EXIT_SUCCESS)
+        }
+        Command::CraterList {
+            data_dir,
+            filter,
+            include_archived,
+        } => {
+            let config = options.load_config(data_dir)?;
+            let data = data::Data::new(&config)?;
+            crater::list(&data, filter.as_ref(), include_archived)?;
+            Ok(exit::EXIT_SUCCESS)
+        }
+        Command::Clone {
+            data_dir,
+            dest,
+            filter,
+            jobs,
+        } => {
+            let config = options.load_config(data_dir)?;
+            let data = data::Data::new(&config)?;
+            clone::run(&data, &dest, filter.as_ref(), jobs)?;
+            Ok(exit::EXIT_SUCCESS)
+        }
+        Command::Lockfiles(data_dir) => {
+            let config = options.load_config(data_dir)?;
+            config.require_api_access()?;
+            let _lock = lock::DataDirLock::acquire(&config.data_dir).context(ErrorKind::Storage)?;
+            let data = data::Data::new(&config)?;
+
+            let should_stop = Arc::new(AtomicBool::new(false));
+            let stop = should_stop.clone();
+            ctrlc::set_handler(move || {
+                info!("received Ctrl+C, terminating...");
+                stop.store(true, Ordering::SeqCst);
+            })?;
+
+            let summary = summary::Summary::default();
+            github::fetch_lockfiles(&data, &config, &summary, &should_stop)?;
+            Ok(exit::EXIT_SUCCESS)
+        }
+        Command::Advisories {
+            data_dir,
+            advisory_db,
+            output,
+        } => {
+            let config = options.load_config(data_dir)?;
+            let data = data::Data::new(&config)?;
+            advisories::report(&data, &advisory_db, &output)?;
+            Ok(exit::EXIT_SUCCESS)
+        }
+        Command::CratesMap {
+            data_dir,
+            crates_csv,
+            output,
+        } => {
+            let config = options.load_config(data_dir)?;
+            let data = data::Data::new(&config)?;
+            crates::map(&data, &crates_csv, &output)?;
+            Ok(exit::EXIT_SUCCESS)
+        }
+        Command::Sbom { data_dir, format } => {
+            let config = options.load_config(data_dir)?;
+            let data = data::Data::new(&config)?;
+            sbom::generate(&data, format)?;
+            Ok(exit::EXIT_SUCCESS)
+        }
+        Command::DepGraph {
+            data_dir,
+            format,
+            min_repos,
+        } => {
+            let config = options.load_config(data_dir)?;
+            let data = data::Data::new(&config)?;
+            depgraph::export(&data, format, min_repos)?;
+            Ok(exit::EXIT_SUCCESS)
+        }
+        Command::Owners {
+            data_dir,
+            size,
+            seed,
+            budget,
+        } => {
+            let config = options.load_config(data_dir)?;
+            config.require_api_access()?;
+            let _lock = lock::DataDirLock::acquire(&config.data_dir).context(ErrorKind::Storage)?;
+            let data = data::Data::new(&config)?;
+            let summary = summary::Summary::default();
+            github::fetch_owners(&data, &config, &summary, size, seed, budget)?;
+            Ok(exit::EXIT_SUCCESS)
+        }
+        #[cfg(feature = "monitoring")]
+        Command::Digest { data_dir, days } => {
+            let config = options.load_config(data_dir)?;
+            let data = data::Data::new(&config)?;
+            notify::send_digest(&data, &config, days)?;
+            Ok(exit::EXIT_SUCCESS)
+        }
+        Command::Feed {
+            data_dir,
+            filter,
+            limit,
+            output,
+        } => {
+            let config = options.load_config(data_dir)?;
+            let data = data::Data::new(&config)?;
+            let feed = feed::render(&data, filter.as_ref(), limit)?;
+            match output {
+                Some(path) => data::write_atomically(&path, |out| {
+                    out.write_all(feed.as_bytes())?;
+                    Ok(())
+                })?,
+                None => print!("{}", feed),
+            }
+            Ok(exit::EXIT_SUCCESS)
+        }
+        Command::DecryptExport { data_dir, output } => {
+            let config = options.load_config(data_dir)?;
+            let data = data::Data::new(&config)?;
+            match output {
+                Some(path) => {
+                    data::write_atomically(&path, |out| data.decrypt_repos("github", out))?
+                }
+                None => data.decrypt_repos("github", &mut io::stdout().lock())?,
+            }
+            Ok(exit::EXIT_SUCCESS)
+        }
+        Command::History {
+            data_dir,
+            filter,
+            as_of,
+            output,
+        } => {
+            let config = options.load_config(data_dir)?;
+            let data = data::Data::new(&config)?;
+            let as_of = as_of.as_deref();
+            match output {
+                Some(path) => data::write_atomically(&path, |out| {
+                    history::write(&data, filter.as_ref(), as_of, out)
+                })?,
+                None => history::write(&data, filter.as_ref(), as_of, &mut io::stdout().lock())?,
+            }
+            Ok(exit::EXIT_SUCCESS)
+        }
+        Command::Stats { data_dir, format } => {
+            let config = options.load_config(data_dir)?;
+            let data = data::Data::new(&config)?;
+            stats::print(&data, format)?;
+            Ok(exit::EXIT_SUCCESS)
+        }
+        Command::Report {
+            data_dir,
+            format,
+            template,
+        } => {
+            let config = options.load_config(data_dir)?;
+            let data = data::Data::new(&config)?;
+            report::generate(&data, format, template.as_deref())?;
+            Ok(exit::EXIT_SUCCESS)
+        }
+        Command::Compact { data_dir, sort } => {
+            let config = options.load_config(data_dir)?;
+            let _lock = lock::DataDirLock::acquire(&config.data_dir).context(ErrorKind::Storage)?;
+            let data = data::Data::new(&config)?;
+            let removed = data.compact("github", sort)?;
+            info!("removed {} outdated rows", removed);
+            Ok(exit::EXIT_SUCCESS)
+        }
+        Command::Enrich {
+            data_dir,
+            fields,
+            budget,
+        } => {
+            let config = options.load_config(data_dir)?;
+            config.require_api_access()?;
+            let _lock = lock::DataDirLock::acquire(&config.data_dir).context(ErrorKind::Storage)?;
+            let data = data::Data::new(&config)?;
+
+            let should_stop = Arc::new(AtomicBool::new(false));
+            let stop = should_stop.clone();
+            ctrlc::set_handler(move || {
+                info!("received Ctrl+C, terminating...");
+                stop.store(true, Ordering::SeqCst);
+            })?;
+
+            let summary = summary::Summary::default();
+            github::enrich(&data, &config, &summary, &fields, budget, &should_stop)?;
+            Ok(exit::EXIT_SUCCESS)
+        }
+        Command::StateShow(data_dir) => {
+            let config = options.load_config(data_dir)?;
+            let data = data::Data::new(&config)?;
+            state::show(&data)?;
+            Ok(exit::EXIT_SUCCESS)
+        }
+        Command::StateSet {
+            data_dir,
+            forge,
+            changes,
+        } => {
+            let config = options.load_config(data_dir)?;
+            let _lock = lock::DataDirLock::acquire(&config.data_dir).context(ErrorKind::Storage)?;
+            let data = data::Data::new(&config)?;
+            state::set(&data, &forge, &changes)?;
+            Ok(exit::EXIT_SUCCESS)
+        }
+        Command::StatePush {
+            data_dir,
+            with_data,
+        } => {
+            let config = options.load_config(data_dir)?;
+            let _lock = lock::DataDirLock::acquire(&config.data_dir).context(ErrorKind::Storage)?;
+            let data = data::Data::new(&config)?;
+            state::push(&data, &config, with_data)?;
+            Ok(exit::EXIT_SUCCESS)
+        }
+        Command::StatePull {
+            data_dir,
+            with_data,
+        } => {
+            let config = options.load_config(data_dir)?;
+            std::fs::create_dir_all(&config.data_dir).context(ErrorKind::Storage)?;
+            let _lock = lock::DataDirLock::acquire(&config.data_dir).context(ErrorKind::Storage)?;
+            let data = data::Data::new(&config)?;
+            state::pull(&data, &config, with_data)?;
+            Ok(exit::EXIT_SUCCESS)
+        }
+        Command::Seed { data_dir, dump } => {
+            let config = options.load_config(data_dir)?;
+            let _lock = lock::DataDirLock::acquire(&config.data_dir).context(ErrorKind::Storage)?;
+            let data = data::Data::new(&config)?;
+            seed::import(&data, &dump)?;
+            Ok(exit::EXIT_SUCCESS)
+        }
+        Command::Index(data_dir) => {
+            let config = options.load_config(data_dir)?;
+            let _lock = lock::DataDirLock::acquire(&config.data_dir).context(ErrorKind::Storage)?;
+            let data = data::Data::new(&config)?;
+            search::rebuild(&data)?;
+            Ok(exit::EXIT_SUCCESS)
+        }
+        Command::Search {
+            data_dir,
+            query,
+            limit,
+        } => {
+            let config = options.load_config(data_dir)?;
+            let data = data::Data::new(&config)?;
+            search::search(&data, &query, limit)?;
+            Ok(exit::EXIT_SUCCESS)
+        }
+    }
+}
+
+#[cfg(feature = "monitoring")]
+fn spawn_monitoring(
+    config: &config::Config,
+    data: &data::Data,
+    summary: &Arc<summary::Summary>,
+) -> Fallible<()> {
+    health::spawn(config, data.clone(), summary.clone())?;
+    alert::spawn(config, data.clone(), summary.clone())?;
+    notify::spawn(config, summary.clone())?;
+    Ok(())
+}
 
-    // Parse CLI arguments
-    let args = std::env::args().skip(1).collect::<Vec<String>>();
-    if args.is_empty() {
-        bail!("missing argument: <data_dir>");
-    } else if args.len() > 1 {
-        bail!("too many arguments");
+#[cfg(not(feature = "monitoring"))]
+fn spawn_monitoring(
+    config: &config::Config,
+    _data: &data::Data,
+    _summary: &Arc<summary::Summary>,
+) -> Fallible<()> {
+    if config.health_addr.is_some() || config.alert_webhook.is_some() || config.notifiers.is_some()
+    {
+        bail!("rust-repos was built without the `monitoring` feature");
     }
+    Ok(())
+}
+
+/// Which repositories a scraper run looks for.
+enum ScrapeMode {
+    /// New repositories, from the last scraped ID onwards.
+    Frontier,
+    /// Repositories in the gaps of the scraped ID space.
+    Backfill,
+    /// Repositories with at least this amount of stars, found with the search API.
+    Popular(u64),
+}
 
+fn scrape(
+    options: &Options,
+    data_dir: PathBuf,
+    discovery_only: bool,
+    mode: ScrapeMode,
+) -> Fallible<exit::Outcome> {
     // Ensure the data directory exists
-    let data_dir = PathBuf::from(&args[0]);
     if !data_dir.is_dir() {
         debug!(
             "created missing data directory: {}",
             data_dir.to_string_lossy()
         );
-        std::fs::create_dir_all(&data_dir)?;
+        std::fs::create_dir_all(&data_dir).context(ErrorKind::Storage)?;
     }
 
-    let config = Config {
-        github_token,
-        data_dir,
-        timeout,
-    };
-
-    let data = data::Data::new(&config);
+    let mut config = options.load_config(data_dir)?;
+    config.discovery_only = discovery_only;
+    config.require_api_access()?;
 
     let should_stop = Arc::new(AtomicBool::new(false));
     let stop = should_stop.clone();
@@ -95,9 +498,60 @@ fn app() -> Fallible<()> {
         stop.store(true, Ordering::SeqCst);
     })?;
 
-    github::scrape(&data, &config, &should_stop)?;
+    // When replicated, only the elected replica scrapes, before taking the data directory which
+    // might be shared with the others
+    let _leadership = match config.leader_url {
+        Some(_) => match leader::elect(&config, &should_stop)? {
+            Some(leadership) => Some(leadership),
+            None => return Ok(exit::Outcome::Interrupted),
+        },
+        None => None,
+    };
 
-    Ok(())
+    let _lock = lock::DataDirLock::acquire(&config.data_dir).context(ErrorKind::Storage)?;
+    let mut data = data::Data::new(&config)?;
+    if let Some(leadership) = &_leadership {
+        data.set_fence(leadership.lost());
+    }
+    let denylist = denylist::Denylist::load(&config)?;
+
+    let summary = Arc::new(summary::Summary::default());
+    spawn_monitoring(&config, &data, &summary)?;
+    let size_before = data.csv_size("github")?;
+
+    systemd::ready();
+    let result = match mode {
+        ScrapeMode::Frontier => github::scrape(&data, &config, &denylist, &summary, &should_stop),
+        ScrapeMode::Backfill => github::backfill(&data, &config, &denylist, &summary, &should_stop),
+        ScrapeMode::Popular(min_stars) => {
+            github::scrape_popular(&data, &config, &denylist, &summary, &should_stop, min_stars)
+        }
+    };
+    systemd::stopping();
+    data.flush()?;
+    data.record_run(
+        "github",
+        summary.repos_seen.load(Ordering::SeqCst) as u64,
+        summary.repos_stored.load(Ordering::SeqCst) as u64,
+    )?;
+    if config.sorted_output {
+        info!("sorting the stored data by ID");
+        data.compact("github", true)?;
+    }
+
+    // The size is only informative, and must not replace the outcome of the scrape
+    let size_after = data.csv_size("github").unwrap_or(size_before);
+    summary.log(size_after.saturating_sub(size_before));
+
+    // Failed batches don't stop the run, but the rate limit or the tokens failing them must
+    // still be reported in the exit code
+    match (result, summary.worst_error()) {
+        (Ok(_), Some(kind)) => {
+            warn!("some batches failed: {}", kind);
+            Err(kind.into())
+        }
+        (result, _) => result,
+    }
 }
 
 fn main() {
@@ -109,21 +563,37 @@ fn main() {
     if let Ok(content) = std::env::var("RUST_LOG") {
         logger.parse_filters(&content);
     }
+    let log_file = config::LogConfig::from_env().and_then(|log_config| match log_config.dir {
+        Some(dir) => Ok(Some(logfile::RotatingFile::open(
+            dir,
+            log_config.retention,
+        )?)),
+        None => Ok(None),
+    });
+    match log_file {
+        Ok(Some(file)) => logfile::install(&mut logger, file),
+        Ok(None) => {}
+        Err(err) => {
+            eprintln!("failed to set up the log files: {}", err);
+            std::process::exit(exit::EXIT_FAILURE);
+        }
+    }
     logger.init();
 
     let start = Instant::now();
 
-    let result = app();
-    if let Err(ref err) = &result {
-        utils::log_error(err);
-    }
+    let code = match app() {
+        Ok(code) => code,
+        Err(err) => {
+            utils::log_error(&err);
+            exit::exit_code(&err)
+        }
+    };
 
     info!(
         "execution completed in {} seconds",
         start.elapsed().as_secs()
     );
 
-    if result.is_err() {
-        std::process::exit(1);
-    }
+    std::process::exit(code);
 }
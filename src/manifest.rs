@@ -0,0 +1,170 @@
+// Copyright (c) 2018 Pietro Albini <pietro@pietroalbini.org>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies
+// of the Software, and to permit persons to whom the Software is furnished to do
+// so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Extraction of a few fields from the `Cargo.toml` of the repositories.
+
+/// Fields of a `Cargo.toml` recorded in the dataset.
+#[derive(Debug, Default)]
+pub struct Manifest {
+    /// Unstable Cargo features enabled with the top-level `cargo-features` key.
+    pub cargo_features: Vec<String>,
+    /// Minimum supported Rust version declared with `rust-version`.
+    pub rust_version: Option<String>,
+    /// Edition of the package, defaulting to 2015 when a package doesn't declare it.
+    pub edition: Option<String>,
+    /// crates.io categories of the package.
+    pub categories: Vec<String>,
+    /// crates.io keywords of the package.
+    pub keywords: Vec<String>,
+    /// Whether the manifest defines a package, rather than being only a virtual workspace.
+    pub has_package: bool,
+    /// Whether the manifest has a `[workspace]` table.
+    pub has_workspace: bool,
+    /// Whether the manifest configures a library target with a `[lib]` table.
+    pub has_lib_target: bool,
+    /// Whether the manifest declares binary targets with `[[bin]]` tables.
+    pub has_bin_targets: bool,
+}
+
+impl Manifest {
+    /// Classify the crate as `bin`, `lib`, `both` or `workspace` (for virtual manifests), based
+    /// on the targets declared in the manifest and on whether `src/lib.rs` and `src/main.rs` (or
+    /// `src/bin/`) exist. `None` when no target could be found.
+    pub fn crate_kind(&self, has_lib_rs: bool, has_main_rs: bool) -> Option<&'static str> {
+        if !self.has_package {
+            return if self.has_workspace {
+                Some("workspace")
+            } else {
+                None
+            };
+        }
+        match (
+            self.has_lib_target || has_lib_rs,
+            self.has_bin_targets || has_main_rs,
+        ) {
+            (true, true) => Some("both"),
+            (true, false) => Some("lib"),
+            (false, true) => Some("bin"),
+            (false, false) => None,
+        }
+    }
+}
+
+/// Extract the interesting fields from the content of a `Cargo.toml`.
+///
+/// Like the lockfile parser this doesn't parse the whole TOML, only simple `key = value` lines
+/// (including multiline arrays), which is how the fields we care about are written in practice.
+pub fn parse(content: &str) -> Manifest {
+    let mut manifest = Manifest::default();
+    let mut table = String::new();
+
+    let mut inherits_edition = false;
+    let mut workspace_rust_version = None;
+    let mut workspace_edition = None;
+    let mut workspace_categories = Vec::new();
+    let mut workspace_keywords = Vec::new();
+
+    let mut lines = content.lines();
+    while let Some(line) = lines.next() {
+        let line = strip_comment(line).trim();
+        if line.starts_with('[') {
+            table = line.trim_matches(['[', ']']).trim().to_string();
+            match table.as_str() {
+                "package" => manifest.has_package = true,
+                "workspace" => manifest.has_workspace = true,
+                "lib" => manifest.has_lib_target = true,
+                "bin" => manifest.has_bin_targets = true,
+                _ => {}
+            }
+            continue;
+        }
+
+        let (key, value) = match line.split_once('=') {
+            Some((key, value)) => (key.trim().trim_matches('"'), value.trim()),
+            None => continue,
+        };
+        let mut value = value.to_string();
+        if value.starts_with('[') {
+            while !value.contains(']') {
+                match lines.next() {
+                    Some(next) => value.push_str(strip_comment(next)),
+                    None => break,
+                }
+            }
+        }
+
+        match (table.as_str(), key) {
+            ("", "cargo-features") => manifest.cargo_features = strings(&value),
+            ("package", "rust-version") => manifest.rust_version = string(&value),
+            ("package", "edition") => manifest.edition = string(&value),
+            ("package", "edition.workspace") => inherits_edition = true,
+            ("workspace.package", "rust-version") => workspace_rust_version = string(&value),
+            ("workspace.package", "edition") => workspace_edition = string(&value),
+            ("package", "categories") => manifest.categories = strings(&value),
+            ("package", "keywords") => manifest.keywords = strings(&value),
+            ("workspace.package", "categories") => workspace_categories = strings(&value),
+            ("workspace.package", "keywords") => workspace_keywords = strings(&value),
+            _ => {}
+        }
+    }
+
+    // Values inherited from the workspace are only known when the workspace is in the same file
+    manifest.rust_version = manifest.rust_version.or(workspace_rust_version);
+    manifest.edition = manifest.edition.or(workspace_edition);
+    if manifest.categories.is_empty() {
+        manifest.categories = workspace_categories;
+    }
+    if manifest.keywords.is_empty() {
+        manifest.keywords = workspace_keywords;
+    }
+    if manifest.edition.is_none() && manifest.has_package && !inherits_edition {
+        manifest.edition = Some("2015".into());
+    }
+
+    manifest
+}
+
+/// Extract the value of a string, ignoring other kinds of values.
+fn string(value: &str) -> Option<String> {
+    if value.starts_with('"') || value.starts_with('\'') {
+        strings(value).into_iter().next()
+    } else {
+        None
+    }
+}
+
+/// Extract the strings contained in a value, which can either be a string or an array.
+fn strings(value: &str) -> Vec<String> {
+    value
+        .split(['"', '\''])
+        .skip(1)
+        .step_by(2)
+        .filter(|item| !item.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+fn strip_comment(line: &str) -> &str {
+    // Good enough, as the fields we care about never contain `#` in their values
+    match line.find('#') {
+        Some(idx) => &line[..idx],
+        None => line,
+    }
+}
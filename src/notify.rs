@@ -0,0 +1,491 @@
+// Copyright (c) 2018 Pietro Albini <pietro@pietroalbini.org>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies
+// of the Software, and to permit persons to whom the Software is furnished to do
+// so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Notifications of the repositories found by the scraper, posted to Discord channels and Matrix
+//! rooms. The channels are configured in a TOML file with a `[[discord]]` or `[[matrix]]` table
+//! each, which can filter the repositories and customize the message of each one. Messages are
+//! batched and rate limited per channel. `[[email]]` tables instead receive a periodic digest,
+//! sent with the `digest` subcommand.
+
+use config::Config;
+use data::{Data, Repo};
+use feed;
+use http;
+use prelude::*;
+use query::Filter;
+use reqwest::blocking::Client;
+use reqwest::Url;
+use smtp;
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+use summary::Summary;
+use utils::format_timestamp;
+
+/// How often the queued repositories are dispatched to the channels.
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+/// Repositories queued for a channel beyond this amount are dropped, oldest first.
+const MAX_QUEUED: usize = 1000;
+/// Maximum length of a Discord message.
+const DISCORD_MAX_LENGTH: usize = 2000;
+
+const DEFAULT_TEMPLATE: &str = "{name} ({stars} stars): {description} {url}";
+const DEFAULT_BATCH_SIZE: usize = 10;
+const DEFAULT_INTERVAL: u64 = 60;
+/// Repositories listed in a digest when no batch size is provided.
+const DEFAULT_DIGEST_SIZE: usize = 50;
+const DEFAULT_DIGEST_SUBJECT: &str = "New Rust repositories on GitHub";
+
+enum Destination {
+    Discord {
+        webhook: String,
+    },
+    Matrix {
+        homeserver: Url,
+        room: String,
+        token: String,
+    },
+    Email {
+        server: smtp::Server,
+        from: String,
+        to: Vec<String>,
+        subject: String,
+    },
+}
+
+struct Channel {
+    name: String,
+    destination: Destination,
+    filter: Option<Filter>,
+    template: String,
+    batch_size: usize,
+    interval: Duration,
+    queue: VecDeque<Repo>,
+    last_sent: Option<Instant>,
+}
+
+/// Background thread posting the repositories recorded in the summary to the channels.
+struct Notifier {
+    client: Client,
+    summary: Arc<Summary>,
+    channels: Vec<Channel>,
+    /// Counter making the Matrix transaction IDs unique within the run.
+    transactions: u64,
+}
+
+pub fn spawn(config: &Config, summary: Arc<Summary>) -> Fallible<()> {
+    let path = match &config.notifiers {
+        Some(path) => path,
+        None => return Ok(()),
+    };
+    let mut channels = load_channels(path)
+        .with_context(|_| format!("failed to load the notifiers from {}", path.display()))?;
+    channels.retain(|channel| !channel.is_digest());
+    if channels.is_empty() {
+        return Ok(());
+    }
+    info!("sending notifications to {} channels", channels.len());
+
+    summary.track_discoveries();
+    let mut notifier = Notifier {
+        client: http::client(config)?,
+        summary,
+        channels,
+        transactions: 0,
+    };
+    thread::spawn(move || loop {
+        thread::sleep(CHECK_INTERVAL);
+        notifier.dispatch();
+    });
+
+    Ok(())
+}
+
+impl Channel {
+    fn is_digest(&self) -> bool {
+        match self.destination {
+            Destination::Email { .. } => true,
+            Destination::Discord { .. } | Destination::Matrix { .. } => false,
+        }
+    }
+}
+
+impl Notifier {
+    fn dispatch(&mut self) {
+        for repo in self.summary.take_discoveries() {
+            for channel in &mut self.channels {
+                let matches = match &channel.filter {
+                    Some(filter) => filter.matches(&repo).unwrap_or(false),
+                    None => true,
+                };
+                if matches {
+                    if channel.queue.len() == MAX_QUEUED {
+                        channel.queue.pop_front();
+                        warn!(
+                            "too many notifications queued for {}, dropping",
+                            channel.name
+                        );
+                    }
+                    channel.queue.push_back(repo.clone());
+                }
+            }
+        }
+
+        let now = Instant::now();
+        for i in 0..self.channels.len() {
+            let channel = &self.channels[i];
+            let ready = channel
+                .last_sent
+                .map(|sent| now.duration_since(sent) >= channel.interval)
+                .unwrap_or(true);
+            if channel.queue.is_empty() || !ready {
+                continue;
+            }
+
+            let count = channel.queue.len().min(channel.batch_size);
+            let text = channel
+                .queue
+                .iter()
+                .take(count)
+                .map(|repo| render(&channel.template, repo))
+                .collect::<Vec<_>>()
+                .join("\n");
+            if let Err(err) = self.send(i, &text) {
+                warn!("failed to notify {}: {}", self.channels[i].name, err);
+            }
+
+            // Failed batches are dropped rather than retried, to avoid flooding the channel
+            let channel = &mut self.channels[i];
+            channel.queue.drain(..count);
+            channel.last_sent = Some(now);
+        }
+    }
+
+    fn send(&mut self, channel: usize, text: &str) -> Fallible<()> {
+        match &self.channels[channel].destination {
+            Destination::Discord { webhook } => {
+                let text = truncate(text, DISCORD_MAX_LENGTH);
+                self.client
+                    .post(webhook)
+                    .json(&json!({ "content": text }))
+                    .send()?
+                    .error_for_status()?;
+            }
+            Destination::Matrix {
+                homeserver,
+                room,
+                token,
+            } => {
+                self.transactions += 1;
+                let transaction =
+                    format!("rust-repos-{}-{}", ::std::process::id(), self.transactions);
+                let mut url = homeserver.clone();
+                url.path_segments_mut()
+                    .map_err(|_| err_msg("invalid Matrix homeserver URL"))?
+                    .pop_if_empty()
+                    .extend(&["_matrix", "client", "v3", "rooms", room])
+                    .extend(&["send", "m.room.message", &transaction]);
+                self.client
+                    .put(url)
+                    .bearer_auth(token)
+                    .json(&json!({ "msgtype": "m.notice", "body": text }))
+                    .send()?
+                    .error_for_status()?;
+            }
+            Destination::Email { .. } => unreachable!("digests are sent by send_digest"),
+        }
+        Ok(())
+    }
+}
+
+/// Email a digest of the repositories discovered in the last `days` days, and of the growth of
+/// the dataset, to every `[[email]]` channel.
+pub fn send_digest(data: &Data, config: &Config, days: u64) -> Fallible<()> {
+    let path = config
+        .notifiers
+        .as_ref()
+        .ok_or_else(|| err_msg("RUST_REPOS_NOTIFIERS must point to the notifiers configuration"))?;
+    let mut channels = load_channels(path)
+        .with_context(|_| format!("failed to load the notifiers from {}", path.display()))?;
+    channels.retain(Channel::is_digest);
+    if channels.is_empty() {
+        bail!("no `[[email]]` channel configured in {}", path.display());
+    }
+
+    let now = SystemTime::now();
+    let since = format_timestamp(now - Duration::from_secs(days * 86_400));
+    let header = format!(
+        "Rust repositories on GitHub from {} to {}.\n\n{}",
+        &since[..10],
+        &format_timestamp(now)[..10],
+        growth(data, &since[..10])?
+    );
+
+    let mut failed = 0;
+    for channel in &channels {
+        let mut repos = Vec::new();
+        feed::discoveries(data, channel.filter.as_ref(), |repo, discovered_at| {
+            if discovered_at >= since {
+                repos.push(repo);
+            }
+        })?;
+        repos.sort_by(|a, b| b.stars.cmp(&a.stars).then_with(|| a.name.cmp(&b.name)));
+
+        let mut body = header.clone();
+        body += &format!("{} new repositories", repos.len());
+        if repos.len() > channel.batch_size {
+            body += &format!(", the {} most starred", channel.batch_size);
+        }
+        body += ":\n\n";
+        for repo in repos.iter().take(channel.batch_size) {
+            body += &format!("- {}\n", render(&channel.template, repo));
+        }
+
+        if let Destination::Email {
+            server,
+            from,
+            to,
+            subject,
+        } = &channel.destination
+        {
+            let email = smtp::Email {
+                from,
+                to,
+                subject,
+                body: &body,
+            };
+            match smtp::send(server, &email) {
+                Ok(()) => info!("sent the digest to {}", to.join(", ")),
+                Err(err) => {
+                    warn!("failed to send the digest to {}: {}", to.join(", "), err);
+                    failed += 1;
+                }
+            }
+        }
+    }
+
+    if failed > 0 {
+        bail!("failed to send {} digests", failed);
+    }
+    Ok(())
+}
+
+/// Describe how the number of Rust repositories changed since the given date.
+fn growth(data: &Data, since: &str) -> Fallible<String> {
+    let rows = data.growth("github")?;
+    let latest = match rows.last() {
+        Some(latest) => latest,
+        None => return Ok(String::new()),
+    };
+    let before = rows.iter().rev().find(|row| row.date.as_str() <= since);
+
+    let mut text = "Dataset growth:\n".to_string();
+    for (label, count, previous) in &[
+        (
+            "Rust repositories",
+            latest.rust_repos,
+            before.map(|row| row.rust_repos),
+        ),
+        (
+            "with a Cargo.toml",
+            latest.with_cargo_toml,
+            before.map(|row| row.with_cargo_toml),
+        ),
+        (
+            "with a Cargo.lock",
+            latest.with_cargo_lock,
+            before.map(|row| row.with_cargo_lock),
+        ),
+    ] {
+        text += &format!("  {}: {}", label, count);
+        if let Some(previous) = previous {
+            text += &format!(" ({:+})", *count as i64 - *previous as i64);
+        }
+        text += "\n";
+    }
+    text += "\n";
+    Ok(text)
+}
+
+/// Replace the `{name}`, `{stars}`, `{description}` and `{url}` placeholders of a template.
+fn render(template: &str, repo: &Repo) -> String {
+    template
+        .replace("{name}", &repo.name)
+        .replace(
+            "{stars}",
+            &repo.stars.map(|s| s.to_string()).unwrap_or_default(),
+        )
+        .replace(
+            "{description}",
+            repo.description.as_deref().unwrap_or_default(),
+        )
+        .replace("{url}", &format!("https://github.com/{}", repo.name))
+}
+
+fn truncate(text: &str, max: usize) -> &str {
+    match text.char_indices().nth(max) {
+        Some((idx, _)) => &text[..idx],
+        None => text,
+    }
+}
+
+fn load_channels(path: &Path) -> Fallible<Vec<Channel>> {
+    let mut channels = Vec::new();
+    for (kind, mut table) in parse_tables(&fs::read_to_string(path)?)? {
+        let name = format!("{} channel #{}", kind, channels.len() + 1);
+        let mut take = |key: &str| table.remove(key);
+        let required = |value: Option<String>, key: &str| {
+            value.ok_or_else(|| format_err!("missing `{}` in the {}", key, name))
+        };
+
+        let destination = match kind.as_str() {
+            "discord" => Destination::Discord {
+                webhook: required(take("webhook"), "webhook")?,
+            },
+            "matrix" => Destination::Matrix {
+                homeserver: Url::parse(&required(take("homeserver"), "homeserver")?)?,
+                room: required(take("room"), "room")?,
+                token: required(take("token"), "token")?,
+            },
+            "email" => Destination::Email {
+                server: smtp::Server {
+                    address: required(take("server"), "server")?,
+                    security: match take("security") {
+                        Some(security) => security.parse()?,
+                        None => smtp::Security::StartTls,
+                    },
+                    credentials: match (take("username"), take("password")) {
+                        (Some(username), Some(password)) => Some((username, password)),
+                        (None, None) => None,
+                        _ => bail!("both `username` and `password` are needed in the {}", name),
+                    },
+                },
+                from: required(take("from"), "from")?,
+                to: required(take("to"), "to")?
+                    .split(',')
+                    .map(|to| to.trim().to_string())
+                    .filter(|to| !to.is_empty())
+                    .collect(),
+                subject: take("subject").unwrap_or_else(|| DEFAULT_DIGEST_SUBJECT.to_string()),
+            },
+            other => bail!("unknown notifier kind: {}", other),
+        };
+        let is_digest = kind == "email";
+        let filter = match take("where") {
+            Some(expr) => Some(Filter::parse(&expr)?),
+            None => None,
+        };
+        let template = take("template").unwrap_or_else(|| DEFAULT_TEMPLATE.to_string());
+        let batch_size = match take("batch_size") {
+            Some(value) => value.parse()?,
+            None if is_digest => DEFAULT_DIGEST_SIZE,
+            None => DEFAULT_BATCH_SIZE,
+        };
+        let interval = match take("interval") {
+            Some(value) => value.parse()?,
+            None => DEFAULT_INTERVAL,
+        };
+        if let Some(key) = table.keys().next() {
+            bail!("unknown key `{}` in the {}", key, name);
+        }
+
+        channels.push(Channel {
+            name,
+            destination,
+            filter,
+            template,
+            batch_size: batch_size.max(1),
+            interval: Duration::from_secs(interval),
+            queue: VecDeque::new(),
+            last_sent: None,
+        });
+    }
+    Ok(channels)
+}
+
+/// Parse the subset of TOML used by the configuration: arrays of tables (`[[name]]`) containing
+/// string and integer values.
+fn parse_tables(content: &str) -> Fallible<Vec<(String, HashMap<String, String>)>> {
+    let mut tables: Vec<(String, HashMap<String, String>)> = Vec::new();
+    for (number, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let error = |message: &str| format_err!("line {}: {}", number + 1, message);
+
+        if line.starts_with("[[") {
+            let name = line
+                .strip_prefix("[[")
+                .and_then(|line| line.split('#').next())
+                .and_then(|line| line.trim().strip_suffix("]]"))
+                .ok_or_else(|| error("invalid table header"))?;
+            tables.push((name.trim().to_string(), HashMap::new()));
+            continue;
+        }
+
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| error("expected `key = value`"))?;
+        let value = parse_value(value.trim()).ok_or_else(|| error("invalid value"))?;
+        match tables.last_mut() {
+            Some((_, table)) => {
+                table.insert(key.trim().to_string(), value);
+            }
+            None => {
+                return Err(error(
+                    "keys must be inside a `[[discord]]` or `[[matrix]]` table",
+                ))
+            }
+        }
+    }
+    Ok(tables)
+}
+
+/// Parse a basic string (with escapes) or an integer, ignoring a trailing comment.
+fn parse_value(value: &str) -> Option<String> {
+    if let Some(rest) = value.strip_prefix('"') {
+        let mut result = String::new();
+        let mut chars = rest.chars();
+        loop {
+            match chars.next()? {
+                '"' => break,
+                '\\' => result.push(match chars.next()? {
+                    'n' => '\n',
+                    't' => '\t',
+                    other => other,
+                }),
+                other => result.push(other),
+            }
+        }
+        let rest = chars.as_str().trim();
+        if rest.is_empty() || rest.starts_with('#') {
+            Some(result)
+        } else {
+            None
+        }
+    } else {
+        let value = value.split('#').next()?.trim();
+        value.parse::<u64>().ok().map(|_| value.to_string())
+    }
+}
@@ -18,4 +18,5 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-pub use failure::{err_msg, Error, Fail, Fallible, ResultExt};
+pub use exit::ErrorKind;
+pub use failure::{err_msg, Context, Error, Fail, Fallible, ResultExt};
@@ -0,0 +1,904 @@
+// Copyright (c) 2018 Pietro Albini <pietro@pietroalbini.org>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies
+// of the Software, and to permit persons to whom the Software is furnished to do
+// so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! A small SQL dialect to query the dataset without loading it into a database first.
+//!
+//! Only single-table queries are supported:
+//!
+//! ```text
+//! SELECT <* | columns | count(*)> FROM repos [WHERE <expr>] [GROUP BY <columns>] [LIMIT <n>]
+//! ```
+//!
+//! Expressions support `AND`, `OR`, `NOT`, parentheses, comparisons (`=`, `!=`, `<>`, `<`, `<=`,
+//! `>`, `>=`), `LIKE` (case-insensitive, with `%` and `_` wildcards) and `IS [NOT] NULL`.
+
+use csv;
+use data::{Data, Repo};
+use prelude::*;
+use serde_json::{self, Map, Value};
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+static TABLE: &str = "repos";
+static PLATFORM: &str = "github";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Table,
+    Csv,
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Fallible<Self> {
+        match s {
+            "table" => Ok(Format::Table),
+            "csv" => Ok(Format::Csv),
+            "json" => Ok(Format::Json),
+            other => bail!("unknown output format: {}", other),
+        }
+    }
+}
+
+pub fn run(data: &Data, sql: &str, format: Format) -> Fallible<()> {
+    let query = Parser::new(sql)?.query()?;
+    let (columns, rows) = execute(data, &query)?;
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    match format {
+        Format::Table => print_table(&mut out, &columns, &rows)?,
+        Format::Csv => {
+            let mut csv = csv::Writer::from_writer(&mut out);
+            csv.write_record(&columns)?;
+            for row in &rows {
+                csv.write_record(row.iter().map(display))?;
+            }
+            csv.flush()?;
+        }
+        Format::Json => {
+            let objects = rows
+                .into_iter()
+                .map(|row| Value::Object(columns.iter().cloned().zip(row).collect()))
+                .collect::<Vec<_>>();
+            serde_json::to_writer_pretty(&mut out, &objects)?;
+            writeln!(out)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// A `WHERE` expression on its own, to filter the repositories in other commands.
+pub struct Filter(Expr);
+
+impl Filter {
+    pub fn parse(expr: &str) -> Fallible<Self> {
+        let mut parser = Parser::new(expr)?;
+        let expr = parser.expr()?;
+        if let Some(token) = parser.peek() {
+            bail!("unexpected {:?} at the end of the expression", token);
+        }
+        Ok(Filter(expr))
+    }
+
+    pub fn matches(&self, repo: &Repo) -> Fallible<bool> {
+        Ok(self.0.eval(&row(repo)?) == Value::Bool(true))
+    }
+}
+
+#[derive(Debug)]
+enum Selection {
+    All,
+    Columns(Vec<SelectItem>),
+}
+
+#[derive(Debug, PartialEq)]
+enum SelectItem {
+    Column(String),
+    Count,
+}
+
+#[derive(Debug)]
+struct Query {
+    selection: Selection,
+    filter: Option<Expr>,
+    group_by: Vec<String>,
+    limit: Option<usize>,
+}
+
+#[derive(Debug)]
+enum Expr {
+    Column(String),
+    Literal(Value),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Compare(Box<Expr>, CompareOp, Box<Expr>),
+    Like(Box<Expr>, Box<Expr>),
+    IsNull(Box<Expr>),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum CompareOp {
+    Eq,
+    NotEq,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+}
+
+impl Expr {
+    fn eval(&self, row: &Map<String, Value>) -> Value {
+        match self {
+            Expr::Column(name) => row.get(name).cloned().unwrap_or(Value::Null),
+            Expr::Literal(value) => value.clone(),
+            Expr::Not(expr) => match expr.eval(row) {
+                Value::Bool(value) => Value::Bool(!value),
+                _ => Value::Null,
+            },
+            Expr::And(left, right) => match (left.eval(row), right.eval(row)) {
+                (Value::Bool(false), _) | (_, Value::Bool(false)) => Value::Bool(false),
+                (Value::Bool(true), Value::Bool(true)) => Value::Bool(true),
+                _ => Value::Null,
+            },
+            Expr::Or(left, right) => match (left.eval(row), right.eval(row)) {
+                (Value::Bool(true), _) | (_, Value::Bool(true)) => Value::Bool(true),
+                (Value::Bool(false), Value::Bool(false)) => Value::Bool(false),
+                _ => Value::Null,
+            },
+            Expr::Compare(left, op, right) => match compare(&left.eval(row), &right.eval(row)) {
+                Some(ordering) => Value::Bool(match op {
+                    CompareOp::Eq => ordering == Ordering::Equal,
+                    CompareOp::NotEq => ordering != Ordering::Equal,
+                    CompareOp::Lt => ordering == Ordering::Less,
+                    CompareOp::LtEq => ordering != Ordering::Greater,
+                    CompareOp::Gt => ordering == Ordering::Greater,
+                    CompareOp::GtEq => ordering != Ordering::Less,
+                }),
+                None => Value::Null,
+            },
+            Expr::Like(value, pattern) => match (value.eval(row), pattern.eval(row)) {
+                (Value::String(value), Value::String(pattern)) => {
+                    let value = value.to_lowercase().chars().collect::<Vec<_>>();
+                    let pattern = pattern.to_lowercase().chars().collect::<Vec<_>>();
+                    Value::Bool(like(&value, &pattern))
+                }
+                _ => Value::Null,
+            },
+            Expr::IsNull(expr) => Value::Bool(expr.eval(row).is_null()),
+        }
+    }
+}
+
+/// Compare two values of the same type, returning `None` for nulls and mismatched types.
+fn compare(left: &Value, right: &Value) -> Option<Ordering> {
+    match (left, right) {
+        (Value::Bool(left), Value::Bool(right)) => Some(left.cmp(right)),
+        (Value::String(left), Value::String(right)) => Some(left.cmp(right)),
+        (Value::Number(left), Value::Number(right)) => left.as_f64()?.partial_cmp(&right.as_f64()?),
+        _ => None,
+    }
+}
+
+/// Match a value against a `LIKE` pattern in linear space and O(value * pattern) time: each `%`
+/// matches greedily, and on a mismatch only the last `%` seen is extended by one more character.
+/// Earlier ones never need to be, as the last one can absorb anything they would have.
+fn like(value: &[char], pattern: &[char]) -> bool {
+    let (mut v, mut p) = (0, 0);
+    // Position of the last `%` in the pattern, and of the value right after what it matched
+    let mut backtrack = None;
+    while v < value.len() {
+        match pattern.get(p) {
+            Some('%') => {
+                backtrack = Some((p, v));
+                p += 1;
+            }
+            Some(c) if *c == '_' || *c == value[v] => {
+                v += 1;
+                p += 1;
+            }
+            _ => match backtrack {
+                Some((percent, matched)) => {
+                    backtrack = Some((percent, matched + 1));
+                    p = percent + 1;
+                    v = matched + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '%')
+}
+
+fn execute(data: &Data, query: &Query) -> Fallible<(Vec<String>, Vec<Vec<Value>>)> {
+    evaluate(data.repos(PLATFORM)?, query)
+}
+
+/// Run a query on the repositories, returning the names of the columns and the rows.
+fn evaluate<I>(repos: I, query: &Query) -> Fallible<(Vec<String>, Vec<Vec<Value>>)>
+where
+    I: IntoIterator<Item = Fallible<Repo>>,
+{
+    let items = match &query.selection {
+        Selection::All => {
+            if !query.group_by.is_empty() {
+                bail!("SELECT * can't be used with GROUP BY");
+            }
+            Repo::schema()
+                .iter()
+                .map(|column| SelectItem::Column(column.name.to_string()))
+                .collect()
+        }
+        Selection::Columns(items) => items.iter().map(clone_item).collect::<Vec<_>>(),
+    };
+    for item in &items {
+        if let SelectItem::Column(column) = item {
+            check_column(column)?;
+        }
+    }
+    for column in &query.group_by {
+        check_column(column)?;
+    }
+
+    let aggregate = !query.group_by.is_empty() || items.contains(&SelectItem::Count);
+    if aggregate {
+        for item in &items {
+            if let SelectItem::Column(column) = item {
+                if !query.group_by.contains(column) {
+                    bail!("column {} must appear in the GROUP BY clause", column);
+                }
+            }
+        }
+    }
+
+    let columns = items
+        .iter()
+        .map(|item| match item {
+            SelectItem::Column(column) => column.clone(),
+            SelectItem::Count => "count".to_string(),
+        })
+        .collect::<Vec<_>>();
+
+    let mut rows = Vec::new();
+    let mut groups: BTreeMap<String, (Map<String, Value>, u64)> = BTreeMap::new();
+    for repo in repos {
+        if !aggregate && Some(rows.len()) == query.limit {
+            break;
+        }
+
+        let row = row(&repo?)?;
+        if let Some(filter) = &query.filter {
+            if filter.eval(&row) != Value::Bool(true) {
+                continue;
+            }
+        }
+
+        if aggregate {
+            let key = query
+                .group_by
+                .iter()
+                .map(|column| row[column].clone())
+                .collect::<Vec<_>>();
+            let group = groups
+                .entry(serde_json::to_string(&key)?)
+                .or_insert_with(|| (row, 0));
+            group.1 += 1;
+        } else {
+            rows.push(project(&items, &row, 0));
+        }
+    }
+
+    if aggregate {
+        // Counting without grouping always returns a row, even when nothing matched
+        if groups.is_empty() && query.group_by.is_empty() {
+            groups.insert(String::new(), (Map::new(), 0));
+        }
+        rows = groups
+            .values()
+            .take(query.limit.unwrap_or(usize::MAX))
+            .map(|(row, count)| project(&items, row, *count))
+            .collect();
+    }
+
+    Ok((columns, rows))
+}
+
+fn row(repo: &Repo) -> Fallible<Map<String, Value>> {
+    match serde_json::to_value(repo)? {
+        Value::Object(row) => Ok(row),
+        _ => unreachable!(),
+    }
+}
+
+fn clone_item(item: &SelectItem) -> SelectItem {
+    match item {
+        SelectItem::Column(column) => SelectItem::Column(column.clone()),
+        SelectItem::Count => SelectItem::Count,
+    }
+}
+
+fn check_column(column: &str) -> Fallible<()> {
+    if !Repo::columns().contains(&column) {
+        bail!("unknown column: {}", column);
+    }
+    Ok(())
+}
+
+fn project(items: &[SelectItem], row: &Map<String, Value>, count: u64) -> Vec<Value> {
+    items
+        .iter()
+        .map(|item| match item {
+            SelectItem::Column(column) => row.get(column).cloned().unwrap_or(Value::Null),
+            SelectItem::Count => Value::from(count),
+        })
+        .collect()
+}
+
+fn display(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(value) => value.clone(),
+        other => other.to_string(),
+    }
+}
+
+fn print_table<W: Write>(out: &mut W, columns: &[String], rows: &[Vec<Value>]) -> Fallible<()> {
+    let rows = rows
+        .iter()
+        .map(|row| row.iter().map(display).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    let widths = columns
+        .iter()
+        .enumerate()
+        .map(|(i, column)| {
+            rows.iter()
+                .map(|row| row[i].chars().count())
+                .chain(Some(column.chars().count()))
+                .max()
+                .unwrap_or(0)
+        })
+        .collect::<Vec<_>>();
+
+    let print_row = |out: &mut W, row: &[String]| -> Fallible<()> {
+        let cells = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:1$}", cell, width))
+            .collect::<Vec<_>>();
+        writeln!(out, "{}", cells.join(" | ").trim_end())?;
+        Ok(())
+    };
+
+    print_row(out, columns)?;
+    let separator = widths
+        .iter()
+        .map(|width| "-".repeat(*width))
+        .collect::<Vec<_>>();
+    writeln!(out, "{}", separator.join("-+-"))?;
+    for row in &rows {
+        print_row(out, row)?;
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    String(String),
+    Number(String),
+    Symbol(&'static str),
+}
+
+fn tokenize(sql: &str) -> Fallible<Vec<Token>> {
+    static SYMBOLS: &[&str] = &["<=", ">=", "!=", "<>", "(", ")", ",", "*", "=", "<", ">"];
+
+    let mut tokens = Vec::new();
+    let mut chars = sql.char_indices().peekable();
+    while let Some(&(start, c)) = chars.peek() {
+        if c.is_whitespace() || c == ';' {
+            chars.next();
+        } else if c.is_alphabetic() || c == '_' {
+            let mut word = String::new();
+            while let Some(&(_, c)) = chars.peek() {
+                if !c.is_alphanumeric() && c != '_' {
+                    break;
+                }
+                word.push(c);
+                chars.next();
+            }
+            tokens.push(Token::Word(word));
+        } else if c.is_ascii_digit() || c == '-' {
+            let mut number = c.to_string();
+            chars.next();
+            while let Some(&(_, c)) = chars.peek() {
+                if !c.is_ascii_digit() && c != '.' {
+                    break;
+                }
+                number.push(c);
+                chars.next();
+            }
+            tokens.push(Token::Number(number));
+        } else if c == '\'' {
+            chars.next();
+            let mut string = String::new();
+            loop {
+                match chars.next() {
+                    // Quotes inside strings are escaped by doubling them
+                    Some((_, '\'')) if chars.peek().map(|&(_, c)| c) == Some('\'') => {
+                        chars.next();
+                        string.push('\'');
+                    }
+                    Some((_, '\'')) => break,
+                    Some((_, c)) => string.push(c),
+                    None => bail!("unterminated string literal"),
+                }
+            }
+            tokens.push(Token::String(string));
+        } else {
+            match SYMBOLS
+                .iter()
+                .find(|symbol| sql[start..].starts_with(*symbol))
+            {
+                Some(symbol) => {
+                    for _ in 0..symbol.len() {
+                        chars.next();
+                    }
+                    tokens.push(Token::Symbol(symbol));
+                }
+                None => bail!("unexpected character in query: {}", c),
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn new(sql: &str) -> Fallible<Self> {
+        Ok(Parser {
+            tokens: tokenize(sql)?,
+            pos: 0,
+        })
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Fallible<Token> {
+        let token = self
+            .tokens
+            .get(self.pos)
+            .cloned()
+            .ok_or_else(|| err_msg("unexpected end of the query"))?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        match self.peek() {
+            Some(Token::Word(word)) => word.eq_ignore_ascii_case(keyword),
+            _ => false,
+        }
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        let found = self.is_keyword(keyword);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Fallible<()> {
+        if !self.eat_keyword(keyword) {
+            bail!("expected {}", keyword);
+        }
+        Ok(())
+    }
+
+    fn eat_symbol(&mut self, symbol: &'static str) -> bool {
+        let found = self.peek() == Some(&Token::Symbol(symbol));
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn expect_symbol(&mut self, symbol: &'static str) -> Fallible<()> {
+        if !self.eat_symbol(symbol) {
+            bail!("expected {}", symbol);
+        }
+        Ok(())
+    }
+
+    fn identifier(&mut self) -> Fallible<String> {
+        match self.next()? {
+            Token::Word(word) => Ok(word.to_lowercase()),
+            other => bail!("expected an identifier, found {:?}", other),
+        }
+    }
+
+    fn query(mut self) -> Fallible<Query> {
+        self.expect_keyword("SELECT")?;
+        let selection = if self.eat_symbol("*") {
+            Selection::All
+        } else {
+            let mut items = vec![self.select_item()?];
+            while self.eat_symbol(",") {
+                items.push(self.select_item()?);
+            }
+            Selection::Columns(items)
+        };
+
+        self.expect_keyword("FROM")?;
+        let table = self.identifier()?;
+        if table != TABLE {
+            bail!("unknown table: {} (only {} is available)", table, TABLE);
+        }
+
+        let filter = if self.eat_keyword("WHERE") {
+            Some(self.expr()?)
+        } else {
+            None
+        };
+
+        let mut group_by = Vec::new();
+        if self.eat_keyword("GROUP") {
+            self.expect_keyword("BY")?;
+            group_by.push(self.identifier()?);
+            while self.eat_symbol(",") {
+                group_by.push(self.identifier()?);
+            }
+        }
+
+        let limit = if self.eat_keyword("LIMIT") {
+            match self.next()? {
+                Token::Number(number) => Some(number.parse()?),
+                other => bail!("expected a number after LIMIT, found {:?}", other),
+            }
+        } else {
+            None
+        };
+
+        if let Some(token) = self.peek() {
+            bail!("unexpected {:?} at the end of the query", token);
+        }
+
+        Ok(Query {
+            selection,
+            filter,
+            group_by,
+            limit,
+        })
+    }
+
+    fn select_item(&mut self) -> Fallible<SelectItem> {
+        let name = self.identifier()?;
+        if name == "count" && self.eat_symbol("(") {
+            self.expect_symbol("*")?;
+            self.expect_symbol(")")?;
+            Ok(SelectItem::Count)
+        } else {
+            Ok(SelectItem::Column(name))
+        }
+    }
+
+    fn expr(&mut self) -> Fallible<Expr> {
+        let mut expr = self.and_expr()?;
+        while self.eat_keyword("OR") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and_expr()?));
+        }
+        Ok(expr)
+    }
+
+    fn and_expr(&mut self) -> Fallible<Expr> {
+        let mut expr = self.not_expr()?;
+        while self.eat_keyword("AND") {
+            expr = Expr::And(Box::new(expr), Box::new(self.not_expr()?));
+        }
+        Ok(expr)
+    }
+
+    fn not_expr(&mut self) -> Fallible<Expr> {
+        if self.eat_keyword("NOT") {
+            Ok(Expr::Not(Box::new(self.not_expr()?)))
+        } else {
+            self.comparison()
+        }
+    }
+
+    fn comparison(&mut self) -> Fallible<Expr> {
+        let left = self.primary()?;
+
+        if self.eat_keyword("IS") {
+            let negated = self.eat_keyword("NOT");
+            self.expect_keyword("NULL")?;
+            let expr = Expr::IsNull(Box::new(left));
+            return Ok(if negated {
+                Expr::Not(Box::new(expr))
+            } else {
+                expr
+            });
+        }
+
+        let negated = self.eat_keyword("NOT");
+        if self.eat_keyword("LIKE") {
+            let expr = Expr::Like(Box::new(left), Box::new(self.primary()?));
+            return Ok(if negated {
+                Expr::Not(Box::new(expr))
+            } else {
+                expr
+            });
+        } else if negated {
+            bail!("expected LIKE after NOT");
+        }
+
+        let op = match self.peek() {
+            Some(Token::Symbol("=")) => CompareOp::Eq,
+            Some(Token::Symbol("!=")) | Some(Token::Symbol("<>")) => CompareOp::NotEq,
+            Some(Token::Symbol("<")) => CompareOp::Lt,
+            Some(Token::Symbol("<=")) => CompareOp::LtEq,
+            Some(Token::Symbol(">")) => CompareOp::Gt,
+            Some(Token::Symbol(">=")) => CompareOp::GtEq,
+            _ => return Ok(left),
+        };
+        self.pos += 1;
+        Ok(Expr::Compare(Box::new(left), op, Box::new(self.primary()?)))
+    }
+
+    fn primary(&mut self) -> Fallible<Expr> {
+        if self.eat_symbol("(") {
+            let expr = self.expr()?;
+            self.expect_symbol(")")?;
+            return Ok(expr);
+        }
+
+        Ok(match self.next()? {
+            Token::String(string) => Expr::Literal(Value::String(string)),
+            Token::Number(number) => Expr::Literal(
+                serde_json::from_str(&number)
+                    .with_context(|_| format!("invalid number: {}", number))?,
+            ),
+            Token::Word(ref word) if word.eq_ignore_ascii_case("true") => {
+                Expr::Literal(Value::Bool(true))
+            }
+            Token::Word(ref word) if word.eq_ignore_ascii_case("false") => {
+                Expr::Literal(Value::Bool(false))
+            }
+            Token::Word(ref word) if word.eq_ignore_ascii_case("null") => {
+                Expr::Literal(Value::Null)
+            }
+            Token::Word(word) => {
+                let column = word.to_lowercase();
+                check_column(&column)?;
+                Expr::Column(column)
+            }
+            other => bail!("unexpected {:?} in expression", other),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{evaluate, like, Filter, Parser};
+    use data::Repo;
+    use serde_json::{self, Value};
+
+    fn repo(id: u64, name: &str, stars: Option<u64>, edition: Option<&str>) -> Repo {
+        serde_json::from_value(json!({
+            "id": id.to_string(),
+            "name": name,
+            "has_cargo_toml": true,
+            "has_cargo_lock": id.is_multiple_of(2),
+            "stars": stars,
+            "edition": edition,
+            "description": format!("The {} project", name),
+        }))
+        .unwrap()
+    }
+
+    fn repos() -> Vec<Repo> {
+        vec![
+            repo(1, "alice/foo", Some(10), Some("2021")),
+            repo(2, "bob/bar", Some(200), Some("2018")),
+            repo(3, "carol/baz", None, Some("2021")),
+            repo(4, "dave/foo_bar", Some(50), None),
+        ]
+    }
+
+    fn query(sql: &str) -> Vec<Vec<Value>> {
+        let query = Parser::new(sql).unwrap().query().unwrap();
+        evaluate(repos().into_iter().map(Ok), &query).unwrap().1
+    }
+
+    fn names(sql: &str) -> Vec<String> {
+        query(sql)
+            .into_iter()
+            .map(|row| row[0].as_str().unwrap().to_string())
+            .collect()
+    }
+
+    fn matches(filter: &str, repo: &Repo) -> bool {
+        Filter::parse(filter).unwrap().matches(repo).unwrap()
+    }
+
+    fn like_str(value: &str, pattern: &str) -> bool {
+        like(
+            &value.chars().collect::<Vec<_>>(),
+            &pattern.chars().collect::<Vec<_>>(),
+        )
+    }
+
+    #[test]
+    fn test_like() {
+        assert!(like_str("foo", "foo"));
+        assert!(!like_str("foo", "fo"));
+        assert!(like_str("foo", "f_o"));
+        assert!(!like_str("fo", "f_o"));
+        assert!(like_str("", "%"));
+        assert!(like_str("foo", "%"));
+        assert!(like_str("foobar", "%bar"));
+        assert!(like_str("foobar", "foo%"));
+        assert!(like_str("abcbc", "%bc"));
+        assert!(like_str("abcabd", "%ab_"));
+        assert!(!like_str("abcabe", "%abd"));
+        assert!(like_str("a long description", "%long%script%"));
+        assert!(!like_str("a long description", "%script%long%"));
+        assert!(like_str("mississippi", "m%iss%ppi"));
+        assert!(like_str("mississippi", "%%s%%p%"));
+
+        // Patterns with many wildcards don't backtrack exponentially
+        let value = "a".repeat(10_000);
+        assert!(!like_str(&value, "%a%a%a%a%a%a%a%a%b"));
+        assert!(like_str(&value, "%a%a%a%a%a%a%a%a%"));
+    }
+
+    #[test]
+    fn test_like_is_case_insensitive() {
+        let repo = repo(1, "Alice/Foo", Some(1), None);
+        assert!(matches("name LIKE 'alice/%'", &repo));
+        assert!(matches("name LIKE '%FOO'", &repo));
+        assert!(!matches("name NOT LIKE '%foo'", &repo));
+        assert!(!matches("stars LIKE '1'", &repo));
+    }
+
+    #[test]
+    fn test_precedence() {
+        let repo = repo(1, "alice/foo", Some(10), Some("2021"));
+        // AND binds tighter than OR
+        assert!(matches(
+            "stars = 1 AND edition = '2018' OR name = 'alice/foo'",
+            &repo
+        ));
+        assert!(!matches(
+            "stars = 1 AND (edition = '2018' OR name = 'alice/foo')",
+            &repo
+        ));
+        assert!(matches(
+            "name = 'alice/foo' OR stars = 1 AND edition = '2018'",
+            &repo
+        ));
+        // NOT binds tighter than AND
+        assert!(!matches("NOT stars = 10 AND edition = '2021'", &repo));
+        assert!(matches("NOT (stars = 1 AND edition = '2021')", &repo));
+        assert!(matches("NOT NOT stars >= 10", &repo));
+    }
+
+    #[test]
+    fn test_comparisons() {
+        let repo = repo(1, "alice/foo", Some(10), Some("2021"));
+        assert!(matches("stars > 9.5", &repo));
+        assert!(matches("stars <= 10", &repo));
+        assert!(matches("stars <> 11", &repo));
+        assert!(matches("stars != 11", &repo));
+        assert!(matches("has_cargo_toml = true", &repo));
+        assert!(matches("name < 'bob'", &repo));
+        // Mismatched types never compare equal, nor unequal
+        assert!(!matches("stars = '10'", &repo));
+        assert!(!matches("stars != '10'", &repo));
+    }
+
+    #[test]
+    fn test_nulls() {
+        let repo = repo(3, "carol/baz", None, None);
+        assert!(matches("stars IS NULL", &repo));
+        assert!(!matches("stars IS NOT NULL", &repo));
+        // Comparisons with NULL are unknown, and so is their negation
+        assert!(!matches("stars > 10", &repo));
+        assert!(!matches("NOT stars > 10", &repo));
+        assert!(!matches("stars = NULL", &repo));
+        // Unknown values are absorbed by AND with false and OR with true
+        assert!(matches("NOT (stars > 10 AND false)", &repo));
+        assert!(matches("stars > 10 OR true", &repo));
+        assert!(!matches("stars > 10 OR false", &repo));
+    }
+
+    #[test]
+    fn test_select() {
+        assert_eq!(
+            names("SELECT name FROM repos WHERE stars >= 50"),
+            vec!["bob/bar", "dave/foo_bar"]
+        );
+        assert_eq!(names("select name from repos limit 1"), vec!["alice/foo"]);
+        assert_eq!(
+            query("SELECT name, stars FROM repos WHERE name LIKE '%baz'"),
+            vec![vec![json!("carol/baz"), Value::Null]]
+        );
+        assert_eq!(
+            query("SELECT * FROM repos LIMIT 1")[0].len(),
+            Repo::columns().len()
+        );
+    }
+
+    #[test]
+    fn test_group_by() {
+        assert_eq!(
+            query("SELECT edition, count(*) FROM repos GROUP BY edition"),
+            vec![
+                vec![json!("2018"), json!(1)],
+                vec![json!("2021"), json!(2)],
+                vec![Value::Null, json!(1)],
+            ]
+        );
+        assert_eq!(
+            query("SELECT count(*) FROM repos WHERE stars > 1000"),
+            vec![vec![json!(0)]]
+        );
+        assert_eq!(
+            query(
+                "SELECT has_cargo_lock, count(*) FROM repos WHERE stars IS NOT NULL \
+                 GROUP BY has_cargo_lock LIMIT 1"
+            ),
+            vec![vec![json!(false), json!(1)]]
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |sql: &str| Parser::new(sql).and_then(|parser| parser.query()).err();
+        assert!(error("SELECT name FROM crates").is_some());
+        assert!(error("SELECT name FROM repos WHERE").is_some());
+        assert!(error("SELECT name FROM repos WHERE nope = 1").is_some());
+        assert!(error("SELECT name FROM repos WHERE name = 'unterminated").is_some());
+        assert!(error("SELECT name FROM repos LIMIT 1 2").is_some());
+        assert!(error("SELECT name FROM repos WHERE stars NOT = 1").is_some());
+        assert!(error("SELECT name FROM repos WHERE name = 'it''s'").is_none());
+
+        let run = |sql: &str| {
+            let query = Parser::new(sql).unwrap().query().unwrap();
+            evaluate(repos().into_iter().map(Ok), &query).err()
+        };
+        assert!(run("SELECT * FROM repos GROUP BY edition").is_some());
+        assert!(run("SELECT name, count(*) FROM repos GROUP BY edition").is_some());
+        assert!(run("SELECT nope FROM repos").is_some());
+        assert!(Filter::parse("stars > 1 stars").is_err());
+    }
+}
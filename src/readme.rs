@@ -0,0 +1,92 @@
+// Copyright (c) 2018 Pietro Albini <pietro@pietroalbini.org>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies
+// of the Software, and to permit persons to whom the Software is furnished to do
+// so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Detection of the README of the repositories, and extraction of a short excerpt from it.
+
+/// Find the README among the names of the files at the root of a repository.
+pub fn find<'a, I: IntoIterator<Item = &'a str>>(names: I) -> Option<&'a str> {
+    names
+        .into_iter()
+        .find(|name| name.to_lowercase().starts_with("readme"))
+}
+
+/// Format of a README, based on its extension.
+pub fn format(name: &str) -> &'static str {
+    let extension = match name.rsplit_once('.') {
+        Some((_, extension)) => extension.to_lowercase(),
+        None => return "txt",
+    };
+    match extension.as_str() {
+        "md" | "markdown" => "md",
+        "rst" => "rst",
+        "adoc" | "asciidoc" => "adoc",
+        "txt" => "txt",
+        _ => "other",
+    }
+}
+
+/// Extract the first paragraph of prose from a README, skipping headings, badges, images and
+/// HTML, and truncating it at a word boundary to at most `max_len` bytes.
+pub fn excerpt(content: &str, max_len: usize) -> Option<String> {
+    let mut paragraph = Vec::new();
+    for line in content.lines().map(|line| line.trim()) {
+        if line.is_empty() {
+            if paragraph.is_empty() {
+                continue;
+            }
+            break;
+        }
+        // Setext headings are underlined with `=` or `-`
+        if line.len() >= 3 && line.chars().all(|c| c == '=' || c == '-') {
+            paragraph.clear();
+            continue;
+        }
+        let skip = line.starts_with('#')
+            || line.starts_with("[![")
+            || line.starts_with("![")
+            || line.starts_with('<')
+            || line.starts_with("..");
+        if skip {
+            if paragraph.is_empty() {
+                continue;
+            }
+            break;
+        }
+        paragraph.push(line);
+    }
+
+    let text = paragraph.join(" ");
+    if text.is_empty() {
+        return None;
+    }
+    if text.len() <= max_len {
+        return Some(text);
+    }
+
+    let mut end = max_len;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    let truncated = match text[..end].rfind(' ') {
+        Some(space) => &text[..space],
+        None => &text[..end],
+    };
+    Some(truncated.trim_end().to_string())
+}
@@ -0,0 +1,257 @@
+// Copyright (c) 2018 Pietro Albini <pietro@pietroalbini.org>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies
+// of the Software, and to permit persons to whom the Software is furnished to do
+// so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Debugging helpers to record the HTTP requests made by the scraper, and to replay them later
+//! without making any network call.
+
+use http;
+use http_types;
+use prelude::*;
+use reqwest::blocking::{Client, Request, Response};
+use reqwest::header::{self, HeaderMap};
+use serde_json;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+static REDACTED: &str = "[REDACTED]";
+
+#[derive(Serialize, Deserialize)]
+struct Exchange {
+    request: RecordedRequest,
+    response: RecordedResponse,
+}
+
+#[derive(Serialize, Deserialize)]
+struct RecordedRequest {
+    method: String,
+    url: String,
+    headers: BTreeMap<String, String>,
+    body: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct RecordedResponse {
+    status: u16,
+    headers: BTreeMap<String, String>,
+    body: String,
+}
+
+/// Dumps every request and response to a JSON file in a directory, with secrets redacted.
+pub struct Recorder {
+    dir: PathBuf,
+    secrets: Vec<String>,
+    counter: AtomicUsize,
+}
+
+impl Recorder {
+    pub fn new(dir: &Path, secrets: Vec<String>) -> Fallible<Self> {
+        fs::create_dir_all(dir)?;
+        info!("recording HTTP requests in {}", dir.to_string_lossy());
+
+        Ok(Recorder {
+            dir: dir.into(),
+            secrets,
+            counter: AtomicUsize::new(0),
+        })
+    }
+
+    pub fn execute(&self, client: &Client, request: Request) -> Fallible<Response> {
+        let recorded_request = RecordedRequest {
+            method: request.method().to_string(),
+            url: self.redact(request.url().as_str()),
+            headers: self.redact_headers(request.headers()),
+            body: request
+                .body()
+                .and_then(|body| body.as_bytes())
+                .map(|body| self.redact(&String::from_utf8_lossy(body))),
+        };
+
+        let response = http::execute(client, request)?;
+        let status = response.status();
+        let headers = response.headers().clone();
+        let body = response.bytes()?.to_vec();
+
+        let exchange = Exchange {
+            request: recorded_request,
+            response: RecordedResponse {
+                status: status.as_u16(),
+                headers: self.redact_headers(&headers),
+                body: self.redact(&String::from_utf8_lossy(&body)),
+            },
+        };
+
+        let id = self.counter.fetch_add(1, Ordering::SeqCst);
+        let path = self.dir.join(format!("{:08}.json", id));
+        fs::write(&path, serde_json::to_vec_pretty(&exchange)?)?;
+
+        let mut rebuilt = http_types::Response::builder().status(status).body(body)?;
+        *rebuilt.headers_mut() = headers;
+        Ok(rebuilt.into())
+    }
+
+    fn redact(&self, content: &str) -> String {
+        redact(&self.secrets, content)
+    }
+
+    fn redact_headers(&self, headers: &HeaderMap) -> BTreeMap<String, String> {
+        headers
+            .iter()
+            .map(|(name, value)| {
+                let value = if name == header::AUTHORIZATION {
+                    REDACTED.to_string()
+                } else {
+                    self.redact(&String::from_utf8_lossy(value.as_bytes()))
+                };
+                (name.as_str().to_string(), value)
+            })
+            .collect()
+    }
+}
+
+fn redact(secrets: &[String], content: &str) -> String {
+    let mut content = content.to_string();
+    for secret in secrets {
+        content = content.replace(secret.as_str(), REDACTED);
+    }
+    content
+}
+
+/// Method, URL and body of a request with the secrets redacted, used to find its recorded response.
+type RequestKey = (String, String, Option<String>);
+
+/// Answers requests with the responses stored by a `Recorder`, matching them by method, URL and
+/// body. Identical requests are answered in the order they were recorded.
+///
+/// The recordings are redacted, so the requests are redacted with the same secrets before being
+/// matched with them.
+pub struct Replayer {
+    secrets: Vec<String>,
+    responses: Mutex<HashMap<RequestKey, VecDeque<RecordedResponse>>>,
+}
+
+impl Replayer {
+    pub fn load(dir: &Path, secrets: Vec<String>) -> Fallible<Self> {
+        let mut files = fs::read_dir(dir)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<_>, _>>()?;
+        files.sort();
+
+        let mut responses: HashMap<_, VecDeque<_>> = HashMap::new();
+        for file in files {
+            let exchange: Exchange = serde_json::from_slice(&fs::read(&file)?)
+                .with_context(|_| format!("failed to load {}", file.to_string_lossy()))?;
+            let request = exchange.request;
+            responses
+                .entry((request.method, request.url, request.body))
+                .or_default()
+                .push_back(exchange.response);
+        }
+
+        info!(
+            "replaying {} recorded HTTP requests from {}",
+            responses.values().map(|r| r.len()).sum::<usize>(),
+            dir.to_string_lossy()
+        );
+        Ok(Replayer {
+            secrets,
+            responses: Mutex::new(responses),
+        })
+    }
+
+    pub fn execute(&self, request: Request) -> Fallible<Response> {
+        let key: RequestKey = (
+            request.method().to_string(),
+            redact(&self.secrets, request.url().as_str()),
+            request
+                .body()
+                .and_then(|body| body.as_bytes())
+                .map(|body| redact(&self.secrets, &String::from_utf8_lossy(body))),
+        );
+
+        let recorded = self
+            .responses
+            .lock()
+            .unwrap()
+            .get_mut(&key)
+            .and_then(|responses| responses.pop_front());
+        let recorded = match recorded {
+            Some(recorded) => recorded,
+            None => bail!("no recorded response for {} {}", key.0, key.1),
+        };
+
+        let mut response = http_types::Response::builder().status(recorded.status);
+        for (name, value) in &recorded.headers {
+            response = response.header(name.as_str(), value.as_str());
+        }
+        Ok(response.body(recorded.body.into_bytes())?.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Exchange, RecordedRequest, RecordedResponse, Replayer, REDACTED};
+    use reqwest::blocking::Client;
+    use serde_json;
+    use std::collections::BTreeMap;
+    use std::fs;
+
+    #[test]
+    fn test_replay_redacted_request() {
+        let dir = ::std::env::temp_dir().join(format!(
+            "rust-repos-recorder-{}-redacted",
+            ::std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        // A secret in the URL is stored redacted by the recorder
+        let exchange = Exchange {
+            request: RecordedRequest {
+                method: "GET".into(),
+                url: format!("https://example.com/?token={}", REDACTED),
+                headers: BTreeMap::new(),
+                body: None,
+            },
+            response: RecordedResponse {
+                status: 200,
+                headers: BTreeMap::new(),
+                body: "recorded".into(),
+            },
+        };
+        fs::write(
+            dir.join("00000000.json"),
+            serde_json::to_vec(&exchange).unwrap(),
+        )
+        .unwrap();
+
+        let replayer = Replayer::load(&dir, vec!["secret".into()]).unwrap();
+        let request = Client::new()
+            .get("https://example.com/?token=secret")
+            .build()
+            .unwrap();
+        let response = replayer.execute(request).unwrap();
+        assert_eq!(response.text().unwrap(), "recorded");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,250 @@
+// Copyright (c) 2018 Pietro Albini <pietro@pietroalbini.org>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies
+// of the Software, and to permit persons to whom the Software is furnished to do
+// so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Rendering of simple documents as Markdown or HTML, shared by `stats` and `report`.
+
+use prelude::*;
+use std::io::Write;
+use std::str::FromStr;
+
+const CHART_WIDTH: usize = 720;
+const CHART_HEIGHT: usize = 300;
+const CHART_MARGIN: usize = 40;
+static CHART_COLORS: &[&str] = &["#b7410e", "#1f77b4", "#2ca02c", "#9467bd"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Markdown,
+    Html,
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Fallible<Self> {
+        match s {
+            "markdown" => Ok(Format::Markdown),
+            "html" => Ok(Format::Html),
+            other => bail!("unknown document format: {}", other),
+        }
+    }
+}
+
+/// Writer of simple documents, made of headings, paragraphs and tables. Text can contain
+/// `code spans`, which are rendered in both formats.
+pub struct Document<W: Write> {
+    out: W,
+    format: Format,
+    page: bool,
+}
+
+impl<W: Write> Document<W> {
+    /// Write a fragment of a document, to be embedded in a larger one.
+    pub fn new(out: W, format: Format) -> Self {
+        Document {
+            out,
+            format,
+            page: false,
+        }
+    }
+
+    /// Write a standalone document with the given title. `finish` must be called at the end.
+    pub fn page(mut out: W, format: Format, title: &str) -> Fallible<Self> {
+        if format == Format::Html {
+            writeln!(out, "<!DOCTYPE html>")?;
+            writeln!(out, "<html>\n<head>")?;
+            writeln!(out, "<meta charset=\"utf-8\">")?;
+            writeln!(out, "<title>{}</title>", escape_html(title))?;
+            writeln!(out, "</head>\n<body>")?;
+        }
+        let mut document = Document {
+            out,
+            format,
+            page: true,
+        };
+        document.heading(1, title)?;
+        Ok(document)
+    }
+
+    pub fn heading(&mut self, level: usize, text: &str) -> Fallible<()> {
+        match self.format {
+            Format::Markdown => writeln!(self.out, "{} {}\n", "#".repeat(level), text)?,
+            Format::Html => writeln!(self.out, "<h{0}>{1}</h{0}>", level, inline_html(text))?,
+        }
+        Ok(())
+    }
+
+    pub fn paragraph(&mut self, text: &str) -> Fallible<()> {
+        match self.format {
+            Format::Markdown => writeln!(self.out, "{}\n", text)?,
+            Format::Html => writeln!(self.out, "<p>{}</p>", inline_html(text))?,
+        }
+        Ok(())
+    }
+
+    pub fn table(&mut self, header: &[&str], rows: &[Vec<String>]) -> Fallible<()> {
+        match self.format {
+            Format::Markdown => {
+                writeln!(self.out, "| {} |", header.join(" | "))?;
+                writeln!(
+                    self.out,
+                    "|{}|",
+                    header.iter().map(|_| "---").collect::<Vec<_>>().join("|")
+                )?;
+                for row in rows {
+                    let row = row
+                        .iter()
+                        .map(|cell| cell.replace('|', "\\|"))
+                        .collect::<Vec<_>>();
+                    writeln!(self.out, "| {} |", row.join(" | "))?;
+                }
+                writeln!(self.out)?;
+            }
+            Format::Html => {
+                writeln!(self.out, "<table>")?;
+                write!(self.out, "<tr>")?;
+                for cell in header {
+                    write!(self.out, "<th>{}</th>", inline_html(cell))?;
+                }
+                writeln!(self.out, "</tr>")?;
+                for row in rows {
+                    write!(self.out, "<tr>")?;
+                    for cell in row {
+                        write!(self.out, "<td>{}</td>", inline_html(cell))?;
+                    }
+                    writeln!(self.out, "</tr>")?;
+                }
+                writeln!(self.out, "</table>")?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write a line chart of some series sharing the same labels on the X axis. Markdown has no
+    /// charts, so the data is written as a table instead.
+    pub fn chart(&mut self, labels: &[String], series: &[(&str, Vec<usize>)]) -> Fallible<()> {
+        if self.format == Format::Markdown || labels.len() < 2 {
+            let mut header = vec![""];
+            header.extend(series.iter().map(|(name, _)| *name));
+            let rows = labels
+                .iter()
+                .enumerate()
+                .map(|(i, label)| {
+                    let mut row = vec![label.clone()];
+                    row.extend(series.iter().map(|(_, values)| values[i].to_string()));
+                    row
+                })
+                .collect::<Vec<_>>();
+            return self.table(&header, &rows);
+        }
+
+        let max = series
+            .iter()
+            .flat_map(|(_, values)| values.iter())
+            .cloned()
+            .max()
+            .unwrap_or(0)
+            .max(1);
+        let x = |i: usize| CHART_MARGIN + i * (CHART_WIDTH - 2 * CHART_MARGIN) / (labels.len() - 1);
+        let y = |value: usize| {
+            CHART_HEIGHT - CHART_MARGIN - value * (CHART_HEIGHT - 2 * CHART_MARGIN) / max
+        };
+
+        writeln!(
+            self.out,
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{0}\" height=\"{1}\" \
+             viewBox=\"0 0 {0} {1}\" font-size=\"12\">",
+            CHART_WIDTH, CHART_HEIGHT
+        )?;
+        writeln!(
+            self.out,
+            "<text x=\"{}\" y=\"{}\">{}</text>",
+            CHART_MARGIN,
+            CHART_MARGIN / 2,
+            max
+        )?;
+        for (i, label) in [
+            (0, &labels[0]),
+            (labels.len() - 1, &labels[labels.len() - 1]),
+        ] {
+            writeln!(
+                self.out,
+                "<text x=\"{}\" y=\"{}\" text-anchor=\"middle\">{}</text>",
+                x(i),
+                CHART_HEIGHT - CHART_MARGIN / 4,
+                escape_html(label)
+            )?;
+        }
+        for (n, (name, values)) in series.iter().enumerate() {
+            let color = CHART_COLORS[n % CHART_COLORS.len()];
+            let points = values
+                .iter()
+                .enumerate()
+                .map(|(i, value)| format!("{},{}", x(i), y(*value)))
+                .collect::<Vec<_>>();
+            writeln!(
+                self.out,
+                "<polyline fill=\"none\" stroke=\"{}\" stroke-width=\"2\" points=\"{}\"/>",
+                color,
+                points.join(" ")
+            )?;
+            writeln!(
+                self.out,
+                "<text x=\"{}\" y=\"{}\" fill=\"{}\">{}</text>",
+                CHART_WIDTH - CHART_MARGIN * 3,
+                CHART_MARGIN / 2 + n * 14,
+                color,
+                escape_html(name)
+            )?;
+        }
+        writeln!(self.out, "</svg>")?;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Fallible<W> {
+        if self.page && self.format == Format::Html {
+            writeln!(self.out, "</body>\n</html>")?;
+        }
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+/// Render text with `code spans` as HTML.
+fn inline_html(text: &str) -> String {
+    escape_html(text)
+        .split('`')
+        .enumerate()
+        .map(|(i, part)| {
+            if i % 2 == 1 {
+                format!("<code>{}</code>", part)
+            } else {
+                part.to_string()
+            }
+        })
+        .collect()
+}
+
+pub fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
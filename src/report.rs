@@ -0,0 +1,145 @@
+// Copyright (c) 2018 Pietro Albini <pietro@pietroalbini.org>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies
+// of the Software, and to permit persons to whom the Software is furnished to do
+// so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Ecosystem report rendered from a template, for publishing outside of the dataset.
+//!
+//! Templates are Markdown or HTML files containing `{{ section }}` placeholders, which are
+//! replaced with the sections rendered in the same format. A default template is provided for
+//! both formats.
+
+use data::Data;
+use prelude::*;
+use render::{escape_html, Document, Format};
+use stats::{self, Stats};
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::SystemTime;
+use utils::format_timestamp;
+
+static DEFAULT_MARKDOWN_TEMPLATE: &str = "\
+# The Rust ecosystem on GitHub
+
+*Generated on {{ generated_at }}.*
+
+## Overview
+
+{{ overview }}
+
+{{ growth }}
+
+{{ top_repos }}
+
+{{ licenses }}
+
+{{ manifests }}
+
+{{ toolchains }}
+
+{{ owners }}
+";
+
+static DEFAULT_HTML_TEMPLATE: &str = "\
+<!DOCTYPE html>
+<html>
+<head>
+<meta charset=\"utf-8\">
+<title>The Rust ecosystem on GitHub</title>
+<style>
+body { font-family: sans-serif; max-width: 60em; margin: 2em auto; color: #222; }
+table { border-collapse: collapse; margin-bottom: 1.5em; }
+th, td { border: 1px solid #ccc; padding: 0.3em 0.6em; text-align: left; }
+th { background: #f4f4f4; }
+</style>
+</head>
+<body>
+<h1>The Rust ecosystem on GitHub</h1>
+<p><em>Generated on {{ generated_at }}.</em></p>
+<h2>Overview</h2>
+{{ overview }}
+{{ growth }}
+{{ top_repos }}
+{{ licenses }}
+{{ manifests }}
+{{ toolchains }}
+{{ owners }}
+</body>
+</html>
+";
+
+pub fn generate(data: &Data, format: Format, template: Option<&Path>) -> Fallible<()> {
+    let template = match template {
+        Some(path) => fs::read_to_string(path)
+            .with_context(|_| format!("failed to read template {}", path.display()))?,
+        None => match format {
+            Format::Markdown => DEFAULT_MARKDOWN_TEMPLATE.into(),
+            Format::Html => DEFAULT_HTML_TEMPLATE.into(),
+        },
+    };
+
+    let stats = Stats::collect(data)?;
+    let generated_at = format_timestamp(SystemTime::now());
+
+    // The whole report is rendered before printing it, to avoid partial output on errors
+    let mut report = String::new();
+    let mut rest = template.as_str();
+    while let Some(start) = rest.find("{{") {
+        report.push_str(&rest[..start]);
+        let end = match rest[start..].find("}}") {
+            Some(end) => start + end,
+            None => bail!("unclosed placeholder in the report template"),
+        };
+
+        let name = rest[start + 2..end].trim();
+        let section = match name {
+            "generated_at" => match format {
+                Format::Markdown => generated_at.clone(),
+                Format::Html => escape_html(&generated_at),
+            },
+            _ => render_section(name, data, &stats, format)?,
+        };
+        report.push_str(&section);
+        rest = &rest[end + 2..];
+    }
+    report.push_str(rest);
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    out.write_all(report.as_bytes())?;
+    out.flush()?;
+
+    Ok(())
+}
+
+fn render_section(name: &str, data: &Data, stats: &Stats, format: Format) -> Fallible<String> {
+    let mut out = Document::new(Vec::new(), format);
+    match name {
+        "overview" => stats::write_overview(&mut out, stats)?,
+        "growth" => stats::write_growth(&mut out, data)?,
+        "top_repos" => stats::write_top_repos(&mut out, stats)?,
+        "licenses" => stats::write_licenses(&mut out, stats)?,
+        "manifests" => stats::write_manifests(&mut out, stats)?,
+        "toolchains" => stats::write_toolchains(&mut out, stats)?,
+        "owners" => stats::write_owners(&mut out, data, stats)?,
+        other => bail!("unknown section in the report template: {}", other),
+    }
+    let rendered = String::from_utf8(out.finish()?)?;
+    Ok(rendered.trim_end().to_string())
+}
@@ -0,0 +1,72 @@
+// Copyright (c) 2018 Pietro Albini <pietro@pietroalbini.org>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies
+// of the Software, and to permit persons to whom the Software is furnished to do
+// so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use csv;
+use data::{Data, Repo};
+use prelude::*;
+use serde_json::{self, Value};
+use std::io;
+
+static PLATFORM: &str = "github";
+
+/// Print a reproducible random sample of the dataset as CSV, optionally stratified by the value
+/// of a column. Numeric columns are grouped by order of magnitude.
+pub fn run(data: &Data, size: usize, seed: u64, stratify_by: Option<&str>) -> Fallible<()> {
+    if let Some(column) = stratify_by {
+        if !Repo::columns().contains(&column) {
+            bail!("unknown column: {}", column);
+        }
+    }
+
+    let sample = data.sample(PLATFORM, size, seed, |repo| match stratify_by {
+        Some(column) => stratum(repo, column),
+        None => String::new(),
+    })?;
+
+    let stdout = io::stdout();
+    let mut csv = csv::Writer::from_writer(stdout.lock());
+    csv.write_record(Repo::columns())?;
+    let mut record = csv::ByteRecord::new();
+    for repo in &sample {
+        repo.write_record(&mut record);
+        csv.write_byte_record(&record)?;
+    }
+    csv.flush()?;
+
+    info!("sampled {} repositories", sample.len());
+    Ok(())
+}
+
+fn stratum(repo: &Repo, column: &str) -> String {
+    let value = serde_json::to_value(repo)
+        .ok()
+        .and_then(|repo| repo.get(column).cloned())
+        .unwrap_or(Value::Null);
+
+    match value {
+        Value::Number(number) => match number.as_u64() {
+            Some(0) => "0".into(),
+            Some(number) => format!("1e{}", (number as f64).log10().floor()),
+            None => number.to_string(),
+        },
+        Value::String(string) => string,
+        other => other.to_string(),
+    }
+}
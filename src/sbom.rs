@@ -0,0 +1,216 @@
+// Copyright (c) 2018 Pietro Albini <pietro@pietroalbini.org>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies
+// of the Software, and to permit persons to whom the Software is furnished to do
+// so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Per-repository software bills of materials, generated from the locked packages.
+
+use data::{write_atomically, Data, LockedPackage};
+use prelude::*;
+use serde_json::{self, Value};
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::SystemTime;
+use utils::format_timestamp;
+
+static PLATFORM: &str = "github";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    CycloneDx,
+    Spdx,
+}
+
+impl Format {
+    fn extension(self) -> &'static str {
+        match self {
+            Format::CycloneDx => "cdx.json",
+            Format::Spdx => "spdx.json",
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Fallible<Self> {
+        match s {
+            "cyclonedx" => Ok(Format::CycloneDx),
+            "spdx" => Ok(Format::Spdx),
+            other => bail!("unknown SBOM format: {}", other),
+        }
+    }
+}
+
+struct RepoInfo {
+    name: String,
+    sha: Option<String>,
+}
+
+/// Write an SBOM document for each repository with a fetched lockfile, in
+/// `<sbom_dir>/<owner>/<name>.<format>.json`.
+pub fn generate(data: &Data, format: Format) -> Fallible<()> {
+    let repos = data
+        .repos(PLATFORM)?
+        .map(|repo| {
+            repo.map(|repo| {
+                let info = RepoInfo {
+                    name: repo.name,
+                    sha: repo.head_sha,
+                };
+                (repo.id, info)
+            })
+        })
+        .collect::<Fallible<HashMap<_, _>>>()?;
+
+    let dir = data.sbom_dir(PLATFORM);
+    let created = format_timestamp(SystemTime::now());
+    let mut count = 0;
+
+    for lockfile in data.lockfiles(PLATFORM)? {
+        let (repo_id, packages) = lockfile?;
+        let repo = match repos.get(&repo_id) {
+            Some(repo) => repo,
+            None => continue,
+        };
+
+        let document = match format {
+            Format::CycloneDx => cyclonedx(repo, &packages, &created),
+            Format::Spdx => spdx(repo, &packages, &created),
+        };
+        let path = dir.join(format!("{}.{}", repo.name, format.extension()));
+        fs::create_dir_all(path.parent().unwrap()).context(ErrorKind::Storage)?;
+        write_document(&path, &document).context(ErrorKind::Storage)?;
+        count += 1;
+    }
+
+    info!(
+        "generated {} SBOM documents in {}",
+        count,
+        dir.to_string_lossy()
+    );
+    Ok(())
+}
+
+fn write_document(path: &Path, document: &Value) -> Fallible<()> {
+    write_atomically(path, |file| {
+        serde_json::to_writer_pretty(&mut *file, document)?;
+        file.write_all(b"\n")?;
+        Ok(())
+    })
+}
+
+/// Package URL of a package, only available for the ones published on crates.io.
+fn purl(package: &LockedPackage) -> Option<String> {
+    if package.is_from_crates_io() {
+        Some(format!("pkg:cargo/{}@{}", package.name, package.version))
+    } else {
+        None
+    }
+}
+
+fn cyclonedx(repo: &RepoInfo, packages: &[LockedPackage], created: &str) -> Value {
+    let components = packages
+        .iter()
+        .map(|package| {
+            let mut component = json!({
+                "type": "library",
+                "bom-ref": format!("{}@{}", package.name, package.version),
+                "name": package.name,
+                "version": package.version,
+            });
+            if let Some(purl) = purl(package) {
+                component["purl"] = Value::String(purl);
+            }
+            component
+        })
+        .collect::<Vec<_>>();
+
+    let mut component = json!({
+        "type": "application",
+        "bom-ref": repo.name,
+        "name": repo.name,
+    });
+    if let Some(sha) = &repo.sha {
+        component["version"] = Value::String(sha.clone());
+    }
+
+    json!({
+        "bomFormat": "CycloneDX",
+        "specVersion": "1.4",
+        "version": 1,
+        "metadata": {
+            "timestamp": created,
+            "tools": [{ "name": "rust-repos" }],
+            "component": component,
+        },
+        "components": components,
+    })
+}
+
+fn spdx(repo: &RepoInfo, packages: &[LockedPackage], created: &str) -> Value {
+    let mut spdx_packages = Vec::new();
+    let mut relationships = Vec::new();
+    for (i, package) in packages.iter().enumerate() {
+        let id = format!("SPDXRef-Package-{}", i);
+        let mut spdx_package = json!({
+            "SPDXID": id,
+            "name": package.name,
+            "versionInfo": package.version,
+            "downloadLocation": "NOASSERTION",
+        });
+        if let Some(purl) = purl(package) {
+            spdx_package["downloadLocation"] = Value::String(format!(
+                "https://crates.io/api/v1/crates/{}/{}/download",
+                package.name, package.version
+            ));
+            spdx_package["externalRefs"] = json!([{
+                "referenceCategory": "PACKAGE-MANAGER",
+                "referenceType": "purl",
+                "referenceLocator": purl,
+            }]);
+        }
+        spdx_packages.push(spdx_package);
+        relationships.push(json!({
+            "spdxElementId": "SPDXRef-DOCUMENT",
+            "relationshipType": "DESCRIBES",
+            "relatedSpdxElement": id,
+        }));
+    }
+
+    json!({
+        "spdxVersion": "SPDX-2.3",
+        "dataLicense": "CC0-1.0",
+        "SPDXID": "SPDXRef-DOCUMENT",
+        "name": repo.name,
+        "documentNamespace": format!(
+            "https://github.com/{}/sbom/{}",
+            repo.name,
+            repo.sha.as_deref().unwrap_or("unknown")
+        ),
+        "creationInfo": {
+            "created": created,
+            "creators": ["Tool: rust-repos"],
+        },
+        "packages": spdx_packages,
+        "relationships": relationships,
+    })
+}
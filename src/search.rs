@@ -0,0 +1,363 @@
+// Copyright (c) 2018 Pietro Albini <pietro@pietroalbini.org>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies
+// of the Software, and to permit persons to whom the Software is furnished to do
+// so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Full-text index of the repository names and descriptions.
+//!
+//! The index is an inverted index split into shards by the hash of each term, so searching only
+//! reads the shards containing the query terms. Each shard is a TSV file with one posting per
+//! line (`term`, `weight`, `id`, `generation`, `name`), and new repositories are appended to it
+//! as they're stored.
+//!
+//! Repositories scraped again are indexed again, without removing their previous postings: each
+//! indexing gets a new generation, recorded in `generations.tsv`, and only the postings of the
+//! latest generation of a repository are considered when searching.
+
+use data::{Data, Repo};
+use prelude::*;
+use std::collections::{BTreeSet, HashMap};
+use std::fs::{self, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Number of files the index is split into.
+const SHARDS: u64 = 64;
+/// Weight of terms found in the name of the repository, compared to the description.
+const NAME_WEIGHT: u32 = 2;
+const DESCRIPTION_WEIGHT: u32 = 1;
+/// Number of results printed when no limit is provided.
+pub const DEFAULT_LIMIT: usize = 20;
+
+static PLATFORM: &str = "github";
+
+/// Split some text into normalized terms.
+fn terms(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|term| term.chars().count() > 1)
+        .map(|term| term.to_lowercase())
+}
+
+/// FNV-1a hash of the term, which unlike the standard library hasher is stable across releases.
+fn shard(term: &str) -> u64 {
+    let hash = term.bytes().fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    });
+    hash % SHARDS
+}
+
+fn shard_path(dir: &Path, shard: u64) -> PathBuf {
+    dir.join(format!("{:02}.tsv", shard))
+}
+
+/// File listing the latest generation of each repository (`id`, `generation`), appended to.
+fn generations_path(dir: &Path) -> PathBuf {
+    dir.join("generations.tsv")
+}
+
+/// File containing the generation the next indexed repository gets.
+fn next_generation_path(dir: &Path) -> PathBuf {
+    dir.join("next-generation")
+}
+
+/// Load the latest generation of each indexed repository.
+fn load_generations(dir: &Path) -> Fallible<HashMap<String, u64>> {
+    let path = generations_path(dir);
+    let mut generations = HashMap::new();
+    if !path.exists() {
+        return Ok(generations);
+    }
+    for line in BufReader::new(fs::File::open(&path)?).lines() {
+        let line = line?;
+        match line.split_once('\t') {
+            Some((id, generation)) => {
+                generations.insert(id.to_string(), generation.parse()?);
+            }
+            None => bail!("corrupted search index file: {}", path.to_string_lossy()),
+        }
+    }
+    Ok(generations)
+}
+
+/// Add the repositories to the index in the provided directory, superseding the previous
+/// postings of the ones already indexed.
+pub fn index_repos(dir: &Path, repos: &[Repo]) -> Fallible<()> {
+    fs::create_dir_all(dir)?;
+    let next_path = next_generation_path(dir);
+    let first_generation = if next_path.exists() {
+        fs::read_to_string(&next_path)?.trim().parse::<u64>()?
+    } else {
+        0
+    };
+
+    let mut shards: HashMap<u64, Vec<String>> = HashMap::new();
+    let mut generations = String::new();
+    for (repo, generation) in repos.iter().zip(first_generation..) {
+        let mut weights: HashMap<String, u32> = HashMap::new();
+        for term in repo.description.iter().flat_map(|desc| terms(desc)) {
+            weights.insert(term, DESCRIPTION_WEIGHT);
+        }
+        for term in terms(&repo.name) {
+            weights.insert(term, NAME_WEIGHT);
+        }
+
+        for (term, weight) in weights {
+            shards.entry(shard(&term)).or_default().push(format!(
+                "{}\t{}\t{}\t{}\t{}\n",
+                term, weight, repo.id, generation, repo.name
+            ));
+        }
+        generations.push_str(&format!("{}\t{}\n", repo.id, generation));
+    }
+
+    for (shard, postings) in shards {
+        append(&shard_path(dir, shard), postings.concat().as_bytes())?;
+    }
+    // The postings of a generation are ignored until it's recorded, so an interrupted indexing
+    // doesn't hide the previous postings of the repositories
+    append(&generations_path(dir), generations.as_bytes())?;
+    fs::write(
+        &next_path,
+        format!("{}\n", first_generation + repos.len() as u64),
+    )?;
+
+    Ok(())
+}
+
+fn append(path: &Path, content: &[u8]) -> Fallible<()> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let mut file = BufWriter::new(file);
+    file.write_all(content)?;
+    file.flush()?;
+    Ok(())
+}
+
+/// Build the index from scratch with all the stored repositories.
+pub fn rebuild(data: &Data) -> Fallible<()> {
+    let dir = data.search_dir(PLATFORM);
+    if dir.exists() {
+        fs::remove_dir_all(&dir).context(ErrorKind::Storage)?;
+    }
+
+    let mut count = 0;
+    let mut batch = Vec::new();
+    for repo in data.repos(PLATFORM)? {
+        batch.push(repo?);
+        if batch.len() >= 10_000 {
+            count += batch.len();
+            index_repos(&dir, &batch).context(ErrorKind::Storage)?;
+            batch.clear();
+        }
+    }
+    count += batch.len();
+    index_repos(&dir, &batch).context(ErrorKind::Storage)?;
+
+    info!("indexed {} repositories", count);
+    Ok(())
+}
+
+#[derive(Debug)]
+struct Hit {
+    name: String,
+    score: u32,
+    matched: usize,
+}
+
+/// Print the repositories matching all the terms of the query, best matches first.
+pub fn search(data: &Data, query: &str, limit: usize) -> Fallible<()> {
+    let dir = data.search_dir(PLATFORM);
+    if !dir.is_dir() {
+        bail!("the search index doesn't exist, build it with `rust-repos index <data_dir>`");
+    }
+
+    let results = find(&dir, query)?;
+    for hit in results.iter().take(limit) {
+        println!("https://github.com/{}", hit.name);
+    }
+    if results.len() > limit {
+        info!("{} more results not shown", results.len() - limit);
+    }
+
+    Ok(())
+}
+
+/// Find the repositories whose latest indexed version matches all the terms of the query, best
+/// matches first.
+fn find(dir: &Path, query: &str) -> Fallible<Vec<Hit>> {
+    let query_terms = terms(query).collect::<BTreeSet<_>>();
+    if query_terms.is_empty() {
+        bail!("the search query doesn't contain any term");
+    }
+    let generations = load_generations(dir)?;
+
+    let mut hits: HashMap<String, Hit> = HashMap::new();
+    for term in &query_terms {
+        let path = shard_path(dir, shard(term));
+        if !path.exists() {
+            continue;
+        }
+
+        for line in BufReader::new(fs::File::open(&path)?).lines() {
+            let line = line?;
+            let mut fields = line.splitn(5, '\t');
+            if fields.next() != Some(term.as_str()) {
+                continue;
+            }
+            let (weight, id, generation, name) =
+                match (fields.next(), fields.next(), fields.next(), fields.next()) {
+                    (Some(weight), Some(id), Some(generation), Some(name)) => {
+                        (weight, id, generation, name)
+                    }
+                    _ => bail!(
+                        "corrupted search index shard: {} (rebuild it with `rust-repos index`)",
+                        path.to_string_lossy()
+                    ),
+                };
+            let generation = generation.parse::<u64>().with_context(|_| {
+                format!(
+                    "corrupted search index shard: {} (rebuild it with `rust-repos index`)",
+                    path.to_string_lossy()
+                )
+            })?;
+            // Postings of previous versions of the repository are superseded
+            if generations.get(id) != Some(&generation) {
+                continue;
+            }
+
+            let hit = hits.entry(id.to_string()).or_insert(Hit {
+                name: name.to_string(),
+                score: 0,
+                matched: 0,
+            });
+            hit.score += weight.parse::<u32>()?;
+            hit.matched += 1;
+        }
+    }
+
+    let mut results = hits
+        .into_values()
+        .filter(|hit| hit.matched == query_terms.len())
+        .collect::<Vec<_>>();
+    results.sort_by(|a, b| b.score.cmp(&a.score).then_with(|| a.name.cmp(&b.name)));
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{find, index_repos};
+    use data::Repo;
+    use serde_json;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    fn repo(id: &str, name: &str, description: Option<&str>) -> Repo {
+        serde_json::from_value(json!({
+            "id": id,
+            "name": name,
+            "has_cargo_toml": true,
+            "has_cargo_lock": false,
+            "description": description,
+        }))
+        .unwrap()
+    }
+
+    fn index_dir(name: &str) -> PathBuf {
+        let dir = ::std::env::temp_dir().join(format!(
+            "rust-repos-search-{}-{}",
+            ::std::process::id(),
+            name
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn names(dir: &Path, query: &str) -> Vec<String> {
+        find(dir, query)
+            .unwrap()
+            .into_iter()
+            .map(|hit| hit.name)
+            .collect()
+    }
+
+    #[test]
+    fn test_search() {
+        let dir = index_dir("search");
+        index_repos(
+            &dir,
+            &[
+                repo(
+                    "1",
+                    "alice/embedded-hal",
+                    Some("Traits for embedded systems"),
+                ),
+                repo("2", "bob/hal", Some("An embedded HAL")),
+                repo("3", "carol/web", None),
+            ],
+        )
+        .unwrap();
+
+        // Terms in the name weigh more than in the description
+        assert_eq!(
+            names(&dir, "embedded"),
+            vec!["alice/embedded-hal", "bob/hal"]
+        );
+        assert_eq!(
+            names(&dir, "Embedded HAL"),
+            vec!["alice/embedded-hal", "bob/hal"]
+        );
+        assert_eq!(names(&dir, "embedded web"), Vec::<String>::new());
+        assert_eq!(names(&dir, "web"), vec!["carol/web"]);
+        assert!(find(&dir, "a").is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_reindexed_repos_drop_stale_terms() {
+        let dir = index_dir("reindex");
+        index_repos(&dir, &[repo("1", "alice/old-name", Some("Parser library"))]).unwrap();
+        index_repos(&dir, &[repo("2", "bob/parser", None)]).unwrap();
+        // Renamed, with a new description, and indexed twice in the same batch
+        index_repos(
+            &dir,
+            &[
+                repo("1", "alice/intermediate", Some("Parser library")),
+                repo("1", "alice/new-name", Some("Lexer library")),
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(names(&dir, "old"), Vec::<String>::new());
+        assert_eq!(names(&dir, "intermediate"), Vec::<String>::new());
+        assert_eq!(names(&dir, "parser"), vec!["bob/parser"]);
+        assert_eq!(names(&dir, "new lexer"), vec!["alice/new-name"]);
+        // Matching terms of the latest version are only counted once
+        assert_eq!(find(&dir, "library").unwrap()[0].score, 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_old_index_format() {
+        let dir = index_dir("old-format");
+        fs::create_dir_all(&dir).unwrap();
+        index_repos(&dir, &[repo("1", "alice/foo", None)]).unwrap();
+        let shard = dir.join(format!("{:02}.tsv", super::shard("foo")));
+        fs::write(&shard, "foo\t2\t1\talice/foo\n").unwrap();
+        assert!(find(&dir, "foo").is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,105 @@
+// Copyright (c) 2018 Pietro Albini <pietro@pietroalbini.org>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies
+// of the Software, and to permit persons to whom the Software is furnished to do
+// so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Import of third-party datasets (like the GHTorrent dumps) listing repositories known not to
+//! use Rust, to skip loading them while scraping from scratch.
+
+use canonical;
+use data::{Data, SeededRepo};
+use prelude::*;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+
+static NULL: &str = "\\N";
+
+/// Import the non-Rust repositories from the `projects.csv` file of a GHTorrent dump, replacing
+/// the previously imported ones.
+///
+/// Forks and deleted repositories are ignored, as are repositories without a known language.
+pub fn import(data: &Data, dump: &Path) -> Fallible<()> {
+    info!("importing the repositories from {}", dump.display());
+
+    // The GHTorrent dumps are MySQL exports: no headers, `\N` for NULL and backslash escapes
+    let reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .double_quote(false)
+        .escape(Some(b'\\'))
+        .flexible(true)
+        .from_path(dump)
+        .with_context(|_| format!("failed to open {}", dump.display()))?;
+
+    let repos = reader.into_records().filter_map(|record| {
+        let record = match record {
+            Ok(record) => record,
+            Err(err) => return Some(Err(err.into())),
+        };
+        let field = |idx| record.get(idx).filter(|value| *value != NULL);
+
+        let name = canonical::canonical(field(1)?).ok()?;
+        let language = field(5)?;
+        if field(7).is_some() || field(8) == Some("1") || language == "Rust" {
+            return None;
+        }
+        Some(Ok(SeededRepo {
+            name,
+            language: language.to_string(),
+        }))
+    });
+
+    let count = data.store_seed("github", repos)?;
+    info!("imported {} repositories known not to use Rust", count);
+    Ok(())
+}
+
+/// Repositories known not to use Rust. Only hashes of the names are kept in memory, as the
+/// datasets contain tens of millions of repositories.
+#[derive(Default)]
+pub struct Seed {
+    names: HashSet<u64>,
+}
+
+impl Seed {
+    pub fn load(data: &Data, platform: &str) -> Fallible<Self> {
+        let mut seed = Seed::default();
+        if let Some(repos) = data.seed(platform)? {
+            for repo in repos {
+                seed.names.insert(hash(&repo?.name));
+            }
+            info!(
+                "loaded {} repositories known not to use Rust",
+                seed.names.len()
+            );
+        }
+        Ok(seed)
+    }
+
+    /// Check whether a repository (in the `owner/name` format) is known not to use Rust.
+    pub fn contains(&self, name_with_owner: &str) -> bool {
+        !self.names.is_empty() && self.names.contains(&hash(&name_with_owner.to_lowercase()))
+    }
+}
+
+fn hash(name: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    name.hash(&mut hasher);
+    hasher.finish()
+}
@@ -0,0 +1,225 @@
+// Copyright (c) 2018 Pietro Albini <pietro@pietroalbini.org>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies
+// of the Software, and to permit persons to whom the Software is furnished to do
+// so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Minimal SMTP client, sending plain text emails through a relay with optional TLS (either
+//! implicit or with `STARTTLS`) and `AUTH PLAIN` authentication.
+
+use base64;
+#[cfg(feature = "native-tls")]
+use native_tls::TlsConnector;
+use prelude::*;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use utils::format_timestamp;
+
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How the connection to the SMTP server is secured.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Security {
+    /// TLS from the start of the connection, usually on port 465.
+    Tls,
+    /// Plain connection upgraded with `STARTTLS`, usually on port 587.
+    StartTls,
+    None,
+}
+
+impl FromStr for Security {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Fallible<Self> {
+        match s {
+            "tls" => Ok(Security::Tls),
+            "starttls" => Ok(Security::StartTls),
+            "none" => Ok(Security::None),
+            other => bail!("unknown SMTP security: {}", other),
+        }
+    }
+}
+
+pub struct Server {
+    /// Host name and port, like `smtp.example.org:587`.
+    pub address: String,
+    pub security: Security,
+    pub credentials: Option<(String, String)>,
+}
+
+pub struct Email<'a> {
+    pub from: &'a str,
+    pub to: &'a [String],
+    pub subject: &'a str,
+    pub body: &'a str,
+}
+
+struct Connection<S: Read + Write> {
+    stream: BufReader<S>,
+}
+
+impl<S: Read + Write> Connection<S> {
+    fn new(stream: S) -> Self {
+        Connection {
+            stream: BufReader::new(stream),
+        }
+    }
+
+    /// Read a (possibly multiline) reply, failing if its code is not the expected one.
+    fn expect(&mut self, code: u16) -> Fallible<()> {
+        let mut text = String::new();
+        loop {
+            let mut line = String::new();
+            if self.stream.read_line(&mut line)? == 0 {
+                bail!("the SMTP server closed the connection");
+            }
+            text.push_str(&line);
+            // The last line of a reply has a space after the code instead of a dash
+            if line.as_bytes().get(3) != Some(&b'-') {
+                break;
+            }
+        }
+        if text.get(..3) != Some(&code.to_string()) {
+            bail!("unexpected SMTP reply: {}", text.trim_end());
+        }
+        Ok(())
+    }
+
+    fn command(&mut self, command: &str, code: u16) -> Fallible<()> {
+        let stream = self.stream.get_mut();
+        stream.write_all(command.as_bytes())?;
+        stream.write_all(b"\r\n")?;
+        stream.flush()?;
+        self.expect(code)
+            .with_context(|_| format!("SMTP command failed: {}", verb(command)))?;
+        Ok(())
+    }
+
+    fn deliver(&mut self, server: &Server, email: &Email) -> Fallible<()> {
+        if let Some((username, password)) = &server.credentials {
+            let token = base64::encode(format!("\0{}\0{}", username, password));
+            self.command(&format!("AUTH PLAIN {}", token), 235)?;
+        }
+        self.command(&format!("MAIL FROM:<{}>", email.from), 250)?;
+        for to in email.to {
+            self.command(&format!("RCPT TO:<{}>", to), 250)?;
+        }
+        self.command("DATA", 354)?;
+        self.command(&message(email), 250)?;
+        self.command("QUIT", 221)?;
+        Ok(())
+    }
+}
+
+/// The verb of a command, to avoid logging credentials.
+fn verb(command: &str) -> &str {
+    command.split_whitespace().next().unwrap_or("")
+}
+
+/// Secure a connection with the TLS backend enabled with the Cargo features.
+#[cfg(feature = "native-tls")]
+fn tls_connect(host: &str, stream: TcpStream) -> Fallible<impl Read + Write> {
+    Ok(TlsConnector::new()?.connect(host, stream)?)
+}
+
+pub fn send(server: &Server, email: &Email) -> Fallible<()> {
+    let host = server
+        .address
+        .rsplit_once(':')
+        .map(|(host, _)| host)
+        .ok_or_else(|| format_err!("missing port in the SMTP address {}", server.address))?;
+    let addr = server
+        .address
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| format_err!("failed to resolve {}", server.address))?;
+    let stream = TcpStream::connect_timeout(&addr, TIMEOUT)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+
+    match server.security {
+        Security::Tls => {
+            let stream = tls_connect(host, stream)?;
+            let mut connection = Connection::new(stream);
+            connection.expect(220)?;
+            connection.command("EHLO rust-repos", 250)?;
+            connection.deliver(server, email)
+        }
+        Security::StartTls => {
+            let mut connection = Connection::new(stream);
+            connection.expect(220)?;
+            connection.command("EHLO rust-repos", 250)?;
+            connection.command("STARTTLS", 220)?;
+            let stream = tls_connect(host, connection.stream.into_inner())?;
+            let mut connection = Connection::new(stream);
+            connection.command("EHLO rust-repos", 250)?;
+            connection.deliver(server, email)
+        }
+        Security::None => {
+            let mut connection = Connection::new(stream);
+            connection.expect(220)?;
+            connection.command("EHLO rust-repos", 250)?;
+            connection.deliver(server, email)
+        }
+    }
+}
+
+/// Format the message sent after `DATA`, including its terminating line.
+fn message(email: &Email) -> String {
+    let mut message = format!(
+        "From: {}\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\nMIME-Version: 1.0\r\n\
+         Content-Type: text/plain; charset=utf-8\r\nContent-Transfer-Encoding: 8bit\r\n\r\n",
+        email.from,
+        email.to.join(", "),
+        email.subject,
+        date(SystemTime::now())
+    );
+    for line in email.body.lines() {
+        // Lines starting with a dot are escaped, as a lone dot ends the message
+        if line.starts_with('.') {
+            message.push('.');
+        }
+        message.push_str(line);
+        message.push_str("\r\n");
+    }
+    message.push('.');
+    message
+}
+
+/// Format a point in time as an RFC 5322 date, like `Sat, 21 Jul 2018 12:34:56 +0000`.
+fn date(time: SystemTime) -> String {
+    const WEEKDAYS: &[&str] = &["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: &[&str] = &[
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let days = time
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs() / 86_400)
+        .unwrap_or(0);
+    let timestamp = format_timestamp(time);
+    let month = timestamp[5..7].parse::<usize>().unwrap_or(1);
+    format!(
+        "{}, {} {} {} {} +0000",
+        WEEKDAYS[(days % 7) as usize],
+        &timestamp[8..10],
+        MONTHS[month - 1],
+        &timestamp[..4],
+        &timestamp[11..19]
+    )
+}
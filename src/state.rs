@@ -0,0 +1,322 @@
+// Copyright (c) 2018 Pietro Albini <pietro@pietroalbini.org>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies
+// of the Software, and to permit persons to whom the Software is furnished to do
+// so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use config::Config;
+use data::{write_atomically, Data};
+use http::{self, ResponseExt};
+use prelude::*;
+use reqwest::blocking::{Body, Client, RequestBuilder, Response};
+use reqwest::{header, StatusCode};
+use serde_json;
+use std::collections::BTreeSet;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+/// Forges the scraper keeps a state for.
+const FORGES: &[&str] = &["github"];
+/// Object listing the files of the data directory pushed along with the state.
+const MANIFEST: &str = "manifest.json";
+
+#[derive(Serialize, Deserialize)]
+struct Manifest {
+    files: Vec<String>,
+}
+
+/// Changes to apply to the state of a forge with `state set`.
+#[derive(Default)]
+pub struct StateChanges {
+    pub last_id: Option<usize>,
+    pub lockfile_position: Option<usize>,
+    pub clear_retry_queue: bool,
+}
+
+impl StateChanges {
+    fn is_empty(&self) -> bool {
+        self.last_id.is_none() && self.lockfile_position.is_none() && !self.clear_retry_queue
+    }
+}
+
+/// Print the state of every forge in a human readable format.
+pub fn show(data: &Data) -> Fallible<()> {
+    for forge in FORGES {
+        let state = data.forge_state(forge)?;
+
+        println!("{}:", forge);
+        match state.last_id {
+            Some(id) => println!("  last ID: {}", id),
+            None => println!("  last ID: none"),
+        }
+        if let Some(id) = state.processed_id {
+            println!("  processed ID: {}", id);
+        }
+        if let Some(cursor) = &state.cursor {
+            println!("  cursor: {}", cursor);
+        }
+        println!("  lockfile position: {}", state.lockfile_position);
+        println!("  retry queue: {} repositories", state.retry_queue.len());
+        if let Some(search) = &state.popular_search {
+            println!(
+                "  popular search: {} partitions left (at least {} stars)",
+                search.partitions.len(),
+                search.min_stars
+            );
+        }
+        for (target, watermark) in &state.export_watermarks {
+            println!("  exported to {}: up to {}", target, watermark);
+        }
+        println!(
+            "  runs: {} ({} repositories seen, {} stored)",
+            state.counters.runs, state.counters.repos_seen, state.counters.repos_stored
+        );
+        if let Some(last_run_at) = &state.last_run_at {
+            println!("  last run: {}", last_run_at);
+        }
+    }
+    Ok(())
+}
+
+/// Validate and apply changes to the state of a forge.
+pub fn set(data: &Data, forge: &str, changes: &StateChanges) -> Fallible<()> {
+    if !FORGES.contains(&forge) {
+        bail!("unknown forge: {}", forge);
+    }
+    if changes.is_empty() {
+        bail!("no changes to the state provided");
+    }
+
+    if let Some(position) = changes.lockfile_position {
+        let rows = data.repos(forge)?.count();
+        if position > rows {
+            bail!(
+                "the lockfile position can't be past the end of the data ({} rows)",
+                rows
+            );
+        }
+    }
+
+    data.edit_forge_state(forge, |state| {
+        if let Some(id) = changes.last_id {
+            state.last_id = Some(id);
+            state.processed_id = None;
+        }
+        if let Some(position) = changes.lockfile_position {
+            state.lockfile_position = position;
+        }
+        if changes.clear_retry_queue {
+            state.retry_queue.clear();
+        }
+    })?;
+
+    info!("updated the state of {}", forge);
+    Ok(())
+}
+
+/// Upload the state, and optionally all the other files of the data directory, to the object
+/// storage. The state is uploaded last, so that it never refers to data missing remotely.
+pub fn push(data: &Data, config: &Config, with_data: bool) -> Fallible<()> {
+    let remote = Remote::new(config)?;
+
+    if with_data {
+        let mut files = Vec::new();
+        list_data_files(data, data.base_dir(), &mut files)?;
+        for file in &files {
+            info!("uploading {}", file);
+            remote.upload(file, &data.base_dir().join(file))?;
+        }
+        remote.put(
+            MANIFEST,
+            Body::from(serde_json::to_vec(&Manifest { files })?),
+        )?;
+    }
+
+    for file in state_files(data)? {
+        info!("uploading {}", file);
+        remote.upload(&file, &data.base_dir().join(&file))?;
+    }
+
+    info!("pushed the state to {}", remote.base_url);
+    Ok(())
+}
+
+/// Download the state, and optionally the files of the data directory pushed with it, from the
+/// object storage, replacing the local copies.
+pub fn pull(data: &Data, config: &Config, with_data: bool) -> Fallible<()> {
+    let remote = Remote::new(config)?;
+
+    if with_data {
+        let manifest: Manifest = match remote.get(MANIFEST)? {
+            Some(resp) => serde_json::from_slice(&resp.bytes()?)?,
+            None => bail!("no data was pushed to {}", remote.base_url),
+        };
+        for file in &manifest.files {
+            info!("downloading {}", file);
+            if !remote.download(file, &local_path(data, file)?)? {
+                bail!("{} is listed in the manifest but missing", file);
+            }
+        }
+    }
+
+    let mut found = false;
+    for file in state_files(data)? {
+        info!("downloading {}", file);
+        found |= remote.download(&file, &local_path(data, &file)?)?;
+    }
+    if !found {
+        bail!("no state was pushed to {}", remote.base_url);
+    }
+
+    info!("pulled the state from {}", remote.base_url);
+    Ok(())
+}
+
+/// Object storage reached over HTTP, with objects named after the paths relative to the data
+/// directory. `PUT` and `GET` requests are all it needs, which GCS, S3-compatible storage with
+/// public or token authentication, and plain WebDAV servers support.
+struct Remote {
+    client: Client,
+    base_url: String,
+    token: Option<String>,
+}
+
+impl Remote {
+    fn new(config: &Config) -> Fallible<Self> {
+        let base_url = match &config.checkpoint_url {
+            Some(url) => url.trim_end_matches('/').to_string(),
+            None => bail!("missing RUST_REPOS_CHECKPOINT_URL"),
+        };
+        Ok(Remote {
+            client: http::client(config)?,
+            base_url,
+            token: config.checkpoint_token.clone(),
+        })
+    }
+
+    fn request(&self, builder: RequestBuilder) -> Fallible<Response> {
+        let builder = match &self.token {
+            Some(token) => builder.bearer_auth(token),
+            None => builder,
+        };
+        Ok(builder.send()?)
+    }
+
+    fn url(&self, name: &str) -> String {
+        format!("{}/{}", self.base_url, name)
+    }
+
+    fn put(&self, name: &str, body: Body) -> Fallible<()> {
+        let resp = self.request(self.client.put(self.url(name)).body(body))?;
+        if !resp.status().is_success() {
+            bail!("uploading {} failed with {}", name, resp.status());
+        }
+        Ok(())
+    }
+
+    fn upload(&self, name: &str, path: &Path) -> Fallible<()> {
+        self.put(name, Body::from(File::open(path)?))
+    }
+
+    /// Fetch an object, returning `None` if it doesn't exist.
+    fn get(&self, name: &str) -> Fallible<Option<Response>> {
+        // The objects are streamed to disk, and decompressing them would load them in memory
+        let resp = self.request(
+            self.client
+                .get(self.url(name))
+                .header(header::ACCEPT_ENCODING, "identity"),
+        )?;
+        match resp.status() {
+            StatusCode::NOT_FOUND => Ok(None),
+            status if status.is_success() => Ok(Some(resp.decompressed()?)),
+            status => bail!("downloading {} failed with {}", name, status),
+        }
+    }
+
+    /// Download an object to a file, returning whether it exists.
+    fn download(&self, name: &str, path: &Path) -> Fallible<bool> {
+        let mut resp = match self.get(name)? {
+            Some(resp) => resp,
+            None => return Ok(false),
+        };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        write_atomically(path, |file| {
+            io::copy(&mut resp, file)?;
+            Ok(())
+        })?;
+        Ok(true)
+    }
+}
+
+/// Paths of the state files relative to the data directory.
+fn state_files(data: &Data) -> Fallible<BTreeSet<String>> {
+    FORGES
+        .iter()
+        .map(|forge| relative_path(data, &data.state_path(forge)))
+        .collect()
+}
+
+/// Paths of the files of the data directory relative to it, except the state files and the
+/// files belonging to a running process (the lock and the temporary files).
+fn list_data_files(data: &Data, dir: &Path, files: &mut Vec<String>) -> Fallible<()> {
+    if !dir.exists() {
+        return Ok(());
+    }
+    let state_files = state_files(data)?;
+    let mut entries = fs::read_dir(dir)?.collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with('.') || name.ends_with(".tmp") {
+            continue;
+        }
+        let path = entry.path();
+        if entry.file_type()?.is_dir() {
+            list_data_files(data, &path, files)?;
+        } else {
+            let relative = relative_path(data, &path)?;
+            if !state_files.contains(&relative) {
+                files.push(relative);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn relative_path(data: &Data, path: &Path) -> Fallible<String> {
+    let relative = path.strip_prefix(data.base_dir())?;
+    let parts = relative
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>();
+    Ok(parts.join("/"))
+}
+
+/// Local path of a file listed in the manifest, refusing the paths escaping the data directory.
+fn local_path(data: &Data, name: &str) -> Fallible<PathBuf> {
+    let relative = Path::new(name);
+    if !relative
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)))
+    {
+        bail!("invalid path in the manifest: {}", name);
+    }
+    Ok(data.base_dir().join(relative))
+}
@@ -0,0 +1,447 @@
+// Copyright (c) 2018 Pietro Albini <pietro@pietroalbini.org>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies
+// of the Software, and to permit persons to whom the Software is furnished to do
+// so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Aggregated statistics about the dataset, printed as Markdown or HTML.
+
+use data::Data;
+use prelude::*;
+use render::{Document, Format};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{self, Write};
+use toolchain;
+use version::Version;
+
+static PLATFORM: &str = "github";
+
+/// Number of rows shown in the "top" tables.
+const TOP: usize = 20;
+
+/// Star tiers used to break down the distributions, as (lower bound, label).
+const STAR_TIERS: &[(u64, &str)] = &[
+    (0, "0 stars"),
+    (1, "1-9 stars"),
+    (10, "10-99 stars"),
+    (100, "100-999 stars"),
+    (1000, "1000+ stars"),
+];
+
+/// Distribution of the values of a column, broken down by star tier. The first count of each
+/// value includes the repositories with an unknown number of stars.
+#[derive(Default)]
+struct Distribution(HashMap<String, Vec<usize>>);
+
+impl Distribution {
+    fn add(&mut self, value: String, stars: Option<u64>) {
+        let counts = self
+            .0
+            .entry(value)
+            .or_insert_with(|| vec![0; STAR_TIERS.len() + 1]);
+        counts[0] += 1;
+        if let Some(tier) = stars.map(star_tier) {
+            counts[tier + 1] += 1;
+        }
+    }
+
+    fn total(&self) -> usize {
+        self.0.values().map(|counts| counts[0]).sum()
+    }
+
+    /// Render the distribution as table rows, with the share of each value in every tier.
+    fn rows(&self, values: &[String]) -> Vec<Vec<String>> {
+        let mut totals = vec![0; STAR_TIERS.len() + 1];
+        for counts in self.0.values() {
+            for (total, count) in totals.iter_mut().zip(counts) {
+                *total += count;
+            }
+        }
+
+        values
+            .iter()
+            .map(|value| {
+                let mut row = vec![format!("`{}`", value)];
+                for (count, total) in self.0[value].iter().zip(&totals) {
+                    row.push(percentage(*count, *total));
+                }
+                row
+            })
+            .collect()
+    }
+}
+
+/// Statistics collected in a single pass over the dataset.
+#[derive(Default)]
+pub struct Stats {
+    repos: usize,
+    with_cargo_toml: usize,
+    with_cargo_lock: usize,
+    archived: usize,
+    uses_nightly: usize,
+    licenses: HashMap<String, usize>,
+    toolchain_categories: BTreeMap<&'static str, usize>,
+    toolchains: HashMap<String, usize>,
+    rust_versions: Distribution,
+    editions: Distribution,
+    owners: HashSet<String>,
+    /// Most starred repositories, as (stars, name, description).
+    top_repos: Vec<(u64, String, Option<String>)>,
+    /// Ranges of the ID space that were scraped, as (first, last).
+    coverage: Vec<(u64, u64)>,
+}
+
+impl Stats {
+    pub fn collect(data: &Data) -> Fallible<Self> {
+        let mut stats = Stats::default();
+        for repo in data.repos(PLATFORM)? {
+            let repo = repo?;
+            stats.repos += 1;
+            stats.with_cargo_toml += repo.has_cargo_toml as usize;
+            stats.with_cargo_lock += repo.has_cargo_lock as usize;
+            stats.archived += (repo.is_archived == Some(true)) as usize;
+            stats.uses_nightly += (repo.uses_nightly == Some(true)) as usize;
+            if let Some(owner) = repo.name.split('/').next() {
+                stats.owners.insert(owner.to_lowercase());
+            }
+
+            if let Some(license) = repo.license {
+                *stats.licenses.entry(license).or_default() += 1;
+            }
+            if let Some(channel) = repo.toolchain {
+                *stats
+                    .toolchain_categories
+                    .entry(toolchain::category(&channel))
+                    .or_default() += 1;
+                *stats.toolchains.entry(channel).or_default() += 1;
+            }
+            if let Some(rust_version) = repo.rust_version {
+                stats.rust_versions.add(rust_version, repo.stars);
+            }
+            if let Some(edition) = repo.edition {
+                stats.editions.add(edition, repo.stars);
+            }
+
+            if let Some(stars) = repo.stars {
+                stats.top_repos.push((stars, repo.name, repo.description));
+                // Avoid keeping every repository in memory
+                if stats.top_repos.len() >= TOP * 10 {
+                    stats.sort_top_repos();
+                }
+            }
+        }
+        stats.sort_top_repos();
+        stats.coverage = data.coverage(PLATFORM)?;
+        Ok(stats)
+    }
+
+    fn sort_top_repos(&mut self) {
+        self.top_repos
+            .sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+        self.top_repos.truncate(TOP);
+    }
+}
+
+pub fn print(data: &Data, format: Format) -> Fallible<()> {
+    let stats = Stats::collect(data)?;
+
+    let stdout = io::stdout();
+    let mut out = Document::page(stdout.lock(), format, "Dataset statistics")?;
+    write_overview(&mut out, &stats)?;
+    write_licenses(&mut out, &stats)?;
+    write_toolchains(&mut out, &stats)?;
+    write_manifests(&mut out, &stats)?;
+    write_owners(&mut out, data, &stats)?;
+    out.finish().map(|_| ())
+}
+
+pub fn write_overview<W: Write>(out: &mut Document<W>, stats: &Stats) -> Fallible<()> {
+    let mut rows = vec![
+        vec!["Total".into(), stats.repos.to_string()],
+        vec![
+            "With `Cargo.toml`".into(),
+            stats.with_cargo_toml.to_string(),
+        ],
+        vec![
+            "With `Cargo.lock`".into(),
+            stats.with_cargo_lock.to_string(),
+        ],
+        vec!["Archived".into(), stats.archived.to_string()],
+        vec![
+            "Likely using nightly".into(),
+            stats.uses_nightly.to_string(),
+        ],
+    ];
+
+    // Share of the IDs up to the highest scraped one that were scraped
+    if let Some(&(_, max)) = stats.coverage.last() {
+        let covered = stats
+            .coverage
+            .iter()
+            .map(|(first, last)| last - first + 1)
+            .sum::<u64>();
+        // Gaps are between the scraped ranges, and before the first one if it doesn't start at 1
+        let gaps = stats.coverage.len() - 1 + usize::from(stats.coverage[0].0 > 1);
+        rows.push(vec![
+            "ID space covered".into(),
+            format!(
+                "{} (up to ID {})",
+                percentage(covered as usize, max as usize),
+                max
+            ),
+        ]);
+        rows.push(vec!["Gaps in the ID space".into(), gaps.to_string()]);
+    }
+
+    out.table(&["", "Repositories"], &rows)
+}
+
+pub fn write_top_repos<W: Write>(out: &mut Document<W>, stats: &Stats) -> Fallible<()> {
+    out.heading(2, "Most starred repositories")?;
+    out.table(
+        &["Repository", "Stars", "Description"],
+        &stats
+            .top_repos
+            .iter()
+            .map(|(stars, name, description)| {
+                vec![
+                    name.clone(),
+                    stars.to_string(),
+                    description.clone().unwrap_or_default(),
+                ]
+            })
+            .collect::<Vec<_>>(),
+    )
+}
+
+/// Write the growth of the dataset over time, from the daily totals recorded while scraping.
+pub fn write_growth<W: Write>(out: &mut Document<W>, data: &Data) -> Fallible<()> {
+    let rows = data.growth(PLATFORM)?;
+    out.heading(2, "Growth")?;
+    if rows.is_empty() {
+        return out.paragraph("No daily totals were recorded yet.");
+    }
+
+    let labels = rows.iter().map(|row| row.date.clone()).collect::<Vec<_>>();
+    out.chart(
+        &labels,
+        &[
+            (
+                "Rust repositories",
+                rows.iter().map(|row| row.rust_repos).collect(),
+            ),
+            (
+                "With Cargo.toml",
+                rows.iter().map(|row| row.with_cargo_toml).collect(),
+            ),
+            (
+                "With Cargo.lock",
+                rows.iter().map(|row| row.with_cargo_lock).collect(),
+            ),
+        ],
+    )
+}
+
+pub fn write_licenses<W: Write>(out: &mut Document<W>, stats: &Stats) -> Fallible<()> {
+    let known = stats.licenses.values().sum::<usize>();
+    let mut licenses = stats.licenses.iter().collect::<Vec<_>>();
+    licenses.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+
+    out.heading(2, "Licenses")?;
+    out.paragraph(&format!(
+        "{} repositories have a license detected by GitHub.",
+        known
+    ))?;
+    out.table(
+        &["License", "Repositories", "Share"],
+        &licenses
+            .into_iter()
+            .take(TOP)
+            .map(|(license, count)| {
+                vec![
+                    format!("`{}`", license),
+                    count.to_string(),
+                    percentage(*count, known),
+                ]
+            })
+            .collect::<Vec<_>>(),
+    )
+}
+
+pub fn write_toolchains<W: Write>(out: &mut Document<W>, stats: &Stats) -> Fallible<()> {
+    let pinned = stats.toolchain_categories.values().sum::<usize>();
+    out.heading(2, "Pinned toolchains")?;
+    out.paragraph(&format!(
+        "{} repositories pin their toolchain with a `rust-toolchain` file.",
+        pinned
+    ))?;
+    out.table(
+        &["Channel", "Repositories", "Share"],
+        &stats
+            .toolchain_categories
+            .iter()
+            .map(|(category, count)| {
+                vec![
+                    category.to_string(),
+                    count.to_string(),
+                    percentage(*count, pinned),
+                ]
+            })
+            .collect::<Vec<_>>(),
+    )?;
+
+    let mut toolchains = stats.toolchains.iter().collect::<Vec<_>>();
+    toolchains.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+    out.heading(3, "Most pinned toolchains")?;
+    out.table(
+        &["Toolchain", "Repositories"],
+        &toolchains
+            .into_iter()
+            .take(TOP)
+            .map(|(channel, count)| vec![format!("`{}`", channel), count.to_string()])
+            .collect::<Vec<_>>(),
+    )
+}
+
+/// Write the MSRV and edition distributions, broken down by star tier.
+pub fn write_manifests<W: Write>(out: &mut Document<W>, stats: &Stats) -> Fallible<()> {
+    let (rust_versions, editions) = (&stats.rust_versions, &stats.editions);
+    let mut header = vec!["Value", "All"];
+    header.extend(STAR_TIERS.iter().map(|(_, label)| *label));
+
+    out.heading(2, "Minimum supported Rust versions")?;
+    out.paragraph(&format!(
+        "{} repositories declare a `rust-version` in their `Cargo.toml`.",
+        rust_versions.total()
+    ))?;
+    // The most recent versions first, as that's what release planning cares about
+    let mut values = rust_versions.0.keys().cloned().collect::<Vec<_>>();
+    values.sort_by_cached_key(|value| {
+        std::cmp::Reverse(padded_version(value).parse::<Version>().ok())
+    });
+    values.truncate(TOP);
+    out.table(&header, &rust_versions.rows(&values))?;
+
+    out.heading(2, "Editions")?;
+    out.paragraph(&format!(
+        "{} repositories have a known edition in their `Cargo.toml`.",
+        editions.total()
+    ))?;
+    let mut values = editions.0.keys().cloned().collect::<Vec<_>>();
+    values.sort();
+    out.table(&header, &editions.rows(&values))?;
+
+    Ok(())
+}
+
+/// Write the breakdown of the owner profiles fetched by the `owners` command, if any.
+pub fn write_owners<W: Write>(out: &mut Document<W>, data: &Data, stats: &Stats) -> Fallible<()> {
+    let owners = &stats.owners;
+    let profiles = data
+        .owners(PLATFORM)?
+        .into_iter()
+        .filter(|(login, _)| owners.contains(login))
+        .map(|(_, owner)| owner)
+        .collect::<Vec<_>>();
+    if profiles.is_empty() {
+        return Ok(());
+    }
+
+    let mut kinds = BTreeMap::new();
+    let mut locations = HashMap::new();
+    let mut companies = HashMap::new();
+    for owner in &profiles {
+        let kind = owner.kind.as_deref().unwrap_or("Deleted").to_string();
+        *kinds.entry(kind).or_insert(0) += 1;
+        if let Some(location) = owner.location.as_deref().and_then(normalize) {
+            *locations.entry(location).or_insert(0) += 1;
+        }
+        if let Some(company) = owner.company.as_deref().and_then(normalize) {
+            *companies.entry(company).or_insert(0) += 1;
+        }
+    }
+
+    out.heading(2, "Owners")?;
+    out.paragraph(&format!(
+        "Profiles were fetched for {} of the {} owners.",
+        profiles.len(),
+        owners.len()
+    ))?;
+    out.table(
+        &["Type", "Owners", "Share"],
+        &kinds
+            .iter()
+            .map(|(kind, count)| {
+                vec![
+                    kind.clone(),
+                    count.to_string(),
+                    percentage(*count, profiles.len()),
+                ]
+            })
+            .collect::<Vec<_>>(),
+    )?;
+
+    for (title, counts) in &[("locations", locations), ("companies", companies)] {
+        let mut counts = counts.iter().collect::<Vec<_>>();
+        counts.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+        out.heading(3, &format!("Most common {}", title))?;
+        out.table(
+            &["Value", "Owners"],
+            &counts
+                .into_iter()
+                .take(TOP)
+                .map(|(value, count)| vec![value.clone(), count.to_string()])
+                .collect::<Vec<_>>(),
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Normalize free-form profile fields, so that `@Acme ` and `acme` are counted together.
+fn normalize(value: &str) -> Option<String> {
+    let value = value.trim().trim_start_matches('@').trim().to_lowercase();
+    if value.is_empty() {
+        None
+    } else {
+        Some(value)
+    }
+}
+
+fn star_tier(stars: u64) -> usize {
+    STAR_TIERS
+        .iter()
+        .rposition(|(min, _)| stars >= *min)
+        .unwrap_or(0)
+}
+
+/// `rust-version` can omit the minor and patch versions, which the version parser requires.
+fn padded_version(version: &str) -> String {
+    match version.matches('.').count() {
+        0 => format!("{}.0.0", version),
+        1 => format!("{}.0", version),
+        _ => version.to_string(),
+    }
+}
+
+fn percentage(count: usize, total: usize) -> String {
+    if total == 0 {
+        "-".into()
+    } else {
+        format!("{:.1}%", count as f64 * 100.0 / total as f64)
+    }
+}
@@ -0,0 +1,345 @@
+// Copyright (c) 2018 Pietro Albini <pietro@pietroalbini.org>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies
+// of the Software, and to permit persons to whom the Software is furnished to do
+// so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use data::Repo;
+use exit;
+use prelude::*;
+use std::collections::BTreeMap;
+use std::mem;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Upper bounds of the buckets of the latency histograms, in milliseconds.
+pub const LATENCY_BUCKETS_MS: &[u64] = &[100, 250, 500, 1000, 2500, 5000, 10_000, 30_000];
+
+/// Statistics of the requests sent to an endpoint or with a token.
+#[derive(Default, Clone)]
+pub struct RequestStats {
+    pub requests: usize,
+    /// Number of requests in each latency bucket, the last one counting the slowest requests.
+    pub latency_buckets: [usize; LATENCY_BUCKETS_MS.len() + 1],
+    pub latency_total_ms: u64,
+    /// Number of responses with each status code, or `error` when none was received.
+    pub statuses: BTreeMap<String, usize>,
+    /// Requests left in the rate limit window, as of the latest response.
+    pub rate_limit_remaining: Option<u64>,
+}
+
+impl RequestStats {
+    fn record(&mut self, status: &str, elapsed: Duration, rate_limit_remaining: Option<u64>) {
+        let elapsed_ms = elapsed.as_millis() as u64;
+        self.requests += 1;
+        let bucket = LATENCY_BUCKETS_MS
+            .iter()
+            .position(|&bound| elapsed_ms <= bound)
+            .unwrap_or(LATENCY_BUCKETS_MS.len());
+        self.latency_buckets[bucket] += 1;
+        self.latency_total_ms += elapsed_ms;
+        *self.statuses.entry(status.to_string()).or_insert(0) += 1;
+        if rate_limit_remaining.is_some() {
+            self.rate_limit_remaining = rate_limit_remaining;
+        }
+    }
+
+    /// Upper bound of the latency bucket containing the given quantile of the requests, or
+    /// `None` if it's in the last, unbounded bucket.
+    pub fn latency_quantile_ms(&self, quantile: f64) -> Option<u64> {
+        let target = (self.requests as f64 * quantile).ceil() as usize;
+        let mut seen = 0;
+        for (bound, count) in LATENCY_BUCKETS_MS.iter().zip(&self.latency_buckets) {
+            seen += count;
+            if seen >= target {
+                return Some(*bound);
+            }
+        }
+        None
+    }
+}
+
+/// Counters collected during a run, printed when the scraper exits.
+#[derive(Default)]
+pub struct Summary {
+    pub pages_fetched: AtomicUsize,
+    pub repos_seen: AtomicUsize,
+    pub repos_stored: AtomicUsize,
+    /// Repositories not loaded because the imported seed lists them as not using Rust.
+    pub repos_seeded: AtomicUsize,
+    /// Repositories not loaded because a previous run found they don't use Rust.
+    pub repos_known_non_rust: AtomicUsize,
+    pub api_calls: AtomicUsize,
+    /// Tokens removed from the pool after being rejected.
+    pub tokens_quarantined: AtomicUsize,
+    pub graphql_points: AtomicUsize,
+    /// Number of responses received with each HTTP version, to check connections are reused.
+    http_versions: Mutex<BTreeMap<String, usize>>,
+    /// Total time spent waiting for responses, in milliseconds.
+    http_wait_ms: AtomicUsize,
+    /// Statistics of the requests, grouped by endpoint.
+    endpoints: Mutex<BTreeMap<String, RequestStats>>,
+    /// Statistics of the requests, grouped by the identifier of the token they used.
+    tokens: Mutex<BTreeMap<String, RequestStats>>,
+    errors: Mutex<BTreeMap<String, usize>>,
+    /// Most severe class of the errors recorded, which the run must exit with.
+    worst_error: Mutex<Option<ErrorKind>>,
+    /// Unexpected fields and values found in the responses of the GitHub API.
+    schema_drift: Mutex<BTreeMap<String, usize>>,
+    last_progress: Mutex<Option<Instant>>,
+    /// Repositories found since the notifiers last took them, when notifications are enabled.
+    discoveries: Mutex<Option<Vec<Repo>>>,
+}
+
+impl Summary {
+    pub fn incr(counter: &AtomicUsize, amount: usize) {
+        counter.fetch_add(amount, Ordering::SeqCst);
+    }
+
+    /// Start keeping the repositories found, to be taken by `take_discoveries`.
+    pub fn track_discoveries(&self) {
+        *self.discoveries.lock().unwrap() = Some(Vec::new());
+    }
+
+    /// Record a repository found by the scraper, if something takes them.
+    pub fn record_discovery(&self, repo: &Repo) {
+        if let Some(discoveries) = self.discoveries.lock().unwrap().as_mut() {
+            discoveries.push(repo.clone());
+        }
+    }
+
+    pub fn take_discoveries(&self) -> Vec<Repo> {
+        self.discoveries
+            .lock()
+            .unwrap()
+            .as_mut()
+            .map(mem::take)
+            .unwrap_or_default()
+    }
+
+    /// Record that the scraper made progress, for example by fetching a new page.
+    pub fn record_progress(&self) {
+        *self.last_progress.lock().unwrap() = Some(Instant::now());
+    }
+
+    /// Time elapsed since the last progress was recorded, if any was.
+    pub fn since_last_progress(&self) -> Option<Duration> {
+        self.last_progress
+            .lock()
+            .unwrap()
+            .map(|instant| instant.elapsed())
+    }
+
+    pub fn record_error(&self, category: &str) {
+        *self
+            .errors
+            .lock()
+            .unwrap()
+            .entry(category.to_string())
+            .or_insert(0) += 1;
+    }
+
+    /// Record the class of an error that didn't stop the run, like a failed batch.
+    pub fn record_error_kind(&self, err: &Error) {
+        if let Some(kind) = exit::error_kind(err) {
+            let mut worst = self.worst_error.lock().unwrap();
+            *worst = (*worst).max(Some(kind));
+        }
+    }
+
+    /// Most severe class of the errors recorded with `record_error_kind`, if any.
+    pub fn worst_error(&self) -> Option<ErrorKind> {
+        *self.worst_error.lock().unwrap()
+    }
+
+    /// Record an unexpected field or value in an API response, returning whether it's the first
+    /// time it was seen.
+    pub fn record_drift(&self, drift: &str) -> bool {
+        let mut schema_drift = self.schema_drift.lock().unwrap();
+        let count = schema_drift.entry(drift.to_string()).or_insert(0);
+        *count += 1;
+        *count == 1
+    }
+
+    /// Total number of unexpected fields and values found in the API responses.
+    pub fn schema_drift_count(&self) -> usize {
+        self.schema_drift.lock().unwrap().values().sum()
+    }
+
+    /// Total number of errors recorded, across all the categories.
+    pub fn errors_count(&self) -> usize {
+        self.errors.lock().unwrap().values().sum()
+    }
+
+    pub fn record_response(&self, version: &str, elapsed: Duration) {
+        *self
+            .http_versions
+            .lock()
+            .unwrap()
+            .entry(version.to_string())
+            .or_insert(0) += 1;
+        Summary::incr(&self.http_wait_ms, elapsed.as_millis() as usize);
+    }
+
+    /// Record a request sent to an endpoint, possibly with a token, with the status code of its
+    /// response (or `error` if it failed) and the remaining rate limit reported by it.
+    pub fn record_request(
+        &self,
+        endpoint: &str,
+        token: Option<&str>,
+        status: &str,
+        elapsed: Duration,
+        rate_limit_remaining: Option<u64>,
+    ) {
+        self.endpoints
+            .lock()
+            .unwrap()
+            .entry(endpoint.to_string())
+            .or_default()
+            .record(status, elapsed, rate_limit_remaining);
+        if let Some(token) = token {
+            self.tokens
+                .lock()
+                .unwrap()
+                .entry(token.to_string())
+                .or_default()
+                .record(status, elapsed, rate_limit_remaining);
+        }
+    }
+
+    /// Snapshot of the statistics of each endpoint.
+    pub fn endpoints(&self) -> BTreeMap<String, RequestStats> {
+        self.endpoints.lock().unwrap().clone()
+    }
+
+    /// Snapshot of the statistics of each token, by identifier.
+    pub fn tokens(&self) -> BTreeMap<String, RequestStats> {
+        self.tokens.lock().unwrap().clone()
+    }
+
+    pub fn log(&self, bytes_written: u64) {
+        let get = |counter: &AtomicUsize| counter.load(Ordering::SeqCst);
+
+        info!("run summary:");
+        info!("  pages fetched: {}", get(&self.pages_fetched));
+        info!("  repositories seen: {}", get(&self.repos_seen));
+        info!("  Rust repositories stored: {}", get(&self.repos_stored));
+        if get(&self.repos_seeded) > 0 {
+            info!(
+                "  repositories skipped thanks to the seed: {}",
+                get(&self.repos_seeded)
+            );
+        }
+        if get(&self.repos_known_non_rust) > 0 {
+            info!(
+                "  repositories already known not to use Rust: {}",
+                get(&self.repos_known_non_rust)
+            );
+        }
+        info!("  API calls made: {}", get(&self.api_calls));
+        if get(&self.tokens_quarantined) > 0 {
+            info!(
+                "  tokens quarantined after being rejected: {}",
+                get(&self.tokens_quarantined)
+            );
+        }
+        info!("  GraphQL points spent: {}", get(&self.graphql_points));
+        info!(
+            "  data growth: {} rows, {} bytes",
+            get(&self.repos_stored),
+            bytes_written
+        );
+
+        let versions = self.http_versions.lock().unwrap();
+        let responses = versions.values().sum::<usize>();
+        if let Some(average) = get(&self.http_wait_ms).checked_div(responses) {
+            info!(
+                "  HTTP responses: {}",
+                versions
+                    .iter()
+                    .map(|(version, count)| format!("{} {}", count, version))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+            info!("  average response time: {} ms", average);
+        }
+
+        let endpoints = self.endpoints.lock().unwrap();
+        if !endpoints.is_empty() {
+            info!("  requests by endpoint:");
+        }
+        for (endpoint, stats) in endpoints.iter() {
+            let p95 = match stats.latency_quantile_ms(0.95) {
+                Some(bound) => format!("<= {} ms", bound),
+                None => format!("> {} ms", LATENCY_BUCKETS_MS[LATENCY_BUCKETS_MS.len() - 1]),
+            };
+            info!(
+                "    {}: {} requests, average {} ms, p95 {}, statuses {}",
+                endpoint,
+                stats.requests,
+                stats.latency_total_ms / stats.requests as u64,
+                p95,
+                stats
+                    .statuses
+                    .iter()
+                    .map(|(status, count)| format!("{}: {}", status, count))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+
+        let tokens = self.tokens.lock().unwrap();
+        if !tokens.is_empty() {
+            info!("  requests by token:");
+        }
+        for (token, stats) in tokens.iter() {
+            info!(
+                "    {}: {} requests, {} rate limit remaining, statuses {}",
+                token,
+                stats.requests,
+                stats
+                    .rate_limit_remaining
+                    .map_or("unknown".to_string(), |remaining| remaining.to_string()),
+                stats
+                    .statuses
+                    .iter()
+                    .map(|(status, count)| format!("{}: {}", status, count))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+
+        let errors = self.errors.lock().unwrap();
+        if errors.is_empty() {
+            info!("  errors: none");
+        } else {
+            info!("  errors:");
+            for (category, count) in errors.iter() {
+                info!("    {}: {}", category, count);
+            }
+        }
+
+        let schema_drift = self.schema_drift.lock().unwrap();
+        if !schema_drift.is_empty() {
+            info!("  schema drift:");
+            for (drift, count) in schema_drift.iter() {
+                info!("    {}: {}", drift, count);
+            }
+        }
+    }
+}
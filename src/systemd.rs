@@ -0,0 +1,87 @@
+// Copyright (c) 2018 Pietro Albini <pietro@pietroalbini.org>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies
+// of the Software, and to permit persons to whom the Software is furnished to do
+// so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Minimal implementation of the systemd notification protocol, used when the scraper runs as a
+//! `Type=notify` service. Nothing is sent when `NOTIFY_SOCKET` is not set.
+
+/// Tell systemd the scraper finished starting up.
+pub fn ready() {
+    notify("READY=1");
+}
+
+/// Tell systemd the scraper is still making progress, resetting the watchdog timer.
+pub fn watchdog() {
+    notify("WATCHDOG=1");
+}
+
+/// Tell systemd the scraper is shutting down.
+pub fn stopping() {
+    notify("STOPPING=1");
+}
+
+#[cfg(unix)]
+fn notify(state: &str) {
+    if let Some(socket) = std::env::var_os("NOTIFY_SOCKET") {
+        if let Err(err) = send(&socket.to_string_lossy(), state) {
+            warn!("failed to notify systemd: {}", err);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn notify(_state: &str) {}
+
+#[cfg(unix)]
+fn send(socket: &str, state: &str) -> std::io::Result<()> {
+    use std::os::unix::net::UnixDatagram;
+
+    let sock = UnixDatagram::unbound()?;
+    // Names starting with `@` refer to sockets in the abstract namespace
+    if let Some(name) = socket.strip_prefix('@') {
+        send_abstract(&sock, name, state)
+    } else {
+        sock.send_to(state.as_bytes(), socket).map(|_| ())
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn send_abstract(
+    sock: &std::os::unix::net::UnixDatagram,
+    name: &str,
+    state: &str,
+) -> std::io::Result<()> {
+    use std::os::linux::net::SocketAddrExt;
+    use std::os::unix::net::SocketAddr;
+
+    let addr = SocketAddr::from_abstract_name(name.as_bytes())?;
+    sock.send_to_addr(state.as_bytes(), &addr).map(|_| ())
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn send_abstract(
+    _sock: &std::os::unix::net::UnixDatagram,
+    _name: &str,
+    _state: &str,
+) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Other,
+        "abstract sockets are only supported on Linux",
+    ))
+}
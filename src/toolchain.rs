@@ -0,0 +1,65 @@
+// Copyright (c) 2018 Pietro Albini <pietro@pietroalbini.org>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies
+// of the Software, and to permit persons to whom the Software is furnished to do
+// so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Parsing of the `rust-toolchain` and `rust-toolchain.toml` files pinning the toolchain.
+
+/// Extract the pinned channel from the content of a toolchain file.
+///
+/// The legacy `rust-toolchain` file can either contain just the channel name or the same TOML
+/// as `rust-toolchain.toml`, where the channel is in the `[toolchain]` table.
+pub fn parse_channel(content: &str) -> Option<String> {
+    if content.contains("[toolchain]") {
+        content
+            .lines()
+            .map(|line| line.trim())
+            .filter(|line| line.starts_with("channel"))
+            .filter_map(|line| line.split_once('=').map(|(_, value)| value))
+            .map(|value| {
+                value
+                    .trim()
+                    .trim_matches(|c| c == '"' || c == '\'')
+                    .to_string()
+            })
+            .find(|channel| !channel.is_empty())
+    } else {
+        content
+            .lines()
+            .map(|line| line.trim())
+            .find(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(String::from)
+    }
+}
+
+/// Broad category of a pinned channel, used to aggregate the pins.
+pub fn category(channel: &str) -> &'static str {
+    if channel == "stable" || channel.starts_with("stable-") {
+        "stable"
+    } else if channel == "beta" || channel.starts_with("beta-") {
+        "beta"
+    } else if channel == "nightly" {
+        "nightly (latest)"
+    } else if channel.starts_with("nightly-") {
+        "nightly (dated)"
+    } else if channel.starts_with("1.") {
+        "version"
+    } else {
+        "other"
+    }
+}
@@ -0,0 +1,357 @@
+// Copyright (c) 2018 Pietro Albini <pietro@pietroalbini.org>
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy of
+// this software and associated documentation files (the "Software"), to deal in
+// the Software without restriction, including without limitation the rights to
+// use, copy, modify, merge, publish, distribute, sublicense, and/or sell copies
+// of the Software, and to permit persons to whom the Software is furnished to do
+// so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Minimal implementation of Cargo's semantic versions and version requirements, enough to
+//! match the versions of locked packages against advisories.
+
+use prelude::*;
+use std::cmp::Ordering;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Version {
+    major: u64,
+    minor: u64,
+    patch: u64,
+    pre: Vec<String>,
+}
+
+impl FromStr for Version {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Fallible<Self> {
+        // Build metadata doesn't affect the precedence
+        let s = s.trim().split('+').next().unwrap();
+        let (numbers, pre) = match s.find('-') {
+            Some(pos) => (
+                &s[..pos],
+                s[pos + 1..].split('.').map(String::from).collect(),
+            ),
+            None => (s, Vec::new()),
+        };
+
+        let parts = numbers
+            .split('.')
+            .map(|part| part.parse::<u64>())
+            .collect::<Result<Vec<_>, _>>()
+            .with_context(|_| format!("invalid version: {}", s))?;
+        if parts.len() != 3 {
+            bail!("invalid version: {}", s);
+        }
+
+        Ok(Version {
+            major: parts[0],
+            minor: parts[1],
+            patch: parts[2],
+            pre,
+        })
+    }
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.major, self.minor, self.patch)
+            .cmp(&(other.major, other.minor, other.patch))
+            .then_with(|| match (self.pre.is_empty(), other.pre.is_empty()) {
+                // Pre-releases come before the release
+                (true, true) => Ordering::Equal,
+                (true, false) => Ordering::Greater,
+                (false, true) => Ordering::Less,
+                (false, false) => compare_pre(&self.pre, &other.pre),
+            })
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+fn compare_pre(a: &[String], b: &[String]) -> Ordering {
+    for (a, b) in a.iter().zip(b) {
+        let ordering = match (a.parse::<u64>(), b.parse::<u64>()) {
+            (Ok(a), Ok(b)) => a.cmp(&b),
+            (Ok(_), Err(_)) => Ordering::Less,
+            (Err(_), Ok(_)) => Ordering::Greater,
+            (Err(_), Err(_)) => a.cmp(b),
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    a.len().cmp(&b.len())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Exact,
+    Greater,
+    GreaterEq,
+    Less,
+    LessEq,
+    Tilde,
+    Caret,
+    Wildcard,
+}
+
+/// A single comparator of a requirement, like `>= 1.2` or `^0.3.1`.
+#[derive(Debug, Clone)]
+struct Comparator {
+    op: Op,
+    major: u64,
+    minor: Option<u64>,
+    patch: Option<u64>,
+    pre: Vec<String>,
+}
+
+impl Comparator {
+    fn lower(&self) -> Version {
+        Version {
+            major: self.major,
+            minor: self.minor.unwrap_or(0),
+            patch: self.patch.unwrap_or(0),
+            pre: self.pre.clone(),
+        }
+    }
+
+    /// First version not matched anymore by a requirement bounded from above.
+    fn upper(&self) -> Version {
+        let (major, minor, patch) = match (self.op, self.minor, self.patch) {
+            (Op::Caret, _, _) if self.major > 0 => (self.major + 1, 0, 0),
+            (Op::Caret, Some(minor), _) if minor > 0 => (0, minor + 1, 0),
+            (Op::Caret, Some(_), Some(patch)) => (0, 0, patch + 1),
+            (Op::Tilde, Some(minor), _) => (self.major, minor + 1, 0),
+            // Partial versions like `1.2` match all the versions starting with them
+            (_, Some(minor), None) => (self.major, minor + 1, 0),
+            (_, Some(minor), Some(patch)) if self.op != Op::Caret => (self.major, minor, patch + 1),
+            _ => (self.major + 1, 0, 0),
+        };
+        Version {
+            major,
+            minor,
+            patch,
+            pre: vec!["0".into()],
+        }
+    }
+
+    fn matches(&self, version: &Version) -> bool {
+        match self.op {
+            Op::Greater => *version >= self.upper(),
+            Op::GreaterEq => *version >= self.lower(),
+            Op::Less => *version < self.lower(),
+            Op::LessEq => *version < self.upper(),
+            Op::Exact | Op::Tilde | Op::Caret | Op::Wildcard => {
+                *version >= self.lower() && *version < self.upper()
+            }
+        }
+    }
+}
+
+impl FromStr for Comparator {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Fallible<Self> {
+        let s = s.trim();
+        let (op, rest) = [
+            (">=", Op::GreaterEq),
+            ("<=", Op::LessEq),
+            (">", Op::Greater),
+            ("<", Op::Less),
+            ("=", Op::Exact),
+            ("~", Op::Tilde),
+            ("^", Op::Caret),
+        ]
+        .iter()
+        .find(|(prefix, _)| s.starts_with(prefix))
+        .map(|(prefix, op)| (*op, s[prefix.len()..].trim()))
+        .unwrap_or((Op::Caret, s));
+
+        let (numbers, pre) = match rest.find('-') {
+            Some(pos) => (
+                &rest[..pos],
+                rest[pos + 1..].split('.').map(String::from).collect(),
+            ),
+            None => (rest, Vec::new()),
+        };
+        let mut parts = numbers.split('.');
+        let mut next = |wildcard: &mut bool| -> Fallible<Option<u64>> {
+            match parts.next() {
+                None => Ok(None),
+                Some("*") | Some("x") | Some("X") => {
+                    *wildcard = true;
+                    Ok(None)
+                }
+                Some(part) if *wildcard => bail!("invalid version requirement: {} ({})", s, part),
+                Some(part) => {
+                    Ok(Some(part.parse::<u64>().with_context(|_| {
+                        format!("invalid version requirement: {}", s)
+                    })?))
+                }
+            }
+        };
+
+        let mut wildcard = false;
+        let major = next(&mut wildcard)?;
+        let minor = next(&mut wildcard)?;
+        let patch = next(&mut wildcard)?;
+        let major = match major {
+            Some(major) => major,
+            None if wildcard => {
+                return Ok(Comparator {
+                    op: Op::GreaterEq,
+                    major: 0,
+                    minor: None,
+                    patch: None,
+                    pre: Vec::new(),
+                })
+            }
+            None => bail!("invalid version requirement: {}", s),
+        };
+
+        Ok(Comparator {
+            op: if wildcard { Op::Wildcard } else { op },
+            major,
+            minor,
+            patch,
+            pre,
+        })
+    }
+}
+
+/// A version requirement, made of comma-separated comparators that must all match.
+#[derive(Debug, Clone)]
+pub struct VersionReq(Vec<Comparator>);
+
+impl VersionReq {
+    /// Whether the version is in the range described by the requirement. Unlike Cargo, which only
+    /// picks a pre-release when asked to, pre-releases are compared like any other version: the
+    /// requirements of the advisories are ranges of versions, and `>= 1.2.0` covers `2.0.0-rc.1`.
+    pub fn matches(&self, version: &Version) -> bool {
+        self.0.iter().all(|comparator| comparator.matches(version))
+    }
+}
+
+impl FromStr for VersionReq {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Fallible<Self> {
+        Ok(VersionReq(
+            s.split(',')
+                .map(|comparator| comparator.parse())
+                .collect::<Fallible<_>>()?,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Version, VersionReq};
+
+    fn matches(req: &str, version: &str) -> bool {
+        req.parse::<VersionReq>()
+            .unwrap()
+            .matches(&version.parse::<Version>().unwrap())
+    }
+
+    #[test]
+    fn test_version_ordering() {
+        let ordered = [
+            "0.9.9",
+            "1.0.0-alpha",
+            "1.0.0-alpha.1",
+            "1.0.0-alpha.beta",
+            "1.0.0-beta.2",
+            "1.0.0-beta.11",
+            "1.0.0-rc.1",
+            "1.0.0",
+            "1.0.1",
+            "1.10.0",
+        ];
+        for pair in ordered.windows(2) {
+            let (a, b) = (pair[0].parse::<Version>(), pair[1].parse::<Version>());
+            assert!(a.unwrap() < b.unwrap(), "{} < {}", pair[0], pair[1]);
+        }
+        assert_eq!(
+            "1.2.3+build.5".parse::<Version>().unwrap(),
+            "1.2.3".parse::<Version>().unwrap()
+        );
+        assert!("1.2".parse::<Version>().is_err());
+        assert!("1.2.x".parse::<Version>().is_err());
+    }
+
+    #[test]
+    fn test_caret() {
+        assert!(matches("^1.2.3", "1.2.3"));
+        assert!(matches("^1.2.3", "1.9.0"));
+        assert!(!matches("^1.2.3", "1.2.2"));
+        assert!(!matches("^1.2.3", "2.0.0"));
+        assert!(matches("^0.2.3", "0.2.9"));
+        assert!(!matches("^0.2.3", "0.3.0"));
+        assert!(matches("^0.0.3", "0.0.3"));
+        assert!(!matches("^0.0.3", "0.0.4"));
+        // Bare versions are caret requirements
+        assert!(matches("1.2.3", "1.4.0"));
+        assert!(!matches("0.2", "0.3.0"));
+    }
+
+    #[test]
+    fn test_tilde() {
+        assert!(matches("~1.2.3", "1.2.9"));
+        assert!(!matches("~1.2.3", "1.3.0"));
+        assert!(matches("~1.2", "1.2.0"));
+        assert!(!matches("~1.2", "1.3.0"));
+        assert!(matches("~1", "1.9.9"));
+        assert!(!matches("~1", "2.0.0"));
+    }
+
+    #[test]
+    fn test_partial_and_wildcard() {
+        assert!(matches("= 1.2", "1.2.7"));
+        assert!(!matches("= 1.2", "1.3.0"));
+        assert!(matches("> 1.2", "1.3.0"));
+        assert!(!matches("> 1.2", "1.2.9"));
+        assert!(matches("<= 1.2", "1.2.9"));
+        assert!(!matches("<= 1.2", "1.3.0"));
+        assert!(matches("1.*", "1.5.0"));
+        assert!(!matches("1.*", "2.0.0"));
+        assert!(matches("*", "0.0.1"));
+        assert!("1.*.3".parse::<VersionReq>().is_err());
+    }
+
+    #[test]
+    fn test_ranges() {
+        assert!(matches(">= 0.2.7, < 0.2.23", "0.2.22"));
+        assert!(!matches(">= 0.2.7, < 0.2.23", "0.2.23"));
+        assert!(!matches(">= 0.2.7, < 0.2.23", "0.2.6"));
+    }
+
+    #[test]
+    fn test_pre_releases() {
+        // Pre-releases are compared as plain versions, as the advisories expect
+        assert!(matches(">= 1.5.0", "2.0.0-rc.1"));
+        assert!(!matches(">= 2.0.0", "2.0.0-rc.1"));
+        assert!(matches(">= 2.0.0-rc.1", "2.0.0-rc.2"));
+        assert!(matches("< 1.0.0", "1.0.0-alpha"));
+        assert!(!matches("^1.2.3", "2.0.0-alpha"));
+        assert!(matches("^1.2.3", "1.3.0-beta"));
+        assert!(!matches("^1.2.3", "1.2.3-beta"));
+    }
+}